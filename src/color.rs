@@ -0,0 +1,148 @@
+//! Terminal colors in git's notation, e.g. `bold red` or `#ff8000 ul`, as
+//! given in `%C(...)` placeholders and `color.*` config, and the decision
+//! whether to use them at all.
+
+use std::{
+    env,
+    io::{self, IsTerminal},
+};
+
+pub const RESET: &str = "\x1b[m";
+pub const BOLD: &str = "\x1b[1m";
+pub const RED: &str = "\x1b[31m";
+pub const GREEN: &str = "\x1b[32m";
+pub const YELLOW: &str = "\x1b[33m";
+pub const BLUE: &str = "\x1b[34m";
+pub const MAGENTA: &str = "\x1b[35m";
+pub const CYAN: &str = "\x1b[36m";
+pub const BOLD_RED: &str = "\x1b[1;31m";
+pub const BOLD_GREEN: &str = "\x1b[1;32m";
+pub const BOLD_YELLOW: &str = "\x1b[1;33m";
+pub const BOLD_BLUE: &str = "\x1b[1;34m";
+pub const BOLD_MAGENTA: &str = "\x1b[1;35m";
+pub const BOLD_CYAN: &str = "\x1b[1;36m";
+
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+/// Attribute names by their SGR code
+const ATTRIBUTES: [(&str, u8); 7] = [
+    ("bold", 1),
+    ("dim", 2),
+    ("italic", 3),
+    ("ul", 4),
+    ("blink", 5),
+    ("reverse", 7),
+    ("strike", 9),
+];
+
+/// Returns whether to color output given a `--color` argument or `color.*`
+/// setting: `always`, `never` or `auto` (the default), which colors only
+/// when writing to a terminal.
+pub fn want_color(setting: Option<&str>) -> bool {
+    match setting.map(str::to_lowercase).as_deref() {
+        Some("always" | "true" | "yes" | "on" | "1") => true,
+        Some("never" | "false" | "no" | "off" | "0") => false,
+        _ => io::stdout().is_terminal() && env::var("TERM").map_or(true, |term| term != "dumb"),
+    }
+}
+
+/// Parses a color like `red`, `bold blue reverse` or `#ff0000 black` (the
+/// second color being the background) into its escape sequence.
+pub fn parse(spec: &str) -> Option<String> {
+    if spec.trim() == "reset" {
+        return Some(RESET.to_string());
+    }
+
+    let mut attributes = vec![];
+    let mut colors = vec![];
+    for word in spec.split_whitespace() {
+        // The first color is the foreground, the second the background
+        if let Some(color) = parse_color(word, colors.len() == 1) {
+            if colors.len() == 2 {
+                return None;
+            }
+            colors.push(color);
+            continue;
+        }
+
+        let negated = word.strip_prefix("no-").or_else(|| word.strip_prefix("no"));
+        let (name, offset) = match negated {
+            Some(name) => (name, 20),
+            None => (word, 0),
+        };
+        let (_, code) = ATTRIBUTES.iter().find(|(attr, _)| *attr == name)?;
+        // `nobold` is 22 like `nodim`, 21 would be double underline
+        let code = match (code, offset) {
+            (1, 20) => 22,
+            (code, offset) => code + offset,
+        };
+        attributes.push(code.to_string());
+    }
+
+    attributes.sort_by_key(|code| code.parse::<u8>().unwrap_or(0));
+    attributes.dedup();
+    let codes: Vec<String> = attributes
+        .into_iter()
+        .chain(colors.into_iter().flatten())
+        .collect();
+    if codes.is_empty() {
+        return Some(String::new());
+    }
+    Some(format!("\x1b[{}m", codes.join(";")))
+}
+
+/// Parses a single color word into its SGR parameters, or `None` for
+/// `normal`, which leaves the color unchanged.
+fn parse_color(word: &str, background: bool) -> Option<Option<String>> {
+    let base = if background { 40 } else { 30 };
+    if word == "normal" {
+        return Some(None);
+    }
+    if word == "default" {
+        return Some(Some((base + 9).to_string()));
+    }
+    if let Some(hex) = word.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        let (r, g, b) = (channel(0)?, channel(2)?, channel(4)?);
+        return Some(Some(format!("{};2;{r};{g};{b}", base + 8)));
+    }
+    if let Ok(n) = word.parse::<i16>() {
+        return match n {
+            -1 => Some(None),
+            0..=7 => Some(Some((base + n).to_string())),
+            8..=255 => Some(Some(format!("{};5;{n}", base + 8))),
+            _ => None,
+        };
+    }
+    let (name, base) = match word.strip_prefix("bright") {
+        Some(name) => (name, base + 60),
+        None => (word, base),
+    };
+    let n = COLOR_NAMES.iter().position(|c| *c == name)? as i16;
+    Some(Some((base + n).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse("red").unwrap(), RED);
+        assert_eq!(parse("bold red").unwrap(), BOLD_RED);
+        assert_eq!(parse("red bold").unwrap(), BOLD_RED);
+        assert_eq!(parse("reset").unwrap(), RESET);
+        assert_eq!(parse("yellow blue ul").unwrap(), "\x1b[4;33;44m");
+        assert_eq!(parse("normal red").unwrap(), "\x1b[41m");
+        assert_eq!(parse("brightgreen nobold").unwrap(), "\x1b[22;92m");
+        assert_eq!(parse("#ff8000").unwrap(), "\x1b[38;2;255;128;0m");
+        assert_eq!(parse("208").unwrap(), "\x1b[38;5;208m");
+        assert_eq!(parse("").unwrap(), "");
+        assert_eq!(parse("blurple"), None);
+        assert_eq!(parse("red green blue"), None);
+    }
+}
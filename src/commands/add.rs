@@ -0,0 +1,307 @@
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    process::Command,
+};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use colored::*;
+
+use crate::repository::{
+    patch::{Edit, FilePatch, HunkKind, PatchMode},
+    Repository,
+};
+
+#[derive(Args)]
+pub struct AddArgs {
+    /// Choose the hunks of the changes to stage interactively
+    #[clap(short, long)]
+    patch: bool,
+
+    /// Hash and compress files with this many threads (default: one per core)
+    #[clap(short, long)]
+    jobs: Option<usize>,
+
+    pathspecs: Vec<String>,
+}
+
+/// Add file contents to the index.
+pub fn run(args: AddArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+
+    if args.patch {
+        return patch(&repo, PatchMode::Stage, &args.pathspecs);
+    }
+    if args.pathspecs.is_empty() {
+        eprintln!("Nothing specified, nothing added.");
+        eprintln!("hint: Maybe you wanted to say 'minigit add .'?");
+        return Ok(());
+    }
+    repo.add_paths(&args.pathspecs, args.jobs)?;
+
+    Ok(())
+}
+
+/// What the answers to a patch session's prompts do, e.g. `Discard` and
+/// ` from worktree`.
+fn action(mode: PatchMode) -> (&'static str, &'static str) {
+    match mode {
+        PatchMode::Stage => ("Stage", ""),
+        PatchMode::Reset { head: true, .. } => ("Unstage", ""),
+        PatchMode::Reset { head: false, .. } => ("Apply", " to index"),
+        PatchMode::Restore { head, index, tree } => {
+            let verb = match (tree, head) {
+                (Some(_), false) => "Apply",
+                _ => "Discard",
+            };
+            let target = match (verb, index) {
+                ("Apply", false) => " to worktree",
+                ("Apply", true) => " to index and worktree",
+                (_, false) => " from worktree",
+                (_, true) => " from index and worktree",
+            };
+            (verb, target)
+        }
+    }
+}
+
+/// Goes through the changes of the files matching `pathspecs` hunk by
+/// hunk, asking on stdin which to apply as `mode` says, then applies them.
+pub fn patch(repo: &Repository, mode: PatchMode, pathspecs: &[String]) -> Result<()> {
+    let files = repo.patch_files(mode, pathspecs)?;
+    let changed = !files.is_empty();
+    let mut files: Vec<FilePatch> = files.into_iter().filter(|f| !f.hunks.is_empty()).collect();
+    if files.is_empty() {
+        match changed {
+            true => eprintln!("Only binary files changed."),
+            false => eprintln!("No changes."),
+        }
+        return Ok(());
+    }
+
+    let mut stdin = io::stdin().lock();
+    let mut out = io::stdout().lock();
+    for file in &mut files {
+        let mut header = vec![];
+        file.write_header(&mut header)?;
+        for line in String::from_utf8_lossy(&header).lines() {
+            writeln!(out, "{}", line.bold())?;
+        }
+        let quit = patch_file(repo, mode, file, &mut stdin, &mut out)?;
+        writeln!(out)?;
+        if quit {
+            break;
+        }
+    }
+
+    repo.apply_patches(mode, &files)?;
+    Ok(())
+}
+
+/// Asks about the hunks of a file. Returns true if the session is over.
+fn patch_file(
+    repo: &Repository,
+    mode: PatchMode,
+    file: &mut FilePatch,
+    stdin: &mut impl BufRead,
+    out: &mut impl Write,
+) -> Result<bool> {
+    let (verb, target) = action(mode);
+    let mut i = 0;
+    while i < file.hunks.len() {
+        write_hunk(file, i, out)?;
+        let mut options = String::from("y,n,q,a,d");
+        if file.can_split(i) {
+            options += ",s";
+        }
+        if file.can_edit(i) {
+            options += ",e";
+        }
+        let what = match file.hunks[i].kind {
+            HunkKind::ModeChange => "mode change",
+            HunkKind::Deletion => "deletion",
+            HunkKind::Addition => "addition",
+            HunkKind::Lines => "this hunk",
+        };
+        let prompt = format!(
+            "({}/{}) {verb} {what}{target} [{options},?]? ",
+            i + 1,
+            file.hunks.len()
+        );
+        write!(out, "{}", prompt.blue().bold())?;
+        out.flush()?;
+
+        let Some(answer) = read_answer(stdin)? else {
+            return Ok(true);
+        };
+        match answer.chars().next().map(|c| c.to_ascii_lowercase()) {
+            Some('y') => {
+                file.hunks[i].selected = true;
+                i += 1;
+            }
+            Some('n') => {
+                file.hunks[i].selected = false;
+                i += 1;
+            }
+            Some('a') => {
+                file.hunks[i..].iter_mut().for_each(|h| h.selected = true);
+                return Ok(false);
+            }
+            Some('d') => {
+                file.hunks[i..].iter_mut().for_each(|h| h.selected = false);
+                return Ok(false);
+            }
+            Some('q') => return Ok(true),
+            Some('s') if file.can_split(i) => {
+                let count = file.split(i);
+                writeln!(out, "{}", format!("Split into {count} hunks.").cyan())?;
+            }
+            Some('s') => writeln!(out, "{}", "Sorry, cannot split this hunk".red())?,
+            Some('e') if file.can_edit(i) => {
+                let applied = edit_hunk(repo, mode, file, i, stdin, out)?;
+                if applied {
+                    i += 1;
+                }
+            }
+            Some('e') => writeln!(out, "{}", "Sorry, cannot edit this hunk".red())?,
+            Some(_) => {
+                let verb = verb.to_lowercase();
+                let mut help = format!(
+                    "y - {verb} this hunk{target}\n\
+                     n - do not {verb} this hunk{target}\n\
+                     q - quit; do not {verb} this hunk or any of the remaining ones\n\
+                     a - {verb} this hunk and all later hunks in the file\n\
+                     d - do not {verb} this hunk or any of the later hunks in the file\n"
+                );
+                if options.contains('s') {
+                    help += "s - split the current hunk into smaller hunks\n";
+                }
+                if options.contains('e') {
+                    help += "e - manually edit the current hunk\n";
+                }
+                help += "? - print help";
+                for line in help.lines() {
+                    writeln!(out, "{}", line.red().bold())?;
+                }
+            }
+            None => {}
+        }
+    }
+
+    Ok(false)
+}
+
+/// Reads a line of input, or returns `None` at the end of it.
+fn read_answer(stdin: &mut impl BufRead) -> Result<Option<String>> {
+    let mut answer = String::new();
+    match stdin.read_line(&mut answer)? {
+        0 => Ok(None),
+        _ => Ok(Some(answer.trim().to_string())),
+    }
+}
+
+/// Writes a hunk in the colors of a diff.
+fn write_hunk(file: &FilePatch, i: usize, out: &mut impl Write) -> Result<()> {
+    let mut hunk = vec![];
+    file.write_hunk(i, &mut hunk)?;
+    let mut in_lines = false;
+    for line in String::from_utf8_lossy(&hunk).lines() {
+        let line = match line.as_bytes().first() {
+            _ if line.starts_with("@@") => {
+                in_lines = true;
+                match line[2..].find("@@") {
+                    Some(end) => format!("{}{}", line[..end + 4].cyan(), &line[end + 4..]),
+                    None => line.cyan().to_string(),
+                }
+            }
+            _ if !in_lines => line.bold().to_string(),
+            Some(b'-') => line.red().to_string(),
+            Some(b'+') => line.green().to_string(),
+            _ => line.to_string(),
+        };
+        writeln!(out, "{line}")?;
+    }
+    Ok(())
+}
+
+/// Lets the user edit a hunk in their editor until it applies. Returns
+/// true if the edited hunk replaced it.
+fn edit_hunk(
+    repo: &Repository,
+    mode: PatchMode,
+    file: &mut FilePatch,
+    i: usize,
+    stdin: &mut impl BufRead,
+    out: &mut impl Write,
+) -> Result<bool> {
+    let reverse = mode.reverse_apply();
+    let (removed, added) = if reverse { ('+', '-') } else { ('-', '+') };
+    let marked = match action(mode).0 {
+        "Stage" => "staging",
+        "Unstage" => "unstaging",
+        "Discard" => "discarding",
+        _ => "applying",
+    };
+    let path = repo.get_path().join("addp-hunk-edit.diff");
+
+    loop {
+        let mut text = b"# Manual hunk edit mode -- see bottom for a quick guide.\n".to_vec();
+        file.write_hunk(i, &mut text)?;
+        let guide = format!(
+            "---\n\
+             To remove '{removed}' lines, make them ' ' lines (context).\n\
+             To remove '{added}' lines, delete them.\n\
+             Lines starting with # will be removed.\n\
+             If the patch applies cleanly, the edited hunk will immediately be marked for {marked}.\n\
+             If it does not apply cleanly, you will be given an opportunity to\n\
+             edit again.  If all lines of the hunk are removed, then the edit is\n\
+             aborted and the hunk is left unchanged.\n"
+        );
+        for line in guide.lines() {
+            text.extend_from_slice(format!("# {line}\n").as_bytes());
+        }
+        fs::write(&path, text)?;
+        launch_editor(repo, &path.to_string_lossy())?;
+        let edited = fs::read(&path)?;
+        let _ = fs::remove_file(&path);
+
+        match file.edit(i, &edited, reverse) {
+            Edit::Applied => return Ok(true),
+            Edit::Empty => return Ok(false),
+            Edit::DoesNotApply => {
+                let question =
+                    "Your edited hunk does not apply. Edit again (saying \"no\" discards!) [y/n]? ";
+                write!(out, "{}", question.blue().bold())?;
+                out.flush()?;
+                match read_answer(stdin)? {
+                    Some(answer) if answer.to_lowercase().starts_with('y') => {}
+                    _ => return Ok(false),
+                }
+            }
+        }
+    }
+}
+
+/// Opens a file in the editor set by `GIT_EDITOR`, `core.editor`, `VISUAL`
+/// or `EDITOR`, or else `vi`.
+fn launch_editor(repo: &Repository, path: &str) -> Result<()> {
+    let config = repo.config()?;
+    let editor = std::env::var("GIT_EDITOR")
+        .ok()
+        .or(config.get("core.editor").map(str::to_string))
+        .or(std::env::var("VISUAL").ok())
+        .or(std::env::var("EDITOR").ok())
+        .unwrap_or_else(|| "vi".to_string());
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(&editor)
+        .arg(path)
+        .status();
+    if !status.is_ok_and(|status| status.success()) {
+        bail!("There was a problem with the editor '{editor}'.");
+    }
+    Ok(())
+}
@@ -1,67 +1,327 @@
-use std::{fs, path::Path};
-
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use clap::Args;
 use colored::*;
 
-fn collect_entries<P: AsRef<Path>>(path: P, trim_path: &str) -> Result<Vec<String>> {
-    let mut entries = Vec::new();
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let entry_path = entry.path();
-
-        if entry_path.is_dir() {
-            // Recursively collect entries from subdirectories.
-            entries.extend(
-                collect_entries(&entry_path, trim_path)
-                    .context("collect branches in subdirectories")?,
+use crate::{
+    object::commit,
+    ref_filter::{self, Format, Quote, RefFilter, RefItem},
+    repository::Repository,
+};
+
+#[derive(Args)]
+pub struct BranchArgs {
+    /// List both remote-tracking and local branches
+    #[clap(short, long)]
+    all: bool,
+
+    /// Delete a fully merged branch
+    #[clap(short, long, conflicts_with_all = ["move_branch", "copy"])]
+    delete: bool,
+
+    /// Delete a branch even if it's not merged
+    #[clap(short = 'D')]
+    force_delete: bool,
+
+    /// Move/rename a branch, its reflog and its config
+    #[clap(short = 'm', long = "move", conflicts_with = "copy")]
+    move_branch: bool,
+
+    /// Move/rename a branch, even if the target exists
+    #[clap(short = 'M')]
+    force_move: bool,
+
+    /// Copy a branch, its reflog and its config
+    #[clap(short, long)]
+    copy: bool,
+
+    /// Copy a branch, even if the target exists
+    #[clap(short = 'C')]
+    force_copy: bool,
+
+    /// Reset <branchname> to <startpoint>, even if <branchname> exists
+    #[clap(short, long)]
+    force: bool,
+
+    /// Show hash and subject, give twice for upstream branch
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Print only branches that contain the commit
+    #[clap(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    contains: Vec<String>,
+
+    /// Print only branches that are merged into the commit
+    #[clap(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    merged: Option<String>,
+
+    /// Print only branches that are not merged into the commit
+    #[clap(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    no_merged: Option<String>,
+
+    /// List branch names, optionally matching the given patterns
+    #[clap(short, long)]
+    list: bool,
+
+    /// Field name to sort on, prefix with '-' for descending order
+    #[clap(long, value_name = "key")]
+    sort: Vec<String>,
+
+    /// Format to use for the output
+    #[clap(long)]
+    format: Option<String>,
+
+    /// Branch names, patterns or start point, depending on the mode
+    args: Vec<String>,
+}
+
+/// List, create, or delete branches.
+pub fn run(args: BranchArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+
+    if args.delete || args.force_delete {
+        return delete(&repo, &args.args, args.force_delete || args.force);
+    }
+    if args.move_branch || args.force_move || args.copy || args.force_copy {
+        let copy = args.copy || args.force_copy;
+        let force = args.force_move || args.force_copy || args.force;
+        return rename(&repo, &args.args, copy, force);
+    }
+
+    let filtering = args.list
+        || args.verbose > 0
+        || args.all
+        || args.format.is_some()
+        || !args.contains.is_empty()
+        || args.merged.is_some()
+        || args.no_merged.is_some();
+    if args.args.is_empty() || filtering {
+        return list(&repo, &args);
+    }
+
+    create(&repo, &args.args, args.force)
+}
+
+/// Returns the name of the current branch, if HEAD is not detached.
+fn current_branch(repo: &Repository) -> Result<Option<String>> {
+    Ok(repo
+        .read_symbolic_ref("HEAD")?
+        .and_then(|head| head.strip_prefix("refs/heads/").map(str::to_string)))
+}
+
+fn create(repo: &Repository, args: &[String], force: bool) -> Result<()> {
+    let (branch, start) = match args {
+        [branch] => (branch, "HEAD"),
+        [branch, start] => (branch, start.as_str()),
+        _ => bail!("too many arguments for a create operation"),
+    };
+    if force && current_branch(repo)?.as_deref() == Some(branch) {
+        bail!("cannot force update the current branch");
+    }
+    if force {
+        if let Some(path) = repo.worktree_using_branch(&format!("refs/heads/{branch}"))? {
+            bail!(
+                "cannot force update the branch '{branch}' used by worktree at '{}'",
+                path.display()
             );
-        } else {
-            entries.push(
-                entry_path
-                    .to_string_lossy()
-                    .trim_start_matches(trim_path)
-                    .to_string(),
+        }
+    }
+    let hash = repo
+        .rev_parse(start)
+        .with_context(|| format!("not a valid object name: '{start}'"))?;
+    repo.create_branch_at(branch, &hash, force)?;
+
+    Ok(())
+}
+
+fn delete(repo: &Repository, branches: &[String], force: bool) -> Result<()> {
+    if branches.is_empty() {
+        bail!("branch name required");
+    }
+    let current = current_branch(repo)?;
+
+    for branch in branches {
+        if current.as_deref() == Some(branch) {
+            bail!("cannot delete branch '{branch}' used by the current worktree");
+        }
+        if let Some(path) = repo.worktree_using_branch(&format!("refs/heads/{branch}"))? {
+            bail!(
+                "cannot delete branch '{branch}' used by worktree at '{}'",
+                path.display()
             );
         }
+        let hash = repo
+            .read_ref(&format!("refs/heads/{branch}"))?
+            .with_context(|| format!("branch '{branch}' not found"))?;
+
+        // A branch is safe to delete if it's merged into its upstream, or into HEAD
+        if !force {
+            let target = match repo.upstream(branch)? {
+                Some(upstream) => repo.read_ref(&upstream)?,
+                None => repo.read_ref("HEAD")?,
+            };
+            let merged = match target {
+                Some(target) => commit::is_ancestor(&hash, &target, repo)?,
+                None => false,
+            };
+            if !merged {
+                bail!(
+                    "the branch '{branch}' is not fully merged\n\
+                     If you are sure you want to delete it, run 'minigit branch -D {branch}'"
+                );
+            }
+        }
+
+        repo.delete_branch(branch)?;
+        let mut config = repo.config()?;
+        config.remove_section(&format!("branch.{branch}"))?;
+        config.save()?;
+
+        println!("Deleted branch {} (was {}).", branch, &hash[..7]);
+    }
+
+    Ok(())
+}
+
+fn rename(repo: &Repository, args: &[String], copy: bool, force: bool) -> Result<()> {
+    let (old, new) = match args {
+        [new] => (
+            current_branch(repo)?.context("no branch name to rename, HEAD is detached")?,
+            new.clone(),
+        ),
+        [old, new] => (old.clone(), new.clone()),
+        [] => bail!("branch name required"),
+        _ => bail!("too many arguments for a rename operation"),
+    };
+
+    if copy {
+        repo.copy_branch(&old, &new, force)?;
+    } else {
+        repo.rename_branch(&old, &new, force)?;
     }
 
-    Ok(entries)
+    Ok(())
 }
 
-// TODO: write tests
-/// List branches.
-pub fn run(all: bool) -> Result<()> {
-    let heads_path = Path::new(".git/refs/heads");
-    let mut heads_entries: Vec<String> =
-        collect_entries(heads_path, ".git/refs/heads/").context("collect branches")?;
-    heads_entries.sort();
-
-    let current_head = fs::read_to_string(".git/HEAD")
-        .context("read HEAD")?
-        .trim_start_matches("ref: refs/heads/")
-        .trim()
-        .to_string();
-
-    // Print branches, '*' indicates the current branch.
-    for entry in heads_entries {
-        if current_head == entry {
-            println!("* {}", entry.green());
-        } else {
-            println!("  {}", entry);
+fn list(repo: &Repository, args: &BranchArgs) -> Result<()> {
+    let resolve = |rev: &str| {
+        repo.rev_parse(rev)
+            .with_context(|| format!("malformed object name {rev}"))
+    };
+    let filter = RefFilter {
+        patterns: args.args.clone(),
+        match_as_path: false,
+        contains: args
+            .contains
+            .iter()
+            .map(|rev| resolve(rev))
+            .collect::<Result<_>>()?,
+        merged: args.merged.as_deref().map(resolve).transpose()?,
+        no_merged: args.no_merged.as_deref().map(resolve).transpose()?,
+        points_at: None,
+    };
+
+    let mut refs = repo.list_refs("refs/heads/")?;
+    if args.all {
+        refs.extend(repo.list_refs("refs/remotes/")?);
+    }
+    let mut items = filter.filter(refs, repo)?;
+    let sort = if args.sort.is_empty() {
+        vec![String::from("refname")]
+    } else {
+        args.sort.clone()
+    };
+    ref_filter::sort_refs(&mut items, &sort, repo)?;
+
+    if let Some(format) = &args.format {
+        let format = Format::parse(format)?;
+        for item in &items {
+            println!("{}", format.expand(item, repo, Quote::None)?);
         }
+        return Ok(());
     }
 
-    // Print remote branches if `--all` is passed.
-    let remotes_path = Path::new(".git/refs/remotes");
-    if all && remotes_path.exists() {
-        let mut remotes_entries: Vec<String> =
-            collect_entries(remotes_path, ".git/refs/").context("collect remote branches")?;
-        remotes_entries.sort();
+    let current = repo.read_symbolic_ref("HEAD")?;
+    let mut other_worktrees = vec![];
+    for worktree in repo.worktrees()? {
+        if !repo.is_current_worktree(&worktree)? {
+            other_worktrees.extend(worktree.branch);
+        }
+    }
+    let width = items
+        .iter()
+        .map(|item| display_name(item).len())
+        .max()
+        .unwrap_or(0);
 
-        for entry in remotes_entries {
-            println!("  {}", entry.red());
+    // Print branches, '*' indicates the current branch, '+' one checked out
+    // in another worktree.
+    for item in &items {
+        let name = display_name(item);
+        let padded = if args.verbose > 0 {
+            format!("{name:<width$}")
+        } else {
+            name.to_string()
+        };
+        let line = if current.as_deref() == Some(&item.refname) {
+            format!("* {}", padded.green())
+        } else if other_worktrees.contains(&item.refname) {
+            format!("+ {}", padded.cyan())
+        } else if item.refname.starts_with("refs/remotes/") {
+            format!("  {}", padded.red())
+        } else {
+            format!("  {}", padded)
+        };
+
+        if args.verbose == 0 {
+            println!("{line}");
+        } else {
+            println!("{line} {}", verbose_info(item, args.verbose, repo)?);
         }
     }
 
     Ok(())
 }
+
+/// Returns the name a branch is listed by: the short name for local
+/// branches and `remotes/<name>` for remote-tracking ones.
+fn display_name(item: &RefItem) -> &str {
+    match item.refname.strip_prefix("refs/heads/") {
+        Some(name) => name,
+        None => item.refname.strip_prefix("refs/").unwrap_or(&item.refname),
+    }
+}
+
+/// Returns the abbreviated hash, upstream tracking info and subject of a branch.
+fn verbose_info(item: &RefItem, verbose: u8, repo: &Repository) -> Result<String> {
+    let mut info = item.objectname[..7].to_string();
+
+    if let Some(branch) = item.refname.strip_prefix("refs/heads/") {
+        if let Some(upstream) = repo.upstream(branch)? {
+            let short = ref_filter::shorten_refname(&upstream);
+            let track = match repo.read_ref(&upstream)? {
+                Some(upstream_hash) => {
+                    match commit::ahead_behind(&item.objectname, &upstream_hash, repo)? {
+                        (0, 0) => String::new(),
+                        (ahead, 0) => format!("ahead {ahead}"),
+                        (0, behind) => format!("behind {behind}"),
+                        (ahead, behind) => format!("ahead {ahead}, behind {behind}"),
+                    }
+                }
+                None => String::from("gone"),
+            };
+            match (verbose, track.is_empty()) {
+                (1, true) => {}
+                (1, false) => info.push_str(&format!(" [{track}]")),
+                (_, true) => info.push_str(&format!(" [{}]", short.blue())),
+                (_, false) => info.push_str(&format!(" [{}: {track}]", short.blue())),
+            }
+        }
+    }
+
+    if let Some(commit) = &item.commit {
+        info.push(' ');
+        info.push_str(&commit.subject());
+    }
+
+    Ok(info)
+}
@@ -0,0 +1,94 @@
+use std::io::{self, BufRead, Write};
+
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{
+    commands::status::quote_path,
+    repository::{attributes::AttrState, index::Index, Repository},
+};
+
+#[derive(Args)]
+pub struct CheckAttrArgs {
+    /// Show all attributes that are set on each path
+    #[clap(short, long)]
+    all: bool,
+
+    /// Only use the .gitattributes files of the index
+    #[clap(long)]
+    cached: bool,
+
+    /// Read the paths from standard input, one per line
+    #[clap(long)]
+    stdin: bool,
+
+    /// Separate input and output entries with NUL instead of LF
+    #[clap(short = 'z')]
+    nul_terminated: bool,
+
+    /// The attributes, followed by the paths unless separated by `--`
+    args: Vec<String>,
+
+    /// The paths, after `--`
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+/// Display gitattributes information.
+pub fn run(args: CheckAttrArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let attributes = match args.cached {
+        true => repo.attributes_of(&Index::load(&repo)?.files())?,
+        false => repo.attributes()?,
+    };
+
+    // Without `--`, the first argument is an attribute and the rest are paths
+    let (names, mut paths) = match (args.all, args.stdin) {
+        (true, _) => (vec![], [args.args, args.paths].concat()),
+        (false, true) => (args.args, args.paths),
+        (false, false) if !args.paths.is_empty() => (args.args, args.paths),
+        (false, false) => match args.args.split_first() {
+            Some((name, paths)) => (vec![name.clone()], paths.to_vec()),
+            None => (vec![], vec![]),
+        },
+    };
+    if args.all && !names.is_empty() {
+        bail!("Attributes and --all both specified");
+    }
+    if !args.all && names.is_empty() {
+        bail!("No attribute specified");
+    }
+    if args.stdin {
+        if !paths.is_empty() {
+            bail!("Can't specify files with --stdin");
+        }
+        let separator = if args.nul_terminated { b'\0' } else { b'\n' };
+        for path in io::stdin().lock().split(separator) {
+            paths.push(String::from_utf8_lossy(&path?).to_string());
+        }
+    } else if paths.is_empty() {
+        bail!("No file specified");
+    }
+
+    let mut out = io::stdout().lock();
+    for path in &paths {
+        let path = path.trim_start_matches("./");
+        let states = attributes.check(path)?;
+        let names = match args.all {
+            true => attributes.names(),
+            false => names.clone(),
+        };
+        for name in names {
+            let state = states.get(&name);
+            if args.all && *state == AttrState::Unspecified {
+                continue;
+            }
+            match args.nul_terminated {
+                true => write!(out, "{path}\0{name}\0{state}\0")?,
+                false => writeln!(out, "{}: {name}: {state}", quote_path(path, false))?,
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+};
+
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{
+    commands::status::quote_path,
+    repository::{index::Index, Repository},
+};
+
+#[derive(Args)]
+pub struct CheckIgnoreArgs {
+    /// Show the pattern that matched each path, and where it comes from
+    #[clap(short, long)]
+    verbose: bool,
+
+    /// Show paths that match no pattern too, with --verbose
+    #[clap(short, long, requires = "verbose")]
+    non_matching: bool,
+
+    /// Output nothing, only set the exit status
+    #[clap(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Check tracked files too, instead of treating them as not ignored
+    #[clap(long)]
+    no_index: bool,
+
+    /// Read the paths from standard input, one per line
+    #[clap(long, conflicts_with = "paths")]
+    stdin: bool,
+
+    /// Separate input and output entries with NUL instead of LF
+    #[clap(short = 'z', requires = "stdin")]
+    nul_terminated: bool,
+
+    paths: Vec<String>,
+}
+
+/// Debug gitignore / exclude files.
+pub fn run(args: CheckIgnoreArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let mut ignore = repo.ignore()?;
+    let index = match args.no_index {
+        true => None,
+        false => Some(Index::load(&repo)?),
+    };
+
+    let paths = match args.stdin {
+        true => {
+            let separator = if args.nul_terminated { b'\0' } else { b'\n' };
+            io::stdin()
+                .lock()
+                .split(separator)
+                .map(|path| Ok(String::from_utf8_lossy(&path?).to_string()))
+                .collect::<Result<Vec<_>>>()?
+        }
+        false => args.paths,
+    };
+    if paths.is_empty() {
+        bail!("no path specified");
+    }
+
+    let mut out = io::stdout().lock();
+    let quote = |path: &str| match args.nul_terminated {
+        true => path.to_string(),
+        false => quote_path(path, false),
+    };
+    let mut matched = false;
+    for path in &paths {
+        // Tracked files are never ignored
+        let tracked = index
+            .as_ref()
+            .is_some_and(|index| index.entries.iter().any(|e| e.path == *path));
+        let is_dir = path.ends_with('/')
+            || fs::symlink_metadata(repo.get_root().join(path)).is_ok_and(|meta| meta.is_dir());
+        let pattern = match tracked {
+            true => None,
+            false => ignore.matching_pattern(path, is_dir)?,
+        };
+        // Without --verbose, re-included paths aren't reported
+        let pattern = pattern.filter(|pattern| args.verbose || !pattern.negated);
+        matched |= pattern.is_some();
+
+        if args.quiet {
+            continue;
+        }
+        match (pattern, args.nul_terminated) {
+            (Some(pattern), false) if args.verbose => writeln!(
+                out,
+                "{}:{}:{pattern}\t{}",
+                pattern.source,
+                pattern.line,
+                quote(path)
+            )?,
+            (Some(pattern), true) if args.verbose => write!(
+                out,
+                "{}\0{}\0{pattern}\0{path}\0",
+                pattern.source, pattern.line
+            )?,
+            (Some(_), _) => write!(out, "{}{}", quote(path), eol(args.nul_terminated))?,
+            (None, false) if args.non_matching => writeln!(out, "::\t{}", quote(path))?,
+            (None, true) if args.non_matching => write!(out, "\0\0\0{path}\0")?,
+            (None, _) => {}
+        }
+    }
+
+    if !matched {
+        out.flush()?;
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn eol(nul_terminated: bool) -> char {
+    if nul_terminated {
+        '\0'
+    } else {
+        '\n'
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::{bail, Context, Result};
+use clap::Args;
+
+use crate::repository::{
+    refname::{self, RefFormatOptions},
+    Repository,
+};
+
+#[derive(Args)]
+pub struct CheckRefFormatArgs {
+    /// Print the normalized refname, with duplicate slashes removed
+    #[clap(long)]
+    normalize: bool,
+
+    /// Allow refnames with a single component
+    #[clap(long, overrides_with = "no_allow_onelevel")]
+    allow_onelevel: bool,
+
+    /// Require refnames to have at least two components (default)
+    #[clap(long)]
+    no_allow_onelevel: bool,
+
+    /// Allow a single `*` as a wildcard component
+    #[clap(long)]
+    refspec_pattern: bool,
+
+    /// Expand a branch name shorthand like `@{-1}` and check it as a branch
+    #[clap(long)]
+    branch: bool,
+
+    refname: String,
+}
+
+/// Ensure that a reference name is well formed.
+pub fn run(args: CheckRefFormatArgs) -> Result<()> {
+    if args.branch {
+        let branch = expand_branch(&args.refname)?;
+        if !refname::is_valid_branch_name(&branch) {
+            bail!("'{}' is not a valid branch name", args.refname);
+        }
+        println!("{branch}");
+        return Ok(());
+    }
+
+    let opts = RefFormatOptions {
+        allow_onelevel: args.allow_onelevel,
+        refspec_pattern: args.refspec_pattern,
+        normalize: args.normalize,
+    };
+    match refname::check_ref_format(&args.refname, opts) {
+        Some(name) if args.normalize => println!("{name}"),
+        Some(_) => {}
+        None => std::process::exit(1),
+    }
+
+    Ok(())
+}
+
+/// Expands `@{-N}`, the N-th last branch checked out, using the
+/// `checkout: moving from <a> to <b>` entries of HEAD's reflog.
+fn expand_branch(name: &str) -> Result<String> {
+    let Some(n) = name
+        .strip_prefix("@{-")
+        .and_then(|rest| rest.strip_suffix('}'))
+    else {
+        return Ok(name.to_string());
+    };
+    let n: usize = n
+        .parse()
+        .ok()
+        .filter(|&n| n > 0)
+        .with_context(|| format!("'{name}' is not a valid branch name"))?;
+
+    let repo = Repository::from_path(".")?;
+    repo.read_reflog("HEAD")?
+        .iter()
+        .rev()
+        .filter_map(|entry| entry.message.strip_prefix("checkout: moving from "))
+        .filter_map(|moving| moving.split_once(" to ").map(|(from, _)| from.to_string()))
+        .nth(n - 1)
+        .with_context(|| format!("'{name}' is not a valid branch name"))
+}
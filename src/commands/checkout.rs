@@ -1,20 +1,225 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use clap::Args;
 
-use crate::repository::Repository;
+use crate::{
+    object::Commit,
+    repository::{
+        checkout::CheckoutOptions,
+        reset::RestoreOptions,
+        transaction::{RefChange, RefTransaction, RefUpdate},
+        Repository,
+    },
+};
 
-// TODO: support detached HEAD
-// TODO: branches in subdirectories
-// TODO: write tests
-/// Switch branches and optionally create a new branch.
-pub fn run(create_branch: bool, branch: &str) -> Result<()> {
+#[derive(Args)]
+pub struct CheckoutArgs {
+    /// Create and check out a new branch
+    #[clap(short = 'b', value_name = "new-branch")]
+    create_branch: Option<String>,
+
+    /// Create or reset a branch and check it out
+    #[clap(
+        short = 'B',
+        value_name = "new-branch",
+        conflicts_with = "create_branch"
+    )]
+    reset_branch: Option<String>,
+
+    /// Detach HEAD at the named commit
+    #[clap(long)]
+    detach: bool,
+
+    /// Throw away local changes
+    #[clap(short, long)]
+    force: bool,
+
+    /// Merge local changes into the branch being switched to
+    #[clap(short, long, conflicts_with = "force")]
+    merge: bool,
+
+    /// The branch or commit to check out, the start point of a new branch,
+    /// or the tree to restore paths from
+    target: Option<String>,
+
+    /// Restore these paths from the index, or from the given tree into the
+    /// index too, instead of switching branches
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+/// Switch branches, updating the working tree and the index, or restore
+/// paths.
+pub fn run(args: CheckoutArgs) -> Result<()> {
     let repo = Repository::from_path(".")?;
-    if create_branch {
-        repo.create_branch(branch)?;
+    if !args.paths.is_empty() {
+        return restore(&repo, &args);
+    }
+    if args.target.is_none() && args.create_branch.is_none() && args.reset_branch.is_none() {
+        bail!("you must specify a branch to check out");
     }
 
-    repo.switch_branch(branch)?;
+    switch(
+        &repo,
+        Switch {
+            new_branch: args
+                .create_branch
+                .as_deref()
+                .or(args.reset_branch.as_deref()),
+            reset: args.reset_branch.is_some(),
+            target: args.target.as_deref(),
+            detach: args.detach,
+            force: args.force,
+            merge: args.merge,
+        },
+    )
+}
+
+/// Restores the paths of `checkout [<tree-ish>] -- <paths>`. Unlike
+/// `restore`, files that aren't in the tree are kept.
+fn restore(repo: &Repository, args: &CheckoutArgs) -> Result<()> {
+    if let Some(branch) = args.create_branch.as_ref().or(args.reset_branch.as_ref()) {
+        bail!("Cannot update paths and switch to branch '{branch}' at the same time.");
+    }
+    if args.detach {
+        bail!("'--detach' cannot be used with updating paths");
+    }
+
+    let tree = match &args.target {
+        Some(target) => match repo.rev_parse(&format!("{target}^{{tree}}")) {
+            Ok(tree) => Some(tree),
+            Err(_) => bail!("invalid reference: {target}"),
+        },
+        None => None,
+    };
+    let options = RestoreOptions {
+        source: tree.as_deref(),
+        staged: tree.is_some(),
+        worktree: true,
+        ignore_unmatch: false,
+        overlay: true,
+    };
+    repo.restore_paths(&options, &args.paths)?;
 
-    println!("Switched to branch '{}'", branch);
+    Ok(())
+}
+
+/// What to switch to, shared by `checkout` and `switch`.
+pub struct Switch<'a> {
+    /// Create a branch with this name at `target` and switch to it
+    pub new_branch: Option<&'a str>,
+    /// Reset `new_branch` if it already exists
+    pub reset: bool,
+    /// A branch, or a commit to detach HEAD at
+    pub target: Option<&'a str>,
+    /// Detach HEAD even if `target` is a branch
+    pub detach: bool,
+    pub force: bool,
+    pub merge: bool,
+}
+
+/// Switches HEAD and the working tree to a branch or commit.
+pub fn switch(repo: &Repository, switch: Switch) -> Result<()> {
+    let head = repo.read_ref("HEAD")?;
+    let head_branch = repo.read_symbolic_ref("HEAD")?;
+    let from = match (&head_branch, &head) {
+        (Some(branch), _) => branch.trim_start_matches("refs/heads/").to_string(),
+        (None, Some(hash)) => hash.clone(),
+        (None, None) => "HEAD".to_string(),
+    };
+
+    // A branch to switch to, or `None` to detach HEAD
+    let (branch, target) = match (switch.new_branch, switch.target) {
+        (Some(branch), target) => (Some(branch), target.unwrap_or("HEAD")),
+        (None, Some(target)) if !switch.detach && repo.branch_exists(target)? => {
+            (Some(target), target)
+        }
+        (None, Some(target)) => (None, target),
+        (None, None) => (None, "HEAD"),
+    };
+    if switch.new_branch.is_some() && !switch.reset && repo.branch_exists(branch.unwrap_or(""))? {
+        bail!("a branch named '{}' already exists", branch.unwrap_or(""));
+    }
+    if let Some(branch) = branch {
+        if let Some(path) = repo.worktree_using_branch(&format!("refs/heads/{branch}"))? {
+            bail!(
+                "'{branch}' is already used by worktree at '{}'",
+                path.display()
+            );
+        }
+    }
+
+    // Switching to a new branch on an unborn HEAD only moves HEAD
+    if head.is_none() && switch.new_branch.is_some() && switch.target.is_none() {
+        let branch = branch.unwrap_or_default();
+        repo.set_symbolic_ref("HEAD", &format!("refs/heads/{branch}"), "")?;
+        println!("Switched to a new branch '{branch}'");
+        return Ok(());
+    }
+
+    let Ok(commit) = repo.rev_parse(&format!("{target}^{{commit}}")) else {
+        bail!("invalid reference: {target}");
+    };
+    let old_tree = head
+        .as_deref()
+        .map(|head| Commit::read(head, repo))
+        .transpose()?
+        .map(|commit| commit.tree);
+    let new_commit = Commit::read(&commit, repo)?;
+
+    let options = CheckoutOptions {
+        force: switch.force,
+        merge: switch.merge,
+        label: branch.unwrap_or(target),
+    };
+    let merged = repo.checkout_tree(old_tree.as_deref(), &new_commit.tree, &options)?;
+    for file in merged.iter().filter(|file| file.conflicts) {
+        println!("CONFLICT (content): Merge conflict in {}", file.path);
+    }
+
+    if let (None, Some(head)) = (&head_branch, &head) {
+        if *head != commit || branch.is_some() {
+            let subject = Commit::read(head, repo)?.subject();
+            println!("Previous HEAD position was {} {subject}", &head[..7]);
+        }
+    }
+
+    let message = format!(
+        "checkout: moving from {from} to {}",
+        branch.unwrap_or(target)
+    );
+    let Some(branch) = branch else {
+        let mut transaction = RefTransaction::new(repo);
+        transaction.update(RefUpdate {
+            refname: "HEAD".to_string(),
+            change: RefChange::Set(commit.clone()),
+            old: None,
+            no_deref: true,
+            message,
+        })?;
+        transaction.commit()?;
+        println!("HEAD is now at {} {}", &commit[..7], new_commit.subject());
+        return Ok(());
+    };
+
+    let branch_ref = format!("refs/heads/{branch}");
+    let existed = repo.branch_exists(branch)?;
+    if switch.new_branch.is_some() {
+        repo.create_branch_at(branch, &commit, switch.reset)?;
+    }
+    if head_branch.as_deref() == Some(branch_ref.as_str()) {
+        match switch.new_branch {
+            Some(_) => println!("Reset branch '{branch}'"),
+            None => println!("Already on '{branch}'"),
+        }
+        return Ok(());
+    }
+    repo.set_symbolic_ref("HEAD", &branch_ref, &message)?;
+
+    match switch.new_branch {
+        Some(_) if switch.reset && existed => println!("Switched to and reset branch '{branch}'"),
+        Some(_) => println!("Switched to a new branch '{branch}'"),
+        None => println!("Switched to branch '{branch}'"),
+    }
 
     Ok(())
 }
@@ -0,0 +1,98 @@
+use std::fs;
+
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::repository::{
+    clean::{CleanEntry, CleanIgnored, CleanOptions},
+    Repository,
+};
+
+#[derive(Args)]
+pub struct CleanArgs {
+    /// Only show what would be removed
+    #[clap(short = 'n', long)]
+    dry_run: bool,
+
+    /// Remove the files, required unless clean.requireForce is false; given
+    /// twice, remove directories holding other repositories too
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    force: u8,
+
+    /// Remove untracked directories too
+    #[clap(short = 'd')]
+    directories: bool,
+
+    /// Don't use the ignore rules, removing ignored files too
+    #[clap(short = 'x', conflicts_with = "only_ignored")]
+    ignored: bool,
+
+    /// Only remove ignored files
+    #[clap(short = 'X')]
+    only_ignored: bool,
+
+    /// Add an ignore pattern, keeping the files it matches
+    #[clap(short, long = "exclude")]
+    exclude: Vec<String>,
+
+    /// Don't list the removed files
+    #[clap(short, long)]
+    quiet: bool,
+
+    pathspecs: Vec<String>,
+}
+
+/// Remove untracked files from the working tree.
+pub fn run(args: CleanArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+
+    if args.force == 0 && !args.dry_run {
+        match repo.config()?.get_bool("clean.requireForce") {
+            Some(false) => {}
+            Some(true) => bail!(
+                "clean.requireForce set to true and neither -n nor -f given; refusing to clean"
+            ),
+            None => bail!(
+                "clean.requireForce defaults to true and neither -n nor -f given; refusing to clean"
+            ),
+        }
+    }
+
+    let options = CleanOptions {
+        directories: args.directories,
+        repositories: args.force > 1,
+        ignored: match (args.ignored, args.only_ignored) {
+            (true, _) => CleanIgnored::Also,
+            (_, true) => CleanIgnored::Only,
+            _ => CleanIgnored::Keep,
+        },
+        excludes: &args.exclude,
+        pathspecs: &args.pathspecs,
+    };
+    for entry in repo.clean_entries(&options)? {
+        let message = match entry {
+            CleanEntry::SkipRepository(path) if args.dry_run => {
+                format!("Would skip repository {path}")
+            }
+            CleanEntry::SkipRepository(path) => format!("Skipping repository {path}"),
+            CleanEntry::Remove(path) if args.dry_run => format!("Would remove {path}"),
+            CleanEntry::Remove(path) => {
+                let full_path = repo.get_root().join(&path);
+                let removed = match fs::symlink_metadata(&full_path) {
+                    Ok(meta) if meta.is_dir() => fs::remove_dir_all(full_path),
+                    _ => fs::remove_file(full_path),
+                };
+                if let Err(e) = removed {
+                    eprintln!("warning: failed to remove {path}: {e}");
+                    continue;
+                }
+                format!("Removing {path}")
+            }
+        };
+        if !args.quiet {
+            println!("{message}");
+        }
+    }
+
+    Ok(())
+}
@@ -5,7 +5,7 @@ use crate::{object, repository::Repository};
 /// Create a new commit object.
 pub fn run(tree_hash: &str, parent_hash: Option<&str>, message: &str) -> Result<()> {
     let repo = Repository::from_path(".")?;
-    let hash = object::write_commit(tree_hash, parent_hash, message, &repo)?;
+    let hash = object::write_commit(tree_hash, parent_hash.as_slice(), message, &repo)?;
 
     println!("{}", hash);
 
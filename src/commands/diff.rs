@@ -0,0 +1,151 @@
+use std::{
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+
+use crate::{
+    color,
+    diff::DiffColors,
+    pager::Pager,
+    repository::{
+        diff::{DiffOptions, FileChange},
+        error::RepoError,
+        Repository,
+    },
+};
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Show the changes staged for the next commit, relative to HEAD or
+    /// the given commit
+    #[clap(long, visible_alias = "staged")]
+    cached: bool,
+
+    /// Generate diffs with <n> lines of context
+    #[clap(short = 'U', long, value_name = "n")]
+    unified: Option<usize>,
+
+    /// Color the output: always, never or auto
+    #[clap(
+        long,
+        value_name = "when",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "always",
+        overrides_with = "no_color"
+    )]
+    color: Option<String>,
+
+    /// Don't color the output
+    #[clap(long, overrides_with = "color")]
+    no_color: bool,
+
+    /// The commits to compare: `<commit> <commit>`, `<commit>..<commit>`,
+    /// or `<commit>...<commit>` for the changes since their merge base
+    #[clap(value_name = "COMMITS")]
+    revisions: Vec<String>,
+
+    /// Show only the changes to these paths
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+/// Show changes between commits, commit and working tree, etc.
+pub fn run(mut args: DiffArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let config = repo.config()?;
+
+    // The revisions end at the first argument naming a file instead
+    let mut revisions = vec![];
+    let mut paths = vec![];
+    for arg in args.revisions.drain(..) {
+        let is_revision = paths.is_empty() && !arg.is_empty() && is_revision(&repo, &arg)?;
+        if !is_revision && !Path::new(&arg).exists() {
+            bail!("ambiguous argument '{arg}': unknown revision or path not in the working tree.");
+        }
+        match is_revision {
+            true => revisions.push(arg),
+            false => paths.push(arg),
+        }
+    }
+    paths.append(&mut args.paths);
+
+    if revisions.len() > 2 || (args.cached && revisions.len() > 1) {
+        bail!("usage: minigit diff [<options>] [<commit> [<commit>]] [--] [<path>...]");
+    }
+    let sides = repo.diff_sides(args.cached, &revisions)?;
+    let (changes, unmerged) = repo.diff_changes(&sides, &paths)?;
+
+    let color = match (args.no_color, &args.color) {
+        (true, _) => false,
+        (false, Some(when)) => color::want_color(Some(when)),
+        (false, None) => color::want_color(config.get("color.diff").or(config.get("color.ui"))),
+    };
+    let context = match (args.unified, config.get("diff.context")) {
+        (Some(context), _) => context,
+        (None, Some(value)) => value
+            .parse()
+            .map_err(|_| anyhow!("bad config variable 'diff.context': {value}"))?,
+        (None, None) => 3,
+    };
+    let options = DiffOptions {
+        context,
+        colors: match color {
+            true => DiffColors::from_config(&config),
+            false => DiffColors::default(),
+        },
+        worktree: sides.worktree(),
+    };
+
+    let mut pager = Pager::start(&repo, "diff");
+    let result = show_diff(&mut pager, &repo, &changes, &unmerged, &options);
+    pager.finish()?;
+    match result {
+        // The pager was quit before all output was written
+        Err(RepoError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Writes the unmerged paths and the patch of the changes.
+fn show_diff(
+    out: &mut impl Write,
+    repo: &Repository,
+    changes: &[FileChange],
+    unmerged: &[String],
+    options: &DiffOptions,
+) -> Result<(), RepoError> {
+    let mut out = BufWriter::new(out);
+    let mut changes = changes.iter().peekable();
+    for path in unmerged {
+        // In path order with the patches of the other files
+        let before: Vec<FileChange> = std::iter::from_fn(|| changes.next_if(|c| c.path < *path))
+            .cloned()
+            .collect();
+        repo.write_patch(&mut out, &before, options)?;
+        writeln!(out, "* Unmerged path {path}")?;
+    }
+    let rest: Vec<FileChange> = changes.cloned().collect();
+    repo.write_patch(&mut out, &rest, options)?;
+
+    Ok(out.flush()?)
+}
+
+/// Returns true if `arg` names a revision or a range of them.
+fn is_revision(repo: &Repository, arg: &str) -> Result<bool> {
+    let revs = match arg.split_once("..") {
+        Some((old, new)) => vec![old, new.strip_prefix('.').unwrap_or(new)],
+        None => vec![arg],
+    };
+    for rev in revs.into_iter().filter(|rev| !rev.is_empty()) {
+        match repo.rev_parse(rev) {
+            Ok(_) => {}
+            Err(RepoError::UnknownRevision(_)) => return Ok(false),
+            Err(e) => Err(e)?,
+        }
+    }
+    Ok(true)
+}
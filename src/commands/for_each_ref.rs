@@ -0,0 +1,106 @@
+use anyhow::{Context, Result};
+use clap::Args;
+
+use crate::{
+    ref_filter::{self, Format, Quote, RefFilter},
+    repository::Repository,
+};
+
+#[derive(Args)]
+pub struct ForEachRefArgs {
+    /// Format to use for the output
+    #[clap(long, default_value = "%(objectname) %(objecttype)\t%(refname)")]
+    format: String,
+
+    /// Field name to sort on, prefix with '-' for descending order
+    #[clap(long, value_name = "key")]
+    sort: Vec<String>,
+
+    /// Show only <count> matched refs
+    #[clap(long)]
+    count: Option<usize>,
+
+    /// Print only refs which point at the given object
+    #[clap(long, value_name = "object")]
+    points_at: Option<String>,
+
+    /// Print only refs that are merged into the commit
+    #[clap(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    merged: Option<String>,
+
+    /// Print only refs that are not merged into the commit
+    #[clap(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    no_merged: Option<String>,
+
+    /// Print only refs which contain the commit
+    #[clap(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+    contains: Vec<String>,
+
+    /// Quote placeholders suitably for shells
+    #[clap(short, long, group = "quote")]
+    shell: bool,
+
+    /// Quote placeholders suitably for perl
+    #[clap(short, long, group = "quote")]
+    perl: bool,
+
+    /// Quote placeholders suitably for python
+    #[clap(long, group = "quote")]
+    python: bool,
+
+    /// Quote placeholders suitably for Tcl
+    #[clap(long, group = "quote")]
+    tcl: bool,
+
+    /// Only refs matching these patterns (a prefix like `refs/heads` or a glob)
+    patterns: Vec<String>,
+}
+
+/// Output information on each ref.
+pub fn run(args: ForEachRefArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let resolve = |rev: &str| {
+        repo.rev_parse(rev)
+            .with_context(|| format!("malformed object name {rev}"))
+    };
+
+    let filter = RefFilter {
+        patterns: args.patterns,
+        match_as_path: true,
+        contains: args
+            .contains
+            .iter()
+            .map(|rev| resolve(rev))
+            .collect::<Result<_>>()?,
+        merged: args.merged.as_deref().map(resolve).transpose()?,
+        no_merged: args.no_merged.as_deref().map(resolve).transpose()?,
+        points_at: args.points_at.as_deref().map(resolve).transpose()?,
+    };
+    let quote = if args.shell {
+        Quote::Shell
+    } else if args.perl {
+        Quote::Perl
+    } else if args.python {
+        Quote::Python
+    } else if args.tcl {
+        Quote::Tcl
+    } else {
+        Quote::None
+    };
+    let format = Format::parse(&args.format)?;
+
+    let mut items = filter.filter(repo.list_refs("refs/")?, &repo)?;
+    let sort = if args.sort.is_empty() {
+        vec![String::from("refname")]
+    } else {
+        args.sort
+    };
+    ref_filter::sort_refs(&mut items, &sort, &repo)?;
+
+    let count = args.count.unwrap_or(items.len());
+    for item in items.iter().take(count) {
+        println!("{}", format.expand(item, &repo, quote)?);
+    }
+
+    Ok(())
+}
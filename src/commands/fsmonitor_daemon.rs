@@ -0,0 +1,75 @@
+use std::{
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand};
+
+use crate::repository::{worktree::absolute, Repository};
+
+#[derive(Args)]
+pub struct FsmonitorDaemonArgs {
+    #[clap(subcommand)]
+    command: FsmonitorDaemonCommand,
+}
+
+#[derive(Subcommand)]
+enum FsmonitorDaemonCommand {
+    /// Start the daemon in the background
+    Start,
+    /// Stop the daemon
+    Stop,
+    /// Show whether the daemon is watching the working tree
+    Status,
+    /// Run the daemon in the foreground
+    Run,
+}
+
+/// Watch the working tree for changes to speed up status.
+pub fn run(args: FsmonitorDaemonArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let root = absolute(repo.get_root())?;
+
+    match args.command {
+        FsmonitorDaemonCommand::Start => {
+            if repo.fsmonitor_running() {
+                bail!("fsmonitor--daemon is already running '{}'", root.display());
+            }
+            // In its own process group, so it isn't interrupted along with
+            // the terminal's foreground processes
+            let mut daemon = Command::new(std::env::current_exe()?)
+                .args(["fsmonitor--daemon", "run"])
+                .current_dir(&root)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .process_group(0)
+                .spawn()
+                .context("could not spawn fsmonitor--daemon in the background")?;
+            while !repo.fsmonitor_running() {
+                if daemon.try_wait()?.is_some() {
+                    bail!("fsmonitor--daemon failed to start");
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        FsmonitorDaemonCommand::Stop => {
+            if !repo.stop_fsmonitor()? {
+                bail!("fsmonitor--daemon is not running");
+            }
+        }
+        FsmonitorDaemonCommand::Status => {
+            if !repo.fsmonitor_running() {
+                println!("fsmonitor-daemon is not watching '{}'", root.display());
+                std::process::exit(1);
+            }
+            println!("fsmonitor-daemon is watching '{}'", root.display());
+        }
+        FsmonitorDaemonCommand::Run => repo.run_fsmonitor()?,
+    }
+
+    Ok(())
+}
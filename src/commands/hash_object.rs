@@ -1,13 +1,28 @@
-use std::path::Path;
+use std::{fs, path::Path};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 use crate::{object::Object, repository::Repository};
 
 /// Compute the hash of a file and optionally write it to the objects directory.
-pub fn run<P: AsRef<Path>>(file: P, write: bool) -> Result<()> {
+/// Unless `no_filters` is set, the content is converted as it would be when
+/// committing the file at `path`, by default the file's own path.
+pub fn run<P: AsRef<Path>>(
+    file: P,
+    write: bool,
+    no_filters: bool,
+    path: Option<&str>,
+) -> Result<()> {
     let repo = Repository::from_path(".")?;
-    let object = Object::blob_from_file(file)?;
+    let file = file.as_ref();
+    let mut content = fs::read(file)
+        .with_context(|| format!("could not open '{}' for reading", file.display()))?;
+    if !no_filters {
+        let file_path = file.to_string_lossy();
+        let path = path.unwrap_or(file_path.trim_start_matches("./"));
+        content = repo.convert()?.to_git(path, content, write)?;
+    }
+    let object = Object::blob_from_bytes(&content);
 
     let hash = if write {
         object.write_to_objects(&repo)?
@@ -1,18 +1,20 @@
 use std::{env, path::PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
-use crate::repository::Repository;
+use crate::repository::{refs::RefFormat, Repository};
 
 /// Initialize a new Git repository.
-pub fn run(directory: Option<PathBuf>) -> Result<()> {
+pub fn run(directory: Option<PathBuf>, ref_format: &str) -> Result<()> {
+    let format = RefFormat::parse(ref_format)
+        .with_context(|| format!("unknown ref storage format '{ref_format}'"))?;
     let directory = if let Some(directory) = directory {
         env::current_dir()?.join(directory)
     } else {
         env::current_dir()?
     };
 
-    Repository::init(&directory)?;
+    Repository::init_with_format(&directory, format)?;
 
     println!(
         "Initialized empty Git repository in {}/.git",
@@ -0,0 +1,299 @@
+use std::io::{self, BufWriter, IsTerminal, Write};
+
+use anyhow::{anyhow, bail, Result};
+use clap::{Arg, ArgAction, ArgMatches, Args, Command, FromArgMatches};
+
+use crate::{
+    color,
+    commands::rev_list::RevisionArgs,
+    date::DateMode,
+    graph::Graph,
+    pager::Pager,
+    pretty::{Decorations, Format, Pretty},
+    repository::Repository,
+    revision::RevCommit,
+};
+
+#[derive(Args)]
+pub struct LogArgs {
+    #[clap(flatten)]
+    revision: RevisionArgs,
+
+    /// Draw a text-based graph of the history next to the commits
+    #[clap(long, conflicts_with = "reverse")]
+    graph: bool,
+
+    #[clap(flatten)]
+    pretty: PrettyArgs,
+
+    /// Show abbreviated commit hashes
+    #[clap(long, overrides_with = "no_abbrev_commit")]
+    abbrev_commit: bool,
+
+    /// Show full commit hashes
+    #[clap(long)]
+    no_abbrev_commit: bool,
+
+    /// Date format: relative, local, iso, iso-strict, rfc, short, raw, unix
+    /// or format:<strftime string>
+    #[clap(long, value_name = "format")]
+    date: Option<String>,
+
+    /// Show the ref names of commits: short, full, auto or no
+    #[clap(
+        long,
+        value_name = "style",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "short",
+        overrides_with = "no_decorate"
+    )]
+    decorate: Option<String>,
+
+    /// Don't show ref names
+    #[clap(long, overrides_with = "decorate")]
+    no_decorate: bool,
+
+    /// Color the output: always, never or auto
+    #[clap(
+        long,
+        value_name = "when",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "always",
+        overrides_with = "no_color"
+    )]
+    color: Option<String>,
+
+    /// Don't color the output
+    #[clap(long, overrides_with = "color")]
+    no_color: bool,
+}
+
+/// The options choosing the format of the commits, of which the last one
+/// wins.
+struct PrettyArgs {
+    /// The format given last with `--pretty`, `--format` or `--oneline`
+    format: Option<String>,
+    /// Whether `--oneline` was given, which abbreviates the hashes even if
+    /// another format follows it
+    oneline: bool,
+}
+
+impl Args for PrettyArgs {
+    fn augment_args(cmd: Command) -> Command {
+        cmd.arg(
+            Arg::new("pretty")
+                .long("pretty")
+                .value_name("format")
+                .help(
+                    "Pretty-print the commits: oneline, short, medium, full, fuller, \
+                     format:<string> or tformat:<string>",
+                )
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("medium")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("format")
+                .help("Like --pretty, a string with placeholders meaning tformat:<string>")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("oneline")
+                .long("oneline")
+                .help("Shorthand for --pretty=oneline --abbrev-commit")
+                .num_args(0)
+                .default_missing_value("oneline")
+                .action(ArgAction::Append),
+        )
+    }
+
+    fn augment_args_for_update(cmd: Command) -> Command {
+        Self::augment_args(cmd)
+    }
+}
+
+impl FromArgMatches for PrettyArgs {
+    fn from_arg_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let mut formats = vec![];
+        for id in ["pretty", "format", "oneline"] {
+            if let (Some(values), Some(indices)) =
+                (matches.get_many::<String>(id), matches.indices_of(id))
+            {
+                formats.extend(indices.zip(values.cloned()));
+            }
+        }
+        formats.sort();
+        Ok(PrettyArgs {
+            format: formats.pop().map(|(_, format)| format),
+            oneline: matches.contains_id("oneline"),
+        })
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+/// Show commit logs.
+pub fn run(args: LogArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let config = repo.config()?;
+
+    let mut walk = args.revision.rev_walk(&repo, Some("HEAD"))?;
+    walk.topo_order |= args.graph;
+    walk.rewrite_parents = args.graph;
+
+    let format = match args.pretty.format.as_deref() {
+        Some(format) => {
+            Format::parse(format).ok_or_else(|| anyhow!("invalid --pretty format: {format}"))?
+        }
+        None => Format::Medium,
+    };
+    let date_mode = match args.date.as_deref().or(config.get("log.date")) {
+        Some(mode) => DateMode::parse(mode).ok_or_else(|| anyhow!("unknown date format {mode}"))?,
+        None => DateMode::Default,
+    };
+    let color = match (args.no_color, &args.color) {
+        (true, _) => false,
+        (false, Some(when)) => color::want_color(Some(when)),
+        (false, None) => color::want_color(config.get("color.diff").or(config.get("color.ui"))),
+    };
+    let decorate = match args.no_decorate {
+        true => "no",
+        false => args
+            .decorate
+            .as_deref()
+            .or(config.get("log.decorate"))
+            .unwrap_or("auto"),
+    };
+    let (decorate, full) = match decorate {
+        "short" | "true" | "yes" | "on" | "1" => (true, false),
+        "full" => (true, true),
+        "no" | "false" | "off" | "0" => (false, false),
+        "auto" => (io::stdout().is_terminal(), false),
+        style => bail!("invalid --decorate option: {style}"),
+    };
+    let pretty = Pretty {
+        abbrev_commit: (args.pretty.oneline || args.abbrev_commit) && !args.no_abbrev_commit,
+        format,
+        date_mode,
+        color,
+        decorate,
+        decorations: Decorations::load(&repo, full)?,
+    };
+
+    let commits = walk.walk(&repo)?;
+    let graph = args.graph.then(|| {
+        let interesting = commits.iter().map(|rev| rev.hash.clone()).collect();
+        Graph::new(interesting, walk.first_parent, color)
+    });
+    let commits = walk.limit(commits);
+
+    let mut pager = Pager::start(&repo, "log");
+    let result = show_log(&mut pager, &pretty, graph, &commits);
+    pager.finish()?;
+    match result {
+        // The pager was quit before all output was written
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Writes each commit, next to the graph if there is one.
+fn show_log(
+    out: &mut impl Write,
+    pretty: &Pretty,
+    mut graph: Option<Graph>,
+    commits: &[RevCommit],
+) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    let use_terminator = pretty.format.use_terminator();
+    let mut shown_one = false;
+    let mut missing_newline = false;
+
+    for rev in commits {
+        let mut buf = String::new();
+        if let Some(graph) = &mut graph {
+            graph.update(&rev.hash, &rev.commit.parents);
+        }
+
+        // Separate the commits, continuing the graph on the blank line
+        if shown_one && !use_terminator {
+            if let Some(graph) = graph.as_mut().filter(|_| !missing_newline) {
+                graph.show_padding(&mut buf);
+            }
+            buf.push('\n');
+        }
+        if let Some(graph) = &mut graph {
+            graph.show_commit(&mut buf);
+        }
+
+        if !matches!(pretty.format, Format::User { .. }) {
+            buf.push_str(&pretty.commit_line(rev));
+            if pretty.format == Format::Oneline {
+                buf.push(' ');
+            } else {
+                buf.push('\n');
+                if let Some(graph) = &mut graph {
+                    graph.show_oneline(&mut buf);
+                }
+            }
+        }
+
+        let message = pretty.format(rev);
+        missing_newline = !message.ends_with('\n');
+        match &mut graph {
+            Some(graph) => graph.show_commit_msg(&mut buf, &message),
+            None => buf.push_str(&message),
+        }
+        let empty_format =
+            matches!(&pretty.format, Format::User { format, .. } if format.is_empty());
+        if use_terminator && !empty_format {
+            if let Some(graph) = graph.as_mut().filter(|_| !missing_newline) {
+                graph.show_padding(&mut buf);
+            }
+            buf.push('\n');
+        }
+
+        out.write_all(buf.as_bytes())?;
+        shown_one = true;
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pretty_args() {
+        let parse = |args: &[&str]| {
+            let cmd = PrettyArgs::augment_args(Command::new("log"));
+            let matches = cmd.try_get_matches_from([&["log"], args].concat()).unwrap();
+            let pretty = PrettyArgs::from_arg_matches(&matches).unwrap();
+            (pretty.format, pretty.oneline)
+        };
+        let some = |format: &str| Some(format.to_string());
+
+        assert_eq!(parse(&[]), (None, false));
+        assert_eq!(parse(&["--pretty"]), (some("medium"), false));
+        assert_eq!(parse(&["--oneline"]), (some("oneline"), true));
+        // The last one wins, but --oneline still abbreviates the hashes
+        assert_eq!(parse(&["--oneline", "--pretty=full"]), (some("full"), true));
+        assert_eq!(
+            parse(&["--format=%h", "--oneline"]),
+            (some("oneline"), true)
+        );
+        assert_eq!(
+            parse(&["--pretty=oneline", "--format=%s", "--format=%h"]),
+            (some("%h"), false)
+        );
+    }
+}
@@ -1,11 +1,35 @@
+pub mod add;
 pub mod branch;
 pub mod cat_file;
+pub mod check_attr;
+pub mod check_ignore;
+pub mod check_ref_format;
 pub mod checkout;
+pub mod clean;
 pub mod commit;
 pub mod commit_tree;
+pub mod diff;
+pub mod for_each_ref;
+pub mod fsmonitor_daemon;
 pub mod hash_object;
 pub mod init;
+pub mod log;
 pub mod ls_tree;
+pub mod mv;
+pub mod reset;
+pub mod restore;
+pub mod rev_list;
+pub mod rm;
+pub mod show_ref;
+pub mod sparse_checkout;
+pub mod stash;
+pub mod status;
+pub mod submodule;
+pub mod switch;
+pub mod symbolic_ref;
+pub mod update_index;
+pub mod update_ref;
+pub mod worktree;
 pub mod write_tree;
 
 use std::path::PathBuf;
@@ -16,7 +40,13 @@ use clap::Subcommand;
 #[derive(Subcommand)]
 pub enum Commands {
     /// Create an empty Git repository or reinitialize an existing one
-    Init { directory: Option<PathBuf> },
+    Init {
+        directory: Option<PathBuf>,
+
+        /// Ref storage format: files or reftable
+        #[clap(long, value_name = "format", default_value = "files")]
+        ref_format: String,
+    },
     /// Provide contents or details of repository objects
     CatFile { object: String },
     /// Compute object ID and optionally create an object from a file
@@ -26,6 +56,14 @@ pub enum Commands {
         /// Write the object into the object database
         #[clap(short)]
         write: bool,
+
+        /// Hash the file as is, without converting its line endings
+        #[clap(long, conflicts_with = "path")]
+        no_filters: bool,
+
+        /// Convert the file as if it were at this path
+        #[clap(long)]
+        path: Option<String>,
     },
     /// List the contents of a tree object
     LsTree {
@@ -50,32 +88,80 @@ pub enum Commands {
         #[clap(short)]
         message: String,
     },
-    /// List branches
-    Branch {
-        /// list both remote-tracking and local branche
-        #[clap(short)]
-        all: bool,
-    },
+    /// List, create, or delete branches
+    Branch(branch::BranchArgs),
+    /// Output information on each ref
+    ForEachRef(for_each_ref::ForEachRefArgs),
+    /// Update the object name stored in a ref safely
+    UpdateRef(update_ref::UpdateRefArgs),
+    /// Read, modify and delete symbolic refs
+    SymbolicRef(symbolic_ref::SymbolicRefArgs),
+    /// List references in a local repository
+    ShowRef(show_ref::ShowRefArgs),
+    /// Ensure that a reference name is well formed
+    CheckRefFormat(check_ref_format::CheckRefFormatArgs),
+    /// Switch branches or restore working tree files
+    Checkout(checkout::CheckoutArgs),
     /// Switch branches
-    Checkout {
-        /// create and checkout a new branch
-        #[clap(short = 'b')]
-        create_branch: bool,
-        branch: String,
-    },
+    Switch(switch::SwitchArgs),
+    /// Show the working tree status
+    Status(status::StatusArgs),
+    /// Debug gitignore / exclude files
+    CheckIgnore(check_ignore::CheckIgnoreArgs),
+    /// Remove files from the working tree and from the index
+    Rm(rm::RmArgs),
+    /// Move or rename a file, a directory, or a symlink
+    Mv(mv::MvArgs),
+    /// Reset current HEAD to the specified state
+    Reset(reset::ResetArgs),
+    /// Restore working tree files
+    Restore(restore::RestoreArgs),
+    /// Stash the changes in a dirty working directory away
+    Stash(stash::StashArgs),
+    /// Remove untracked files from the working tree
+    Clean(clean::CleanArgs),
+    /// Display gitattributes information
+    CheckAttr(check_attr::CheckAttrArgs),
+    /// Reduce your working tree to a subset of tracked files
+    SparseCheckout(sparse_checkout::SparseCheckoutArgs),
+    /// Manage multiple working trees
+    Worktree(worktree::WorktreeArgs),
+    /// Initialize, update or inspect submodules
+    Submodule(submodule::SubmoduleArgs),
+    /// Add file contents to the index
+    Add(add::AddArgs),
+    /// Watch the working tree for changes to speed up status
+    #[clap(name = "fsmonitor--daemon")]
+    FsmonitorDaemon(fsmonitor_daemon::FsmonitorDaemonArgs),
+    /// Register file contents in the working tree to the index
+    UpdateIndex(update_index::UpdateIndexArgs),
+    /// Show commit logs
+    Log(log::LogArgs),
+    /// List commits in reverse chronological order
+    RevList(rev_list::RevListArgs),
+    /// Show changes between commits, commit and working tree, etc
+    Diff(diff::DiffArgs),
 }
 
 pub fn run(command: Commands) -> Result<()> {
     match command {
-        Commands::Init { directory } => {
-            init::run(directory)?;
+        Commands::Init {
+            directory,
+            ref_format,
+        } => {
+            init::run(directory, &ref_format)?;
         }
         Commands::CatFile { object } => {
             // TODO: pretty print (-p)
             cat_file::run(&object)?;
         }
-        Commands::HashObject { file, write } => {
-            hash_object::run(&file, write)?;
+        Commands::HashObject {
+            file,
+            write,
+            no_filters,
+            path,
+        } => {
+            hash_object::run(&file, write, no_filters, path.as_deref())?;
         }
         Commands::LsTree { treeish, name_only } => {
             ls_tree::run(&treeish, name_only)?;
@@ -93,14 +179,83 @@ pub fn run(command: Commands) -> Result<()> {
         Commands::Commit { message } => {
             commit::run(&message)?;
         }
-        Commands::Branch { all } => {
-            branch::run(all)?;
+        Commands::Branch(args) => {
+            branch::run(args)?;
         }
-        Commands::Checkout {
-            create_branch,
-            branch,
-        } => {
-            checkout::run(create_branch, &branch)?;
+        Commands::ForEachRef(args) => {
+            for_each_ref::run(args)?;
+        }
+        Commands::UpdateRef(args) => {
+            update_ref::run(args)?;
+        }
+        Commands::SymbolicRef(args) => {
+            symbolic_ref::run(args)?;
+        }
+        Commands::ShowRef(args) => {
+            show_ref::run(args)?;
+        }
+        Commands::CheckRefFormat(args) => {
+            check_ref_format::run(args)?;
+        }
+        Commands::Checkout(args) => {
+            checkout::run(args)?;
+        }
+        Commands::Switch(args) => {
+            switch::run(args)?;
+        }
+        Commands::Status(args) => {
+            status::run(args)?;
+        }
+        Commands::CheckIgnore(args) => {
+            check_ignore::run(args)?;
+        }
+        Commands::Rm(args) => {
+            rm::run(args)?;
+        }
+        Commands::Mv(args) => {
+            mv::run(args)?;
+        }
+        Commands::Reset(args) => {
+            reset::run(args)?;
+        }
+        Commands::Restore(args) => {
+            restore::run(args)?;
+        }
+        Commands::Stash(args) => {
+            stash::run(args)?;
+        }
+        Commands::Clean(args) => {
+            clean::run(args)?;
+        }
+        Commands::CheckAttr(args) => {
+            check_attr::run(args)?;
+        }
+        Commands::SparseCheckout(args) => {
+            sparse_checkout::run(args)?;
+        }
+        Commands::Worktree(args) => {
+            worktree::run(args)?;
+        }
+        Commands::Submodule(args) => {
+            submodule::run(args)?;
+        }
+        Commands::Add(args) => {
+            add::run(args)?;
+        }
+        Commands::FsmonitorDaemon(args) => {
+            fsmonitor_daemon::run(args)?;
+        }
+        Commands::UpdateIndex(args) => {
+            update_index::run(args)?;
+        }
+        Commands::Log(args) => {
+            log::run(args)?;
+        }
+        Commands::RevList(args) => {
+            rev_list::run(args)?;
+        }
+        Commands::Diff(args) => {
+            diff::run(args)?;
         }
     }
 
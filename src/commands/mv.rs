@@ -0,0 +1,169 @@
+use std::{collections::BTreeSet, fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+
+use crate::repository::{
+    index::{is_missing, Index},
+    Repository,
+};
+
+#[derive(Args)]
+pub struct MvArgs {
+    /// Overwrite existing destination files
+    #[clap(short, long)]
+    force: bool,
+
+    /// Skip moves that would fail instead of aborting
+    #[clap(short = 'k')]
+    skip_errors: bool,
+
+    /// Only show what would be moved
+    #[clap(short = 'n', long)]
+    dry_run: bool,
+
+    /// Report the names of files as they are moved
+    #[clap(short, long)]
+    verbose: bool,
+
+    /// The files or directories to move, followed by the destination
+    #[clap(required = true, num_args = 2..)]
+    paths: Vec<String>,
+}
+
+/// Move or rename a file, a directory, or a symlink.
+pub fn run(mut args: MvArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let root = repo.get_root();
+    let mut index = Index::lock(&repo)?;
+
+    let dest = args.paths.pop().unwrap_or_default();
+    // Sources are moved into an existing directory
+    let into_dir = is_dir(&root.join(&dest));
+    if args.paths.len() > 1 && !into_dir {
+        bail!("destination '{dest}' is not a directory");
+    }
+
+    // The moves of files and directories, followed by the files inside
+    // moved directories, which only move in the index
+    let mut moves = vec![];
+    let mut children = vec![];
+    let mut targets = BTreeSet::new();
+    for source in &args.paths {
+        let source = source.trim_end_matches('/');
+        let target = match (into_dir, dest.trim_end_matches('/')) {
+            (false, _) => dest.clone(),
+            (true, ".") => source.rsplit('/').next().unwrap_or(source).to_string(),
+            (true, dir) => format!("{dir}/{}", source.rsplit('/').next().unwrap_or(source)),
+        };
+        if args.dry_run {
+            println!("Checking rename of '{source}' to '{target}'");
+        }
+
+        let error = check_move(&index, root, source, &target, args.force, &targets)?;
+        match error {
+            Some(_) if args.skip_errors => continue,
+            Some(error) => bail!("{error}, source={source}, destination={target}"),
+            None => {}
+        }
+        targets.insert(target.clone());
+        moves.push((source.to_string(), target.clone(), true));
+
+        let prefix = format!("{source}/");
+        for entry in index.entries.iter().filter(|e| e.path.starts_with(&prefix)) {
+            let path = format!("{target}/{}", &entry.path[prefix.len()..]);
+            children.push((entry.path.clone(), path, false));
+        }
+    }
+    if args.dry_run {
+        for (source, target, _) in &children {
+            println!("Checking rename of '{source}' to '{target}'");
+        }
+    }
+    moves.extend(children);
+
+    for (source, target, in_worktree) in moves {
+        if args.dry_run || args.verbose {
+            println!("Renaming {source} to {target}");
+        }
+        if args.dry_run {
+            continue;
+        }
+
+        if in_worktree {
+            fs::rename(root.join(&source), root.join(&target))
+                .with_context(|| format!("renaming '{source}' failed"))?;
+        }
+        if let Some(mut entry) = index.get(&source).cloned() {
+            // A file overwritten with --force is replaced
+            index.remove(&target);
+            index.remove(&source);
+            entry.path = target;
+            index.add(entry);
+        }
+    }
+    if !args.dry_run {
+        index.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Returns why `source` can't be moved to `target`, if it can't.
+fn check_move(
+    index: &Index,
+    root: &Path,
+    source: &str,
+    target: &str,
+    force: bool,
+    targets: &BTreeSet<String>,
+) -> Result<Option<&'static str>> {
+    let meta = match fs::symlink_metadata(root.join(source)) {
+        Ok(meta) => meta,
+        Err(e) if is_missing(&e) => return Ok(Some("bad source")),
+        Err(e) => Err(e)?,
+    };
+    let target_meta = match fs::symlink_metadata(root.join(target)) {
+        Ok(meta) => Some(meta),
+        Err(e) if is_missing(&e) => None,
+        Err(e) => Err(e)?,
+    };
+
+    let prefix = format!("{source}/");
+    let error = if target == source || target.starts_with(&prefix) {
+        Some("can not move directory into itself")
+    } else if meta.is_dir() && index.get(source).is_none() {
+        if target_meta.is_some() {
+            Some("cannot move directory over file")
+        } else if !index.entries.iter().any(|e| e.path.starts_with(&prefix)) {
+            Some("source directory is empty")
+        } else if target.ends_with('/') {
+            Some("destination directory does not exist")
+        } else {
+            None
+        }
+    } else if index.get_all(source).next().is_none() {
+        Some("not under version control")
+    } else if index.get(source).is_none() {
+        Some("conflicted")
+    } else if let Some(target_meta) = target_meta {
+        match force {
+            // Only files can overwrite each other
+            true if !target_meta.is_dir() => None,
+            true => Some("Cannot overwrite"),
+            false => Some("destination exists"),
+        }
+    } else if targets.contains(target) {
+        Some("multiple sources for the same target")
+    } else if target.ends_with('/') {
+        Some("destination directory does not exist")
+    } else {
+        None
+    };
+
+    Ok(error)
+}
+
+fn is_dir(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|meta| meta.is_dir())
+}
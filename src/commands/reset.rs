@@ -0,0 +1,257 @@
+use std::io::{self, Write};
+
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{
+    commands::add,
+    object::{tree::EMPTY_TREE, Commit},
+    repository::{
+        error::RepoError,
+        index::Index,
+        patch::PatchMode,
+        reset::{ResetMode, RestoreOptions},
+        status::{Change, UntrackedFiles},
+        Repository,
+    },
+};
+
+#[derive(Args)]
+pub struct ResetArgs {
+    /// Only move the current branch
+    #[clap(long, group = "mode")]
+    soft: bool,
+
+    /// Reset the index too (default)
+    #[clap(long, group = "mode")]
+    mixed: bool,
+
+    /// Reset the index and the working tree, discarding local changes
+    #[clap(long, group = "mode")]
+    hard: bool,
+
+    /// Reset the index and the files that differ between the commits,
+    /// keeping local changes to other files
+    #[clap(long, group = "mode")]
+    keep: bool,
+
+    /// Reset the index and the files that differ from the commit, keeping
+    /// unstaged changes
+    #[clap(long, group = "mode")]
+    merge: bool,
+
+    /// Choose the hunks to reset in the index interactively
+    #[clap(short, long)]
+    patch: bool,
+
+    /// Be quiet, only report errors
+    #[clap(short, long)]
+    quiet: bool,
+
+    /// The commit to reset to (default HEAD), optionally followed by paths
+    #[clap(value_name = "commit")]
+    args: Vec<String>,
+
+    /// Paths to reset in the index, leaving the branch alone
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+/// Reset current HEAD to the specified state.
+pub fn run(mut args: ResetArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let mode = match (args.soft, args.hard, args.keep, args.merge) {
+        (true, ..) => ResetMode::Soft,
+        (_, true, ..) => ResetMode::Hard,
+        (_, _, true, _) => ResetMode::Keep,
+        (.., true) => ResetMode::Merge,
+        _ => ResetMode::Mixed,
+    };
+
+    // Without `--`, the first argument is a commit if it names one
+    let commit = if !args.paths.is_empty() {
+        if args.args.len() > 1 {
+            bail!("only one commit can be given before '--'");
+        }
+        args.args.pop()
+    } else if args
+        .args
+        .first()
+        .is_some_and(|arg| repo.rev_parse(&format!("{arg}^{{commit}}")).is_ok())
+    {
+        let commit = args.args.remove(0);
+        args.paths = args.args;
+        Some(commit)
+    } else {
+        args.paths = args.args;
+        None
+    };
+    let commit = commit.unwrap_or_else(|| "HEAD".to_string());
+
+    let head = repo.read_ref("HEAD")?;
+    // Resetting an unborn HEAD empties the index
+    let target = match repo.rev_parse(&format!("{commit}^{{commit}}")) {
+        Ok(hash) => Some(hash),
+        Err(_) if head.is_none() && commit == "HEAD" => None,
+        Err(_) => bail!("Failed to resolve '{commit}' as a valid revision."),
+    };
+    let tree = match &target {
+        Some(hash) => Commit::read(hash, &repo)?.tree,
+        None => EMPTY_TREE.to_string(),
+    };
+
+    if args.patch {
+        if mode != ResetMode::Mixed || args.mixed {
+            bail!("options '--patch' and '--{{hard,mixed,soft}}' cannot be used together");
+        }
+        let mode = PatchMode::Reset {
+            tree: &tree,
+            head: commit == "HEAD",
+        };
+        return add::patch(&repo, mode, &args.paths);
+    }
+
+    if !args.paths.is_empty() {
+        let name = match mode {
+            ResetMode::Soft => "soft",
+            ResetMode::Hard => "hard",
+            ResetMode::Keep => "keep",
+            ResetMode::Merge => "merge",
+            ResetMode::Mixed => "",
+        };
+        if mode != ResetMode::Mixed {
+            bail!("Cannot do {name} reset with paths.");
+        }
+        if args.mixed {
+            eprintln!("warning: --mixed with paths is deprecated; use 'minigit reset -- <paths>' instead.");
+        }
+        let options = RestoreOptions {
+            source: Some(&tree),
+            staged: true,
+            ignore_unmatch: true,
+            ..Default::default()
+        };
+        repo.restore_paths(&options, &args.paths)?;
+        if !args.quiet {
+            write_unstaged(&repo, &mut io::stdout())?;
+        }
+        return Ok(());
+    }
+
+    if mode == ResetMode::Soft && Index::load(&repo)?.has_conflicts() {
+        bail!("Cannot do a soft reset in the middle of a merge.");
+    }
+    let head_tree = match &head {
+        Some(head) => Some(Commit::read(head, &repo)?.tree),
+        None => None,
+    };
+    match repo.reset_tree(head_tree.as_deref(), &tree, mode) {
+        Err(
+            e @ (RepoError::NotUptodate(_)
+            | RepoError::WouldBeOverwritten(_)
+            | RepoError::UntrackedWouldBeOverwritten(_)),
+        ) => {
+            eprintln!("error: {e}");
+            bail!("Could not reset index file to revision '{commit}'.");
+        }
+        result => result?,
+    }
+
+    if let Some(target) = &target {
+        if let Some(head) = &head {
+            repo.update_ref("ORIG_HEAD", head, "")?;
+        }
+        repo.update_ref("HEAD", target, &format!("reset: moving to {commit}"))?;
+    }
+
+    report(
+        &repo,
+        mode,
+        target.as_deref(),
+        args.quiet,
+        &mut io::stdout(),
+    )?;
+
+    Ok(())
+}
+
+/// Writes what a reset to `target` did: where HEAD is now after a hard
+/// reset, or the files left with unstaged changes after a mixed one.
+/// Nothing is written if `quiet`.
+fn report(
+    repo: &Repository,
+    mode: ResetMode,
+    target: Option<&str>,
+    quiet: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    if quiet {
+        return Ok(());
+    }
+    match (mode, target) {
+        (ResetMode::Hard, Some(target)) => {
+            let subject = Commit::read(target, repo)?.subject();
+            writeln!(out, "HEAD is now at {} {subject}", &target[..7])?;
+        }
+        (ResetMode::Mixed, _) => write_unstaged(repo, out)?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Lists the files that differ between the index and the working tree.
+fn write_unstaged(repo: &Repository, out: &mut impl Write) -> Result<()> {
+    let status = repo.status(UntrackedFiles::No)?;
+    let mut changes: Vec<(&str, char)> = status
+        .changes
+        .iter()
+        .filter(|file| file.unstaged != Change::Unmodified)
+        .map(|file| (file.path.as_str(), file.unstaged.code()))
+        .chain(status.unmerged.iter().map(|file| (file.path.as_str(), 'U')))
+        .collect();
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    changes.sort();
+    writeln!(out, "Unstaged changes after reset:")?;
+    for (path, code) in changes {
+        writeln!(out, "{code}\t{path}")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::object::write_commit;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_report() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+        fs::write(root.join("a"), "a\n").unwrap();
+        let tree = repo.add_all().unwrap();
+        let commit = write_commit(&tree, &[], "one", &repo).unwrap();
+        fs::write(root.join("a"), "changed\n").unwrap();
+
+        let report = |mode, quiet| {
+            let mut out = vec![];
+            report(&repo, mode, Some(&commit), quiet, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let head = format!("HEAD is now at {} one\n", &commit[..7]);
+        assert_eq!(report(ResetMode::Hard, false), head);
+        assert_eq!(report(ResetMode::Hard, true), "");
+        let unstaged = "Unstaged changes after reset:\nM\ta\n";
+        assert_eq!(report(ResetMode::Mixed, false), unstaged);
+        assert_eq!(report(ResetMode::Mixed, true), "");
+        assert_eq!(report(ResetMode::Soft, false), "");
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{
+    commands::add,
+    repository::{patch::PatchMode, reset::RestoreOptions, Repository},
+};
+
+#[derive(Args)]
+pub struct RestoreArgs {
+    /// Restore from this tree instead of the index, or HEAD with --staged
+    #[clap(short, long, value_name = "tree")]
+    source: Option<String>,
+
+    /// Restore the index
+    #[clap(short = 'S', long)]
+    staged: bool,
+
+    /// Restore the working tree (default)
+    #[clap(short = 'W', long)]
+    worktree: bool,
+
+    /// Choose the hunks to restore interactively
+    #[clap(short, long)]
+    patch: bool,
+
+    #[clap(required_unless_present = "patch")]
+    pathspecs: Vec<String>,
+}
+
+/// Restore working tree files.
+pub fn run(args: RestoreArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+
+    let source = match args.source.as_deref() {
+        Some(source) => Some(source),
+        None if args.staged => Some("HEAD"),
+        None => None,
+    };
+    let tree = match source {
+        Some(source) => match repo.rev_parse(&format!("{source}^{{tree}}")) {
+            Ok(tree) => Some(tree),
+            Err(_) => bail!("could not resolve {source}"),
+        },
+        None => None,
+    };
+
+    if args.patch {
+        let head = source == Some("HEAD");
+        let mode = match (&tree, args.staged && !args.worktree) {
+            (Some(tree), true) => PatchMode::Reset { tree, head },
+            _ => PatchMode::Restore {
+                tree: tree.as_deref(),
+                head,
+                index: args.staged,
+            },
+        };
+        return add::patch(&repo, mode, &args.pathspecs);
+    }
+
+    let options = RestoreOptions {
+        source: tree.as_deref(),
+        staged: args.staged,
+        worktree: args.worktree || !args.staged,
+        ignore_unmatch: false,
+        overlay: false,
+    };
+    repo.restore_paths(&options, &args.pathspecs)?;
+
+    Ok(())
+}
@@ -0,0 +1,336 @@
+use std::{
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+use clap::{Arg, ArgAction, ArgMatches, Args, Command, FromArgMatches};
+
+use crate::{
+    date,
+    regex::{Regex, Syntax},
+    repository::{error::RepoError, Repository},
+    revision::RevWalk,
+};
+
+/// The options selecting, filtering and ordering commits, shared by the
+/// commands walking the history.
+#[derive(Args)]
+pub struct RevisionArgs {
+    /// Show at most <number> commits, also given as -<number>
+    #[clap(short = 'n', long, value_name = "number")]
+    max_count: Option<usize>,
+
+    /// Skip <number> commits before starting to show output
+    #[clap(long, value_name = "number")]
+    skip: Option<usize>,
+
+    /// Show only commits by authors matching the pattern
+    #[clap(long, value_name = "pattern")]
+    author: Vec<String>,
+
+    /// Show only commits with a message matching the pattern
+    #[clap(long, value_name = "pattern")]
+    grep: Vec<String>,
+
+    /// Show only commits matching all --grep patterns, not any of them
+    #[clap(long)]
+    all_match: bool,
+
+    /// Match the patterns case-insensitively
+    #[clap(short = 'i', long)]
+    regexp_ignore_case: bool,
+
+    /// Use extended regular expressions for the patterns
+    #[clap(short = 'E', long, conflicts_with = "fixed_strings")]
+    extended_regexp: bool,
+
+    /// Match the patterns as fixed strings
+    #[clap(short = 'F', long)]
+    fixed_strings: bool,
+
+    /// Show commits more recent than the date
+    #[clap(long, visible_alias = "after", value_name = "date")]
+    since: Option<String>,
+
+    /// Show commits older than the date
+    #[clap(long, visible_alias = "before", value_name = "date")]
+    until: Option<String>,
+
+    /// Follow only the first parent of merge commits
+    #[clap(long)]
+    first_parent: bool,
+
+    /// Show only merge commits
+    #[clap(long)]
+    merges: bool,
+
+    /// Show no merge commits
+    #[clap(long)]
+    no_merges: bool,
+
+    /// Show only commits with at least this many parents
+    #[clap(long, value_name = "number")]
+    min_parents: Option<usize>,
+
+    /// Show only commits with at most this many parents
+    #[clap(long, value_name = "number")]
+    max_parents: Option<usize>,
+
+    /// Show no parents before all of their children are shown
+    #[clap(long, overrides_with = "date_order")]
+    topo_order: bool,
+
+    /// Like --topo-order, otherwise showing commits by date
+    #[clap(long, overrides_with = "topo_order")]
+    date_order: bool,
+
+    /// Show the commits in reverse order
+    #[clap(long)]
+    reverse: bool,
+
+    /// Show only commits that are descendants of an excluded commit and
+    /// ancestors of an included one
+    #[clap(long)]
+    ancestry_path: bool,
+
+    #[clap(flatten)]
+    revisions: RevisionList,
+
+    /// Show only commits changing these paths
+    #[clap(last = true)]
+    paths: Vec<String>,
+}
+
+impl RevisionArgs {
+    /// Returns the walk over the given revisions, or over `default` if
+    /// none are given.
+    pub fn rev_walk(&self, repo: &Repository, default: Option<&str>) -> Result<RevWalk> {
+        let mut walk = RevWalk::default();
+        walk.max_count = self.max_count;
+        walk.skip = self.skip.unwrap_or(0);
+        walk.first_parent = self.first_parent;
+        walk.topo_order = self.topo_order;
+        walk.date_order = self.date_order;
+        walk.reverse = self.reverse;
+        walk.ancestry_path = self.ancestry_path;
+        walk.min_parents = self.min_parents.unwrap_or(if self.merges { 2 } else { 0 });
+        walk.max_parents = self.max_parents.or(self.no_merges.then_some(1));
+        walk.all_match = self.all_match;
+        let now = date::now();
+        let parse_date =
+            |s: &str| date::parse_approxidate(s, now).ok_or_else(|| anyhow!("invalid date '{s}'"));
+        walk.since = self.since.as_deref().map(parse_date).transpose()?;
+        walk.until = self.until.as_deref().map(parse_date).transpose()?;
+
+        let syntax = match (self.extended_regexp, self.fixed_strings) {
+            (true, _) => Syntax::Extended,
+            (_, true) => Syntax::Fixed,
+            _ => Syntax::Basic,
+        };
+        let compile = |pattern: &String| {
+            Regex::new(pattern, syntax, self.regexp_ignore_case)
+                .map_err(|e| anyhow!("command line, '{pattern}': {e}"))
+        };
+        walk.authors = self.author.iter().map(compile).collect::<Result<_>>()?;
+        walk.greps = self.grep.iter().map(compile).collect::<Result<_>>()?;
+
+        // The revisions end at the first argument naming a file instead
+        let mut given = false;
+        let mut args = self.revisions.0.iter();
+        for arg in args.by_ref() {
+            // `-<n>` is `-n <n>`
+            if let Some(n) = arg.strip_prefix('-').and_then(|n| n.parse().ok()) {
+                walk.max_count = Some(n);
+                continue;
+            }
+
+            match walk.add_revision(repo, arg) {
+                Err(RepoError::UnknownRevision(_)) if Path::new(arg).exists() => {
+                    walk.paths.push(arg.clone());
+                    break;
+                }
+                Err(RepoError::UnknownRevision(_)) => bail!(
+                    "ambiguous argument '{arg}': unknown revision or path not in the working tree."
+                ),
+                result => result?,
+            }
+            given |= arg != "--not";
+        }
+        for arg in args {
+            if !Path::new(arg).exists() {
+                bail!(
+                    "ambiguous argument '{arg}': unknown revision or path not in the working tree."
+                );
+            }
+            walk.paths.push(arg.clone());
+        }
+        walk.paths.extend(self.paths.iter().cloned());
+
+        match default {
+            _ if given => {}
+            Some(default) => {
+                if repo.read_ref(default)?.is_none() {
+                    let head = repo.get_head()?;
+                    let branch = head.strip_prefix("refs/heads/").unwrap_or(&head);
+                    bail!("your current branch '{branch}' does not have any commits yet");
+                }
+                walk.add_revision(repo, default)?;
+            }
+            None => bail!("no revisions given"),
+        }
+
+        Ok(walk)
+    }
+}
+
+/// The revision arguments in the order they were given, among them the
+/// pseudo-options like `--not` whose meaning depends on that order.
+#[derive(Default)]
+struct RevisionList(Vec<String>);
+
+/// The pseudo-options, with their help and whether they take a pattern.
+const PSEUDO_OPTIONS: [(&str, &str, bool); 5] = [
+    ("all", "Add all refs and HEAD to the revisions", false),
+    (
+        "branches",
+        "Add the branches, or those matching the pattern",
+        true,
+    ),
+    ("tags", "Add the tags, or those matching the pattern", true),
+    (
+        "remotes",
+        "Add the remote-tracking branches, or those matching the pattern",
+        true,
+    ),
+    (
+        "not",
+        "Flip the meaning of `^` for the revisions after it",
+        false,
+    ),
+];
+
+impl Args for RevisionList {
+    fn augment_args(mut cmd: Command) -> Command {
+        for (name, help, pattern) in PSEUDO_OPTIONS {
+            let arg = Arg::new(name)
+                .long(name)
+                .help(help)
+                .action(ArgAction::Append)
+                .default_missing_value("");
+            cmd = match pattern {
+                true => cmd.arg(
+                    arg.value_name("pattern")
+                        .num_args(0..=1)
+                        .require_equals(true),
+                ),
+                false => cmd.arg(arg.num_args(0)),
+            };
+        }
+        cmd.arg(
+            Arg::new("revisions")
+                .value_name("REVISIONS")
+                .help(
+                    "The commits to show with their ancestors: `^<rev>` to exclude a \
+                     commit's ancestors, `<rev>..<rev>` or `<rev>...<rev>` for the \
+                     commits reachable from either but not both",
+                )
+                .action(ArgAction::Append)
+                .allow_negative_numbers(true),
+        )
+    }
+
+    fn augment_args_for_update(cmd: Command) -> Command {
+        Self::augment_args(cmd)
+    }
+}
+
+impl FromArgMatches for RevisionList {
+    fn from_arg_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let mut args = vec![];
+        let ids = PSEUDO_OPTIONS.iter().map(|(name, ..)| *name);
+        for id in ids.chain(["revisions"]) {
+            let (Some(values), Some(indices)) =
+                (matches.get_many::<String>(id), matches.indices_of(id))
+            else {
+                continue;
+            };
+            for (value, index) in values.zip(indices) {
+                let arg = match (id, value.as_str()) {
+                    ("revisions", _) => value.clone(),
+                    (_, "") => format!("--{id}"),
+                    (_, pattern) => format!("--{id}={pattern}"),
+                };
+                args.push((index, arg));
+            }
+        }
+        args.sort();
+        Ok(RevisionList(args.into_iter().map(|(_, arg)| arg).collect()))
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+pub struct RevListArgs {
+    #[clap(flatten)]
+    revision: RevisionArgs,
+
+    /// Print the number of commits instead of listing them
+    #[clap(long)]
+    count: bool,
+
+    /// Mark the commits on the left side of a symmetric range with `<`
+    /// and those on the right side with `>`
+    #[clap(long)]
+    left_right: bool,
+
+    /// Also list the trees and blobs of the commits, with their paths
+    #[clap(long)]
+    objects: bool,
+
+    /// Print the parents of each commit too
+    #[clap(long)]
+    parents: bool,
+}
+
+/// List commits in reverse chronological order.
+pub fn run(args: RevListArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let mut walk = args.revision.rev_walk(&repo, None)?;
+    walk.rewrite_parents = args.parents;
+
+    let commits = walk.limit(walk.walk(&repo)?);
+    let mut out = BufWriter::new(io::stdout());
+    if args.count {
+        let left = commits.iter().filter(|rev| rev.left).count();
+        match args.left_right {
+            true => writeln!(out, "{left}\t{}", commits.len() - left)?,
+            false => writeln!(out, "{}", commits.len())?,
+        }
+        return Ok(out.flush()?);
+    }
+
+    for rev in &commits {
+        if args.left_right {
+            write!(out, "{}", if rev.left { '<' } else { '>' })?;
+        }
+        write!(out, "{}", rev.hash)?;
+        if args.parents {
+            for parent in &rev.commit.parents {
+                write!(out, " {parent}")?;
+            }
+        }
+        writeln!(out)?;
+    }
+    if args.objects {
+        for (hash, path) in walk.objects(&repo, &commits)? {
+            writeln!(out, "{hash} {path}")?;
+        }
+    }
+
+    Ok(out.flush()?)
+}
@@ -0,0 +1,182 @@
+use std::{collections::BTreeSet, fs};
+
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{
+    object::{tree::Tree, Commit},
+    pathspec::{match_pathspec, PathspecMatch},
+    repository::{
+        checkout::remove_path,
+        index::{is_missing, Index},
+        Repository,
+    },
+};
+
+#[derive(Args)]
+pub struct RmArgs {
+    /// Only remove the files from the index, keeping them in the working tree
+    #[clap(long)]
+    cached: bool,
+
+    /// Allow removing directories recursively
+    #[clap(short = 'r')]
+    recursive: bool,
+
+    /// Remove files even if they have changes
+    #[clap(short, long)]
+    force: bool,
+
+    /// Only show which files would be removed
+    #[clap(short = 'n', long)]
+    dry_run: bool,
+
+    /// Don't list the removed files
+    #[clap(short, long)]
+    quiet: bool,
+
+    /// Exit successfully even if a pathspec matches no files
+    #[clap(long)]
+    ignore_unmatch: bool,
+
+    #[clap(required = true)]
+    pathspecs: Vec<String>,
+}
+
+/// Remove files from the working tree and from the index.
+pub fn run(args: RmArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let root = repo.get_root();
+    let mut index = Index::lock(&repo)?;
+
+    let mut paths = BTreeSet::new();
+    for spec in &args.pathspecs {
+        let mut matched = false;
+        for entry in &index.entries {
+            match match_pathspec(spec, &entry.path) {
+                Some(PathspecMatch::LeadingDir) if !args.recursive => {
+                    bail!(
+                        "not removing '{}' recursively without -r",
+                        spec.trim_end_matches('/')
+                    )
+                }
+                Some(_) => {
+                    paths.insert(entry.path.clone());
+                    matched = true;
+                }
+                None => {}
+            }
+        }
+        if !matched && !args.ignore_unmatch {
+            bail!("pathspec '{spec}' did not match any files");
+        }
+    }
+
+    if !args.force {
+        check_local_changes(&repo, &index, &paths, args.cached)?;
+    }
+
+    for path in &paths {
+        if !args.quiet {
+            println!("rm '{path}'");
+        }
+        if args.dry_run {
+            continue;
+        }
+        index.remove(path);
+        if !args.cached {
+            remove_path(root, path)?;
+        }
+    }
+    if !args.dry_run {
+        index.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Refuses to remove files whose changes would be lost: files staged
+/// differently from both HEAD and the working tree, and, unless only the
+/// index is changed, files with staged or unstaged changes.
+fn check_local_changes(
+    repo: &Repository,
+    index: &Index,
+    paths: &BTreeSet<String>,
+    cached: bool,
+) -> Result<()> {
+    let root = repo.get_root();
+    let head = match repo.read_ref("HEAD")? {
+        Some(hash) => Tree::read_recursive(&Commit::read(&hash, repo)?.tree, repo)?,
+        None => Default::default(),
+    };
+    let convert = repo.convert()?;
+
+    let (mut both, mut staged, mut local) = (vec![], vec![], vec![]);
+    for path in paths {
+        // Removing a conflicted file resolves the conflict
+        let Some(entry) = index.get(path) else {
+            continue;
+        };
+        let staged_changes = head.get(path) != Some(&(entry.mode, entry.hash.clone()));
+        let local_changes = match fs::symlink_metadata(root.join(path)) {
+            Ok(_) => index.is_modified(entry, root, &convert)?,
+            Err(e) if is_missing(&e) => false,
+            Err(e) => Err(e)?,
+        };
+
+        if staged_changes && local_changes {
+            both.push(path.as_str());
+        } else if !cached && staged_changes {
+            staged.push(path.as_str());
+        } else if !cached && local_changes {
+            local.push(path.as_str());
+        }
+    }
+
+    let mut errors = vec![];
+    if !both.is_empty() {
+        errors.push(error_files(
+            &both,
+            "has staged content different from both the\nfile and the HEAD:",
+            "have staged content different from both the\nfile and the HEAD:",
+            "(use -f to force removal)",
+        ));
+    }
+    let hint = "(use --cached to keep the file, or -f to force removal)";
+    if !staged.is_empty() {
+        errors.push(error_files(
+            &staged,
+            "has changes staged in the index:",
+            "have changes staged in the index:",
+            hint,
+        ));
+    }
+    if !local.is_empty() {
+        errors.push(error_files(
+            &local,
+            "has local modifications:",
+            "have local modifications:",
+            hint,
+        ));
+    }
+    if !errors.is_empty() {
+        // All but the last error are printed, the last one is returned
+        let last = errors.pop().unwrap_or_default();
+        for error in errors {
+            eprintln!("error: {error}");
+        }
+        bail!(last);
+    }
+
+    Ok(())
+}
+
+/// Formats an error listing files, e.g. `the following file has ...`.
+fn error_files(files: &[&str], singular: &str, plural: &str, hint: &str) -> String {
+    let what = match files.len() {
+        1 => format!("the following file {singular}"),
+        _ => format!("the following files {plural}"),
+    };
+    let files: String = files.iter().map(|file| format!("    {file}\n")).collect();
+    format!("{what}\n{files}{hint}")
+}
@@ -0,0 +1,150 @@
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{
+    object::{ObjectType, Tag},
+    repository::Repository,
+};
+
+#[derive(Args)]
+pub struct ShowRefArgs {
+    /// Show only branches
+    #[clap(long)]
+    heads: bool,
+
+    /// Show only tags
+    #[clap(long)]
+    tags: bool,
+
+    /// Show the HEAD reference, even if it would be filtered out
+    #[clap(long)]
+    head: bool,
+
+    /// Dereference tags into object IDs too, shown as `<ref>^{}`
+    #[clap(short, long)]
+    dereference: bool,
+
+    /// Only show the object ID, optionally abbreviated to <n> digits
+    #[clap(short = 's', long = "hash", value_name = "n", num_args = 0..=1, default_missing_value = "40")]
+    hash: Option<usize>,
+
+    /// Abbreviate object IDs to <n> digits
+    #[clap(long, value_name = "n", num_args = 0..=1, default_missing_value = "7")]
+    abbrev: Option<usize>,
+
+    /// Only check that the refs exist, printing nothing
+    #[clap(short, long)]
+    quiet: bool,
+
+    /// Require the patterns to be exact ref names
+    #[clap(long, conflicts_with = "exists")]
+    verify: bool,
+
+    /// Check whether the given ref exists, exiting with 2 if it doesn't
+    #[clap(long)]
+    exists: bool,
+
+    /// Refs to show, matched against the end of the full ref name
+    patterns: Vec<String>,
+}
+
+/// List references in a local repository.
+pub fn run(args: ShowRefArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+
+    if args.exists {
+        let [refname] = args.patterns.as_slice() else {
+            bail!("--exists requires exactly one reference");
+        };
+        // A dangling symbolic ref still exists
+        let exists =
+            repo.read_symbolic_ref(refname)?.is_some() || repo.read_ref(refname)?.is_some();
+        if !exists {
+            eprintln!("error: reference does not exist");
+            std::process::exit(2);
+        }
+        return Ok(());
+    }
+
+    let mut refs = vec![];
+    if args.verify {
+        for refname in &args.patterns {
+            let hash = match refname == "HEAD" || refname.starts_with("refs/") {
+                true => repo.read_ref(refname)?,
+                false => None,
+            };
+            match hash {
+                Some(hash) => refs.push((refname.clone(), hash)),
+                None if args.quiet => std::process::exit(1),
+                None => bail!("'{refname}' - not a valid ref"),
+            }
+        }
+    } else {
+        if args.head {
+            if let Some(hash) = repo.read_ref("HEAD")? {
+                refs.push((String::from("HEAD"), hash));
+            }
+        }
+        for (refname, hash) in repo.list_refs("refs/")? {
+            let kind_matches = (!args.heads && !args.tags)
+                || (args.heads && refname.starts_with("refs/heads/"))
+                || (args.tags && refname.starts_with("refs/tags/"));
+            if kind_matches && matches_patterns(&refname, &args.patterns) {
+                refs.push((refname, hash));
+            }
+        }
+        if refs.is_empty() {
+            std::process::exit(1);
+        }
+    }
+
+    if args.quiet {
+        return Ok(());
+    }
+    let abbrev = args.hash.or(args.abbrev).unwrap_or(40).clamp(4, 40);
+    for (refname, hash) in refs {
+        show(&refname, &hash, abbrev, args.hash.is_some());
+        if args.dereference {
+            if let Some(peeled) = peel(&hash, &repo) {
+                show(
+                    &format!("{refname}^{{}}"),
+                    &peeled,
+                    abbrev,
+                    args.hash.is_some(),
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn show(refname: &str, hash: &str, abbrev: usize, hash_only: bool) {
+    if hash_only {
+        println!("{}", &hash[..abbrev]);
+    } else {
+        println!("{} {refname}", &hash[..abbrev]);
+    }
+}
+
+/// Returns true if a pattern matches the full refname or its last
+/// components, e.g. `main` and `heads/main` both match `refs/heads/main`.
+fn matches_patterns(refname: &str, patterns: &[String]) -> bool {
+    patterns.is_empty()
+        || patterns.iter().any(|pattern| {
+            refname == pattern
+                || refname
+                    .strip_suffix(pattern.as_str())
+                    .is_some_and(|rest| rest.ends_with('/'))
+        })
+}
+
+/// Returns the object an annotated tag ultimately points to, or `None` if
+/// the hash is not a tag.
+fn peel(hash: &str, repo: &Repository) -> Option<String> {
+    let mut tag = Tag::read(hash, repo).ok()?;
+    while tag.kind == ObjectType::Tag {
+        tag = Tag::read(&tag.object, repo).ok()?;
+    }
+    Some(tag.object)
+}
@@ -0,0 +1,178 @@
+use std::io::{self, BufRead};
+
+use anyhow::{bail, Result};
+use clap::{Args, Subcommand};
+
+use crate::{
+    commands::status::quote_path,
+    repository::{sparse::SparseCheckout, Repository},
+};
+
+#[derive(Args)]
+pub struct SparseCheckoutArgs {
+    #[clap(subcommand)]
+    command: SparseCheckoutCommand,
+}
+
+#[derive(Subcommand)]
+enum SparseCheckoutCommand {
+    /// Enable sparse checkout, keeping the existing patterns if any
+    Init(ModeArgs),
+    /// List the directories or patterns of the sparse checkout
+    List,
+    /// Enable sparse checkout with the given directories or patterns
+    Set {
+        #[clap(flatten)]
+        mode: ModeArgs,
+
+        #[clap(flatten)]
+        patterns: PatternArgs,
+    },
+    /// Add directories or patterns to the sparse checkout
+    Add(PatternArgs),
+    /// Update the working tree to match the patterns again
+    Reapply,
+    /// Disable sparse checkout and restore all files
+    Disable,
+}
+
+#[derive(Args)]
+struct ModeArgs {
+    /// Match directories rather than patterns
+    #[clap(long, overrides_with = "no_cone")]
+    cone: bool,
+
+    /// Match gitignore-style patterns
+    #[clap(long)]
+    no_cone: bool,
+}
+
+#[derive(Args)]
+struct PatternArgs {
+    /// Read the directories or patterns from standard input, one per line
+    #[clap(long)]
+    stdin: bool,
+
+    /// Don't check that the directories aren't patterns
+    #[clap(long)]
+    skip_checks: bool,
+
+    patterns: Vec<String>,
+}
+
+/// Reduce your working tree to a subset of tracked files.
+pub fn run(args: SparseCheckoutArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+
+    match args.command {
+        SparseCheckoutCommand::Init(mode) => {
+            let cone = is_cone(&repo, &mode)?;
+            let sparse = match repo.read_sparse_checkout(cone)? {
+                Some(sparse) => sparse,
+                None => new_sparse_checkout(cone, &[]),
+            };
+            repo.set_sparse_checkout(Some(&sparse))?;
+        }
+        SparseCheckoutCommand::List => {
+            let Some(sparse) = repo.sparse_checkout()? else {
+                bail!("this worktree is not sparse");
+            };
+            for entry in sparse.list() {
+                match sparse.is_cone() {
+                    true => println!("{}", quote_path(&entry, false)),
+                    false => println!("{entry}"),
+                }
+            }
+        }
+        SparseCheckoutCommand::Set { mode, patterns } => {
+            let cone = is_cone(&repo, &mode)?;
+            let patterns = read_patterns(patterns, cone)?;
+            repo.set_sparse_checkout(Some(&new_sparse_checkout(cone, &patterns)))?;
+        }
+        SparseCheckoutCommand::Add(patterns) => {
+            let Some(sparse) = repo.sparse_checkout()? else {
+                bail!("no sparse-checkout to add to");
+            };
+            let cone = sparse.is_cone();
+            let mut all = sparse.list();
+            all.extend(read_patterns(patterns, cone)?);
+            repo.set_sparse_checkout(Some(&new_sparse_checkout(cone, &all)))?;
+        }
+        SparseCheckoutCommand::Reapply => {
+            let Some(sparse) = repo.sparse_checkout()? else {
+                bail!("must be in a sparse-checkout to reapply sparsity patterns");
+            };
+            repo.update_sparse_worktree(Some(&sparse))?;
+        }
+        SparseCheckoutCommand::Disable => repo.set_sparse_checkout(None)?,
+    }
+
+    Ok(())
+}
+
+/// Returns whether to use cone mode: as given, or else as it is if sparse
+/// checkout is enabled already, or else cone mode.
+fn is_cone(repo: &Repository, mode: &ModeArgs) -> Result<bool> {
+    if mode.cone || mode.no_cone {
+        return Ok(mode.cone);
+    }
+    let config = repo.config()?;
+    if config.get_bool("core.sparseCheckout") == Some(true) {
+        return Ok(config.get_bool("core.sparseCheckoutCone") == Some(true));
+    }
+    Ok(true)
+}
+
+fn new_sparse_checkout(cone: bool, patterns: &[String]) -> SparseCheckout {
+    match cone {
+        true => SparseCheckout::from_dirs(patterns),
+        false => SparseCheckout::from_patterns(patterns),
+    }
+}
+
+/// Returns the patterns from the arguments or standard input. In cone mode,
+/// they are checked to be directories and normalized.
+fn read_patterns(args: PatternArgs, cone: bool) -> Result<Vec<String>> {
+    let mut patterns = args.patterns;
+    if args.stdin {
+        for line in io::stdin().lock().lines() {
+            patterns.push(line?);
+        }
+    }
+    if !cone {
+        return Ok(patterns);
+    }
+
+    if !args.skip_checks {
+        for pattern in &patterns {
+            if pattern.starts_with('/') {
+                bail!("specify directories rather than patterns (no leading slash)");
+            }
+            if pattern.contains(['*', '?', '[', ']', '\\']) {
+                bail!(
+                    "specify directories rather than patterns.  If your directory really has any of '*?[]\\' in it, pass --skip-checks"
+                );
+            }
+        }
+    }
+    Ok(patterns
+        .iter()
+        .filter_map(|pattern| normalize(pattern))
+        .collect())
+}
+
+/// Resolves the `.` and `..` components of a directory, dropping empty
+/// ones. Returns `None` for the root.
+fn normalize(dir: &str) -> Option<String> {
+    let mut components = vec![];
+    for component in dir.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    (!components.is_empty()).then(|| components.join("/"))
+}
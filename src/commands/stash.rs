@@ -0,0 +1,263 @@
+use std::io;
+
+use anyhow::{bail, Result};
+use clap::{Args, Subcommand};
+
+use crate::{
+    commands::{
+        checkout::{self, Switch},
+        status::print_long_status,
+    },
+    repository::{
+        diff::DiffOptions,
+        error::RepoError,
+        stash::{Stash, StashOptions},
+        Repository,
+    },
+};
+
+#[derive(Args)]
+#[clap(args_conflicts_with_subcommands = true)]
+pub struct StashArgs {
+    #[clap(subcommand)]
+    command: Option<StashCommand>,
+
+    #[clap(flatten)]
+    push: PushFlags,
+}
+
+#[derive(Subcommand)]
+enum StashCommand {
+    /// Save the local changes to a new stash and revert them (default)
+    Push(PushArgs),
+    /// List the stashes
+    List,
+    /// Show the changes recorded in a stash
+    Show(ShowArgs),
+    /// Apply a stash and remove it from the list
+    Pop(ApplyArgs),
+    /// Apply a stash, keeping it in the list
+    Apply(ApplyArgs),
+    /// Remove a stash from the list
+    Drop {
+        /// Don't report the dropped stash
+        #[clap(short, long)]
+        quiet: bool,
+
+        stash: Option<String>,
+    },
+    /// Remove all stashes
+    Clear,
+    /// Create a branch at the commit a stash was made on and apply the stash
+    Branch {
+        branch: String,
+        stash: Option<String>,
+    },
+}
+
+#[derive(Args)]
+struct PushFlags {
+    /// Describe the stash with this message
+    #[clap(short, long)]
+    message: Option<String>,
+
+    /// Stash untracked files too, and remove them
+    #[clap(short = 'u', long)]
+    include_untracked: bool,
+
+    /// Leave the changes in the index in place
+    #[clap(short, long)]
+    keep_index: bool,
+
+    /// Don't report the saved stash
+    #[clap(short, long)]
+    quiet: bool,
+}
+
+#[derive(Args)]
+struct PushArgs {
+    #[clap(flatten)]
+    flags: PushFlags,
+
+    /// Only stash the changes to these files
+    pathspecs: Vec<String>,
+}
+
+#[derive(Args)]
+struct ShowArgs {
+    /// Show the changes as a patch
+    #[clap(short, long)]
+    patch: bool,
+
+    /// Show a diffstat (default)
+    #[clap(long)]
+    stat: bool,
+
+    stash: Option<String>,
+}
+
+#[derive(Args)]
+struct ApplyArgs {
+    /// Restore the changes to the index too
+    #[clap(long)]
+    index: bool,
+
+    /// Don't show the status afterwards
+    #[clap(short, long)]
+    quiet: bool,
+
+    stash: Option<String>,
+}
+
+/// Stash the changes in a dirty working directory away.
+pub fn run(args: StashArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+
+    match args.command {
+        None => push(&repo, &args.push, &[]),
+        Some(StashCommand::Push(args)) => push(&repo, &args.flags, &args.pathspecs),
+        Some(StashCommand::List) => {
+            for (i, entry) in repo.stash_list()?.iter().enumerate() {
+                println!("stash@{{{i}}}: {}", entry.message);
+            }
+            Ok(())
+        }
+        Some(StashCommand::Show(args)) => {
+            let (_, hash) = resolve(&repo, args.stash.as_deref())?;
+            let stash = Stash::read(&hash, &repo)?;
+            let changes = repo.diff_trees(&stash.base_tree, &stash.worktree_tree)?;
+            let mut out = io::stdout().lock();
+            if args.stat || !args.patch {
+                repo.write_stat(&mut out, &changes)?;
+            }
+            if args.patch {
+                if args.stat {
+                    println!();
+                }
+                repo.write_patch(&mut out, &changes, &DiffOptions::default())?;
+            }
+            Ok(())
+        }
+        Some(StashCommand::Apply(args)) => {
+            let (_, hash) = resolve(&repo, args.stash.as_deref())?;
+            if !apply(&repo, &hash, args.index, args.quiet)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(StashCommand::Pop(args)) => {
+            let (name, hash) = resolve(&repo, args.stash.as_deref())?;
+            if !apply(&repo, &hash, args.index, args.quiet)? {
+                println!("The stash entry is kept in case you need it again.");
+                std::process::exit(1);
+            }
+            drop_stash(&repo, &name, &hash, args.quiet)
+        }
+        Some(StashCommand::Drop { quiet, stash }) => {
+            let (name, hash) = resolve(&repo, stash.as_deref())?;
+            drop_stash(&repo, &name, &hash, quiet)
+        }
+        Some(StashCommand::Clear) => Ok(repo.stash_clear()?),
+        Some(StashCommand::Branch { branch, stash }) => {
+            let (name, hash) = resolve(&repo, stash.as_deref())?;
+            let stash = Stash::read(&hash, &repo)?;
+            checkout::switch(
+                &repo,
+                Switch {
+                    new_branch: Some(&branch),
+                    reset: false,
+                    target: Some(&stash.base),
+                    detach: false,
+                    force: false,
+                    merge: false,
+                },
+            )?;
+            if !apply(&repo, &hash, true, false)? {
+                std::process::exit(1);
+            }
+            drop_stash(&repo, &name, &hash, false)
+        }
+    }
+}
+
+fn push(repo: &Repository, flags: &PushFlags, pathspecs: &[String]) -> Result<()> {
+    let options = StashOptions {
+        message: flags.message.as_deref(),
+        pathspecs,
+        include_untracked: flags.include_untracked,
+        keep_index: flags.keep_index,
+    };
+    match repo.stash_push(&options)? {
+        Some(_) if flags.quiet => {}
+        Some(message) => println!("Saved working directory and index state {message}"),
+        None => println!("No local changes to save"),
+    }
+
+    Ok(())
+}
+
+/// Applies a stash and shows the status, returning false if there are
+/// conflicts or local changes are in the way.
+fn apply(repo: &Repository, hash: &str, index: bool, quiet: bool) -> Result<bool> {
+    let applied = match repo.stash_apply(hash, index) {
+        Ok(outcome) => {
+            for message in &outcome.messages {
+                println!("{message}");
+            }
+            if outcome.conflicts && index {
+                eprintln!("Index was not unstashed.");
+            }
+            !outcome.conflicts
+        }
+        Err(e @ (RepoError::MergeLocalChanges(_) | RepoError::MergeUntrackedOverwritten(_))) => {
+            eprintln!("error: {e}");
+            false
+        }
+        Err(e) => Err(e)?,
+    };
+    if !quiet {
+        print_long_status(repo)?;
+    }
+
+    Ok(applied)
+}
+
+fn drop_stash(repo: &Repository, name: &str, hash: &str, quiet: bool) -> Result<()> {
+    let Some(n) = stash_index(name) else {
+        bail!("'{name}' is not a stash reference");
+    };
+    repo.stash_drop(n)?;
+    if !quiet {
+        println!("Dropped {name} ({hash})");
+    }
+
+    Ok(())
+}
+
+/// Resolves a stash given as `stash@{<n>}`, `<n>` or any revision of a
+/// stash commit, defaulting to the newest one, to its name and hash.
+fn resolve(repo: &Repository, stash: Option<&str>) -> Result<(String, String)> {
+    if stash.is_none() && repo.stash_list()?.is_empty() {
+        eprintln!("No stash entries found.");
+        std::process::exit(1);
+    }
+    let name = match stash {
+        None => "refs/stash@{0}".to_string(),
+        Some(n) if n.bytes().all(|b| b.is_ascii_digit()) => format!("stash@{{{n}}}"),
+        Some(stash) => stash.to_string(),
+    };
+    match repo.rev_parse(&name) {
+        Ok(hash) => Ok((name, hash)),
+        Err(RepoError::UnknownRevision(_)) => bail!("{name} is not a valid reference"),
+        Err(e) => Err(e)?,
+    }
+}
+
+/// Returns `n` of a stash named `stash@{<n>}`.
+fn stash_index(name: &str) -> Option<usize> {
+    let name = name.strip_prefix("refs/").unwrap_or(name);
+    name.strip_prefix("stash@{")?
+        .strip_suffix('}')?
+        .parse()
+        .ok()
+}
@@ -0,0 +1,606 @@
+use std::io::{self, Write};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use colored::*;
+
+use crate::{
+    object::{commit, tree::MODE_GITLINK},
+    ref_filter,
+    repository::{
+        refs::NULL_HASH,
+        status::{Change, FileStatus, Status, UnmergedStatus, UntrackedFiles, Version},
+        Repository,
+    },
+};
+
+#[derive(Args)]
+pub struct StatusArgs {
+    /// Give the output in the short format
+    #[clap(short, long)]
+    short: bool,
+
+    /// Show the branch and tracking info, also in the short format
+    #[clap(short, long)]
+    branch: bool,
+
+    /// Give the output in a stable format for scripts: v1 (default) or v2
+    #[clap(
+        long,
+        value_name = "version",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "v1"
+    )]
+    porcelain: Option<String>,
+
+    /// Give the output in the long format (default)
+    #[clap(long)]
+    long: bool,
+
+    /// Terminate entries with NUL instead of LF, implies --porcelain
+    #[clap(short = 'z')]
+    nul_terminated: bool,
+
+    /// Show untracked files: no, normal or all
+    #[clap(
+        short = 'u',
+        long = "untracked-files",
+        value_name = "mode",
+        num_args = 0..=1,
+        default_missing_value = "all"
+    )]
+    untracked_files: Option<String>,
+}
+
+enum Format {
+    Long,
+    Short,
+    PorcelainV1,
+    PorcelainV2,
+}
+
+/// Show the working tree status.
+pub fn run(args: StatusArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+
+    let format = match (args.porcelain.as_deref(), args.short, args.long) {
+        (Some("v1" | "1"), _, _) => Format::PorcelainV1,
+        (Some("v2" | "2"), _, _) => Format::PorcelainV2,
+        (Some(version), _, _) => bail!("unsupported porcelain version '{version}'"),
+        (None, _, true) => Format::Long,
+        (None, true, _) => Format::Short,
+        (None, false, false) if args.nul_terminated => Format::PorcelainV1,
+        (None, false, false) => Format::Long,
+    };
+    if args.nul_terminated && matches!(format, Format::Long) {
+        bail!("-z is not supported with --long");
+    }
+
+    let untracked = untracked_mode(&repo, args.untracked_files.as_deref())?;
+    let status = repo.status(untracked)?;
+    let branch = BranchInfo::read(&repo)?;
+    let mut out = io::stdout().lock();
+    let eol = if args.nul_terminated { '\0' } else { '\n' };
+    match format {
+        Format::Long => print_long(&mut out, &status, &branch, untracked)?,
+        Format::Short => {
+            if args.branch {
+                print_branch_header(&mut out, &branch, true, eol)?;
+            }
+            print_short(&mut out, &status, true, args.nul_terminated)?;
+        }
+        Format::PorcelainV1 => {
+            if args.branch {
+                print_branch_header(&mut out, &branch, false, eol)?;
+            }
+            print_short(&mut out, &status, false, args.nul_terminated)?;
+        }
+        Format::PorcelainV2 => {
+            print_v2(&mut out, &status, &branch, args.branch, args.nul_terminated)?
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the status in the long format, as other commands do after
+/// changing the working tree.
+pub fn print_long_status(repo: &Repository) -> Result<()> {
+    let untracked = untracked_mode(repo, None)?;
+    let status = repo.status(untracked)?;
+    let branch = BranchInfo::read(repo)?;
+    print_long(&mut io::stdout().lock(), &status, &branch, untracked)
+}
+
+/// Returns which untracked files to show, as given or configured.
+fn untracked_mode(repo: &Repository, mode: Option<&str>) -> Result<UntrackedFiles> {
+    let config = repo.config()?;
+    let mode = mode
+        .or(config.get("status.showUntrackedFiles"))
+        .unwrap_or("normal");
+    Ok(match mode {
+        "no" => UntrackedFiles::No,
+        "normal" => UntrackedFiles::Normal,
+        "all" => UntrackedFiles::All,
+        mode => bail!("invalid untracked files mode '{mode}'"),
+    })
+}
+
+/// HEAD and its upstream.
+struct BranchInfo {
+    /// The short name of the current branch, `None` if HEAD is detached
+    branch: Option<String>,
+    /// The commit HEAD points to, `None` if there are no commits yet
+    head: Option<String>,
+    /// The short name of the upstream branch, and how far the current branch
+    /// is ahead of and behind it, `None` if the upstream is gone
+    upstream: Option<(String, Option<(usize, usize)>)>,
+    /// How HEAD got detached, e.g. `at 1a2b3c4`
+    detached: Option<String>,
+    /// Whether a merge is in progress
+    merging: bool,
+}
+
+impl BranchInfo {
+    fn read(repo: &Repository) -> Result<BranchInfo> {
+        let head = repo.read_ref("HEAD")?;
+        let branch = repo
+            .read_symbolic_ref("HEAD")?
+            .map(|refname| ref_filter::shorten_refname(&refname).to_string());
+
+        let mut upstream = None;
+        if let Some(branch) = &branch {
+            if let Some(upstream_ref) = repo.upstream(branch)? {
+                let counts = match (&head, repo.read_ref(&upstream_ref)?) {
+                    (Some(head), Some(upstream)) => {
+                        Some(commit::ahead_behind(head, &upstream, repo)?)
+                    }
+                    _ => None,
+                };
+                upstream = Some((
+                    ref_filter::shorten_refname(&upstream_ref).to_string(),
+                    counts,
+                ));
+            }
+        }
+
+        // Where HEAD was detached, from the last checkout in the reflog: the
+        // ref that was checked out if it still points there, else the commit
+        let mut detached = None;
+        if branch.is_none() {
+            let checkout = repo
+                .read_reflog("HEAD")?
+                .into_iter()
+                .rev()
+                .find_map(|entry| {
+                    let (_, to) = entry
+                        .message
+                        .strip_prefix("checkout: moving from ")?
+                        .rsplit_once(" to ")?;
+                    Some((to.to_string(), entry.new))
+                });
+            if let (Some((target, commit)), Some(head)) = (checkout, &head) {
+                let refname = match target.as_str() {
+                    "HEAD" => None,
+                    target => repo.expand_ref(target)?,
+                };
+                let name = match refname {
+                    Some(refname)
+                        if repo.rev_parse(&format!("{refname}^{{commit}}")).ok()
+                            == Some(commit.clone()) =>
+                    {
+                        let name = refname.strip_prefix("refs/tags/").unwrap_or(&refname);
+                        name.strip_prefix("refs/remotes/")
+                            .unwrap_or(name)
+                            .to_string()
+                    }
+                    _ => commit[..7].to_string(),
+                };
+                let at = commit == *head;
+                detached = Some(format!("{} {name}", if at { "at" } else { "from" }));
+            }
+        }
+
+        Ok(BranchInfo {
+            branch,
+            head,
+            upstream,
+            detached,
+            merging: repo.read_ref("MERGE_HEAD")?.is_some(),
+        })
+    }
+}
+
+/// Quotes a path like git does when it contains special characters: with
+/// C-style escapes in double quotes. Spaces only need quoting in the short
+/// format, where they would otherwise be ambiguous.
+pub fn quote_path(path: &str, quote_space: bool) -> String {
+    let needs_quoting =
+        |b: u8| !(0x20..0x7f).contains(&b) || b == b'"' || b == b'\\' || (quote_space && b == b' ');
+    if !path.bytes().any(needs_quoting) {
+        return path.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for b in path.bytes() {
+        match b {
+            b'\x07' => quoted.push_str("\\a"),
+            b'\x08' => quoted.push_str("\\b"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\x0b' => quoted.push_str("\\v"),
+            b'\x0c' => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b if !(0x20..0x7f).contains(&b) => quoted.push_str(&format!("\\{b:03o}")),
+            b => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn print_long(
+    out: &mut impl Write,
+    status: &Status,
+    info: &BranchInfo,
+    untracked_mode: UntrackedFiles,
+) -> Result<()> {
+    match (&info.branch, &info.detached) {
+        (Some(branch), _) => writeln!(out, "On branch {branch}")?,
+        (None, Some(detached)) => writeln!(out, "{}", format!("HEAD detached {detached}").red())?,
+        (None, None) => writeln!(out, "{}", "Not currently on any branch.".red())?,
+    }
+    if let Some((upstream, counts)) = &info.upstream {
+        match counts {
+            None => writeln!(
+                out,
+                "Your branch is based on '{upstream}', but the upstream is gone."
+            )?,
+            Some((0, 0)) => writeln!(out, "Your branch is up to date with '{upstream}'.")?,
+            Some((ahead, 0)) => writeln!(
+                out,
+                "Your branch is ahead of '{upstream}' by {ahead} {}.",
+                commits(*ahead)
+            )?,
+            Some((0, behind)) => writeln!(
+                out,
+                "Your branch is behind '{upstream}' by {behind} {}, and can be fast-forwarded.",
+                commits(*behind)
+            )?,
+            Some((ahead, behind)) => writeln!(
+                out,
+                "Your branch and '{upstream}' have diverged,\n\
+                 and have {ahead} and {behind} different commits each, respectively."
+            )?,
+        }
+        writeln!(out)?;
+    }
+    if info.head.is_none() {
+        writeln!(out, "\nNo commits yet\n")?;
+    }
+    if info.merging && !status.unmerged.is_empty() {
+        writeln!(out, "You have unmerged paths.\n")?;
+    }
+    if let Some(percentage) = status.sparse_percentage {
+        writeln!(
+            out,
+            "You are in a sparse checkout with {percentage}% of tracked files present.\n"
+        )?;
+    }
+
+    let staged: Vec<&FileStatus> = status
+        .changes
+        .iter()
+        .filter(|file| file.staged != Change::Unmodified)
+        .collect();
+    let unstaged: Vec<&FileStatus> = status
+        .changes
+        .iter()
+        .filter(|file| file.unstaged != Change::Unmodified)
+        .collect();
+
+    if !staged.is_empty() {
+        writeln!(out, "Changes to be committed:")?;
+        for file in &staged {
+            let line = format!(
+                "{:<12}{}",
+                file.staged.label(),
+                quote_path(&file.path, false)
+            );
+            writeln!(out, "\t{}", line.green())?;
+        }
+        writeln!(out)?;
+    }
+    if !status.unmerged.is_empty() {
+        writeln!(out, "Unmerged paths:")?;
+        for file in &status.unmerged {
+            let line = format!("{:<17}{}", file.label(), quote_path(&file.path, false));
+            writeln!(out, "\t{}", line.red())?;
+        }
+        writeln!(out)?;
+    }
+    if !unstaged.is_empty() {
+        writeln!(out, "Changes not staged for commit:")?;
+        for file in &unstaged {
+            let mut line = format!(
+                "{:<12}{}",
+                file.unstaged.label(),
+                quote_path(&file.path, false)
+            );
+            if let Some(changes) = &file.submodule {
+                line += &format!(" ({})", changes.describe());
+            }
+            writeln!(out, "\t{}", line.red())?;
+        }
+        writeln!(out)?;
+    }
+    if !status.untracked.is_empty() {
+        writeln!(out, "Untracked files:")?;
+        for path in &status.untracked {
+            writeln!(out, "\t{}", quote_path(path, false).red())?;
+        }
+        writeln!(out)?;
+    }
+
+    if !staged.is_empty() {
+        if untracked_mode == UntrackedFiles::No {
+            writeln!(out, "Untracked files not listed")?;
+        }
+    } else if !unstaged.is_empty() || !status.unmerged.is_empty() {
+        writeln!(out, "no changes added to commit")?;
+    } else if !status.untracked.is_empty() {
+        writeln!(out, "nothing added to commit but untracked files present")?;
+    } else if info.head.is_none() || untracked_mode == UntrackedFiles::No {
+        writeln!(out, "nothing to commit")?;
+    } else {
+        writeln!(out, "nothing to commit, working tree clean")?;
+    }
+
+    Ok(())
+}
+
+fn commits(n: usize) -> &'static str {
+    if n == 1 {
+        "commit"
+    } else {
+        "commits"
+    }
+}
+
+/// Prints the `## branch...upstream [ahead 1]` line of the short format.
+fn print_branch_header(
+    out: &mut impl Write,
+    info: &BranchInfo,
+    color: bool,
+    eol: char,
+) -> Result<()> {
+    let paint = |s: &str, c: Color| {
+        if color {
+            s.color(c).to_string()
+        } else {
+            s.to_string()
+        }
+    };
+    let mut line = String::from("## ");
+    match (&info.branch, &info.head) {
+        (Some(branch), None) => line.push_str(&format!(
+            "No commits yet on {}",
+            paint(branch, Color::Green)
+        )),
+        (Some(branch), Some(_)) => line.push_str(&paint(branch, Color::Green)),
+        (None, _) => line.push_str(&paint("HEAD (no branch)", Color::Red)),
+    }
+    if let Some((upstream, counts)) = &info.upstream {
+        line.push_str(&format!("...{}", paint(upstream, Color::Red)));
+        match counts {
+            None => line.push_str(" [gone]"),
+            Some((0, 0)) => {}
+            Some((ahead, 0)) => line.push_str(&format!(
+                " [ahead {}]",
+                paint(&ahead.to_string(), Color::Green)
+            )),
+            Some((0, behind)) => line.push_str(&format!(
+                " [behind {}]",
+                paint(&behind.to_string(), Color::Red)
+            )),
+            Some((ahead, behind)) => line.push_str(&format!(
+                " [ahead {}, behind {}]",
+                paint(&ahead.to_string(), Color::Green),
+                paint(&behind.to_string(), Color::Red)
+            )),
+        }
+    }
+    write!(out, "{line}{eol}")?;
+
+    Ok(())
+}
+
+/// A line of the short formats: a changed file or a file with conflicts.
+enum Entry<'a> {
+    Changed(&'a FileStatus),
+    Unmerged(&'a UnmergedStatus),
+}
+
+impl Entry<'_> {
+    fn path(&self) -> &str {
+        match self {
+            Entry::Changed(file) => &file.path,
+            Entry::Unmerged(file) => &file.path,
+        }
+    }
+}
+
+/// Returns changed and unmerged files, sorted by path.
+fn entries(status: &Status) -> Vec<Entry<'_>> {
+    let mut entries: Vec<Entry> = status
+        .changes
+        .iter()
+        .map(Entry::Changed)
+        .chain(status.unmerged.iter().map(Entry::Unmerged))
+        .collect();
+    entries.sort_by(|a, b| a.path().cmp(b.path()));
+    entries
+}
+
+fn print_short(
+    out: &mut impl Write,
+    status: &Status,
+    color: bool,
+    nul_terminated: bool,
+) -> Result<()> {
+    let paint = |s: String, c: Color| if color { s.color(c).to_string() } else { s };
+    let eol = if nul_terminated { '\0' } else { '\n' };
+    let path = |path: &str| match nul_terminated {
+        true => path.to_string(),
+        false => quote_path(path, true),
+    };
+    let code = |change: Change| match change {
+        Change::Unmodified => ' ',
+        change => change.code(),
+    };
+    // Only the short format tells what changed in submodules
+    let unstaged_code = |file: &FileStatus| match &file.submodule {
+        Some(changes) if color && !changes.new_commits && changes.modified => 'm',
+        Some(changes) if color && !changes.new_commits && !changes.modified => '?',
+        _ => code(file.unstaged),
+    };
+
+    for entry in entries(status) {
+        match entry {
+            Entry::Changed(file) => write!(
+                out,
+                "{}{} {}{eol}",
+                paint(code(file.staged).to_string(), Color::Green),
+                paint(unstaged_code(file).to_string(), Color::Red),
+                path(&file.path)
+            )?,
+            Entry::Unmerged(file) => write!(
+                out,
+                "{} {}{eol}",
+                paint(file.code().to_string(), Color::Red),
+                path(&file.path)
+            )?,
+        }
+    }
+    for untracked in &status.untracked {
+        write!(
+            out,
+            "{} {}{eol}",
+            paint("??".to_string(), Color::Red),
+            path(untracked)
+        )?;
+    }
+
+    Ok(())
+}
+
+fn print_v2(
+    out: &mut impl Write,
+    status: &Status,
+    info: &BranchInfo,
+    show_branch: bool,
+    nul_terminated: bool,
+) -> Result<()> {
+    let eol = if nul_terminated { '\0' } else { '\n' };
+    let path = |path: &str| match nul_terminated {
+        true => path.to_string(),
+        false => quote_path(path, false),
+    };
+
+    if show_branch {
+        let oid = info.head.as_deref().unwrap_or("(initial)");
+        write!(out, "# branch.oid {oid}{eol}")?;
+        write!(
+            out,
+            "# branch.head {}{eol}",
+            info.branch.as_deref().unwrap_or("(detached)")
+        )?;
+        if let Some((upstream, counts)) = &info.upstream {
+            write!(out, "# branch.upstream {upstream}{eol}")?;
+            if let Some((ahead, behind)) = counts {
+                write!(out, "# branch.ab +{ahead} -{behind}{eol}")?;
+            }
+        }
+    }
+
+    let mode = |version: Option<&Version>| version.map_or(0, |(mode, _)| *mode);
+    let hash =
+        |version: Option<&Version>| version.map_or(NULL_HASH.to_string(), |(_, hash)| hash.clone());
+    // Submodules are marked with whether they have new commits, modified
+    // content and untracked files
+    let submodule = |file: &FileStatus| {
+        let is_gitlink = |version: Option<&Version>| mode(version) == MODE_GITLINK;
+        if !is_gitlink(file.head.as_ref()) && !is_gitlink(file.index.as_ref()) {
+            return "N...".to_string();
+        }
+        let changes = file.submodule.unwrap_or_default();
+        let flag = |set: bool, c: char| if set { c } else { '.' };
+        format!(
+            "S{}{}{}",
+            flag(changes.new_commits, 'C'),
+            flag(changes.modified, 'M'),
+            flag(changes.untracked, 'U')
+        )
+    };
+    // Unlike the short format, unmerged files come after the changed ones
+    let entries = status
+        .changes
+        .iter()
+        .map(Entry::Changed)
+        .chain(status.unmerged.iter().map(Entry::Unmerged));
+    for entry in entries {
+        match entry {
+            Entry::Changed(file) => write!(
+                out,
+                "1 {}{} {} {:06o} {:06o} {:06o} {} {} {}{eol}",
+                file.staged.code(),
+                file.unstaged.code(),
+                submodule(file),
+                mode(file.head.as_ref()),
+                mode(file.index.as_ref()),
+                file.worktree_mode.unwrap_or(0),
+                hash(file.head.as_ref()),
+                hash(file.index.as_ref()),
+                path(&file.path)
+            )?,
+            Entry::Unmerged(file) => {
+                let [base, ours, theirs] = file.stages.each_ref().map(Option::as_ref);
+                write!(
+                    out,
+                    "u {} N... {:06o} {:06o} {:06o} {:06o} {} {} {} {}{eol}",
+                    file.code(),
+                    mode(base),
+                    mode(ours),
+                    mode(theirs),
+                    file.worktree_mode.unwrap_or(0),
+                    hash(base),
+                    hash(ours),
+                    hash(theirs),
+                    path(&file.path)
+                )?
+            }
+        }
+    }
+    for untracked in &status.untracked {
+        write!(out, "? {}{eol}", path(untracked))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_path() {
+        assert_eq!(quote_path("plain/path", true), "plain/path");
+        assert_eq!(quote_path("sp ace", false), "sp ace");
+        assert_eq!(quote_path("sp ace", true), "\"sp ace\"");
+        assert_eq!(quote_path("tab\there", false), "\"tab\\there\"");
+        assert_eq!(quote_path("caf\u{e9}", false), "\"caf\\303\\251\"");
+    }
+}
@@ -0,0 +1,247 @@
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand};
+
+use crate::repository::{
+    submodule::{self, Submodule},
+    worktree::absolute,
+    Repository,
+};
+
+#[derive(Args)]
+pub struct SubmoduleArgs {
+    #[clap(subcommand)]
+    command: SubmoduleCommand,
+}
+
+#[derive(Subcommand)]
+enum SubmoduleCommand {
+    /// Copy the URLs of submodules from .gitmodules to the config
+    Init { paths: Vec<String> },
+    /// Clone missing submodules and check out the commits recorded for them
+    Update {
+        /// Initialize submodules that aren't yet
+        #[clap(long)]
+        init: bool,
+
+        paths: Vec<String>,
+    },
+    /// Show the commit checked out in each submodule
+    Status {
+        /// Show the commits recorded in the index instead
+        #[clap(long)]
+        cached: bool,
+
+        paths: Vec<String>,
+    },
+    /// Run a shell command in each checked out submodule
+    Foreach {
+        /// Don't print the name of each submodule
+        #[clap(short, long)]
+        quiet: bool,
+
+        command: String,
+    },
+    /// Update the URLs of submodules from .gitmodules
+    Sync { paths: Vec<String> },
+    /// Move the git directories of submodules into .git/modules
+    Absorbgitdirs { paths: Vec<String> },
+}
+
+/// Initialize, update or inspect submodules.
+pub fn run(args: SubmoduleArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+
+    match args.command {
+        SubmoduleCommand::Init { paths } => {
+            for submodule in select(&repo, &paths)? {
+                init(&repo, &submodule)?;
+            }
+        }
+        SubmoduleCommand::Update {
+            init: init_first,
+            paths,
+        } => {
+            let submodules = select(&repo, &paths)?;
+            if init_first {
+                for submodule in &submodules {
+                    init(&repo, submodule)?;
+                }
+            }
+            update(&repo, &submodules)?;
+        }
+        SubmoduleCommand::Status { cached, paths } => {
+            for submodule in select(&repo, &paths)? {
+                status(&repo, &submodule, cached)?;
+            }
+        }
+        SubmoduleCommand::Foreach { quiet, command } => foreach(&repo, quiet, &command)?,
+        SubmoduleCommand::Sync { paths } => {
+            for submodule in select(&repo, &paths)? {
+                if repo.submodule_url(&submodule)?.is_some() {
+                    println!("Synchronizing submodule url for '{}'", submodule.path);
+                    repo.sync_submodule(&submodule)?;
+                }
+            }
+        }
+        SubmoduleCommand::Absorbgitdirs { paths } => {
+            let paths = match paths.is_empty() {
+                true => repo.gitlinks()?,
+                false => paths,
+            };
+            for path in paths {
+                let path = path.trim_end_matches('/');
+                if !repo.get_root().join(path).join(".git").is_dir() {
+                    continue;
+                }
+                let name = repo
+                    .submodule_name(path)?
+                    .with_context(|| format!("could not lookup name for submodule '{path}'"))?;
+                if let Some((old, new)) = repo.absorb_submodule_git_dir(&name, path)? {
+                    println!(
+                        "Migrating git directory of '{path}' from\n'{}' to\n'{}'",
+                        old.display(),
+                        new.display()
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the submodules at the given paths, or all of them if there are
+/// none.
+fn select(repo: &Repository, paths: &[String]) -> Result<Vec<Submodule>> {
+    let submodules = repo.submodules()?;
+    if paths.is_empty() {
+        return Ok(submodules);
+    }
+
+    let mut selected = vec![];
+    for path in paths {
+        let path = path.trim_end_matches('/');
+        match submodules.iter().find(|submodule| submodule.path == path) {
+            Some(submodule) => selected.push(submodule.clone()),
+            None => bail!("pathspec '{path}' did not match any file(s) known to git"),
+        }
+    }
+    Ok(selected)
+}
+
+fn init(repo: &Repository, submodule: &Submodule) -> Result<()> {
+    if let Some(url) = repo.init_submodule(submodule)? {
+        println!(
+            "Submodule '{}' ({url}) registered for path '{}'",
+            submodule.name, submodule.path
+        );
+    }
+    Ok(())
+}
+
+/// Clones the initialized submodules that are missing, then checks out the
+/// recorded commits.
+fn update(repo: &Repository, submodules: &[Submodule]) -> Result<()> {
+    let mut initialized = vec![];
+    for submodule in submodules {
+        let Some(url) = repo.submodule_url(submodule)? else {
+            continue;
+        };
+        if repo.open_submodule(&submodule.path)?.is_none() {
+            let path = absolute(&repo.get_root().join(&submodule.path))?;
+            eprintln!("Cloning into '{}'...", path.display());
+            repo.clone_submodule(submodule, &url).with_context(|| {
+                format!(
+                    "clone of '{url}' into submodule path '{}' failed",
+                    path.display()
+                )
+            })?;
+            eprintln!("done.");
+        }
+        initialized.push(submodule);
+    }
+
+    for submodule in initialized {
+        if submodule.unmerged {
+            println!("Skipping unmerged submodule {}", submodule.path);
+            continue;
+        }
+        let checked_out = repo.checkout_submodule(submodule).with_context(|| {
+            format!(
+                "Unable to checkout '{}' in submodule path '{}'",
+                submodule.hash, submodule.path
+            )
+        })?;
+        if checked_out {
+            println!(
+                "Submodule path '{}': checked out '{}'",
+                submodule.path, submodule.hash
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the status line of a submodule: its commit prefixed with `-` if
+/// it isn't checked out, `+` if it's at another commit than the recorded
+/// one and `U` if it has conflicts, followed by its path and a description
+/// of the commit.
+fn status(repo: &Repository, submodule: &Submodule, cached: bool) -> Result<()> {
+    let path = &submodule.path;
+    if submodule.unmerged {
+        println!("U{} {path}", "0".repeat(40));
+        return Ok(());
+    }
+    let Some(sub_repo) = repo.open_submodule(path)? else {
+        println!("-{} {path}", submodule.hash);
+        return Ok(());
+    };
+
+    let head = sub_repo.read_ref("HEAD")?;
+    let (flag, hash) = match head {
+        Some(head) if head != submodule.hash && cached => ('+', submodule.hash.clone()),
+        Some(head) if head != submodule.hash => ('+', head),
+        _ => (' ', submodule.hash.clone()),
+    };
+    match submodule::describe(&sub_repo, &hash) {
+        Ok(name) => println!("{flag}{hash} {path} ({name})"),
+        Err(_) => println!("{flag}{hash} {path}"),
+    }
+    Ok(())
+}
+
+/// Runs `command` with `sh` in each checked out submodule, stopping at the
+/// first that fails.
+fn foreach(repo: &Repository, quiet: bool, command: &str) -> Result<()> {
+    let toplevel = absolute(repo.get_root())?;
+    for submodule in repo.submodules()? {
+        let dir = toplevel.join(&submodule.path);
+        if repo.open_submodule(&submodule.path)?.is_none() {
+            continue;
+        }
+        if !quiet {
+            println!("Entering '{}'", submodule.path);
+        }
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .current_dir(&dir)
+            .env("name", &submodule.name)
+            .env("sm_path", &submodule.path)
+            .env("displaypath", &submodule.path)
+            .env("sha1", &submodule.hash)
+            .env("toplevel", &toplevel)
+            .status()
+            .with_context(|| format!("could not run '{command}'"))?;
+        if !status.success() {
+            bail!(
+                "run_command returned non-zero status for {}\n.",
+                submodule.path
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::repository::Repository;
+
+use super::checkout::{switch, Switch};
+
+#[derive(Args)]
+pub struct SwitchArgs {
+    /// Create a new branch and switch to it
+    #[clap(short = 'c', long = "create", value_name = "new-branch")]
+    create: Option<String>,
+
+    /// Create or reset a branch and switch to it
+    #[clap(
+        short = 'C',
+        long = "force-create",
+        value_name = "new-branch",
+        conflicts_with = "create"
+    )]
+    force_create: Option<String>,
+
+    /// Switch to a commit for inspection, detaching HEAD
+    #[clap(short, long)]
+    detach: bool,
+
+    /// Throw away local changes
+    #[clap(short, long, alias = "discard-changes")]
+    force: bool,
+
+    /// Merge local changes into the branch being switched to
+    #[clap(short, long, conflicts_with = "force")]
+    merge: bool,
+
+    /// The branch to switch to, or the start point of a new branch
+    branch: Option<String>,
+}
+
+/// Switch branches.
+pub fn run(args: SwitchArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let new_branch = args.create.as_deref().or(args.force_create.as_deref());
+
+    match (&args.branch, new_branch) {
+        (None, None) => bail!("missing branch or commit argument"),
+        // Unlike checkout, switch doesn't detach HEAD unless asked to
+        (Some(branch), None) if !args.detach && !repo.branch_exists(branch)? => {
+            match repo.rev_parse(&format!("{branch}^{{commit}}")) {
+                Ok(_) => bail!("a branch is expected, got commit '{branch}'"),
+                Err(_) => bail!("invalid reference: {branch}"),
+            }
+        }
+        _ => {}
+    }
+
+    switch(
+        &repo,
+        Switch {
+            new_branch,
+            reset: args.force_create.is_some(),
+            target: args.branch.as_deref(),
+            detach: args.detach,
+            force: args.force,
+            merge: args.merge,
+        },
+    )
+}
@@ -0,0 +1,79 @@
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::{
+    ref_filter,
+    repository::{refname, Repository},
+};
+
+#[derive(Args)]
+pub struct SymbolicRefArgs {
+    /// Delete the symbolic ref
+    #[clap(short, long)]
+    delete: bool,
+
+    /// Don't print an error if <name> is not a symbolic ref
+    #[clap(short, long)]
+    quiet: bool,
+
+    /// Shorten the ref output, e.g. `main` for `refs/heads/main`
+    #[clap(long)]
+    short: bool,
+
+    /// Reason of the update, recorded in the reflog
+    #[clap(short, value_name = "reason")]
+    message: Option<String>,
+
+    /// The symbolic ref, e.g. HEAD
+    name: String,
+
+    /// The ref to point <name> at
+    target: Option<String>,
+}
+
+/// Read, modify and delete symbolic refs.
+pub fn run(args: SymbolicRefArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let name = args.name.as_str();
+
+    if args.delete {
+        if args.target.is_some() {
+            bail!("-d takes a single <name>");
+        }
+        if name == "HEAD" {
+            bail!("deleting '{name}' is not allowed");
+        }
+        if repo.read_symbolic_ref(name)?.is_none() {
+            if args.quiet {
+                std::process::exit(1);
+            }
+            bail!("Cannot delete {name}, not a symbolic ref");
+        }
+        repo.delete_ref(name)?;
+        return Ok(());
+    }
+
+    match &args.target {
+        Some(target) => {
+            if name == "HEAD" && !target.starts_with("refs/") {
+                bail!("Refusing to point {name} outside of refs/");
+            }
+            let opts = refname::RefFormatOptions {
+                allow_onelevel: true,
+                ..Default::default()
+            };
+            if refname::check_ref_format(target, opts).is_none() {
+                bail!("Refusing to set '{name}' to invalid ref '{target}'");
+            }
+            repo.set_symbolic_ref(name, target, args.message.as_deref().unwrap_or(""))?;
+        }
+        None => match repo.read_symbolic_ref(name)? {
+            Some(target) if args.short => println!("{}", ref_filter::shorten_refname(&target)),
+            Some(target) => println!("{target}"),
+            None if args.quiet => std::process::exit(1),
+            None => bail!("ref {name} is not a symbolic ref"),
+        },
+    }
+
+    Ok(())
+}
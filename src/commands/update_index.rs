@@ -0,0 +1,40 @@
+use anyhow::Result;
+use clap::Args;
+
+use crate::repository::{index::Index, untracked_cache::UntrackedCache, Repository};
+
+#[derive(Args)]
+pub struct UpdateIndexArgs {
+    /// Cache the untracked files of every directory in the index
+    #[clap(long, conflicts_with = "no_untracked_cache")]
+    untracked_cache: bool,
+
+    /// Remove the untracked cache from the index
+    #[clap(long)]
+    no_untracked_cache: bool,
+}
+
+/// Register file contents in the working tree to the index.
+pub fn run(args: UpdateIndexArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let config = repo.config()?;
+    let mut index = Index::lock(&repo)?;
+
+    if args.untracked_cache {
+        if config.get_bool("core.untrackedCache") == Some(false) {
+            eprintln!("warning: core.untrackedCache is set to false; remove or change it, if you really want to enable the untracked cache");
+        }
+        if index.untracked_cache().is_none() {
+            let cache = UntrackedCache::new(&repo)?;
+            index.set_untracked_cache(Some(cache));
+        }
+    } else if args.no_untracked_cache {
+        if config.get_bool("core.untrackedCache") == Some(true) {
+            eprintln!("warning: core.untrackedCache is set to true; remove or change it, if you really want to disable the untracked cache");
+        }
+        index.set_untracked_cache(None);
+    }
+    index.commit()?;
+
+    Ok(())
+}
@@ -0,0 +1,248 @@
+use std::io::{self, Read};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+
+use crate::repository::{
+    refs::NULL_HASH,
+    transaction::{RefChange, RefTransaction, RefUpdate},
+    Repository,
+};
+
+#[derive(Args)]
+pub struct UpdateRefArgs {
+    /// Reason of the update, recorded in the reflog
+    #[clap(short, value_name = "reason")]
+    message: Option<String>,
+
+    /// Delete the ref, after verifying it still has <oldvalue>
+    #[clap(short)]
+    delete: bool,
+
+    /// Update the symbolic ref itself instead of the ref it points to
+    #[clap(long)]
+    no_deref: bool,
+
+    /// Read instructions from standard input and apply them atomically
+    #[clap(long)]
+    stdin: bool,
+
+    /// Instructions on standard input are NUL-terminated
+    #[clap(short = 'z', requires = "stdin")]
+    nul_terminated: bool,
+
+    /// <ref> <newvalue> [<oldvalue>], or <ref> [<oldvalue>] with -d
+    args: Vec<String>,
+}
+
+/// Update the object name stored in a ref safely.
+pub fn run(args: UpdateRefArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+    let message = args.message.clone().unwrap_or_default();
+
+    if args.stdin {
+        if !args.args.is_empty() || args.delete {
+            bail!("--stdin takes no other arguments");
+        }
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        return run_stdin(&repo, &input, args.nul_terminated, &message, args.no_deref);
+    }
+
+    let (refname, change, old) = match (args.delete, args.args.as_slice()) {
+        (true, [refname]) => (refname, RefChange::Delete, None),
+        (true, [refname, old]) => (refname, RefChange::Delete, Some(old)),
+        (false, [refname, new]) => (refname, RefChange::Set(resolve(&repo, new)?), None),
+        (false, [refname, new, old]) => (refname, RefChange::Set(resolve(&repo, new)?), Some(old)),
+        _ => bail!(
+            "usage: minigit update-ref [-m <reason>] (-d <ref> [<old>] | <ref> <new> [<old>])"
+        ),
+    };
+    let old = old.map(|old| resolve_old(&repo, old)).transpose()?;
+
+    let mut transaction = RefTransaction::new(&repo);
+    transaction.update(RefUpdate {
+        refname: refname.clone(),
+        change,
+        old,
+        no_deref: args.no_deref,
+        message,
+    })?;
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Resolves a new value to a full hash.
+fn resolve(repo: &Repository, value: &str) -> Result<String> {
+    repo.rev_parse(value)
+        .with_context(|| format!("{value}: not a valid SHA1"))
+}
+
+/// Resolves an expected old value, where an empty value or the null hash
+/// mean that the ref must not exist.
+fn resolve_old(repo: &Repository, value: &str) -> Result<String> {
+    match value {
+        "" => Ok(NULL_HASH.to_string()),
+        NULL_HASH => Ok(value.to_string()),
+        value => resolve(repo, value),
+    }
+}
+
+/// A command of the `--stdin` language.
+struct Instruction {
+    verb: String,
+    args: Vec<String>,
+}
+
+/// Splits the input into instructions. In text mode each line holds a verb
+/// and its space separated arguments, with `-z` the verb and the ref are
+/// followed by NUL and each further argument is NUL-terminated.
+fn parse_instructions(input: &str, nul_terminated: bool) -> Result<Vec<Instruction>> {
+    let mut instructions = vec![];
+
+    if !nul_terminated {
+        for line in input.lines().filter(|line| !line.is_empty()) {
+            let mut words = line.split(' ').map(str::to_string);
+            let verb = words.next().unwrap_or_default();
+            instructions.push(Instruction {
+                verb,
+                args: words.collect(),
+            });
+        }
+        return Ok(instructions);
+    }
+
+    let mut fields = input.split('\0').peekable();
+    while let Some(field) = fields.next() {
+        if field.is_empty() && fields.peek().is_none() {
+            break;
+        }
+        let (verb, first) = match field.split_once(' ') {
+            Some((verb, first)) => (verb.to_string(), Some(first.to_string())),
+            None => (field.to_string(), None),
+        };
+        let extra = match verb.as_str() {
+            "update" => 2,
+            "create" | "delete" | "verify" => 1,
+            _ => 0,
+        };
+        let mut args: Vec<String> = first.into_iter().collect();
+        for _ in 0..extra {
+            let arg = fields
+                .next()
+                .with_context(|| format!("{verb}: missing argument"))?;
+            args.push(arg.to_string());
+        }
+        instructions.push(Instruction { verb, args });
+    }
+
+    Ok(instructions)
+}
+
+fn run_stdin(
+    repo: &Repository,
+    input: &str,
+    nul_terminated: bool,
+    message: &str,
+    no_deref: bool,
+) -> Result<()> {
+    let mut transaction = RefTransaction::new(repo);
+    // Whether an explicit `start` was given, which requires an explicit `commit`
+    let mut explicit = false;
+    let mut pending = false;
+    let mut next_no_deref = false;
+
+    for Instruction { verb, args } in parse_instructions(input, nul_terminated)? {
+        // An empty old value in -z mode means "don't check"
+        let old = |i: usize| -> Result<Option<String>> {
+            match args.get(i).map(String::as_str) {
+                None => Ok(None),
+                Some("") if nul_terminated => Ok(None),
+                Some(value) => resolve_old(repo, value).map(Some),
+            }
+        };
+        let refname = || -> Result<String> {
+            args.first()
+                .filter(|refname| !refname.is_empty())
+                .cloned()
+                .with_context(|| format!("{verb}: missing <ref>"))
+        };
+        let new = |i: usize| -> Result<String> {
+            let value = args
+                .get(i)
+                .with_context(|| format!("{verb} {}: missing <newvalue>", args[0]))?;
+            match value.as_str() {
+                "" | NULL_HASH => Ok(NULL_HASH.to_string()),
+                value => resolve(repo, value),
+            }
+        };
+
+        let (change, old) = match verb.as_str() {
+            "update" => match new(1)?.as_str() {
+                NULL_HASH => (RefChange::Delete, old(2)?),
+                hash => (RefChange::Set(hash.to_string()), old(2)?),
+            },
+            "create" => (RefChange::Set(new(1)?), Some(NULL_HASH.to_string())),
+            "delete" => (RefChange::Delete, old(1)?),
+            "verify" => (
+                RefChange::Verify,
+                Some(old(1)?.unwrap_or_else(|| NULL_HASH.to_string())),
+            ),
+            "option" => {
+                match args.first().map(String::as_str) {
+                    Some("no-deref") => next_no_deref = true,
+                    option => bail!("option unknown: {}", option.unwrap_or_default()),
+                }
+                continue;
+            }
+            "start" => {
+                if pending {
+                    bail!("start: transaction already in progress");
+                }
+                explicit = true;
+                println!("start: ok");
+                continue;
+            }
+            "prepare" => {
+                transaction.prepare()?;
+                println!("prepare: ok");
+                continue;
+            }
+            "commit" => {
+                transaction.commit()?;
+                transaction = RefTransaction::new(repo);
+                (explicit, pending) = (false, false);
+                println!("commit: ok");
+                continue;
+            }
+            "abort" => {
+                transaction.abort()?;
+                transaction = RefTransaction::new(repo);
+                (explicit, pending) = (false, false);
+                println!("abort: ok");
+                continue;
+            }
+            verb => bail!("unknown command: {verb}"),
+        };
+
+        transaction.update(RefUpdate {
+            refname: refname()?,
+            change,
+            old,
+            no_deref: no_deref || next_no_deref,
+            message: message.to_string(),
+        })?;
+        next_no_deref = false;
+        pending = true;
+    }
+
+    if explicit {
+        // An explicit transaction that wasn't committed is rolled back
+        transaction.abort()?;
+    } else {
+        transaction.commit()?;
+    }
+
+    Ok(())
+}
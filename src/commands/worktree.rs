@@ -0,0 +1,319 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand};
+
+use crate::{
+    object::Commit,
+    repository::{
+        worktree::{Worktree, WorktreeHead},
+        Repository,
+    },
+};
+
+#[derive(Args)]
+pub struct WorktreeArgs {
+    #[clap(subcommand)]
+    command: WorktreeCommand,
+}
+
+#[derive(Subcommand)]
+enum WorktreeCommand {
+    /// Create a worktree at <path> and check out <commit-ish> in it
+    Add(AddArgs),
+    /// List the main worktree followed by the linked worktrees
+    List {
+        /// Output in an easy-to-parse format for scripts
+        #[clap(long)]
+        porcelain: bool,
+
+        /// Show the reasons worktrees are locked or prunable
+        #[clap(short, long)]
+        verbose: bool,
+    },
+    /// Remove a worktree
+    Remove {
+        /// Remove it even if it's dirty, give twice if it's locked
+        #[clap(short, long, action = clap::ArgAction::Count)]
+        force: u8,
+
+        worktree: String,
+    },
+    /// Prune the information of worktrees that are gone
+    Prune {
+        /// Don't remove anything, only report what would be removed
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+
+        /// Report all removals
+        #[clap(short, long)]
+        verbose: bool,
+    },
+    /// Keep a worktree from being pruned, moved or removed
+    Lock {
+        /// An explanation why the worktree is locked
+        #[clap(long)]
+        reason: Option<String>,
+
+        worktree: String,
+    },
+    /// Unlock a worktree, allowing it to be pruned, moved or removed
+    Unlock { worktree: String },
+}
+
+#[derive(Args)]
+struct AddArgs {
+    /// Check out <commit-ish> even if it's checked out in another worktree
+    #[clap(short, long)]
+    force: bool,
+
+    /// Create a new branch at <commit-ish> and check it out
+    #[clap(short = 'b', value_name = "new-branch")]
+    new_branch: Option<String>,
+
+    /// Create or reset a branch at <commit-ish> and check it out
+    #[clap(short = 'B', value_name = "new-branch", conflicts_with = "new_branch")]
+    force_new_branch: Option<String>,
+
+    /// Detach HEAD in the new worktree
+    #[clap(short, long, conflicts_with_all = ["new_branch", "force_new_branch"])]
+    detach: bool,
+
+    /// Keep the new worktree locked
+    #[clap(long)]
+    lock: bool,
+
+    /// An explanation why the worktree is locked
+    #[clap(long, requires = "lock")]
+    reason: Option<String>,
+
+    /// Suppress feedback messages
+    #[clap(short, long)]
+    quiet: bool,
+
+    path: PathBuf,
+
+    #[clap(value_name = "commit-ish")]
+    commit_ish: Option<String>,
+}
+
+/// Manage multiple working trees.
+pub fn run(args: WorktreeArgs) -> Result<()> {
+    let repo = Repository::from_path(".")?;
+
+    match args.command {
+        WorktreeCommand::Add(args) => add(&repo, args)?,
+        WorktreeCommand::List { porcelain, verbose } => list(&repo, porcelain, verbose)?,
+        WorktreeCommand::Remove { force, worktree } => {
+            let arg = worktree;
+            let worktree = find_linked(&repo, &arg)?;
+            match &worktree.locked {
+                Some(reason) if force < 2 && reason.is_empty() => bail!(
+                    "cannot remove a locked working tree;\nuse 'remove -f -f' to override or unlock first"
+                ),
+                Some(reason) if force < 2 => bail!(
+                    "cannot remove a locked working tree, lock reason: {reason}\nuse 'remove -f -f' to override or unlock first"
+                ),
+                _ => {}
+            }
+            if force == 0 && worktree.path.exists() && repo.worktree_is_dirty(&worktree)? {
+                bail!("'{arg}' contains modified or untracked files, use --force to delete it");
+            }
+            repo.remove_worktree(&worktree)?;
+        }
+        WorktreeCommand::Prune { dry_run, verbose } => {
+            for (id, reason) in repo.prune_worktrees(dry_run)? {
+                if dry_run || verbose {
+                    println!("Removing worktrees/{id}: {reason}");
+                }
+            }
+        }
+        WorktreeCommand::Lock { reason, worktree } => {
+            let arg = worktree;
+            let worktree = find_lockable(&repo, &arg)?;
+            match &worktree.locked {
+                Some(reason) if reason.is_empty() => bail!("'{arg}' is already locked"),
+                Some(reason) => bail!("'{arg}' is already locked, reason: {reason}"),
+                None => repo.lock_worktree(&worktree, reason.as_deref().unwrap_or(""))?,
+            }
+        }
+        WorktreeCommand::Unlock { worktree } => {
+            let arg = worktree;
+            let worktree = find_lockable(&repo, &arg)?;
+            if worktree.locked.is_none() {
+                bail!("'{arg}' is not locked");
+            }
+            repo.unlock_worktree(&worktree)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the linked worktree given on the command line.
+fn find_linked(repo: &Repository, arg: &str) -> Result<Worktree> {
+    let worktree = repo
+        .find_worktree(arg)?
+        .with_context(|| format!("'{arg}' is not a working tree"))?;
+    if worktree.is_main() {
+        bail!("'{arg}' is a main working tree");
+    }
+    Ok(worktree)
+}
+
+/// Returns the linked worktree to lock or unlock.
+fn find_lockable(repo: &Repository, arg: &str) -> Result<Worktree> {
+    let worktree = repo
+        .find_worktree(arg)?
+        .with_context(|| format!("'{arg}' is not a working tree"))?;
+    if worktree.is_main() {
+        bail!("The main working tree cannot be locked or unlocked");
+    }
+    Ok(worktree)
+}
+
+fn add(repo: &Repository, args: AddArgs) -> Result<()> {
+    let path = &args.path;
+    let start = args.commit_ish.as_deref().unwrap_or("HEAD");
+    let resolve = |rev: &str| match repo.rev_parse(&format!("{rev}^{{commit}}")) {
+        Ok(hash) => Ok(hash),
+        Err(_) => bail!("invalid reference: {rev}"),
+    };
+
+    // Without a branch or commit, check out the branch named after the
+    // worktree, creating it if needed
+    let basename = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let new_branch = match (&args.new_branch, &args.force_new_branch) {
+        (Some(branch), _) | (_, Some(branch)) => Some(branch.as_str()),
+        _ if args.commit_ish.is_none() && !args.detach && !repo.branch_exists(&basename)? => {
+            Some(basename.as_str())
+        }
+        _ => None,
+    };
+    let checkout = args.commit_ish.as_deref().unwrap_or(&basename);
+    // The branch to create or reset, at the given commit
+    let (head, create, preparing) = match new_branch {
+        Some(branch) => {
+            let refname = format!("refs/heads/{branch}");
+            let preparing = match repo.read_ref(&refname)? {
+                Some(old) if args.force_new_branch.is_some() => {
+                    format!("resetting branch '{branch}'; was at {}", &old[..7])
+                }
+                _ => format!("new branch '{branch}'"),
+            };
+            let hash = resolve(start)?;
+            (WorktreeHead::Branch(refname), Some(hash), preparing)
+        }
+        None if !args.detach && repo.branch_exists(checkout)? => {
+            let refname = format!("refs/heads/{checkout}");
+            let preparing = format!("checking out '{checkout}'");
+            (WorktreeHead::Branch(refname), None, preparing)
+        }
+        None => {
+            let hash = resolve(start)?;
+            let preparing = format!("detached HEAD {}", &hash[..7]);
+            (WorktreeHead::Detached(hash), None, preparing)
+        }
+    };
+
+    if !args.quiet {
+        println!("Preparing worktree ({preparing})");
+    }
+    if path.exists() && path.read_dir().map_or(true, |mut dir| dir.next().is_some()) {
+        bail!("'{}' already exists", path.display());
+    }
+    if let WorktreeHead::Branch(refname) = &head {
+        let branch = refname.trim_start_matches("refs/heads/");
+        let exists = repo.read_ref(refname)?.is_some();
+        if exists && args.new_branch.is_some() {
+            bail!("a branch named '{branch}' already exists");
+        }
+        if let Some(path) = repo.worktree_using_branch(refname)? {
+            if !args.force {
+                bail!(
+                    "'{branch}' is already used by worktree at '{}'",
+                    path.display()
+                );
+            }
+        }
+        if let Some(hash) = &create {
+            repo.create_branch_at(branch, hash, true)?;
+        }
+    }
+    let lock = args.lock.then(|| args.reason.as_deref().unwrap_or(""));
+    repo.add_worktree(path, &head, lock)?;
+
+    if !args.quiet {
+        let hash = match &head {
+            WorktreeHead::Branch(refname) => repo.read_ref(refname)?.unwrap_or_default(),
+            WorktreeHead::Detached(hash) => hash.clone(),
+        };
+        let subject = Commit::read(&hash, repo)?.subject();
+        println!("HEAD is now at {} {subject}", &hash[..7]);
+    }
+
+    Ok(())
+}
+
+fn list(repo: &Repository, porcelain: bool, verbose: bool) -> Result<()> {
+    let worktrees = repo.worktrees()?;
+
+    if porcelain {
+        for worktree in &worktrees {
+            println!("worktree {}", worktree.path.display());
+            match &worktree.head {
+                Some(head) => println!("HEAD {head}"),
+                None => println!("HEAD {}", "0".repeat(40)),
+            }
+            match &worktree.branch {
+                Some(branch) => println!("branch {branch}"),
+                None => println!("detached"),
+            }
+            match worktree.locked.as_deref() {
+                Some("") => println!("locked"),
+                Some(reason) => println!("locked {reason}"),
+                None => {}
+            }
+            if let Some(reason) = &worktree.prunable {
+                println!("prunable {reason}");
+            }
+            println!();
+        }
+        return Ok(());
+    }
+
+    let width = worktrees
+        .iter()
+        .map(|worktree| worktree.path.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        + 1;
+    for worktree in &worktrees {
+        let head = worktree.head.as_deref().unwrap_or("0000000");
+        let mut line = format!("{:<width$} {}", worktree.path.display(), &head[..7]);
+        match &worktree.branch {
+            Some(branch) => {
+                line += &format!(" [{}]", branch.trim_start_matches("refs/heads/"));
+            }
+            None => line += " (detached HEAD)",
+        }
+
+        match (&worktree.locked, verbose) {
+            (Some(reason), true) if !reason.is_empty() => line += &format!("\n\tlocked: {reason}"),
+            (Some(_), true) => line += "\n\tlocked",
+            (Some(_), false) => line += " locked",
+            (None, _) => {}
+        }
+        match (&worktree.prunable, verbose) {
+            (Some(reason), true) => line += &format!("\n\tprunable: {reason}"),
+            (Some(_), false) => line += " prunable",
+            (None, _) => {}
+        }
+        println!("{line}");
+    }
+
+    Ok(())
+}
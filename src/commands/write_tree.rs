@@ -1,11 +1,14 @@
 use anyhow::Result;
 
-use crate::{object, repository::Repository};
+use crate::repository::{index::Index, Repository};
 
 /// Create a tree object from the current index.
 pub fn run() -> Result<()> {
     let repo = Repository::from_path(".")?;
-    let hash = object::write_tree(".", &repo)?;
+    let mut index = Index::lock(&repo)?;
+    let hash = index.write_tree(&repo)?;
+    // Keep the trees written in the cache tree
+    index.commit()?;
 
     println!("{}", hash);
 
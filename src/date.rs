@@ -0,0 +1,454 @@
+//! Formatting of commit timestamps, following git's `--date` formats, and
+//! parsing of the dates given to options like `--since`.
+
+use std::time::SystemTime;
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const WEEKDAYS_LONG: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const MONTHS_LONG: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DateMode {
+    /// `Thu Jul 11 15:07:21 2024 +0200`
+    Default,
+    /// `2024-07-11 15:07:21 +0200`
+    Iso,
+    /// `2024-07-11T15:07:21+02:00`
+    IsoStrict,
+    /// `Thu, 11 Jul 2024 15:07:21 +0200`
+    Rfc,
+    /// `2024-07-11`
+    Short,
+    /// `1720703241`
+    Unix,
+    /// `1720703241 +0200`
+    Raw,
+    /// `2 weeks ago`
+    Relative,
+    /// A strftime-style format string
+    Format(String),
+}
+
+impl DateMode {
+    /// Parses a `--date` argument or a `:<format>` atom modifier.
+    pub fn parse(s: &str) -> Option<DateMode> {
+        let mode = match s {
+            "default" | "local" => DateMode::Default,
+            "iso" | "iso8601" => DateMode::Iso,
+            "iso-strict" | "iso8601-strict" => DateMode::IsoStrict,
+            "rfc" | "rfc2822" => DateMode::Rfc,
+            "short" => DateMode::Short,
+            "unix" => DateMode::Unix,
+            "raw" => DateMode::Raw,
+            "relative" => DateMode::Relative,
+            s => DateMode::Format(s.strip_prefix("format:")?.to_string()),
+        };
+        Some(mode)
+    }
+}
+
+/// Date and time components of a timestamp in some timezone.
+struct Tm {
+    year: i64,
+    month: usize,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+    weekday: usize,
+    yearday: i64,
+}
+
+impl Tm {
+    fn new(time: i64, offset: i64) -> Tm {
+        let local = time + offset;
+        let days = local.div_euclid(86400);
+        let secs = local.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+
+        Tm {
+            year,
+            month: month as usize - 1,
+            day,
+            hour: secs / 3600,
+            minute: secs % 3600 / 60,
+            second: secs % 60,
+            // 1970-01-01 was a Thursday
+            weekday: (days + 4).rem_euclid(7) as usize,
+            yearday: days - days_from_civil(year, 1, 1) + 1,
+        }
+    }
+}
+
+/// Converts days since the unix epoch to a (year, month, day) date.
+/// See <https://howardhinnant.github.io/date_algorithms.html>.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Converts a (year, month, day) date to days since the unix epoch.
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Parses a timezone like `+0200` into an offset in seconds.
+pub fn tz_offset(tz: &str) -> i64 {
+    let sign = if tz.starts_with('-') { -1 } else { 1 };
+    let digits = tz.trim_start_matches(['+', '-']);
+    let hours: i64 = digits.get(..2).and_then(|h| h.parse().ok()).unwrap_or(0);
+    let minutes: i64 = digits.get(2..4).and_then(|m| m.parse().ok()).unwrap_or(0);
+    sign * (hours * 3600 + minutes * 60)
+}
+
+/// Returns the current time in seconds since the unix epoch.
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Formats a timestamp with the given timezone (e.g. `+0200`) in the given mode.
+pub fn format_date(time: i64, tz: &str, mode: &DateMode) -> String {
+    let tm = Tm::new(time, tz_offset(tz));
+    match mode {
+        DateMode::Default => format!(
+            "{} {} {} {:02}:{:02}:{:02} {} {}",
+            WEEKDAYS[tm.weekday],
+            MONTHS[tm.month],
+            tm.day,
+            tm.hour,
+            tm.minute,
+            tm.second,
+            tm.year,
+            tz
+        ),
+        DateMode::Iso => format!(
+            "{}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+            tm.year,
+            tm.month + 1,
+            tm.day,
+            tm.hour,
+            tm.minute,
+            tm.second,
+            tz
+        ),
+        DateMode::IsoStrict => {
+            let tz = if tz_offset(tz) == 0 {
+                "Z".to_string()
+            } else {
+                format!("{}:{}", &tz[..3], &tz[3..])
+            };
+            format!(
+                "{}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+                tm.year,
+                tm.month + 1,
+                tm.day,
+                tm.hour,
+                tm.minute,
+                tm.second,
+                tz
+            )
+        }
+        DateMode::Rfc => format!(
+            "{}, {} {} {} {:02}:{:02}:{:02} {}",
+            WEEKDAYS[tm.weekday],
+            tm.day,
+            MONTHS[tm.month],
+            tm.year,
+            tm.hour,
+            tm.minute,
+            tm.second,
+            tz
+        ),
+        DateMode::Short => format!("{}-{:02}-{:02}", tm.year, tm.month + 1, tm.day),
+        DateMode::Unix => time.to_string(),
+        DateMode::Raw => format!("{time} {tz}"),
+        DateMode::Relative => format_relative(time, now()),
+        DateMode::Format(format) => strftime(format, &tm, tz),
+    }
+}
+
+/// Formats the time elapsed between `time` and `now` like `3 hours ago`.
+pub fn format_relative(time: i64, now: i64) -> String {
+    let diff = now - time;
+    if diff < 0 {
+        return "in the future".to_string();
+    }
+    let plural = |n: i64, unit: &str| {
+        if n == 1 {
+            format!("{n} {unit} ago")
+        } else {
+            format!("{n} {unit}s ago")
+        }
+    };
+
+    if diff < 90 {
+        return plural(diff, "second");
+    }
+    let minutes = (diff + 30) / 60;
+    if minutes < 90 {
+        return plural(minutes, "minute");
+    }
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return plural(hours, "hour");
+    }
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return plural(days, "day");
+    }
+    if days < 70 {
+        return plural((days + 3) / 7, "week");
+    }
+    if days < 365 {
+        return plural((days + 15) / 30, "month");
+    }
+    if days < 1825 {
+        // Say "1 year, 2 months ago" for recent years
+        let total_months = (days * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        let years = if years == 1 {
+            "1 year".to_string()
+        } else {
+            format!("{years} years")
+        };
+        return match months {
+            0 => format!("{years} ago"),
+            1 => format!("{years}, 1 month ago"),
+            months => format!("{years}, {months} months ago"),
+        };
+    }
+    plural((days + 183) / 365, "year")
+}
+
+/// Parses a date the way git's approxidate does, for the subset of forms
+/// that are common on the command line: `now`, `yesterday`, `@<timestamp>`,
+/// `2 weeks ago` (or `2.weeks.ago`) and `2024-07-11` with an optional
+/// `15:07[:21]` time and timezone. A date without a time keeps the current
+/// time of day, like git. Dates without a timezone are taken as UTC.
+pub fn parse_approxidate(s: &str, now: i64) -> Option<i64> {
+    let s = s.trim().to_lowercase();
+    if let Some(time) = s.strip_prefix('@') {
+        return time.parse().ok();
+    }
+    if s.len() >= 9 && s.bytes().all(|b| b.is_ascii_digit()) {
+        return s.parse().ok();
+    }
+    match s.as_str() {
+        "now" | "today" => return Some(now),
+        "yesterday" => return Some(now - 86400),
+        _ => {}
+    }
+
+    let words: Vec<&str> = s.split([' ', '.', '_']).filter(|w| !w.is_empty()).collect();
+    if let [n, unit, rest @ ..] = words.as_slice() {
+        if let (Ok(n), [] | ["ago"]) = (n.parse::<i64>(), rest) {
+            let unit = unit.strip_suffix('s').unwrap_or(unit);
+            let seconds = match unit {
+                "second" | "sec" => 1,
+                "minute" | "min" => 60,
+                "hour" => 3600,
+                "day" => 86400,
+                "week" => 7 * 86400,
+                "month" | "year" => {
+                    let months = if unit == "year" { 12 * n } else { n };
+                    let tm = Tm::new(now, 0);
+                    let month = tm.year * 12 + tm.month as i64 - months;
+                    let days =
+                        days_from_civil(month.div_euclid(12), month.rem_euclid(12) + 1, tm.day);
+                    return Some(days * 86400 + now.rem_euclid(86400));
+                }
+                _ => return None,
+            };
+            return Some(now - n * seconds);
+        }
+    }
+
+    // <date>[ T]<time>[ ]<timezone>
+    let (date, rest) = s.split_at(s.find([' ', 't']).unwrap_or(s.len()));
+    let mut date = date.split(['-', '/']).map(|n| n.parse::<i64>().ok());
+    let (Some(Some(year)), Some(Some(month)), Some(Some(day)), None) =
+        (date.next(), date.next(), date.next(), date.next())
+    else {
+        return None;
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut time = now.rem_euclid(86400);
+    let mut offset = 0;
+    let rest = rest.trim_start_matches([' ', 't']);
+    let (clock, tz) = rest.split_at(rest.find([' ', '+', '-', 'z']).unwrap_or(rest.len()));
+    if !clock.is_empty() {
+        let mut fields = clock.split(':').map(|n| n.parse::<i64>().ok());
+        let hour = fields.next()??;
+        let minute = fields.next()??;
+        let second = fields.next().unwrap_or(Some(0))?;
+        time = hour * 3600 + minute * 60 + second;
+    }
+    match tz.trim() {
+        "" | "z" => {}
+        tz if tz.len() == 5 && tz[1..].bytes().all(|b| b.is_ascii_digit()) => {
+            offset = tz_offset(tz)
+        }
+        _ => return None,
+    }
+
+    Some(days_from_civil(year, month, day) * 86400 + time - offset)
+}
+
+fn strftime(format: &str, tm: &Tm, tz: &str) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&tm.year.to_string()),
+            Some('y') => out.push_str(&format!("{:02}", tm.year % 100)),
+            Some('m') => out.push_str(&format!("{:02}", tm.month + 1)),
+            Some('d') => out.push_str(&format!("{:02}", tm.day)),
+            Some('e') => out.push_str(&format!("{:2}", tm.day)),
+            Some('H') => out.push_str(&format!("{:02}", tm.hour)),
+            Some('I') => out.push_str(&format!("{:02}", (tm.hour + 11) % 12 + 1)),
+            Some('p') => out.push_str(if tm.hour < 12 { "AM" } else { "PM" }),
+            Some('M') => out.push_str(&format!("{:02}", tm.minute)),
+            Some('S') => out.push_str(&format!("{:02}", tm.second)),
+            Some('j') => out.push_str(&format!("{:03}", tm.yearday)),
+            Some('a') => out.push_str(WEEKDAYS[tm.weekday]),
+            Some('A') => out.push_str(WEEKDAYS_LONG[tm.weekday]),
+            Some('b') | Some('h') => out.push_str(MONTHS[tm.month]),
+            Some('B') => out.push_str(MONTHS_LONG[tm.month]),
+            Some('F') => out.push_str(&format!("{}-{:02}-{:02}", tm.year, tm.month + 1, tm.day)),
+            Some('T') => out.push_str(&format!("{:02}:{:02}:{:02}", tm.hour, tm.minute, tm.second)),
+            Some('z') => out.push_str(tz),
+            Some('n') => out.push('\n'),
+            Some('%') => out.push('%'),
+            Some(c) => {
+                out.push('%');
+                out.push(c);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIME: i64 = 1720703241;
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(
+            format_date(TIME, "+0200", &DateMode::Default),
+            "Thu Jul 11 15:07:21 2024 +0200"
+        );
+        assert_eq!(
+            format_date(TIME, "+0200", &DateMode::Iso),
+            "2024-07-11 15:07:21 +0200"
+        );
+        assert_eq!(
+            format_date(TIME, "-0130", &DateMode::IsoStrict),
+            "2024-07-11T11:37:21-01:30"
+        );
+        assert_eq!(
+            format_date(TIME, "+0000", &DateMode::Rfc),
+            "Thu, 11 Jul 2024 13:07:21 +0000"
+        );
+        assert_eq!(format_date(TIME, "+0200", &DateMode::Short), "2024-07-11");
+        assert_eq!(
+            format_date(
+                TIME,
+                "+0200",
+                &DateMode::parse("format:%d/%m/%y %j").unwrap()
+            ),
+            "11/07/24 193"
+        );
+    }
+
+    #[test]
+    fn test_format_relative() {
+        assert_eq!(format_relative(TIME, TIME + 5), "5 seconds ago");
+        assert_eq!(format_relative(TIME, TIME + 3600 * 3), "3 hours ago");
+        assert_eq!(format_relative(TIME, TIME + 86400 * 20), "3 weeks ago");
+        assert_eq!(
+            format_relative(TIME, TIME + 86400 * 430),
+            "1 year, 2 months ago"
+        );
+        assert_eq!(
+            format_relative(TIME, TIME + 86400 * 365 * 10),
+            "10 years ago"
+        );
+    }
+
+    #[test]
+    fn test_parse_approxidate() {
+        assert_eq!(parse_approxidate("@1720703241", 0), Some(TIME));
+        assert_eq!(parse_approxidate("2 hours ago", TIME), Some(TIME - 7200));
+        assert_eq!(
+            parse_approxidate("1.week.ago", TIME),
+            Some(TIME - 7 * 86400)
+        );
+        assert_eq!(parse_approxidate("yesterday", TIME), Some(TIME - 86400));
+        assert_eq!(
+            parse_approxidate("2 months ago", TIME).map(|t| format_date(
+                t,
+                "+0000",
+                &DateMode::Iso
+            )),
+            Some("2024-05-11 13:07:21 +0000".to_string())
+        );
+        assert_eq!(
+            parse_approxidate("2024-07-11 15:07:21 +0200", 0),
+            Some(TIME)
+        );
+        // Without a time the current time of day is kept
+        assert_eq!(parse_approxidate("2024-07-11", TIME), Some(TIME));
+        assert_eq!(parse_approxidate("soon", TIME), None);
+    }
+}
@@ -0,0 +1,490 @@
+//! Line diffs using Myers' O(ND) algorithm, and their output in the
+//! unified format.
+
+use std::{
+    io::{self, Write},
+    ops::Range,
+};
+
+use crate::{color, repository::config::Config};
+
+/// A run of lines that differ between two sequences: `old` lines were
+/// replaced by `new` lines. One of the ranges is empty for a pure
+/// insertion or deletion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// Splits content into lines, keeping the line terminators.
+pub fn lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Returns the hunks turning `a` into `b`, as a minimal edit script.
+pub fn diff<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Hunk> {
+    // Common prefix and suffix never take part in an edit
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut hunks = vec![];
+    let mut hunk: Option<Hunk> = None;
+    let (mut x, mut y) = (0, 0);
+    for (mx, my) in matches(a_mid, b_mid)
+        .into_iter()
+        .chain([(a_mid.len(), b_mid.len())])
+    {
+        if mx > x || my > y {
+            let edit = Hunk {
+                old: prefix + x..prefix + mx,
+                new: prefix + y..prefix + my,
+            };
+            hunk = Some(match hunk.take() {
+                Some(h) if h.old.end == edit.old.start && h.new.end == edit.new.start => Hunk {
+                    old: h.old.start..edit.old.end,
+                    new: h.new.start..edit.new.end,
+                },
+                h => {
+                    hunks.extend(h);
+                    edit
+                }
+            });
+        }
+        (x, y) = (mx + 1, my + 1);
+    }
+    hunks.extend(hunk);
+
+    hunks
+}
+
+/// Hunks of a unified diff: nearby changes with up to `context` unchanged
+/// lines around them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnifiedHunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+    pub changes: Vec<Hunk>,
+}
+
+/// Groups the hunks of a diff of `old_len` lines into unified hunks. Like
+/// in git, changes whose context would touch or overlap are grouped.
+pub fn unified(hunks: &[Hunk], old_len: usize, context: usize) -> Vec<UnifiedHunk> {
+    let mut groups: Vec<Vec<Hunk>> = vec![];
+    for hunk in hunks {
+        match groups.last_mut() {
+            Some(group)
+                if hunk.old.start - group.last().map_or(0, |h| h.old.end) <= 2 * context =>
+            {
+                group.push(hunk.clone())
+            }
+            _ => groups.push(vec![hunk.clone()]),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|changes| {
+            let (first, last) = (&changes[0], &changes[changes.len() - 1]);
+            // The lines around the changes are the same on both sides
+            let before = first.old.start.min(context);
+            let after = (old_len - last.old.end).min(context);
+            UnifiedHunk {
+                old: first.old.start - before..last.old.end + after,
+                new: first.new.start - before..last.new.end + after,
+                changes,
+            }
+        })
+        .collect()
+}
+
+/// Returns true if content looks binary, i.e. has a NUL byte near the start.
+pub fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(8000).any(|&b| b == 0)
+}
+
+/// Returns the number of added and removed lines of a diff.
+pub fn count_changes(hunks: &[Hunk]) -> (usize, usize) {
+    hunks.iter().fold((0, 0), |(added, removed), hunk| {
+        (added + hunk.new.len(), removed + hunk.old.len())
+    })
+}
+
+/// The colors of the parts of a diff, as set by git's `color.diff.<slot>`
+/// config. All are empty when not coloring.
+#[derive(Debug, Clone, Default)]
+pub struct DiffColors {
+    pub context: String,
+    /// The header lines of a file's diff
+    pub meta: String,
+    /// The line ranges of hunk headers
+    pub frag: String,
+    /// The function names of hunk headers
+    pub func: String,
+    pub old: String,
+    pub new: String,
+    /// Whitespace errors in added lines
+    pub whitespace: String,
+    pub reset: String,
+}
+
+impl DiffColors {
+    /// Returns git's diff colors, as changed by the config.
+    pub fn from_config(config: &Config) -> DiffColors {
+        let slot = |names: &[&str], default: &str| {
+            names
+                .iter()
+                .find_map(|name| config.get(&format!("color.diff.{name}")))
+                .and_then(color::parse)
+                .unwrap_or_else(|| default.to_string())
+        };
+        DiffColors {
+            context: slot(&["context", "plain"], ""),
+            meta: slot(&["meta"], color::BOLD),
+            frag: slot(&["frag"], color::CYAN),
+            func: slot(&["func"], ""),
+            old: slot(&["old"], color::RED),
+            new: slot(&["new"], color::GREEN),
+            whitespace: slot(&["whitespace"], "\x1b[41m"),
+            reset: color::RESET.to_string(),
+        }
+    }
+}
+
+/// Writes the hunks turning `old` into `new` in the unified format, with
+/// `context` lines of context.
+pub fn write_unified(
+    out: &mut impl Write,
+    old: &[u8],
+    new: &[u8],
+    context: usize,
+    colors: &DiffColors,
+) -> io::Result<()> {
+    let reset = &colors.reset;
+    // Blank lines added at the end are whitespace errors as a whole
+    let blank_at_eof = match (trailing_blank_lines(old), trailing_blank_lines(new)) {
+        (old_blank, new_blank) if new_blank > old_blank => lines(new).len() - new_blank,
+        _ => usize::MAX,
+    };
+    let (old, new) = (lines(old), lines(new));
+    for hunk in unified(&diff(&old, &new), old.len(), context) {
+        write!(
+            out,
+            "{}@@ -{} +{} @@{reset}",
+            colors.frag,
+            hunk_range(&hunk.old),
+            hunk_range(&hunk.new),
+        )?;
+        if let Some(name) = function_name(&old[..hunk.old.start]) {
+            write!(
+                out,
+                "{} {reset}{}{name}{reset}",
+                colors.context, colors.func
+            )?;
+        }
+        writeln!(out)?;
+
+        let mut pos = hunk.old.start;
+        for change in &hunk.changes {
+            for line in &old[pos..change.old.start] {
+                write_line(out, &colors.context, b' ', line, colors)?;
+            }
+            for line in &old[change.old.clone()] {
+                write_line(out, &colors.old, b'-', line, colors)?;
+            }
+            for (i, line) in new[change.new.clone()].iter().enumerate() {
+                write_added_line(out, line, change.new.start + i >= blank_at_eof, colors)?;
+            }
+            pos = change.old.end;
+        }
+        for line in &old[pos..hunk.old.end] {
+            write_line(out, &colors.context, b' ', line, colors)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Formats the line range of a hunk header, e.g. `3,4`. The length is left
+/// out if it's 1, and an empty range starts at the line before it.
+pub fn hunk_range(range: &Range<usize>) -> String {
+    match range.len() {
+        0 => format!("{},0", range.start),
+        1 => format!("{}", range.start + 1),
+        len => format!("{},{len}", range.start + 1),
+    }
+}
+
+/// Returns the last line that looks like the start of a function, as git
+/// shows it in hunk headers by default: a line starting with a letter, `_`
+/// or `$`, cut to 80 bytes.
+pub fn function_name(lines: &[&[u8]]) -> Option<String> {
+    let line = lines.iter().rev().find(|line| {
+        line.first()
+            .is_some_and(|&b| b.is_ascii_alphabetic() || b == b'_' || b == b'$')
+    })?;
+    let line = &line[..line.len().min(80)];
+    Some(String::from_utf8_lossy(line).trim_end().to_string())
+}
+
+/// Writes a line with its prefix in one color, marking a missing newline
+/// at the end.
+fn write_line(
+    out: &mut impl Write,
+    color: &str,
+    prefix: u8,
+    line: &[u8],
+    colors: &DiffColors,
+) -> io::Result<()> {
+    let content = line.strip_suffix(b"\n").unwrap_or(line);
+    write!(out, "{color}{}", prefix as char)?;
+    out.write_all(content)?;
+    writeln!(out, "{}", colors.reset)?;
+    write_missing_newline(out, line, colors)
+}
+
+/// Writes an added line, highlighting whitespace errors like git does by
+/// default: whitespace at the end of the line, spaces before a tab in the
+/// indent, and blank lines at the end of the file.
+fn write_added_line(
+    out: &mut impl Write,
+    line: &[u8],
+    blank_at_eof: bool,
+    colors: &DiffColors,
+) -> io::Result<()> {
+    let (ws, new, reset) = (&colors.whitespace, &colors.new, &colors.reset);
+    if ws.is_empty() {
+        return write_line(out, new, b'+', line, colors);
+    }
+    if blank_at_eof && line.iter().all(|&b| is_space(b)) {
+        return write_line(out, ws, b'+', line, colors);
+    }
+
+    write!(out, "{new}+{reset}")?;
+    let content = line.strip_suffix(b"\n").unwrap_or(line);
+    let trailing = content.len() - content.iter().rev().take_while(|&&b| is_space(b)).count();
+    let mut written = 0;
+    for i in 0..trailing {
+        match content[i] {
+            b' ' => continue,
+            b'\t' => {}
+            _ => break,
+        }
+        if written < i {
+            write!(out, "{ws}")?;
+            out.write_all(&content[written..i])?;
+            write!(out, "{reset}")?;
+            out.write_all(&content[i..=i])?;
+        } else {
+            out.write_all(&content[written..=i])?;
+        }
+        written = i + 1;
+    }
+    if trailing > written {
+        write!(out, "{new}")?;
+        out.write_all(&content[written..trailing])?;
+        write!(out, "{reset}")?;
+    }
+    if trailing < content.len() {
+        write!(out, "{ws}")?;
+        out.write_all(&content[trailing..])?;
+        write!(out, "{reset}")?;
+    }
+    writeln!(out)?;
+    write_missing_newline(out, line, colors)
+}
+
+fn write_missing_newline(out: &mut impl Write, line: &[u8], colors: &DiffColors) -> io::Result<()> {
+    match line.ends_with(b"\n") {
+        true => Ok(()),
+        false => writeln!(
+            out,
+            "{}\\ No newline at end of file{}",
+            colors.context, colors.reset
+        ),
+    }
+}
+
+/// Whitespace as git's `isspace()` sees it.
+fn is_space(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+/// Returns the number of blank lines at the end of the content, counted
+/// like git's `count_trailing_blank()`, quirks at the start of the content
+/// included.
+fn trailing_blank_lines(content: &[u8]) -> usize {
+    let Some(&last) = content.last() else {
+        return 0;
+    };
+    // The last byte of the current line before its newline, or of the line
+    // before it if the line is empty
+    let mut end = content.len() as isize - 1;
+    if last == b'\n' {
+        end -= 1;
+    }
+
+    let mut count = 0;
+    while 0 < end {
+        let start = content[..=end as usize]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(-1, |i| i as isize);
+        if !content[(start + 1) as usize..(end + 1) as usize]
+            .iter()
+            .all(|&b| is_space(b))
+        {
+            break;
+        }
+        count += 1;
+        end = start - 1;
+    }
+    count
+}
+
+/// Returns the pairs of indices of matching elements along a shortest edit
+/// path from `a` to `b`.
+fn matches<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    // The furthest reaching paths of every step, to walk the path back
+    let mut trace = vec![];
+
+    'search: for d in 0..=max as isize {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walk back through the trace, collecting the diagonal (matching) moves
+    let mut pairs = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let i = (k + offset) as usize;
+        let prev_k = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = if d == 0 {
+            0
+        } else {
+            v[(prev_k + offset) as usize]
+        };
+        let prev_y = if d == 0 { 0 } else { prev_x - prev_k };
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            pairs.push((x as usize, y as usize));
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    pairs.reverse();
+
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let a: Vec<char> = "ABCABBA".chars().collect();
+        let b: Vec<char> = "CBABAC".chars().collect();
+        let hunks = diff(&a, &b);
+        // The edit script is minimal: 5 deletions and insertions in total
+        let edits: usize = hunks.iter().map(|h| h.old.len() + h.new.len()).sum();
+        assert_eq!(edits, 5);
+
+        // Applying the hunks to `a` gives `b`
+        let mut result = vec![];
+        let mut pos = 0;
+        for hunk in &hunks {
+            result.extend_from_slice(&a[pos..hunk.old.start]);
+            result.extend_from_slice(&b[hunk.new.clone()]);
+            pos = hunk.old.end;
+        }
+        result.extend_from_slice(&a[pos..]);
+        assert_eq!(result, b);
+
+        assert_eq!(diff(&a, &a), []);
+        assert_eq!(
+            diff(&lines(b"a\nb\nc\n"), &lines(b"a\nc\nd\n")),
+            [
+                Hunk {
+                    old: 1..2,
+                    new: 1..1
+                },
+                Hunk {
+                    old: 3..3,
+                    new: 2..3
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_unified() {
+        let old: String = (1..=20).map(|i| format!("{i}\n")).collect();
+        let new: String = (1..=20)
+            .filter_map(|i| match i {
+                2 => Some("two\n".to_string()),
+                9 => None,
+                20 => Some("20".to_string()),
+                i => Some(format!("{i}\n")),
+            })
+            .collect();
+        let mut out = vec![];
+        let colors = DiffColors::default();
+        write_unified(&mut out, old.as_bytes(), new.as_bytes(), 3, &colors).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "@@ -1,12 +1,11 @@\n 1\n-2\n+two\n 3\n 4\n 5\n 6\n 7\n 8\n-9\n 10\n 11\n 12\n\
+             @@ -17,4 +16,4 @@\n 17\n 18\n 19\n-20\n+20\n\\ No newline at end of file\n"
+        );
+
+        let mut out = vec![];
+        write_unified(&mut out, b"", b"a\n", 3, &colors).unwrap();
+        assert_eq!(out, b"@@ -0,0 +1 @@\n+a\n");
+
+        // Trailing whitespace on added lines is highlighted
+        let mut out = vec![];
+        let colors = DiffColors {
+            new: "<new>".to_string(),
+            whitespace: "<ws>".to_string(),
+            reset: "<reset>".to_string(),
+            ..DiffColors::default()
+        };
+        write_unified(&mut out, b"", b"a \n", 3, &colors).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "@@ -0,0 +1 @@<reset>\n<new>+<reset><new>a<reset><ws> <reset>\n"
+        );
+    }
+}
@@ -0,0 +1,738 @@
+//! Drawing the history graph to the left of `log --graph` output, a port of
+//! git's graph.c so that the lines are laid out the same way.
+//!
+//! The graph is drawn one line at a time. After [`Graph::update`] with the
+//! next commit, the lines up to and including the one with the commit's
+//! `*` are drawn by [`Graph::show_commit`], then each line of the commit's
+//! message is prefixed with the next graph line, and whatever is left for
+//! the commit (e.g. the lines of a merge) is drawn after the message.
+
+use std::collections::HashSet;
+
+use crate::color;
+
+const COLUMN_COLORS: [&str; 12] = [
+    color::RED,
+    color::GREEN,
+    color::YELLOW,
+    color::BLUE,
+    color::MAGENTA,
+    color::CYAN,
+    color::BOLD_RED,
+    color::BOLD_GREEN,
+    color::BOLD_YELLOW,
+    color::BOLD_BLUE,
+    color::BOLD_MAGENTA,
+    color::BOLD_CYAN,
+];
+
+/// The characters leading to the parents of a merge, depending on its
+/// layout.
+const MERGE_CHARS: [char; 3] = ['/', '|', '\\'];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Lines that leave all branch lines unchanged
+    Padding,
+    /// A `...` line, for when the previous commit wasn't finished
+    Skip,
+    /// Lines that make room for an octopus merge
+    PreCommit,
+    /// The line with the commit
+    Commit,
+    /// The line after a merge, leading to its parents
+    PostMerge,
+    /// Lines that move branch lines to the left
+    Collapsing,
+}
+
+/// A branch line, waiting for the commit it leads to.
+#[derive(Debug, Clone)]
+struct Column {
+    commit: String,
+    /// The index into [`COLUMN_COLORS`], if colored
+    color: Option<usize>,
+}
+
+/// A line of the graph being drawn, with its width in characters.
+#[derive(Default)]
+struct Line {
+    buf: String,
+    width: usize,
+}
+
+impl Line {
+    fn push(&mut self, c: char) {
+        self.buf.push(c);
+        self.width += 1;
+    }
+
+    fn push_n(&mut self, c: char, n: usize) {
+        for _ in 0..n {
+            self.push(c);
+        }
+    }
+
+    fn push_column(&mut self, column: &Column, c: char) {
+        match column.color {
+            Some(color) => {
+                self.buf.push_str(COLUMN_COLORS[color]);
+                self.push(c);
+                self.buf.push_str(color::RESET);
+            }
+            None => self.push(c),
+        }
+    }
+}
+
+/// The state of the graph between the commits drawn.
+pub struct Graph {
+    /// The commits shown, where branch lines may lead to
+    interesting: HashSet<String>,
+    first_parent: bool,
+    color: bool,
+    /// The commit being drawn and its interesting parents
+    commit: Option<String>,
+    parents: Vec<String>,
+    /// The width of the graph, used to pad all lines of a commit
+    width: usize,
+    /// The row of the lines making room for an octopus merge
+    expansion_row: usize,
+    state: State,
+    prev_state: State,
+    /// The column of the commit, and of the previous commit
+    commit_index: usize,
+    prev_commit_index: usize,
+    /// How a merge leads to its parents: -1 when not yet chosen, 0 when
+    /// the first parent is to the left, 1 otherwise
+    merge_layout: isize,
+    /// The number of branch lines added to the right by this and the
+    /// previous commit
+    edges_added: isize,
+    prev_edges_added: isize,
+    /// The branch lines before and after the commit
+    columns: Vec<Column>,
+    new_columns: Vec<Column>,
+    /// For each character of the line, the index into `new_columns` of the
+    /// branch line there, or -1
+    mapping: Vec<isize>,
+    old_mapping: Vec<isize>,
+    mapping_size: usize,
+    default_column_color: usize,
+}
+
+impl Graph {
+    /// Creates a graph of the given commits. Branch lines only lead to
+    /// interesting commits, and with `first_parent` only to first parents.
+    pub fn new(interesting: HashSet<String>, first_parent: bool, color: bool) -> Graph {
+        Graph {
+            interesting,
+            first_parent,
+            color,
+            commit: None,
+            parents: vec![],
+            width: 0,
+            expansion_row: 0,
+            state: State::Padding,
+            prev_state: State::Padding,
+            commit_index: 0,
+            prev_commit_index: 0,
+            merge_layout: 0,
+            edges_added: 0,
+            prev_edges_added: 0,
+            columns: vec![],
+            new_columns: vec![],
+            mapping: vec![],
+            old_mapping: vec![],
+            mapping_size: 0,
+            // Start at the last color, so the first one used is the first
+            default_column_color: COLUMN_COLORS.len() - 1,
+        }
+    }
+
+    /// Moves on to the next commit to draw.
+    pub fn update(&mut self, hash: &str, parents: &[String]) {
+        self.commit = Some(hash.to_string());
+        self.parents = match self.first_parent {
+            true => parents
+                .iter()
+                .take(1)
+                .filter(|p| self.interesting.contains(*p))
+                .cloned()
+                .collect(),
+            false => parents
+                .iter()
+                .filter(|p| self.interesting.contains(*p))
+                .cloned()
+                .collect(),
+        };
+        self.prev_commit_index = self.commit_index;
+        self.update_columns();
+        self.expansion_row = 0;
+
+        // If the previous commit wasn't finished, draw a `...` line to say
+        // that part of the graph is missing
+        self.state = if self.state != State::Padding {
+            State::Skip
+        } else if self.needs_pre_commit_line() {
+            State::PreCommit
+        } else {
+            State::Commit
+        };
+    }
+
+    fn update_state(&mut self, state: State) {
+        self.prev_state = self.state;
+        self.state = state;
+    }
+
+    fn current_color(&self) -> Option<usize> {
+        self.color.then_some(self.default_column_color)
+    }
+
+    fn increment_column_color(&mut self) {
+        self.default_column_color = (self.default_column_color + 1) % COLUMN_COLORS.len();
+    }
+
+    fn find_commit_color(&self, commit: &str) -> Option<usize> {
+        match self.columns.iter().find(|c| c.commit == commit) {
+            Some(column) => column.color,
+            None => self.current_color(),
+        }
+    }
+
+    fn find_new_column(&self, commit: &str) -> Option<usize> {
+        self.new_columns.iter().position(|c| c.commit == commit)
+    }
+
+    /// Adds a branch line leading to `commit` for the next commit, coming
+    /// from the column `index` if it's a parent of the current commit.
+    fn insert_into_new_columns(&mut self, commit: &str, index: Option<usize>) {
+        let i = match self.find_new_column(commit) {
+            Some(i) => i,
+            None => {
+                let color = self.find_commit_color(commit);
+                self.new_columns.push(Column {
+                    commit: commit.to_string(),
+                    color,
+                });
+                self.new_columns.len() - 1
+            }
+        } as isize;
+
+        let mapping_index;
+        match index {
+            Some(index) if self.parents.len() > 1 && self.merge_layout == -1 => {
+                // The first parent of a merge picks the layout of the merge
+                // line, depending on whether it's to the left of the merge
+                let dist = index as isize - i;
+                let shift = if dist > 1 { 2 * dist - 3 } else { 1 };
+                self.merge_layout = if dist > 0 { 0 } else { 1 };
+                self.edges_added = self.parents.len() as isize + self.merge_layout - 2;
+                mapping_index = self.width as isize + (self.merge_layout - 1) * shift;
+                self.width += 2 * self.merge_layout as usize;
+            }
+            _ if self.edges_added > 0 && i == self.mapping[self.width - 2] => {
+                // The parent is in the last existing column, so join the
+                // two edges right away
+                mapping_index = self.width as isize - 2;
+                self.edges_added = -1;
+            }
+            _ => {
+                mapping_index = self.width as isize;
+                self.width += 2;
+            }
+        }
+        self.mapping[mapping_index as usize] = i;
+    }
+
+    fn update_columns(&mut self) {
+        // The columns of the previous commit's next commit are ours now
+        std::mem::swap(&mut self.columns, &mut self.new_columns);
+        self.new_columns.clear();
+
+        let max_new_columns = self.columns.len() + self.parents.len();
+        self.mapping_size = 2 * max_new_columns;
+        if self.mapping.len() < self.mapping_size {
+            self.mapping.resize(self.mapping_size, -1);
+            self.old_mapping.resize(self.mapping_size, -1);
+        }
+        self.mapping[..self.mapping_size].fill(-1);
+
+        self.width = 0;
+        self.prev_edges_added = self.edges_added;
+        self.edges_added = 0;
+
+        let commit = self.commit.clone().unwrap_or_default();
+        let mut seen_this = false;
+        let mut is_commit_in_columns = true;
+        for i in 0..=self.columns.len() {
+            let column_commit = match self.columns.get(i) {
+                Some(column) => column.commit.clone(),
+                None if seen_this => break,
+                None => {
+                    is_commit_in_columns = false;
+                    commit.clone()
+                }
+            };
+
+            if column_commit == commit {
+                seen_this = true;
+                self.commit_index = i;
+                self.merge_layout = -1;
+                for parent in self.parents.clone() {
+                    // Merges and new branch lines without children get new
+                    // colors
+                    if self.parents.len() > 1 || !is_commit_in_columns {
+                        self.increment_column_color();
+                    }
+                    self.insert_into_new_columns(&parent, Some(i));
+                }
+                // The commit always takes up 2 characters
+                if self.parents.is_empty() {
+                    self.width += 2;
+                }
+            } else {
+                self.insert_into_new_columns(&column_commit, None);
+            }
+        }
+
+        while self.mapping_size > 1 && self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+    }
+
+    fn num_dashed_parents(&self) -> isize {
+        self.parents.len() as isize + self.merge_layout - 3
+    }
+
+    fn num_expansion_rows(&self) -> isize {
+        self.num_dashed_parents() * 2
+    }
+
+    fn needs_pre_commit_line(&self) -> bool {
+        self.parents.len() >= 3
+            && self.commit_index + 1 < self.columns.len()
+            && (self.expansion_row as isize) < self.num_expansion_rows()
+    }
+
+    /// Returns true if every branch line is at its place, or one to the
+    /// right of it where a `/` leads to it.
+    fn is_mapping_correct(&self) -> bool {
+        self.mapping[..self.mapping_size]
+            .iter()
+            .enumerate()
+            .all(|(i, &target)| target < 0 || target == i as isize / 2)
+    }
+
+    fn pad_horizontally(&self, line: &mut Line) {
+        if line.width < self.width {
+            line.push_n(' ', self.width - line.width);
+        }
+    }
+
+    fn output_padding_line(&self, line: &mut Line) {
+        for column in &self.new_columns {
+            line.push_column(column, '|');
+            line.push(' ');
+        }
+    }
+
+    fn output_skip_line(&mut self, line: &mut Line) {
+        line.buf.push_str("...");
+        line.width += 3;
+        if self.needs_pre_commit_line() {
+            self.update_state(State::PreCommit);
+        } else {
+            self.update_state(State::Commit);
+        }
+    }
+
+    /// Draws a line widening the space around an octopus merge.
+    fn output_pre_commit_line(&mut self, line: &mut Line) {
+        let mut seen_this = false;
+        for (i, column) in self.columns.iter().enumerate() {
+            if Some(&column.commit) == self.commit.as_ref() {
+                seen_this = true;
+                line.push_column(column, '|');
+                line.push_n(' ', self.expansion_row);
+            } else if seen_this && self.expansion_row == 0 {
+                // Continue the `\` lines of a previous merge
+                if self.prev_state == State::PostMerge && self.prev_commit_index < i {
+                    line.push_column(column, '\\');
+                } else {
+                    line.push_column(column, '|');
+                }
+            } else if seen_this {
+                line.push_column(column, '\\');
+            } else {
+                line.push_column(column, '|');
+            }
+            line.push(' ');
+        }
+
+        self.expansion_row += 1;
+        if !self.needs_pre_commit_line() {
+            self.update_state(State::Commit);
+        }
+    }
+
+    /// Draws the dashes of an octopus merge, in the color of the parent
+    /// each one leads to.
+    fn draw_octopus_merge(&self, line: &mut Line) {
+        let dashed_parents = self.num_dashed_parents();
+        for i in 0..dashed_parents {
+            let j = self.mapping[(self.commit_index + i as usize + 2) * 2];
+            let column = &self.new_columns[j as usize];
+            line.push_column(column, '-');
+            line.push_column(column, if i == dashed_parents - 1 { '.' } else { '-' });
+        }
+    }
+
+    fn output_commit_line(&mut self, line: &mut Line) {
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => column,
+                None if seen_this => break,
+                None => {
+                    // The commit isn't in any column, if it has no children
+                    seen_this = true;
+                    line.push('*');
+                    if self.parents.len() > 2 {
+                        self.draw_octopus_merge(line);
+                    }
+                    line.push(' ');
+                    continue;
+                }
+            };
+
+            if Some(&column.commit) == self.commit.as_ref() {
+                seen_this = true;
+                line.push('*');
+                if self.parents.len() > 2 {
+                    self.draw_octopus_merge(line);
+                }
+            } else if seen_this && self.edges_added > 1 {
+                line.push_column(column, '\\');
+            } else if seen_this && self.edges_added == 1 {
+                // The first line of a right-skewed merge, which continues
+                // the `\` lines of a previous merge
+                if self.prev_state == State::PostMerge
+                    && self.prev_edges_added > 0
+                    && self.prev_commit_index < i
+                {
+                    line.push_column(column, '\\');
+                } else {
+                    line.push_column(column, '|');
+                }
+            } else if self.prev_state == State::Collapsing
+                && self.old_mapping[2 * i + 1] == i as isize
+                && self.mapping[2 * i] < i as isize
+            {
+                line.push_column(column, '/');
+            } else {
+                line.push_column(column, '|');
+            }
+            line.push(' ');
+        }
+
+        if self.parents.len() > 1 {
+            self.update_state(State::PostMerge);
+        } else if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        } else {
+            self.update_state(State::Collapsing);
+        }
+    }
+
+    /// Draws the line below a merge, leading to each of its parents.
+    fn output_post_merge_line(&mut self, line: &mut Line) {
+        let first_parent = self.parents.first().cloned();
+        let mut parent_column: Option<Column> = None;
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => Some(column.clone()),
+                None if seen_this => break,
+                None => None,
+            };
+            let column_commit = match &column {
+                Some(column) => &column.commit,
+                None => self.commit.as_ref().unwrap(),
+            };
+
+            if Some(column_commit) == self.commit.as_ref() {
+                seen_this = true;
+                let mut layout = self.merge_layout as usize;
+                for (j, parent) in self.parents.iter().enumerate() {
+                    let Some(par_column) = self.find_new_column(parent) else {
+                        continue;
+                    };
+                    line.push_column(&self.new_columns[par_column], MERGE_CHARS[layout]);
+                    if layout == 2 {
+                        if self.edges_added > 0 || j + 1 < self.parents.len() {
+                            line.push(' ');
+                        }
+                    } else {
+                        layout += 1;
+                    }
+                }
+                if self.edges_added == 0 {
+                    line.push(' ');
+                }
+            } else if let Some(column) = &column {
+                if seen_this {
+                    if self.edges_added > 0 {
+                        line.push_column(column, '\\');
+                    } else {
+                        line.push_column(column, '|');
+                    }
+                    line.push(' ');
+                } else {
+                    line.push_column(column, '|');
+                    if self.merge_layout != 0 || i + 1 != self.commit_index {
+                        match &parent_column {
+                            Some(parent_column) => line.push_column(parent_column, '_'),
+                            None => line.push(' '),
+                        }
+                    }
+                }
+            }
+
+            if column.is_some() && first_parent.as_ref() == Some(column_commit) {
+                parent_column = column.clone();
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        } else {
+            self.update_state(State::Collapsing);
+        }
+    }
+
+    /// Draws a line moving branch lines to the left, towards the column
+    /// they lead to.
+    fn output_collapsing_line(&mut self, line: &mut Line) {
+        let mut used_horizontal = false;
+        let mut horizontal_edge = -1;
+        let mut horizontal_edge_target = -1;
+
+        std::mem::swap(&mut self.mapping, &mut self.old_mapping);
+        self.mapping[..self.mapping_size].fill(-1);
+
+        for i in 0..self.mapping_size {
+            let target = self.old_mapping[i];
+            if target < 0 {
+                continue;
+            }
+            // Branch lines only ever move to the left
+            let target_index = target as usize * 2;
+            if target_index == i {
+                // Already in the right place
+                self.mapping[i] = target;
+            } else if self.mapping[i - 1] < 0 {
+                // Nothing to the left, so move one to the left
+                self.mapping[i - 1] = target;
+                // Draw this edge horizontally if no other is yet
+                if horizontal_edge == -1 {
+                    horizontal_edge = i as isize;
+                    horizontal_edge_target = target;
+                    for j in (target_index + 3..i.saturating_sub(2)).step_by(2) {
+                        self.mapping[j] = target;
+                    }
+                }
+            } else if self.mapping[i - 1] == target {
+                // The line to the left leads to the same commit, so this
+                // one joins it
+            } else {
+                // Cross over the line to the left, leading elsewhere
+                self.mapping[i - 2] = target;
+                if horizontal_edge == -1 {
+                    horizontal_edge_target = target;
+                    horizontal_edge = i as isize - 1;
+                    for j in (target_index + 3..i.saturating_sub(2)).step_by(2) {
+                        self.mapping[j] = target;
+                    }
+                }
+            }
+        }
+
+        self.old_mapping[..self.mapping_size].copy_from_slice(&self.mapping[..self.mapping_size]);
+
+        // The new mapping may be one smaller than the old one
+        if self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+
+        for i in 0..self.mapping_size {
+            let target = self.mapping[i];
+            if target < 0 {
+                line.push(' ');
+            } else if target as usize * 2 == i {
+                line.push_column(&self.new_columns[target as usize], '|');
+            } else if target == horizontal_edge_target && i as isize != horizontal_edge - 1 {
+                // Only the first segment of a horizontal line continues on
+                // the next line
+                if i != target as usize * 2 + 3 {
+                    self.mapping[i] = -1;
+                }
+                used_horizontal = true;
+                line.push_column(&self.new_columns[target as usize], '_');
+            } else {
+                if used_horizontal && (i as isize) < horizontal_edge {
+                    self.mapping[i] = -1;
+                }
+                line.push_column(&self.new_columns[target as usize], '/');
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        }
+    }
+
+    /// Draws the next line of the graph, returning true if it was the line
+    /// with the commit.
+    fn next_line(&mut self, out: &mut String) -> bool {
+        if self.commit.is_none() {
+            return false;
+        }
+
+        let mut line = Line::default();
+        let mut shown_commit_line = false;
+        match self.state {
+            State::Padding => self.output_padding_line(&mut line),
+            State::Skip => self.output_skip_line(&mut line),
+            State::PreCommit => self.output_pre_commit_line(&mut line),
+            State::Commit => {
+                self.output_commit_line(&mut line);
+                shown_commit_line = true;
+            }
+            State::PostMerge => self.output_post_merge_line(&mut line),
+            State::Collapsing => self.output_collapsing_line(&mut line),
+        }
+        self.pad_horizontally(&mut line);
+        out.push_str(&line.buf);
+        shown_commit_line
+    }
+
+    /// Draws a line that leaves all branch lines unchanged, even before
+    /// the commit's own line.
+    fn padding_line(&mut self, out: &mut String) {
+        if self.state != State::Commit {
+            self.next_line(out);
+            return;
+        }
+
+        let mut line = Line::default();
+        for column in &self.columns {
+            line.push_column(column, '|');
+            if Some(&column.commit) == self.commit.as_ref() && self.parents.len() > 2 {
+                line.push_n(' ', (self.parents.len() - 2) * 2);
+            } else {
+                line.push(' ');
+            }
+        }
+        self.pad_horizontally(&mut line);
+        out.push_str(&line.buf);
+        self.prev_state = State::Padding;
+    }
+
+    /// Returns true if all lines of the current commit have been drawn.
+    pub fn is_commit_finished(&self) -> bool {
+        self.state == State::Padding
+    }
+
+    /// Draws the lines up to and including the one with the commit,
+    /// leaving the latter unterminated for the commit's first line.
+    pub fn show_commit(&mut self, out: &mut String) {
+        if self.is_commit_finished() {
+            self.padding_line(out);
+            return;
+        }
+        while !self.is_commit_finished() {
+            if self.next_line(out) {
+                break;
+            }
+            out.push('\n');
+        }
+    }
+
+    /// Draws the next line, unterminated.
+    pub fn show_oneline(&mut self, out: &mut String) {
+        self.next_line(out);
+    }
+
+    /// Draws a padding line, unterminated.
+    pub fn show_padding(&mut self, out: &mut String) {
+        self.padding_line(out);
+    }
+
+    /// Draws the rest of the lines of the current commit, returning true if
+    /// there were any. The last one is left unterminated.
+    pub fn show_remainder(&mut self, out: &mut String) -> bool {
+        if self.is_commit_finished() {
+            return false;
+        }
+        loop {
+            self.next_line(out);
+            if self.is_commit_finished() {
+                return true;
+            }
+            out.push('\n');
+        }
+    }
+
+    /// Writes a commit's message, prefixing each line but the first with
+    /// the next line of the graph, followed by the rest of the graph lines
+    /// of the commit.
+    pub fn show_commit_msg(&mut self, out: &mut String, message: &str) {
+        let mut lines = message.split_inclusive('\n').peekable();
+        while let Some(line) = lines.next() {
+            out.push_str(line);
+            if line.ends_with('\n') && lines.peek().is_some() {
+                self.show_oneline(out);
+            }
+        }
+
+        if !self.is_commit_finished() {
+            let newline_terminated = message.ends_with('\n');
+            if !newline_terminated {
+                out.push('\n');
+            }
+            self.show_remainder(out);
+            if newline_terminated {
+                out.push('\n');
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graph() {
+        // a merge `m` of `b` and `c`, both children of `a`
+        let parents = |hash: &str| -> Vec<String> {
+            match hash {
+                "m" => vec!["b".to_string(), "c".to_string()],
+                "b" | "c" => vec!["a".to_string()],
+                _ => vec![],
+            }
+        };
+        let interesting = ["m", "c", "b", "a"].map(String::from).into();
+        let mut graph = Graph::new(interesting, false, false);
+
+        let mut out = String::new();
+        for hash in ["m", "c", "b", "a"] {
+            graph.update(hash, &parents(hash));
+            graph.show_commit(&mut out);
+            graph.show_commit_msg(&mut out, &format!("{hash}\n"));
+        }
+        assert_eq!(out, "*   m\n|\\  \n| * c\n* | b\n|/  \n* a\n");
+    }
+}
@@ -1,6 +1,19 @@
+pub mod color;
 pub mod commands;
+pub mod date;
+pub mod diff;
+pub mod graph;
+pub mod merge;
 pub mod object;
+pub mod pager;
+pub mod pathspec;
+pub mod pkt_line;
+pub mod pretty;
+pub mod ref_filter;
+pub mod regex;
 pub mod repository;
+pub mod revision;
+pub mod wildmatch;
 
 use anyhow::Result;
 use clap::Parser;
@@ -0,0 +1,134 @@
+//! Three-way merges of file contents.
+
+use std::ops::Range;
+
+use crate::diff::{diff, lines, Hunk};
+
+/// The names shown in conflict markers.
+pub struct Labels<'a> {
+    pub ours: &'a str,
+    pub theirs: &'a str,
+}
+
+/// The result of merging two versions of a file.
+pub struct MergeResult {
+    pub content: Vec<u8>,
+    /// Whether the result contains conflict markers
+    pub conflicts: bool,
+}
+
+/// Merges the changes `ours` and `theirs` made to `base` line by line.
+/// Regions changed differently on both sides are written as conflicts.
+pub fn merge(base: &[u8], ours: &[u8], theirs: &[u8], labels: &Labels) -> MergeResult {
+    let (base, ours, theirs) = (lines(base), lines(ours), lines(theirs));
+    let our_hunks = diff(&base, &ours);
+    let their_hunks = diff(&base, &theirs);
+
+    // Tag hunks with their side and process them ordered by base position
+    let mut hunks: Vec<(bool, &Hunk)> = our_hunks
+        .iter()
+        .map(|h| (true, h))
+        .chain(their_hunks.iter().map(|h| (false, h)))
+        .collect();
+    hunks.sort_by_key(|(ours, h)| (h.old.start, !ours));
+
+    let mut content = vec![];
+    let mut conflicts = false;
+    let mut pos = 0;
+    let mut i = 0;
+    while i < hunks.len() {
+        // Collect the hunks that overlap or touch into one region of base
+        let start = hunks[i].1.old.start;
+        let mut end = hunks[i].1.old.end;
+        let mut j = i + 1;
+        while j < hunks.len() && hunks[j].1.old.start <= end {
+            end = end.max(hunks[j].1.old.end);
+            j += 1;
+        }
+        let region = &hunks[i..j];
+        i = j;
+
+        for line in &base[pos..start] {
+            content.extend_from_slice(line);
+        }
+        pos = end;
+
+        let our_lines = side(region, true, start..end).map_or(&base[start..end], |r| &ours[r]);
+        let their_lines = side(region, false, start..end).map_or(&base[start..end], |r| &theirs[r]);
+        let changed_ours = region.iter().any(|(ours, _)| *ours);
+        let changed_theirs = region.iter().any(|(ours, _)| !*ours);
+
+        if !changed_theirs || our_lines == their_lines {
+            our_lines
+                .iter()
+                .for_each(|line| content.extend_from_slice(line));
+        } else if !changed_ours {
+            their_lines
+                .iter()
+                .for_each(|line| content.extend_from_slice(line));
+        } else {
+            conflicts = true;
+            content.extend_from_slice(format!("<<<<<<< {}\n", labels.ours).as_bytes());
+            write_lines(&mut content, our_lines);
+            content.extend_from_slice(b"=======\n");
+            write_lines(&mut content, their_lines);
+            content.extend_from_slice(format!(">>>>>>> {}\n", labels.theirs).as_bytes());
+        }
+    }
+    for line in &base[pos..] {
+        content.extend_from_slice(line);
+    }
+
+    MergeResult { content, conflicts }
+}
+
+/// Returns the lines one side has in place of the base region, or `None`
+/// if that side didn't change the region.
+fn side(region: &[(bool, &Hunk)], ours: bool, base: Range<usize>) -> Option<Range<usize>> {
+    let mut hunks = region.iter().filter(|(o, _)| *o == ours).map(|(_, h)| h);
+    let first = hunks.next()?;
+    let last = hunks.next_back().unwrap_or(first);
+    // Lines around the side's hunks are unchanged from base
+    Some(first.new.start - (first.old.start - base.start)..last.new.end + (base.end - last.old.end))
+}
+
+/// Writes the lines of a conflict side, terminating the last line.
+fn write_lines(content: &mut Vec<u8>, lines: &[&[u8]]) {
+    for line in lines {
+        content.extend_from_slice(line);
+    }
+    if content.last().is_some_and(|&b| b != b'\n') {
+        content.push(b'\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LABELS: Labels = Labels {
+        ours: "ours",
+        theirs: "theirs",
+    };
+
+    #[test]
+    fn test_merge() {
+        let base = b"a\nb\nc\nd\ne\n";
+        // Changes in different places are combined
+        let result = merge(base, b"A\nb\nc\nd\ne\n", b"a\nb\nc\nd\nE\n", &LABELS);
+        assert!(!result.conflicts);
+        assert_eq!(result.content, b"A\nb\nc\nd\nE\n");
+
+        // The same change on both sides is no conflict
+        let result = merge(base, b"a\nB\nc\nd\ne\n", b"a\nB\nc\nd\ne\n", &LABELS);
+        assert!(!result.conflicts);
+        assert_eq!(result.content, b"a\nB\nc\nd\ne\n");
+
+        let result = merge(base, b"a\nB\nc\nd\ne\n", b"a\nX\nc\nd\ne\nf\n", &LABELS);
+        assert!(result.conflicts);
+        assert_eq!(
+            String::from_utf8(result.content).unwrap(),
+            "a\n<<<<<<< ours\nB\n=======\nX\n>>>>>>> theirs\nc\nd\ne\nf\n"
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use std::{fs, io::Read, path::Path};
 
+use crate::repository::Repository;
+
 use super::{
     error::{ObjectError, Result},
     Object, ObjectType,
@@ -19,4 +21,25 @@ impl Object<()> {
             reader: f,
         })
     }
+
+    /// Returns a new blob object with the given content.
+    pub fn blob_from_bytes(content: &[u8]) -> Object<&[u8]> {
+        Object {
+            kind: ObjectType::Blob,
+            size: content.len() as u64,
+            reader: content,
+        }
+    }
+
+    /// Reads the content of the blob with the given hash.
+    pub fn read_blob(hash: &str, repo: &Repository) -> Result<Vec<u8>> {
+        let mut object = Object::read(hash, repo)?;
+        if object.kind != ObjectType::Blob {
+            Err(ObjectError::Other(format!("{hash} is not a blob")))?;
+        }
+        let mut content = vec![];
+        object.reader.read_to_end(&mut content)?;
+
+        Ok(content)
+    }
 }
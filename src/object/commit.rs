@@ -1,16 +1,231 @@
-use std::{env, fmt::Write, time::SystemTime};
+use std::{
+    collections::{HashSet, VecDeque},
+    env,
+    fmt::{self, Write},
+    io::Read,
+    time::SystemTime,
+};
 
-use crate::repository::Repository;
+use crate::{date, repository::Repository};
 
 use super::{
     error::{ObjectError, Result},
     Object, ObjectType,
 };
 
+/// The author or committer of a commit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// Seconds since the unix epoch
+    pub time: i64,
+    /// Timezone offset, e.g. `+0200`
+    pub tz: String,
+}
+
+impl Signature {
+    /// Returns a signature for the current user at the current time.
+    pub fn now() -> Result<Signature> {
+        // Read author and committer from environment variables, or me as default :)
+        let (name, email) = env::var("NAME")
+            .ok()
+            .zip(env::var("EMAIL").ok())
+            .unwrap_or_else(|| {
+                (
+                    String::from("Vincent Ockers"),
+                    String::from("vincentbockers@gmail.com"),
+                )
+            });
+        // Read the current time
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|_| ObjectError::Other("failed to get time".to_string()))?
+            .as_secs() as i64;
+
+        Ok(Signature {
+            name,
+            email,
+            time,
+            tz: String::from("+0000"),
+        })
+    }
+
+    /// Parses a signature of the form `Name <email> <time> <tz>`.
+    pub fn parse(s: &str) -> Result<Signature> {
+        let err = || ObjectError::Parse(format!("invalid signature '{s}'"));
+        let (name, rest) = s.split_once('<').ok_or_else(err)?;
+        let (email, rest) = rest.split_once('>').ok_or_else(err)?;
+        let mut rest = rest.split_whitespace();
+        let time = rest.next().and_then(|t| t.parse().ok()).unwrap_or(0);
+        let tz = rest.next().unwrap_or("+0000").to_string();
+
+        Ok(Signature {
+            name: name.trim().to_string(),
+            email: email.to_string(),
+            time,
+            tz,
+        })
+    }
+
+    /// Returns the timezone offset in seconds.
+    pub fn tz_offset(&self) -> i64 {
+        date::tz_offset(&self.tz)
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name, self.email, self.time, self.tz
+        )
+    }
+}
+
+/// A parsed commit object.
+#[derive(Debug, Clone)]
+pub struct Commit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Signature,
+    pub committer: Signature,
+    pub message: String,
+}
+
+impl Commit {
+    /// Reads and parses the commit with the given hash.
+    pub fn read(hash: &str, repo: &Repository) -> Result<Commit> {
+        let mut object = Object::read(hash, repo)?;
+        if object.kind != ObjectType::Commit {
+            Err(ObjectError::Other(format!("{hash} is not a commit")))?;
+        }
+        let mut content = String::new();
+        object.reader.read_to_string(&mut content)?;
+
+        Commit::parse(&content)
+    }
+
+    /// Parses the content of a commit object.
+    pub fn parse(content: &str) -> Result<Commit> {
+        let (headers, message) = content.split_once("\n\n").unwrap_or((content, ""));
+
+        let mut tree = None;
+        let mut parents = vec![];
+        let mut author = None;
+        let mut committer = None;
+        for line in headers.lines() {
+            // Continuation lines (e.g. of a gpgsig header) start with a space
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            match key {
+                "tree" => tree = Some(value.to_string()),
+                "parent" => parents.push(value.to_string()),
+                "author" => author = Some(Signature::parse(value)?),
+                "committer" => committer = Some(Signature::parse(value)?),
+                _ => {}
+            }
+        }
+
+        let tree = tree.ok_or_else(|| ObjectError::Parse("commit without tree".to_string()))?;
+        let author =
+            author.ok_or_else(|| ObjectError::Parse("commit without author".to_string()))?;
+        let committer = committer.unwrap_or_else(|| author.clone());
+
+        Ok(Commit {
+            tree,
+            parents,
+            author,
+            committer,
+            message: message.to_string(),
+        })
+    }
+
+    /// Returns the first paragraph of the commit message, joined into one line.
+    pub fn subject(&self) -> String {
+        self.message
+            .trim_start()
+            .split("\n\n")
+            .next()
+            .unwrap_or("")
+            .lines()
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Returns the commit message after the subject.
+    pub fn body(&self) -> &str {
+        match self.message.trim_start().split_once("\n\n") {
+            Some((_, body)) => body,
+            None => "",
+        }
+    }
+}
+
+/// Returns the set of commits reachable from the given commit, including itself.
+pub fn ancestors(hash: &str, repo: &Repository) -> Result<HashSet<String>> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([hash.to_string()]);
+    while let Some(hash) = queue.pop_front() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        queue.extend(Commit::read(&hash, repo)?.parents);
+    }
+
+    Ok(seen)
+}
+
+/// Returns true if `ancestor` is reachable from `descendant`.
+pub fn is_ancestor(ancestor: &str, descendant: &str, repo: &Repository) -> Result<bool> {
+    if ancestor == descendant {
+        return Ok(true);
+    }
+    Ok(ancestors(descendant, repo)?.contains(ancestor))
+}
+
+/// Returns the best common ancestors of `a` and `b`: the commits reachable
+/// from both that aren't ancestors of another such commit.
+pub fn merge_bases(a: &str, b: &str, repo: &Repository) -> Result<Vec<String>> {
+    let a = ancestors(a, repo)?;
+    let common: HashSet<String> = ancestors(b, repo)?
+        .into_iter()
+        .filter(|hash| a.contains(hash))
+        .collect();
+
+    // Everything reachable from the parents of a common ancestor is worse
+    let mut worse = HashSet::new();
+    let mut queue = VecDeque::new();
+    for hash in &common {
+        queue.extend(Commit::read(hash, repo)?.parents);
+    }
+    while let Some(hash) = queue.pop_front() {
+        if worse.insert(hash.clone()) {
+            queue.extend(Commit::read(&hash, repo)?.parents);
+        }
+    }
+
+    let mut bases: Vec<String> = common.difference(&worse).cloned().collect();
+    bases.sort();
+    Ok(bases)
+}
+
+/// Returns the number of commits reachable from `a` but not from `b` and
+/// vice versa, i.e. how far `a` is ahead of and behind `b`.
+pub fn ahead_behind(a: &str, b: &str, repo: &Repository) -> Result<(usize, usize)> {
+    let a = ancestors(a, repo)?;
+    let b = ancestors(b, repo)?;
+
+    Ok((a.difference(&b).count(), b.difference(&a).count()))
+}
+
 /// Write a commit object to the repository.
 pub fn write_commit(
     tree_hash: &str,
-    parent_hash: Option<&str>,
+    parents: &[&str],
     message: &str,
     repo: &Repository,
 ) -> Result<String> {
@@ -19,28 +234,13 @@ pub fn write_commit(
     // impl Write for String can't fail
     writeln!(commit, "tree {}", tree_hash).unwrap();
 
-    // Write parent hash if it exists
-    if let Some(parent_hash) = parent_hash {
+    for parent_hash in parents {
         writeln!(commit, "parent {}", parent_hash).unwrap();
     }
-    // Read author and committer from environment variables, or me as default :)
-    let (name, email) = env::var("NAME")
-        .ok()
-        .zip(env::var("EMAIL").ok())
-        .unwrap_or_else(|| {
-            (
-                String::from("Vincent Ockers"),
-                String::from("vincentbockers@gmail.com"),
-            )
-        });
-    // Read the current time
-    let time = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .map_err(|_| ObjectError::Other("failed to get time".to_string()))?
-        .as_secs();
-
-    writeln!(commit, "author {name} <{email}> {time} +0000").unwrap();
-    writeln!(commit, "committer {name} <{email}> {time} +0000").unwrap();
+    let signature = Signature::now()?;
+
+    writeln!(commit, "author {signature}").unwrap();
+    writeln!(commit, "committer {signature}").unwrap();
     writeln!(commit).unwrap();
     writeln!(commit, "{message}").unwrap();
 
@@ -51,3 +251,29 @@ pub fn write_commit(
     }
     .write_to_objects(repo)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commit() {
+        let commit = Commit::parse(
+            "tree ecabbf6e6c59d8d3d222685a369bb611803d3ce8\n\
+             parent defb1bfe50aa14da7248cc420d2a59c97ec8356c\n\
+             author Vincent Ockers <vincentbockers@gmail.com> 1720703241 +0200\n\
+             committer Vincent Ockers <vincentbockers@gmail.com> 1720703300 +0200\n\n\
+             Implement init command\n\nWith a body.\n",
+        )
+        .unwrap();
+
+        assert_eq!(commit.tree, "ecabbf6e6c59d8d3d222685a369bb611803d3ce8");
+        assert_eq!(commit.parents, ["defb1bfe50aa14da7248cc420d2a59c97ec8356c"]);
+        assert_eq!(commit.author.name, "Vincent Ockers");
+        assert_eq!(commit.author.email, "vincentbockers@gmail.com");
+        assert_eq!(commit.committer.time, 1720703300);
+        assert_eq!(commit.committer.tz_offset(), 7200);
+        assert_eq!(commit.subject(), "Implement init command");
+        assert_eq!(commit.body(), "With a body.\n");
+    }
+}
@@ -1,16 +1,18 @@
 pub mod blob;
 pub mod commit;
 pub mod error;
+pub mod tag;
 pub mod tree;
 
-pub use commit::write_commit;
-pub use tree::write_tree;
+pub use commit::{write_commit, Commit, Signature};
+pub use tag::Tag;
 
 use error::{ObjectError, Result};
 use std::{
     ffi::CStr,
     fs,
     io::{prelude::*, BufReader},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
@@ -18,11 +20,12 @@ use sha1::{Digest, Sha1};
 
 use crate::repository::Repository;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ObjectType {
     Blob,
     Tree,
     Commit,
+    Tag,
 }
 
 impl ObjectType {
@@ -47,6 +50,7 @@ impl TryFrom<&str> for ObjectType {
             "blob" => Ok(ObjectType::Blob),
             "tree" => Ok(ObjectType::Tree),
             "commit" => Ok(ObjectType::Commit),
+            "tag" => Ok(ObjectType::Tag),
             _ => Err(ObjectError::Other(format!(
                 "Unknown object type: {}",
                 value
@@ -61,6 +65,7 @@ impl std::fmt::Display for ObjectType {
             ObjectType::Blob => "blob",
             ObjectType::Tree => "tree",
             ObjectType::Commit => "commit",
+            ObjectType::Tag => "tag",
         };
         write!(f, "{}", kind)
     }
@@ -77,7 +82,7 @@ impl Object<()> {
     /// Returns an object from the objects directory of the repository (.git/objects)
     pub fn read(hash: &str, repo: &Repository) -> Result<Object<impl BufRead>> {
         let object_path = repo
-            .get_path()
+            .get_common_path()
             .join("objects")
             .join(&hash[..2])
             .join(&hash[2..]);
@@ -128,11 +133,18 @@ where
 
     /// Write the object to the objects directory of the repository (.git/objects)
     pub fn write_to_objects(self, repo: &Repository) -> Result<String> {
-        // Since hash is calculated during writing, we need to write to a temp file first
-        let temp_file_path = repo.get_path().join("objects/.temp");
+        // Since hash is calculated during writing, we need to write to a temp file first.
+        // Its name is unique, as objects may be written by several threads at once
+        static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+        let temp_file_name = format!(
+            ".temp-{}-{}",
+            std::process::id(),
+            TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+        );
+        let temp_file_path = repo.get_common_path().join("objects").join(temp_file_name);
         let temp_file = fs::File::create(&temp_file_path)?;
         let hash = self.write(temp_file)?;
-        let object_dir = repo.get_path().join("objects").join(&hash[..2]);
+        let object_dir = repo.get_common_path().join("objects").join(&hash[..2]);
         fs::create_dir_all(&object_dir)?;
         fs::rename(temp_file_path, object_dir.join(&hash[2..]))?;
 
@@ -0,0 +1,64 @@
+use std::io::Read;
+
+use crate::repository::Repository;
+
+use super::{
+    commit::Signature,
+    error::{ObjectError, Result},
+    Object, ObjectType,
+};
+
+/// A parsed annotated tag object.
+#[derive(Debug, Clone)]
+pub struct Tag {
+    pub object: String,
+    pub kind: ObjectType,
+    pub name: String,
+    pub tagger: Option<Signature>,
+    pub message: String,
+}
+
+impl Tag {
+    /// Reads and parses the tag with the given hash.
+    pub fn read(hash: &str, repo: &Repository) -> Result<Tag> {
+        let mut object = Object::read(hash, repo)?;
+        if object.kind != ObjectType::Tag {
+            Err(ObjectError::Other(format!("{hash} is not a tag")))?;
+        }
+        let mut content = String::new();
+        object.reader.read_to_string(&mut content)?;
+
+        Tag::parse(&content)
+    }
+
+    /// Parses the content of a tag object.
+    pub fn parse(content: &str) -> Result<Tag> {
+        let (headers, message) = content.split_once("\n\n").unwrap_or((content, ""));
+
+        let mut object = None;
+        let mut kind = None;
+        let mut name = None;
+        let mut tagger = None;
+        for line in headers.lines() {
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            match key {
+                "object" => object = Some(value.to_string()),
+                "type" => kind = Some(ObjectType::try_from(value)?),
+                "tag" => name = Some(value.to_string()),
+                "tagger" => tagger = Some(Signature::parse(value)?),
+                _ => {}
+            }
+        }
+        let missing = |field: &str| ObjectError::Parse(format!("tag without {field}"));
+
+        Ok(Tag {
+            object: object.ok_or_else(|| missing("object"))?,
+            kind: kind.ok_or_else(|| missing("type"))?,
+            name: name.ok_or_else(|| missing("name"))?,
+            tagger,
+            message: message.to_string(),
+        })
+    }
+}
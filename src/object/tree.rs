@@ -1,57 +1,268 @@
-use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Read,
+    os::unix::fs::PermissionsExt,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+};
 
-use crate::repository::Repository;
+use crate::repository::{config::Config, convert::Convert, Repository};
 
 use super::{
     error::{ObjectError, Result},
     Object, ObjectType,
 };
 
-/// Recursively write a tree object to the repository.
-pub fn write_tree<P: AsRef<Path>>(path: P, repo: &Repository) -> Result<String> {
-    let mut entries = vec![];
+pub const MODE_FILE: u32 = 0o100644;
+pub const MODE_EXECUTABLE: u32 = 0o100755;
+pub const MODE_SYMLINK: u32 = 0o120000;
+pub const MODE_TREE: u32 = 0o40000;
+pub const MODE_GITLINK: u32 = 0o160000;
 
-    let dir = fs::read_dir(path)?;
-    for entry in dir {
-        let entry = entry?;
-        let path = entry.path();
-        let name = entry.file_name().to_string_lossy().to_string();
+/// The hash of the tree without entries.
+pub const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
 
-        // Skip hidden files
-        if name.starts_with('.') {
-            continue;
+/// An entry of a tree object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: String,
+    pub hash: String,
+}
+
+/// A parsed tree object.
+#[derive(Debug, Clone)]
+pub struct Tree {
+    pub entries: Vec<TreeEntry>,
+}
+
+impl Tree {
+    /// Reads and parses the tree with the given hash. Like in git, the empty
+    /// tree exists even if it was never written.
+    pub fn read(hash: &str, repo: &Repository) -> Result<Tree> {
+        if hash == EMPTY_TREE {
+            return Ok(Tree { entries: vec![] });
+        }
+        let mut object = Object::read(hash, repo)?;
+        if object.kind != ObjectType::Tree {
+            Err(ObjectError::Other(format!("{hash} is not a tree")))?;
+        }
+        let mut content = vec![];
+        object.reader.read_to_end(&mut content)?;
+
+        Tree::parse(&content)
+    }
+
+    /// Parses the content of a tree object.
+    pub fn parse(mut content: &[u8]) -> Result<Tree> {
+        let err = || ObjectError::Parse("invalid tree entry".to_string());
+        let mut entries = vec![];
+        // format: "<mode> <name>\0<hash>"
+        while !content.is_empty() {
+            let space = content.iter().position(|&b| b == b' ').ok_or_else(err)?;
+            let nul = content.iter().position(|&b| b == 0).ok_or_else(err)?;
+            if nul < space || content.len() < nul + 21 {
+                Err(err())?;
+            }
+            let mode = std::str::from_utf8(&content[..space]).map_err(|_| err())?;
+            entries.push(TreeEntry {
+                mode: u32::from_str_radix(mode, 8).map_err(|_| err())?,
+                name: String::from_utf8_lossy(&content[space + 1..nul]).to_string(),
+                hash: hex::encode(&content[nul + 1..nul + 21]),
+            });
+            content = &content[nul + 21..];
         }
 
-        let meta = entry.metadata()?;
-        let mut mode = meta.permissions().mode();
-        let hash = if meta.is_dir() {
-            // Trees don't have bits for executable permissions
-            mode = 0o40000;
-            write_tree(path, repo)?
+        Ok(Tree { entries })
+    }
+
+    /// Reads a tree and all its subtrees, returning every non-tree entry by
+    /// its full path as `(mode, hash)`.
+    pub fn read_recursive(
+        hash: &str,
+        repo: &Repository,
+    ) -> Result<BTreeMap<String, (u32, String)>> {
+        let mut files = BTreeMap::new();
+        collect_files(hash, "", repo, &mut files)?;
+        Ok(files)
+    }
+}
+
+fn collect_files(
+    hash: &str,
+    prefix: &str,
+    repo: &Repository,
+    files: &mut BTreeMap<String, (u32, String)>,
+) -> Result<()> {
+    for entry in Tree::read(hash, repo)?.entries {
+        let path = format!("{prefix}{}", entry.name);
+        if entry.mode == MODE_TREE {
+            collect_files(&entry.hash, &format!("{path}/"), repo, files)?;
         } else {
-            Object::blob_from_file(path)?.write_to_objects(repo)?
+            files.insert(path, (entry.mode, entry.hash));
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the tree entry mode of a file, as git normalizes it: regular
+/// files are either executable or not, other permission bits are dropped.
+pub fn file_mode(meta: &fs::Metadata) -> u32 {
+    if meta.is_symlink() {
+        MODE_SYMLINK
+    } else if meta.is_dir() {
+        MODE_TREE
+    } else if meta.permissions().mode() & 0o111 != 0 {
+        MODE_EXECUTABLE
+    } else {
+        MODE_FILE
+    }
+}
+
+/// Returns the number of threads writing blobs by default: as many as there
+/// are cores, or one if `core.preloadIndex` is false.
+pub fn default_jobs(config: &Config) -> usize {
+    match config.get_bool("core.preloadIndex") {
+        Some(false) => 1,
+        _ => thread::available_parallelism().map_or(1, usize::from),
+    }
+}
+
+/// Writes the blobs of the given files, by path relative to the root,
+/// returning their hashes in the same order. With more than one job the
+/// files are read, converted, hashed and compressed by that many threads,
+/// each holding one file at a time. The threads share one conversion, so a
+/// filter process is started once and filters their files in turn.
+pub fn write_blobs(
+    files: &[(String, fs::Metadata)],
+    repo: &Repository,
+    jobs: usize,
+) -> Result<Vec<String>> {
+    let convert = repo
+        .convert()
+        .map_err(|e| ObjectError::Other(format!("could not read attributes: {e}")))?;
+    let jobs = jobs.clamp(1, files.len().max(1));
+    if jobs == 1 {
+        return files
+            .iter()
+            .map(|(path, meta)| write_blob(path, meta, repo, &convert))
+            .collect();
+    }
+
+    // The workers take the next file until there are none left, or one of
+    // them failed
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let mut hashes = vec![String::new(); files.len()];
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| -> Result<Vec<(usize, String)>> {
+                    let mut written = vec![];
+                    while !failed.load(Ordering::Relaxed) {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some((path, meta)) = files.get(i) else {
+                            break;
+                        };
+                        match write_blob(path, meta, repo, &convert) {
+                            Ok(hash) => written.push((i, hash)),
+                            Err(e) => {
+                                failed.store(true, Ordering::Relaxed);
+                                return Err(e);
+                            }
+                        }
+                    }
+                    Ok(written)
+                })
+            })
+            .collect();
+        for worker in workers {
+            let written = worker.join().expect("blob writer thread panicked")?;
+            for (i, hash) in written {
+                hashes[i] = hash;
+            }
+        }
+        Ok(hashes)
+    })
+}
+
+/// Writes the blob of a file in the working tree, by its path relative to
+/// the root, returning its hash. The content is converted as it's checked in.
+pub fn write_blob(
+    path: &str,
+    meta: &fs::Metadata,
+    repo: &Repository,
+    convert: &Convert,
+) -> Result<String> {
+    let content = convert
+        .read_file(path, meta, true)
+        .map_err(|e| ObjectError::Other(e.to_string()))?;
+    Object::blob_from_bytes(&content).write_to_objects(repo)
+}
+
+/// Writes the trees holding the given files, by full path, returning the
+/// hash of the root tree. The blobs must exist already.
+pub fn write_tree_from_files(
+    files: &BTreeMap<String, (u32, String)>,
+    repo: &Repository,
+) -> Result<String> {
+    let files: Vec<(&str, &(u32, String))> = files.iter().map(|(p, f)| (p.as_str(), f)).collect();
+    write_subtree(&files, repo)
+}
+
+/// Writes the tree of `files`, whose paths are relative to the tree.
+fn write_subtree(files: &[(&str, &(u32, String))], repo: &Repository) -> Result<String> {
+    let mut entries = vec![];
+    let mut i = 0;
+    while i < files.len() {
+        let (path, (mode, hash)) = files[i];
+        let Some((dir, _)) = path.split_once('/') else {
+            entries.push((*mode, path.to_string(), hash.clone()));
+            i += 1;
+            continue;
         };
 
-        let hash =
-            hex::decode(&hash).map_err(|_| ObjectError::Other("invalid hash".to_string()))?;
-        entries.push((mode, name, hash));
+        // The files of a directory are next to each other
+        let prefix = format!("{dir}/");
+        let end = files[i..]
+            .iter()
+            .position(|(path, _)| !path.starts_with(&prefix))
+            .map_or(files.len(), |n| i + n);
+        let children: Vec<_> = files[i..end]
+            .iter()
+            .map(|(path, file)| (&path[prefix.len()..], *file))
+            .collect();
+        entries.push((MODE_TREE, dir.to_string(), write_subtree(&children, repo)?));
+        i = end;
     }
 
-    // Git stores entries in a tree in alphabetical order
-    entries.sort_by(|(_, a, _), (_, b, _)| a.cmp(b));
+    write_entries(entries, repo)
+}
+
+/// Writes a tree object with the given `(mode, name, hash)` entries.
+pub fn write_entries(mut entries: Vec<(u32, String, String)>, repo: &Repository) -> Result<String> {
+    // Git sorts the entries of a tree by name, comparing directories as if
+    // their names ended in a slash
+    let sort_key = |mode: u32, name: &str| match mode {
+        MODE_TREE => format!("{name}/"),
+        _ => name.to_string(),
+    };
+    entries.sort_by_cached_key(|(mode, name, _)| sort_key(*mode, name));
     // format: "<mode> <name>\0<hash>"
-    let entries: Vec<u8> = entries
-        .into_iter()
-        .flat_map(|(mode, name, hash)| {
-            let header = format!("{:o} {}\0", mode, name);
-            [header.as_bytes(), &hash].concat()
-        }) //"{:o} {}\0{}", mode, name, hash))
-        .collect();
+    let mut content = vec![];
+    for (mode, name, hash) in entries {
+        let hash =
+            hex::decode(&hash).map_err(|_| ObjectError::Other("invalid hash".to_string()))?;
+        content.extend_from_slice(format!("{:o} {}\0", mode, name).as_bytes());
+        content.extend_from_slice(&hash);
+    }
 
     let object = Object {
         kind: ObjectType::Tree,
-        size: entries.len() as u64,
-        reader: entries.as_slice(),
+        size: content.len() as u64,
+        reader: content.as_slice(),
     };
 
     object.write_to_objects(repo)
@@ -67,7 +278,7 @@ mod tests {
         use crate::commands::init;
         let temp_dir = tempdir().unwrap();
         let temp_dir_path = temp_dir.path().to_path_buf();
-        init::run(Some(temp_dir_path.clone())).unwrap();
+        init::run(Some(temp_dir_path.clone()), "files").unwrap();
 
         let repo = Repository::from_path(&temp_dir_path).unwrap();
 
@@ -75,7 +286,11 @@ mod tests {
         fs::create_dir(&foo_dir).unwrap();
         fs::write(foo_dir.join("bar"), "Hello Test\n").unwrap();
         fs::write(temp_dir_path.join("hello.txt"), "Hello World\n").unwrap();
-        let hash = write_tree(&temp_dir_path, &repo).unwrap();
+        let hash = repo.add_all().unwrap();
         assert_eq!(hash, "817795ce05795f9aa7bc8b744d2c57b2cffcf15c");
+
+        // The same tree, written from its files
+        let files = Tree::read_recursive(&hash, &repo).unwrap();
+        assert_eq!(write_tree_from_files(&files, &repo).unwrap(), hash);
     }
 }
@@ -0,0 +1,99 @@
+//! Paging the output of commands like `log` through `less` when writing to
+//! a terminal, as git does.
+
+use std::{
+    env,
+    io::{self, IsTerminal, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+use crate::repository::Repository;
+
+/// The output of a command: the pager's input if one was started,
+/// otherwise stdout.
+pub struct Pager {
+    child: Option<(Child, ChildStdin)>,
+}
+
+impl Pager {
+    /// Starts the pager for `command` if stdout is a terminal, unless
+    /// `pager.<command>` turns paging off. The pager is `$GIT_PAGER`, the
+    /// one named by `pager.<command>`, `core.pager`, `$PAGER` or `less`.
+    pub fn start(repo: &Repository, command: &str) -> Pager {
+        let no_pager = Pager { child: None };
+        if !io::stdout().is_terminal() {
+            return no_pager;
+        }
+        let Some(pager) = pager_command(repo, command) else {
+            return no_pager;
+        };
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(&pager).stdin(Stdio::piped());
+        // Quit if the output fits on one screen, and pass colors through
+        if env::var_os("LESS").is_none() {
+            cmd.env("LESS", "FRX");
+        }
+        if env::var_os("LV").is_none() {
+            cmd.env("LV", "-c");
+        }
+        match cmd.spawn() {
+            Ok(mut child) => match child.stdin.take() {
+                Some(stdin) => Pager {
+                    child: Some((child, stdin)),
+                },
+                None => no_pager,
+            },
+            Err(_) => no_pager,
+        }
+    }
+
+    /// Waits for the user to quit the pager.
+    pub fn finish(self) -> io::Result<()> {
+        if let Some((mut child, stdin)) = self.child {
+            drop(stdin);
+            child.wait()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.child {
+            Some((_, stdin)) => stdin.write(buf),
+            None => io::stdout().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.child {
+            Some((_, stdin)) => stdin.flush(),
+            None => io::stdout().flush(),
+        }
+    }
+}
+
+/// Returns the pager to use for `command`, or `None` for no pager.
+fn pager_command(repo: &Repository, command: &str) -> Option<String> {
+    let config = repo.config().ok();
+    let config_value = |key: &str| config.as_ref()?.get(key).map(str::to_string);
+
+    let key = format!("pager.{command}");
+    let pager = match config.as_ref().and_then(|c| c.get_bool(&key)) {
+        Some(false) => return None,
+        Some(true) => None,
+        None => config_value(&key),
+    };
+    let pager = env::var("GIT_PAGER")
+        .ok()
+        .or(pager)
+        .or_else(|| config_value("core.pager"))
+        .or_else(|| env::var("PAGER").ok())
+        .unwrap_or_else(|| "less".to_string());
+
+    match pager.as_str() {
+        "" | "cat" => None,
+        _ => Some(pager),
+    }
+}
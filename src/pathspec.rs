@@ -0,0 +1,61 @@
+//! Matching paths against pathspecs, the path arguments of commands.
+//!
+//! A pathspec matches a path exactly, as one of its leading directories, or
+//! as a glob pattern in which `*` also matches `/`.
+
+use crate::wildmatch::{is_glob, wildmatch};
+
+/// How a pathspec matched a path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathspecMatch {
+    Exact,
+    /// The pathspec names a directory containing the path
+    LeadingDir,
+    Glob,
+}
+
+/// Returns how `spec` matches `path`, if it does. Both are relative to the
+/// root of the working tree; `.` matches everything.
+pub fn match_pathspec(spec: &str, path: &str) -> Option<PathspecMatch> {
+    let spec = spec.strip_prefix("./").unwrap_or(spec);
+    if spec.is_empty() || spec == "." {
+        return Some(PathspecMatch::LeadingDir);
+    }
+
+    let dir = spec.trim_end_matches('/');
+    if path == dir && !spec.ends_with('/') {
+        Some(PathspecMatch::Exact)
+    } else if path
+        .strip_prefix(dir)
+        .is_some_and(|rest| rest.starts_with('/'))
+    {
+        Some(PathspecMatch::LeadingDir)
+    } else if is_glob(spec) && wildmatch(spec, path, 0) {
+        Some(PathspecMatch::Glob)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_pathspec() {
+        let cases = [
+            ("a", "a", Some(PathspecMatch::Exact)),
+            ("a", "a/b", Some(PathspecMatch::LeadingDir)),
+            ("a/", "a/b", Some(PathspecMatch::LeadingDir)),
+            ("a/", "a", None),
+            ("a", "ab", None),
+            (".", "x/y", Some(PathspecMatch::LeadingDir)),
+            ("*.rs", "src/main.rs", Some(PathspecMatch::Glob)),
+            ("src/*", "src/a/b", Some(PathspecMatch::Glob)),
+            ("*.rs", "main.c", None),
+        ];
+        for (spec, path, expected) in cases {
+            assert_eq!(match_pathspec(spec, path), expected, "{spec} {path}");
+        }
+    }
+}
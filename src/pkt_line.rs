@@ -0,0 +1,105 @@
+//! Git's pkt-line framing, used to talk to long-running filter processes.
+//!
+//! Each packet starts with its length, including the four length bytes, as
+//! four hex digits. The length `0000` is a flush packet, which ends a list of
+//! packets.
+
+use std::io::{self, Read, Write};
+
+/// The most data a packet can hold.
+pub const MAX_PACKET_DATA: usize = 65516;
+
+/// Writes a packet holding `data`, which must fit in a packet.
+pub fn write_packet(writer: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    if data.len() > MAX_PACKET_DATA {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "packet is too large",
+        ));
+    }
+    write!(writer, "{:04x}", data.len() + 4)?;
+    writer.write_all(data)
+}
+
+/// Writes a packet holding a line of text, adding the newline.
+pub fn write_text(writer: &mut impl Write, text: &str) -> io::Result<()> {
+    write_packet(writer, format!("{text}\n").as_bytes())
+}
+
+/// Writes a flush packet.
+pub fn write_flush(writer: &mut impl Write) -> io::Result<()> {
+    writer.write_all(b"0000")
+}
+
+/// Writes `content` in as many packets as needed, followed by a flush packet.
+pub fn write_content(writer: &mut impl Write, content: &[u8]) -> io::Result<()> {
+    for chunk in content.chunks(MAX_PACKET_DATA) {
+        write_packet(writer, chunk)?;
+    }
+    write_flush(writer)
+}
+
+/// Reads a packet, returning its data, or `None` for a flush packet.
+pub fn read_packet(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0; 4];
+    reader.read_exact(&mut header)?;
+    let len = std::str::from_utf8(&header)
+        .ok()
+        .and_then(|header| usize::from_str_radix(header, 16).ok())
+        .ok_or_else(|| invalid_data(format!("bad packet length '{}'", header.escape_ascii())))?;
+    match len {
+        0 => return Ok(None),
+        1..=4 => Err(invalid_data(format!("bad packet length {len}")))?,
+        _ => {}
+    }
+
+    let mut data = vec![0; len - 4];
+    reader.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+/// Reads packets of text up to a flush packet, without their newlines.
+pub fn read_text_list(reader: &mut impl Read) -> io::Result<Vec<String>> {
+    let mut lines = vec![];
+    while let Some(data) = read_packet(reader)? {
+        let line = String::from_utf8_lossy(&data);
+        lines.push(line.strip_suffix('\n').unwrap_or(&line).to_string());
+    }
+
+    Ok(lines)
+}
+
+/// Reads packets up to a flush packet, returning their data joined.
+pub fn read_content(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut content = vec![];
+    while let Some(data) = read_packet(reader)? {
+        content.extend(data);
+    }
+
+    Ok(content)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkt_line() {
+        let mut buf = vec![];
+        write_text(&mut buf, "version=2").unwrap();
+        write_flush(&mut buf).unwrap();
+        let content = vec![b'x'; MAX_PACKET_DATA + 1];
+        write_content(&mut buf, &content).unwrap();
+        assert!(buf.starts_with(b"000eversion=2\n0000fff0xx"));
+
+        let mut reader = buf.as_slice();
+        assert_eq!(read_text_list(&mut reader).unwrap(), ["version=2"]);
+        assert_eq!(read_content(&mut reader).unwrap(), content);
+        assert!(reader.is_empty());
+        assert!(read_packet(&mut b"0003".as_slice()).is_err());
+    }
+}
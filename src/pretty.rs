@@ -0,0 +1,622 @@
+//! Pretty-printing commits like git's `--pretty`: the built-in formats such
+//! as `medium` and `oneline`, user formats with placeholders like `%h %s`,
+//! and the ref names decorating commits.
+
+use std::collections::HashMap;
+
+use crate::{
+    color,
+    date::{self, DateMode},
+    object::commit::{Commit, Signature},
+    repository::{error::Result, Repository},
+    revision::RevCommit,
+};
+
+/// How to print each commit.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format {
+    Oneline,
+    Short,
+    Medium,
+    Full,
+    Fuller,
+    /// A format string, whose output is terminated by a newline with
+    /// `tformat:` and only separated from the next commit with `format:`
+    User {
+        format: String,
+        terminator: bool,
+    },
+}
+
+impl Format {
+    /// Parses a `--pretty` or `--format` argument.
+    pub fn parse(s: &str) -> Option<Format> {
+        let format = match s {
+            "oneline" => Format::Oneline,
+            "short" => Format::Short,
+            "medium" => Format::Medium,
+            "full" => Format::Full,
+            "fuller" => Format::Fuller,
+            s => {
+                let (format, terminator) =
+                    match (s.strip_prefix("format:"), s.strip_prefix("tformat:")) {
+                        (Some(format), _) => (format, false),
+                        (_, Some(format)) => (format, true),
+                        _ if s.contains('%') => (s, true),
+                        _ => return None,
+                    };
+                Format::User {
+                    format: format.to_string(),
+                    terminator,
+                }
+            }
+        };
+        Some(format)
+    }
+
+    /// Returns true if each commit's output is followed by a newline,
+    /// rather than separated from the next one by a newline.
+    pub fn use_terminator(&self) -> bool {
+        match self {
+            Format::Oneline => true,
+            Format::User { terminator, .. } => *terminator,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RefKind {
+    Head,
+    Branch,
+    Remote,
+    Tag,
+    Stash,
+}
+
+impl RefKind {
+    fn color(self) -> &'static str {
+        match self {
+            RefKind::Head => color::BOLD_CYAN,
+            RefKind::Branch => color::BOLD_GREEN,
+            RefKind::Remote => color::BOLD_RED,
+            RefKind::Tag => color::BOLD_YELLOW,
+            RefKind::Stash => color::BOLD_MAGENTA,
+        }
+    }
+}
+
+/// The refs pointing at each commit, to decorate them with like
+/// `(HEAD -> main, tag: v1.0)`.
+#[derive(Debug, Default)]
+pub struct Decorations {
+    /// The full names of the refs by commit, in the order to show them
+    refs: HashMap<String, Vec<(String, RefKind)>>,
+    /// The branch HEAD points to, shown as `HEAD -> branch`
+    head: Option<String>,
+    /// Show full ref names like `refs/heads/main`
+    full: bool,
+}
+
+impl Decorations {
+    /// Reads the branches, remote-tracking branches, tags, the stash and
+    /// HEAD. Annotated tags decorate the commit they point to.
+    pub fn load(repo: &Repository, full: bool) -> Result<Decorations> {
+        let mut refs: HashMap<String, Vec<(String, RefKind)>> = HashMap::new();
+        // Like git, list the refs last to first, then HEAD before them
+        for (refname, hash) in repo.list_refs("refs/")? {
+            let kind = if refname.starts_with("refs/heads/") {
+                RefKind::Branch
+            } else if refname.starts_with("refs/remotes/") {
+                RefKind::Remote
+            } else if refname.starts_with("refs/tags/") {
+                RefKind::Tag
+            } else if refname == "refs/stash" {
+                RefKind::Stash
+            } else {
+                continue;
+            };
+            let hash = repo.peel_tags(&hash)?;
+            refs.entry(hash).or_default().insert(0, (refname, kind));
+        }
+        if let Some(hash) = repo.read_ref("HEAD")? {
+            refs.entry(hash)
+                .or_default()
+                .insert(0, ("HEAD".to_string(), RefKind::Head));
+        }
+
+        Ok(Decorations {
+            refs,
+            head: repo.read_symbolic_ref("HEAD")?,
+            full,
+        })
+    }
+
+    /// Returns the name to show for a ref: without `refs/heads/`,
+    /// `refs/tags/` or `refs/remotes/` unless full names are shown.
+    fn name<'a>(&self, refname: &'a str) -> &'a str {
+        if self.full {
+            return refname;
+        }
+        ["refs/heads/", "refs/tags/", "refs/remotes/"]
+            .iter()
+            .find_map(|prefix| refname.strip_prefix(prefix))
+            .unwrap_or(refname)
+    }
+
+    /// Formats the decorations of a commit, e.g. ` (HEAD -> main)` with the
+    /// prefix ` (`, separator `, ` and suffix `)`.
+    pub fn format(
+        &self,
+        hash: &str,
+        color: bool,
+        prefix: &str,
+        separator: &str,
+        suffix: &str,
+    ) -> String {
+        let Some(refs) = self.refs.get(hash) else {
+            return String::new();
+        };
+        let paint = |s: &'static str| if color { s } else { "" };
+
+        // The branch HEAD points to is shown together with HEAD
+        let has_head = refs.iter().any(|(_, kind)| *kind == RefKind::Head);
+        let head_branch = refs.iter().find(|(refname, kind)| {
+            has_head && *kind == RefKind::Branch && Some(refname) == self.head.as_ref()
+        });
+
+        let mut out = String::new();
+        let mut prefix = prefix;
+        for entry in refs {
+            if head_branch.is_some_and(|branch| std::ptr::eq(branch, entry)) {
+                continue;
+            }
+            let (refname, kind) = entry;
+            out.push_str(paint(color::YELLOW));
+            out.push_str(prefix);
+            out.push_str(paint(color::RESET));
+            out.push_str(paint(kind.color()));
+            if *kind == RefKind::Tag {
+                out.push_str("tag: ");
+            }
+            out.push_str(self.name(refname));
+            if let (RefKind::Head, Some((branch, kind))) = (kind, head_branch) {
+                out.push_str(" -> ");
+                out.push_str(paint(color::RESET));
+                out.push_str(paint(kind.color()));
+                out.push_str(self.name(branch));
+            }
+            out.push_str(paint(color::RESET));
+            prefix = separator;
+        }
+        out.push_str(paint(color::YELLOW));
+        out.push_str(suffix);
+        out.push_str(paint(color::RESET));
+        out
+    }
+}
+
+/// The options for printing commits.
+#[derive(Debug)]
+pub struct Pretty {
+    pub format: Format,
+    pub date_mode: DateMode,
+    /// Abbreviate the hash in the commit line of the built-in formats
+    pub abbrev_commit: bool,
+    pub color: bool,
+    /// Decorate the commit line of the built-in formats
+    pub decorate: bool,
+    pub decorations: Decorations,
+}
+
+impl Pretty {
+    fn paint(&self, s: &'static str) -> &'static str {
+        if self.color {
+            s
+        } else {
+            ""
+        }
+    }
+
+    fn abbrev<'a>(&self, hash: &'a str) -> &'a str {
+        match self.abbrev_commit {
+            true => &hash[..7],
+            false => hash,
+        }
+    }
+
+    /// Returns the line introducing a commit in the built-in formats, like
+    /// `commit 3b18e51 (HEAD -> main)`, without its line ending.
+    pub fn commit_line(&self, rev: &RevCommit) -> String {
+        let mut line = String::from(self.paint(color::YELLOW));
+        if self.format != Format::Oneline {
+            line.push_str("commit ");
+        }
+        line.push_str(self.abbrev(&rev.hash));
+        line.push_str(self.paint(color::RESET));
+        if self.decorate {
+            line.push_str(
+                &self
+                    .decorations
+                    .format(&rev.hash, self.color, " (", ", ", ")"),
+            );
+        }
+        line
+    }
+
+    /// Formats a commit, after its commit line for the built-in formats.
+    pub fn format(&self, rev: &RevCommit) -> String {
+        if let Format::User { format, .. } = &self.format {
+            return self.expand(format, rev);
+        }
+
+        let commit = &rev.commit;
+        let mut out = String::new();
+        if self.format != Format::Oneline {
+            if commit.parents.len() > 1 {
+                out.push_str("Merge:");
+                for parent in &commit.parents {
+                    out.push(' ');
+                    out.push_str(&parent[..7]);
+                }
+                out.push('\n');
+            }
+            self.push_ident(&mut out, "Author", &commit.author);
+            if matches!(self.format, Format::Full | Format::Fuller) {
+                self.push_ident(&mut out, "Commit", &commit.committer);
+            }
+            out.push('\n');
+        }
+
+        let message = skip_blank_lines(&commit.message);
+        if self.format == Format::Oneline {
+            out.push_str(&subject(message).0);
+            return out;
+        }
+
+        // Indent the message, expanding tabs for the longer formats
+        let expand_tabs = self.format != Format::Short;
+        let mut first = true;
+        for line in message.lines() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                if first {
+                    continue;
+                }
+                if self.format == Format::Short {
+                    break;
+                }
+            }
+            first = false;
+            out.push_str("    ");
+            match expand_tabs {
+                true => out.push_str(&expand_tabs_in(line)),
+                false => out.push_str(line),
+            }
+            out.push('\n');
+        }
+        out.truncate(out.trim_end().len());
+        out.push('\n');
+        out
+    }
+
+    fn push_ident(&self, out: &mut String, what: &str, ident: &Signature) {
+        let padding = if self.format == Format::Fuller {
+            "    "
+        } else {
+            ""
+        };
+        out.push_str(&format!(
+            "{what}: {padding}{} <{}>\n",
+            ident.name, ident.email
+        ));
+        let date = date::format_date(ident.time, &ident.tz, &self.date_mode);
+        match self.format {
+            Format::Medium => out.push_str(&format!("Date:   {date}\n")),
+            Format::Fuller => out.push_str(&format!("{what}Date: {date}\n")),
+            _ => {}
+        }
+    }
+
+    /// Expands the placeholders of a user format for a commit.
+    pub fn expand(&self, format: &str, rev: &RevCommit) -> String {
+        let mut out = String::new();
+        let mut auto_color = false;
+        let mut rest = format;
+        while let Some(start) = rest.find('%') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+
+            // `%+x` adds a newline before a non-empty expansion, `% x` a
+            // space, and `%-x` removes the newlines before an empty one
+            let magic = rest.chars().next().filter(|c| matches!(c, '+' | '-' | ' '));
+            if magic.is_some() {
+                rest = &rest[1..];
+            }
+
+            let mut expansion = String::new();
+            let consumed = self.expand_placeholder(rest, rev, &mut auto_color, &mut expansion);
+            if consumed == 0 {
+                out.push('%');
+                if let Some(c) = magic {
+                    out.push(c);
+                }
+                continue;
+            }
+            rest = &rest[consumed..];
+
+            match magic {
+                Some('+') if !expansion.is_empty() => out.push('\n'),
+                Some(' ') if !expansion.is_empty() => out.push(' '),
+                Some('-') if expansion.is_empty() => out.truncate(out.trim_end_matches('\n').len()),
+                _ => {}
+            }
+            out.push_str(&expansion);
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// Expands the placeholder at the start of `placeholder` (after its
+    /// `%`), returning the number of bytes it takes, or 0 if it's unknown.
+    fn expand_placeholder(
+        &self,
+        placeholder: &str,
+        rev: &RevCommit,
+        auto_color: &mut bool,
+        out: &mut String,
+    ) -> usize {
+        let commit = &rev.commit;
+        let Some(c) = placeholder.chars().next() else {
+            return 0;
+        };
+        let paint_if = |on: bool, s: &'static str| if on { s } else { "" };
+
+        match c {
+            'n' => out.push('\n'),
+            '%' => out.push('%'),
+            'x' => {
+                let Some(byte) = placeholder
+                    .get(1..3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                else {
+                    return 0;
+                };
+                out.push(byte as char);
+                return 3;
+            }
+            'C' => return self.expand_color(placeholder, auto_color, out),
+            'H' => {
+                out.push_str(paint_if(*auto_color, color::YELLOW));
+                out.push_str(&rev.hash);
+                out.push_str(paint_if(*auto_color, color::RESET));
+            }
+            'h' => {
+                out.push_str(paint_if(*auto_color, color::YELLOW));
+                out.push_str(&rev.hash[..7]);
+                out.push_str(paint_if(*auto_color, color::RESET));
+            }
+            'T' => out.push_str(&commit.tree),
+            't' => out.push_str(&commit.tree[..7]),
+            'P' => out.push_str(&commit.parents.join(" ")),
+            'p' => {
+                let parents: Vec<&str> = commit.parents.iter().map(|p| &p[..7]).collect();
+                out.push_str(&parents.join(" "));
+            }
+            'a' | 'c' => {
+                let ident = if c == 'a' {
+                    &commit.author
+                } else {
+                    &commit.committer
+                };
+                let Some(field) = placeholder[1..].chars().next() else {
+                    return 0;
+                };
+                let (time, tz) = (ident.time, ident.tz.as_str());
+                let expansion = match field {
+                    'n' | 'N' => ident.name.clone(),
+                    'e' | 'E' => ident.email.clone(),
+                    'l' | 'L' => ident.email.split('@').next().unwrap_or("").to_string(),
+                    'd' => date::format_date(time, tz, &self.date_mode),
+                    'D' => date::format_date(time, tz, &DateMode::Rfc),
+                    'r' => date::format_date(time, tz, &DateMode::Relative),
+                    't' => time.to_string(),
+                    'i' => date::format_date(time, tz, &DateMode::Iso),
+                    'I' => date::format_date(time, tz, &DateMode::IsoStrict),
+                    's' => date::format_date(time, tz, &DateMode::Short),
+                    _ => return 0,
+                };
+                out.push_str(&expansion);
+                return 2;
+            }
+            's' => out.push_str(&subject(skip_blank_lines(&commit.message)).0),
+            'f' => out.push_str(&sanitized_subject(
+                &subject(skip_blank_lines(&commit.message)).0,
+            )),
+            'b' => out.push_str(body(commit)),
+            'B' => out.push_str(&commit.message),
+            'd' => out.push_str(
+                &self
+                    .decorations
+                    .format(&rev.hash, *auto_color, " (", ", ", ")"),
+            ),
+            'D' => out.push_str(
+                &self
+                    .decorations
+                    .format(&rev.hash, *auto_color, "", ", ", ""),
+            ),
+            _ => return 0,
+        }
+        c.len_utf8()
+    }
+
+    /// Expands `%C(<color>)`, `%C(auto)` or the short `%Cred`, `%Cgreen`,
+    /// `%Cblue` and `%Creset`, which only color if colors are enabled.
+    fn expand_color(&self, placeholder: &str, auto_color: &mut bool, out: &mut String) -> usize {
+        if let Some(spec) = placeholder.strip_prefix("C(") {
+            let Some(end) = spec.find(')') else {
+                return 0;
+            };
+            let consumed = 2 + end + 1;
+            let spec = &spec[..end];
+            if spec == "auto" {
+                *auto_color = self.color;
+                if self.color && !out.is_empty() {
+                    out.push_str(color::RESET);
+                }
+                return consumed;
+            }
+            let (spec, on) = match (spec.strip_prefix("auto,"), spec.strip_prefix("always,")) {
+                (Some(spec), _) => (spec, self.color),
+                (_, Some(spec)) => (spec, true),
+                _ => (spec, self.color),
+            };
+            if on {
+                out.push_str(&color::parse(spec).unwrap_or_default());
+            }
+            return consumed;
+        }
+
+        let colors = [
+            ("Cred", color::RED),
+            ("Cgreen", color::GREEN),
+            ("Cblue", color::BLUE),
+            ("Creset", color::RESET),
+        ];
+        for (name, escape) in colors {
+            if placeholder.starts_with(name) {
+                if self.color {
+                    out.push_str(escape);
+                }
+                return name.len();
+            }
+        }
+        0
+    }
+}
+
+/// Skips the blank lines at the start of a message.
+fn skip_blank_lines(message: &str) -> &str {
+    let mut rest = message;
+    while let Some((line, next)) = rest.split_once('\n') {
+        if !line.trim().is_empty() {
+            break;
+        }
+        rest = next;
+    }
+    if rest.trim().is_empty() {
+        return "";
+    }
+    rest
+}
+
+/// Returns the first paragraph of a message with its lines joined by
+/// spaces, and the rest of the message.
+fn subject(message: &str) -> (String, &str) {
+    let mut lines = vec![];
+    let mut rest = message;
+    while !rest.is_empty() {
+        let (line, next) = rest.split_once('\n').unwrap_or((rest, ""));
+        rest = next;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    (lines.join(" "), rest)
+}
+
+/// Returns the message after the subject and the blank lines following it.
+fn body(commit: &Commit) -> &str {
+    skip_blank_lines(subject(skip_blank_lines(&commit.message)).1)
+}
+
+/// Turns a subject into something usable as a file name, like
+/// `Fix-the-bug` for `Fix the bug!`.
+fn sanitized_subject(subject: &str) -> String {
+    let mut out = String::new();
+    // 2 at the start, 1 after characters to replace with a dash
+    let mut space = 2;
+    let mut chars = subject.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            if space == 1 {
+                out.push('-');
+            }
+            space = 0;
+            out.push(c);
+            if c == '.' {
+                while chars.peek() == Some(&'.') {
+                    chars.next();
+                }
+            }
+        } else {
+            space |= 1;
+        }
+    }
+    out.truncate(out.trim_end_matches(['.', '-']).len());
+    out
+}
+
+/// Expands tabs to the next multiple of 8 characters.
+fn expand_tabs_in(line: &str) -> String {
+    let mut out = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = 8 - width % 8;
+            out.extend(std::iter::repeat_n(' ', spaces));
+            width += spaces;
+        } else {
+            out.push(c);
+            width += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_format() {
+        let signature = Signature {
+            name: "A U Thor".to_string(),
+            email: "author@example.com".to_string(),
+            time: 1720703241,
+            tz: "+0200".to_string(),
+        };
+        let rev = RevCommit {
+            hash: "3b18e512dba79e4c8300dd08aeb37f8e728b8dad".to_string(),
+            commit: Commit {
+                tree: "4b825dc642cb6eb9a060e54bf8d69288fbee4904".to_string(),
+                parents: vec![],
+                author: signature.clone(),
+                committer: signature,
+                message: "\nFix the\nbug!\n\nBecause.\n".to_string(),
+            },
+            left: false,
+        };
+        let pretty = Pretty {
+            format: Format::parse("%h").unwrap(),
+            date_mode: DateMode::Iso,
+            abbrev_commit: false,
+            color: false,
+            decorate: false,
+            decorations: Decorations::default(),
+        };
+
+        let expand = |format: &str| pretty.expand(format, &rev);
+        assert_eq!(expand("%h %t %s"), "3b18e51 4b825dc Fix the bug!");
+        assert_eq!(
+            expand("%an <%ae> %al %ad %as %at"),
+            "A U Thor <author@example.com> author 2024-07-11 15:07:21 +0200 2024-07-11 1720703241"
+        );
+        assert_eq!(expand("%f%n%b"), "Fix-the-bug\nBecause.\n");
+        assert_eq!(expand("%x41%%%q%Cred%C(bold)"), "A%%q");
+        assert_eq!(expand("a%n%-d%+s"), "a\nFix the bug!");
+        assert_eq!(expand("[%D]"), "[]");
+    }
+}
@@ -0,0 +1,556 @@
+//! The `--format` language of `for-each-ref` and `branch`: `%(atom)`
+//! placeholders, `%(if)...%(then)...%(else)...%(end)` conditionals and
+//! quoting of the expanded values for use in scripts.
+
+use std::cmp::Ordering;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    date::{self, DateMode},
+    object::{commit, Signature},
+    repository::Repository,
+};
+
+use super::{shorten_refname, RefItem};
+
+/// How atom values are quoted, for `--shell`, `--perl`, `--python` and `--tcl`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Quote {
+    #[default]
+    None,
+    Shell,
+    Perl,
+    Python,
+    Tcl,
+}
+
+impl Quote {
+    fn quote(&self, s: &str) -> String {
+        match self {
+            Quote::None => s.to_string(),
+            Quote::Shell => format!("'{}'", s.replace('\'', "'\\''")),
+            Quote::Perl => format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'")),
+            Quote::Python => format!(
+                "'{}'",
+                s.replace('\\', "\\\\")
+                    .replace('\'', "\\'")
+                    .replace('\n', "\\n")
+            ),
+            Quote::Tcl => {
+                let mut quoted = String::from("\"");
+                for c in s.chars() {
+                    match c {
+                        '[' | ']' | '{' | '}' | '$' | '\\' | '"' => {
+                            quoted.push('\\');
+                            quoted.push(c);
+                        }
+                        '\x0c' => quoted.push_str("\\f"),
+                        '\r' => quoted.push_str("\\r"),
+                        '\n' => quoted.push_str("\\n"),
+                        '\t' => quoted.push_str("\\t"),
+                        '\x0b' => quoted.push_str("\\v"),
+                        c => quoted.push(c),
+                    }
+                }
+                quoted.push('"');
+                quoted
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Condition {
+    NonEmpty,
+    Equals(String),
+    NotEquals(String),
+}
+
+#[derive(Debug)]
+enum Node {
+    Literal(String),
+    Atom(String),
+    If {
+        condition: Condition,
+        test: Vec<Node>,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// A parsed format string.
+#[derive(Debug)]
+pub struct Format {
+    nodes: Vec<Node>,
+}
+
+enum Token {
+    Literal(String),
+    Atom(String),
+    If(Condition),
+    Then,
+    Else,
+    End,
+}
+
+/// Splits a format string into literals, atoms and conditional markers.
+/// `%%` is a literal percent sign and `%xx` a hex-encoded byte.
+fn tokenize(format: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut literal = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('%') {
+        literal.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(atom) = rest.strip_prefix('(') {
+            let end = atom.find(')').context("malformed format string")?;
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(std::mem::take(&mut literal)));
+            }
+            let atom = &atom[..end];
+            tokens.push(match atom {
+                "if" => Token::If(Condition::NonEmpty),
+                "then" => Token::Then,
+                "else" => Token::Else,
+                "end" => Token::End,
+                atom => match atom.strip_prefix("if:") {
+                    Some(modifier) => {
+                        if let Some(value) = modifier.strip_prefix("equals=") {
+                            Token::If(Condition::Equals(value.to_string()))
+                        } else if let Some(value) = modifier.strip_prefix("notequals=") {
+                            Token::If(Condition::NotEquals(value.to_string()))
+                        } else {
+                            bail!("unrecognized %(if) argument: {modifier}");
+                        }
+                    }
+                    None => Token::Atom(atom.to_string()),
+                },
+            });
+            rest = &rest[end + 2..];
+        } else if let Some(after) = rest.strip_prefix('%') {
+            literal.push('%');
+            rest = after;
+        } else if let Some(byte) = rest.get(..2).and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            literal.push(byte as char);
+            rest = &rest[2..];
+        } else {
+            literal.push('%');
+        }
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+
+    Ok(tokens)
+}
+
+/// Parses tokens into nodes until an unmatched `%(then)`, `%(else)` or
+/// `%(end)`, which is returned along with the nodes.
+fn parse_nodes(tokens: &mut std::vec::IntoIter<Token>) -> Result<(Vec<Node>, Option<Token>)> {
+    let mut nodes = vec![];
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Literal(s) => nodes.push(Node::Literal(s)),
+            Token::Atom(s) => nodes.push(Node::Atom(s)),
+            Token::If(condition) => {
+                let (test, end) = parse_nodes(tokens)?;
+                if !matches!(end, Some(Token::Then)) {
+                    bail!("format: %(if) atom used without a %(then) atom");
+                }
+                let (then, end) = parse_nodes(tokens)?;
+                let otherwise = match end {
+                    Some(Token::Else) => {
+                        let (otherwise, end) = parse_nodes(tokens)?;
+                        if !matches!(end, Some(Token::End)) {
+                            bail!("format: %(else) atom used without a %(end) atom");
+                        }
+                        otherwise
+                    }
+                    Some(Token::End) => vec![],
+                    _ => bail!("format: %(then) atom used without a %(end) atom"),
+                };
+                nodes.push(Node::If {
+                    condition,
+                    test,
+                    then,
+                    otherwise,
+                });
+            }
+            end => return Ok((nodes, Some(end))),
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+impl Format {
+    /// Parses a format string like `%(refname:short) %(objectname)`.
+    pub fn parse(format: &str) -> Result<Format> {
+        let mut tokens = tokenize(format)?.into_iter();
+        let (nodes, end) = parse_nodes(&mut tokens)?;
+        match end {
+            None => Ok(Format { nodes }),
+            Some(Token::Then) => bail!("format: %(then) atom used without an %(if) atom"),
+            Some(Token::Else) => bail!("format: %(else) atom used without an %(if) atom"),
+            Some(_) => bail!("format: %(end) atom used without corresponding atom"),
+        }
+    }
+
+    /// Expands the format for the given item, quoting atom values.
+    pub fn expand(&self, item: &RefItem, repo: &Repository, quote: Quote) -> Result<String> {
+        expand_nodes(&self.nodes, item, repo, quote)
+    }
+}
+
+fn expand_nodes(nodes: &[Node], item: &RefItem, repo: &Repository, quote: Quote) -> Result<String> {
+    let mut out = String::new();
+    for node in nodes {
+        match node {
+            Node::Literal(s) => out.push_str(s),
+            Node::Atom(atom) => out.push_str(&quote.quote(atom_value(item, atom, repo)?.as_str())),
+            Node::If {
+                condition,
+                test,
+                then,
+                otherwise,
+            } => {
+                let value = expand_nodes(test, item, repo, Quote::None)?;
+                let holds = match condition {
+                    Condition::NonEmpty => !value.trim().is_empty(),
+                    Condition::Equals(expected) => value == *expected,
+                    Condition::NotEquals(expected) => value != *expected,
+                };
+                // Like git, a conditional is quoted as a whole
+                let branch = if holds { then } else { otherwise };
+                out.push_str(&quote.quote(&expand_nodes(branch, item, repo, Quote::None)?));
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// A value an atom expands to, which sorts numerically if it's a number.
+pub(super) enum AtomValue {
+    Text(String),
+    Number(i64, String),
+}
+
+impl AtomValue {
+    fn as_str(&self) -> &str {
+        match self {
+            AtomValue::Text(s) | AtomValue::Number(_, s) => s,
+        }
+    }
+
+    pub(super) fn compare(&self, other: &AtomValue) -> Ordering {
+        match (self, other) {
+            (AtomValue::Number(a, _), AtomValue::Number(b, _)) => a.cmp(b),
+            (a, b) => a.as_str().cmp(b.as_str()),
+        }
+    }
+}
+
+/// Expands a signature atom such as `authorname` or `committerdate:iso`.
+fn signature_atom(signature: &Signature, field: &str, modifier: Option<&str>) -> Result<AtomValue> {
+    let value = match field {
+        "name" => AtomValue::Text(signature.name.clone()),
+        "email" => match modifier {
+            Some("trim") => AtomValue::Text(signature.email.clone()),
+            Some("localpart") => AtomValue::Text(
+                signature
+                    .email
+                    .split('@')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+            _ => AtomValue::Text(format!("<{}>", signature.email)),
+        },
+        "date" => {
+            let mode = match modifier {
+                Some(modifier) => {
+                    DateMode::parse(modifier).context(format!("unknown date format {modifier}"))?
+                }
+                None => DateMode::Default,
+            };
+            AtomValue::Number(
+                signature.time,
+                date::format_date(signature.time, &signature.tz, &mode),
+            )
+        }
+        "" => AtomValue::Text(signature.to_string()),
+        _ => bail!("unknown field name: {field}"),
+    };
+
+    Ok(value)
+}
+
+/// Returns the message of the commit or tag the item points to.
+fn message(item: &RefItem) -> &str {
+    match (&item.commit, &item.tag) {
+        (Some(commit), _) => &commit.message,
+        (None, Some(tag)) => &tag.message,
+        (None, None) => "",
+    }
+}
+
+/// Returns the first paragraph of a message, joined into one line.
+fn subject(message: &str) -> String {
+    message
+        .trim_start()
+        .split("\n\n")
+        .next()
+        .unwrap_or("")
+        .lines()
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns the message after the subject.
+fn body(message: &str) -> &str {
+    match message.trim_start().split_once("\n\n") {
+        Some((_, body)) => body,
+        None => "",
+    }
+}
+
+/// Expands an atom such as `refname:short` for the given item.
+pub(super) fn atom_value(item: &RefItem, atom: &str, repo: &Repository) -> Result<AtomValue> {
+    // `%(*atom)` expands the atom for the object an annotated tag points to
+    if let Some(atom) = atom.strip_prefix('*') {
+        return match &item.peeled {
+            Some(peeled) => atom_value(peeled, atom, repo),
+            None => Ok(AtomValue::Text(String::new())),
+        };
+    }
+
+    let (name, modifier) = match atom.split_once(':') {
+        Some((name, modifier)) => (name, Some(modifier)),
+        None => (atom, None),
+    };
+    let commit = item.commit.as_ref();
+    let tag = item.tag.as_ref();
+
+    let value = match name {
+        "refname" => AtomValue::Text(format_refname(&item.refname, modifier)?),
+        "objectname" => AtomValue::Text(format_objectname(&item.objectname, modifier)?),
+        "objecttype" => AtomValue::Text(
+            item.objecttype
+                .map(|kind| kind.to_string())
+                .unwrap_or_default(),
+        ),
+        "tree" => AtomValue::Text(
+            commit
+                .map(|c| format_objectname(&c.tree, modifier))
+                .transpose()?
+                .unwrap_or_default(),
+        ),
+        "parent" => AtomValue::Text(
+            commit
+                .map(|c| {
+                    c.parents
+                        .iter()
+                        .map(|p| format_objectname(p, modifier))
+                        .collect::<Result<Vec<_>>>()
+                })
+                .transpose()?
+                .unwrap_or_default()
+                .join(" "),
+        ),
+        "numparent" => AtomValue::Text(
+            commit
+                .map(|c| c.parents.len().to_string())
+                .unwrap_or_default(),
+        ),
+        "object" => AtomValue::Text(tag.map(|t| t.object.clone()).unwrap_or_default()),
+        "type" => AtomValue::Text(tag.map(|t| t.kind.to_string()).unwrap_or_default()),
+        "tag" => AtomValue::Text(tag.map(|t| t.name.clone()).unwrap_or_default()),
+        "subject" => AtomValue::Text(subject(message(item))),
+        "body" => AtomValue::Text(body(message(item)).to_string()),
+        "contents" => AtomValue::Text(match modifier {
+            None => message(item).to_string(),
+            Some("subject") => subject(message(item)),
+            Some("body") => body(message(item)).to_string(),
+            Some(modifier) => bail!("unrecognized %(contents) argument: {modifier}"),
+        }),
+        "HEAD" => {
+            let head = repo.read_symbolic_ref("HEAD")?;
+            let current = head.as_deref() == Some(&item.refname);
+            AtomValue::Text(if current { "*" } else { " " }.to_string())
+        }
+        "upstream" => AtomValue::Text(format_upstream(item, modifier, repo)?),
+        name => {
+            let field = ["author", "committer", "tagger", "creator"]
+                .iter()
+                .find_map(|prefix| name.strip_prefix(prefix).map(|f| (*prefix, f)));
+            let Some((kind, field)) = field else {
+                bail!("unknown field name: {name}");
+            };
+            // The creator is the tagger of a tag and the committer of a commit
+            let signature = match kind {
+                "author" => commit.map(|c| &c.author),
+                "committer" => commit.map(|c| &c.committer),
+                "tagger" => tag.and_then(|t| t.tagger.as_ref()),
+                _ => commit
+                    .map(|c| &c.committer)
+                    .or_else(|| tag.and_then(|t| t.tagger.as_ref())),
+            };
+            match signature {
+                Some(signature) => signature_atom(signature, field, modifier)?,
+                None => AtomValue::Text(String::new()),
+            }
+        }
+    };
+
+    Ok(value)
+}
+
+fn format_refname(refname: &str, modifier: Option<&str>) -> Result<String> {
+    let value = match modifier {
+        None => refname.to_string(),
+        Some("short") => shorten_refname(refname).to_string(),
+        Some(modifier) => {
+            if let Some(n) = modifier
+                .strip_prefix("lstrip=")
+                .or_else(|| modifier.strip_prefix("strip="))
+            {
+                let n: usize = n.parse().context("invalid lstrip value")?;
+                refname.split('/').skip(n).collect::<Vec<_>>().join("/")
+            } else if let Some(n) = modifier.strip_prefix("rstrip=") {
+                let n: usize = n.parse().context("invalid rstrip value")?;
+                let components: Vec<&str> = refname.split('/').collect();
+                components[..components.len().saturating_sub(n)].join("/")
+            } else {
+                bail!("unrecognized %(refname) argument: {modifier}");
+            }
+        }
+    };
+
+    Ok(value)
+}
+
+fn format_objectname(hash: &str, modifier: Option<&str>) -> Result<String> {
+    let value = match modifier {
+        None => hash.to_string(),
+        Some("short") => hash[..7.min(hash.len())].to_string(),
+        Some(modifier) => match modifier.strip_prefix("short=") {
+            Some(n) => {
+                let n: usize = n.parse().context("invalid short length")?;
+                hash[..n.clamp(4, hash.len())].to_string()
+            }
+            None => bail!("unrecognized %(objectname) argument: {modifier}"),
+        },
+    };
+
+    Ok(value)
+}
+
+fn format_upstream(item: &RefItem, modifier: Option<&str>, repo: &Repository) -> Result<String> {
+    let Some(branch) = item.refname.strip_prefix("refs/heads/") else {
+        return Ok(String::new());
+    };
+    let Some(upstream) = repo.upstream(branch)? else {
+        return Ok(String::new());
+    };
+
+    let value = match modifier {
+        None => upstream,
+        Some("short") => shorten_refname(&upstream).to_string(),
+        Some(modifier @ ("track" | "trackshort")) => {
+            let Some(upstream_hash) = repo.read_ref(&upstream)? else {
+                return Ok(if modifier == "track" { "[gone]" } else { "" }.to_string());
+            };
+            let (ahead, behind) = commit::ahead_behind(&item.objectname, &upstream_hash, repo)?;
+            match (modifier, ahead, behind) {
+                ("track", 0, 0) => String::new(),
+                ("track", ahead, 0) => format!("[ahead {ahead}]"),
+                ("track", 0, behind) => format!("[behind {behind}]"),
+                ("track", ahead, behind) => format!("[ahead {ahead}, behind {behind}]"),
+                (_, 0, 0) => "=".to_string(),
+                (_, _, 0) => ">".to_string(),
+                (_, 0, _) => "<".to_string(),
+                _ => "<>".to_string(),
+            }
+        }
+        Some(modifier) => bail!("unrecognized %(upstream) argument: {modifier}"),
+    };
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item() -> RefItem {
+        RefItem {
+            refname: "refs/heads/feature/login".to_string(),
+            objectname: "3b18e512dba79e4c8300dd08aeb37f8e728b8dad".to_string(),
+            objecttype: None,
+            commit: None,
+            tag: None,
+            peeled: None,
+        }
+    }
+
+    fn expand(format: &str, quote: Quote) -> String {
+        // Atoms used here don't need to touch the repository
+        let repo = Repository::from_path(".").unwrap();
+        Format::parse(format)
+            .unwrap()
+            .expand(&item(), &repo, quote)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_expand_atoms() {
+        assert_eq!(
+            expand("%(refname:short) %(objectname:short)%%%0a", Quote::None),
+            "feature/login 3b18e51%\n"
+        );
+        assert_eq!(
+            expand("%(refname:lstrip=2)|%(refname:rstrip=1)", Quote::None),
+            "feature/login|refs/heads/feature"
+        );
+        assert_eq!(
+            expand("echo %(refname:short)", Quote::Shell),
+            "echo 'feature/login'"
+        );
+    }
+
+    #[test]
+    fn test_expand_conditionals() {
+        assert_eq!(
+            expand("%(if)%(subject)%(then)yes%(else)no%(end)", Quote::None),
+            "no"
+        );
+        assert_eq!(
+            expand(
+                "%(if:equals=feature/login)%(refname:short)%(then)match%(end)!",
+                Quote::None
+            ),
+            "match!"
+        );
+        assert_eq!(
+            expand(
+                "%(if)%(refname)%(then)it's %(refname:short)%(end)",
+                Quote::Shell
+            ),
+            "'it'\\''s feature/login'"
+        );
+        assert!(Format::parse("%(if)%(refname)%(end)").is_err());
+        assert!(Format::parse("%(then)").is_err());
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(Quote::Python.quote("a'b\n"), "'a\\'b\\n'");
+        assert_eq!(Quote::Perl.quote("a\\'b"), "'a\\\\\\'b'");
+        assert_eq!(Quote::Tcl.quote("$x [y]"), "\"\\$x \\[y\\]\"");
+    }
+}
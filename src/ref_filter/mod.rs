@@ -0,0 +1,181 @@
+//! Filtering, sorting and formatting of refs, shared by `branch` and
+//! `for-each-ref`.
+
+pub mod format;
+
+pub use format::{Format, Quote};
+
+use anyhow::Result;
+
+use crate::{
+    object::{commit, Commit, Object, ObjectType, Tag},
+    repository::Repository,
+    wildmatch::{wildmatch, WM_PATHNAME},
+};
+
+/// A ref together with the object it points to.
+pub struct RefItem {
+    pub refname: String,
+    pub objectname: String,
+    pub objecttype: Option<ObjectType>,
+    pub commit: Option<Commit>,
+    pub tag: Option<Tag>,
+    /// The object an annotated tag points to, for `%(*atom)`
+    pub peeled: Option<Box<RefItem>>,
+}
+
+impl RefItem {
+    pub fn new(refname: String, objectname: String, repo: &Repository) -> RefItem {
+        let objecttype = Object::read(&objectname, repo).ok().map(|o| o.kind);
+        let commit = match objecttype {
+            Some(ObjectType::Commit) => Commit::read(&objectname, repo).ok(),
+            _ => None,
+        };
+        let tag = match objecttype {
+            Some(ObjectType::Tag) => Tag::read(&objectname, repo).ok(),
+            _ => None,
+        };
+        let peeled = tag
+            .as_ref()
+            .map(|tag| Box::new(RefItem::new(refname.clone(), tag.object.clone(), repo)));
+
+        RefItem {
+            refname,
+            objectname,
+            objecttype,
+            commit,
+            tag,
+            peeled,
+        }
+    }
+
+    /// Returns the hash of the commit this ref points to, peeling tags.
+    pub fn commit_hash(&self) -> Option<&str> {
+        match (&self.commit, &self.peeled) {
+            (Some(_), _) => Some(&self.objectname),
+            (None, Some(peeled)) => peeled.commit_hash(),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Returns the short form of a refname, e.g. `main` for `refs/heads/main`.
+pub fn shorten_refname(refname: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| refname.strip_prefix(prefix))
+        .unwrap_or(refname)
+}
+
+/// Criteria for selecting refs.
+#[derive(Default)]
+pub struct RefFilter {
+    /// Glob patterns the refs have to match, any of them
+    pub patterns: Vec<String>,
+    /// Match patterns against the full refname as paths, with prefix
+    /// matching (`for-each-ref`), instead of against the short name (`branch`)
+    pub match_as_path: bool,
+    /// Only refs containing all these commits
+    pub contains: Vec<String>,
+    /// Only refs reachable from this commit
+    pub merged: Option<String>,
+    /// Only refs not reachable from this commit
+    pub no_merged: Option<String>,
+    /// Only refs pointing at this object, directly or through a tag
+    pub points_at: Option<String>,
+}
+
+impl RefFilter {
+    fn matches_pattern(&self, refname: &str) -> bool {
+        if self.patterns.is_empty() {
+            return true;
+        }
+        self.patterns.iter().any(|pattern| {
+            if self.match_as_path {
+                let prefix = pattern.trim_end_matches('/');
+                refname == prefix
+                    || refname
+                        .strip_prefix(prefix)
+                        .is_some_and(|rest| rest.starts_with('/'))
+                    || wildmatch(pattern, refname, WM_PATHNAME)
+            } else {
+                wildmatch(pattern, shorten_refname(refname), 0)
+            }
+        })
+    }
+
+    /// Returns the items for the refs that match the filter.
+    pub fn filter(&self, refs: Vec<(String, String)>, repo: &Repository) -> Result<Vec<RefItem>> {
+        let merged = match &self.merged {
+            Some(hash) => Some(commit::ancestors(hash, repo)?),
+            None => None,
+        };
+        let no_merged = match &self.no_merged {
+            Some(hash) => Some(commit::ancestors(hash, repo)?),
+            None => None,
+        };
+
+        let mut items = vec![];
+        for (refname, hash) in refs {
+            if !self.matches_pattern(&refname) {
+                continue;
+            }
+            let item = RefItem::new(refname, hash, repo);
+
+            if let Some(points_at) = &self.points_at {
+                let peeled = item.peeled.as_ref().map(|p| p.objectname.as_str());
+                if item.objectname != *points_at && peeled != Some(points_at) {
+                    continue;
+                }
+            }
+            if merged.is_some() || no_merged.is_some() || !self.contains.is_empty() {
+                // Refs that don't point to commits can't be merged or contain anything
+                let Some(hash) = item.commit_hash() else {
+                    continue;
+                };
+                if merged.as_ref().is_some_and(|m| !m.contains(hash))
+                    || no_merged.as_ref().is_some_and(|m| m.contains(hash))
+                {
+                    continue;
+                }
+                if !self.contains.is_empty() {
+                    let ancestors = commit::ancestors(hash, repo)?;
+                    if !self.contains.iter().all(|c| ancestors.contains(c)) {
+                        continue;
+                    }
+                }
+            }
+
+            items.push(item);
+        }
+
+        Ok(items)
+    }
+}
+
+/// Sorts items by the given keys (e.g. `refname`, `-committerdate`). Like
+/// git, the last key is the primary one.
+pub fn sort_refs(items: &mut Vec<RefItem>, keys: &[String], repo: &Repository) -> Result<()> {
+    for key in keys {
+        let (descending, atom) = match key.strip_prefix('-') {
+            Some(atom) => (true, atom),
+            None => (false, key.as_str()),
+        };
+
+        let mut keyed = items
+            .drain(..)
+            .map(|item| Ok((format::atom_value(&item, atom, repo)?, item)))
+            .collect::<Result<Vec<_>>>()?;
+        keyed.sort_by(|(a, _), (b, _)| {
+            let ordering = a.compare(b);
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        items.extend(keyed.into_iter().map(|(_, item)| item));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,453 @@
+//! POSIX regular expressions, basic and extended with the GNU extensions,
+//! as git uses them to match commits in `log --grep` and `--author`.
+//! Matching backtracks, which is plenty for the lines of commit messages.
+
+/// Which kind of pattern to parse, like git's `-G`, `-E` and `-F`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Syntax {
+    #[default]
+    Basic,
+    Extended,
+    Fixed,
+}
+
+type Alternatives = Vec<Vec<Piece>>;
+
+/// An atom with how often it may repeat.
+#[derive(Debug)]
+struct Piece {
+    atom: Atom,
+    min: usize,
+    max: Option<usize>,
+}
+
+#[derive(Debug)]
+enum Atom {
+    Char(char),
+    Any,
+    Class {
+        negated: bool,
+        items: Vec<ClassItem>,
+    },
+    Start,
+    End,
+    Group(Alternatives),
+}
+
+#[derive(Debug)]
+enum ClassItem {
+    Range(char, char),
+    Named(fn(char) -> bool),
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            ClassItem::Range(first, last) => (*first..=*last).contains(&c),
+            ClassItem::Named(f) => f(c),
+        }
+    }
+}
+
+/// A compiled regular expression.
+#[derive(Debug)]
+pub struct Regex {
+    alternatives: Alternatives,
+    ignore_case: bool,
+}
+
+impl Regex {
+    /// Compiles a pattern, returning an error message like regcomp's if
+    /// it's invalid.
+    pub fn new(pattern: &str, syntax: Syntax, ignore_case: bool) -> Result<Regex, String> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let alternatives = match syntax {
+            Syntax::Fixed => vec![chars
+                .into_iter()
+                .map(|c| Piece {
+                    atom: Atom::Char(c),
+                    min: 1,
+                    max: Some(1),
+                })
+                .collect()],
+            _ => {
+                let mut parser = Parser {
+                    chars,
+                    pos: 0,
+                    extended: syntax == Syntax::Extended,
+                };
+                let alternatives = parser.alternatives(0)?;
+                if parser.pos < parser.chars.len() {
+                    return Err("Unmatched ) or \\)".to_string());
+                }
+                alternatives
+            }
+        };
+
+        Ok(Regex {
+            alternatives,
+            ignore_case,
+        })
+    }
+
+    /// Returns true if the pattern matches any line of `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        text.lines().any(|line| {
+            let line: Vec<char> = line.chars().collect();
+            (0..=line.len()).any(|start| {
+                self.match_alternatives(&self.alternatives, &line, start, &mut |_| true)
+            })
+        })
+    }
+
+    fn match_alternatives(
+        &self,
+        alternatives: &Alternatives,
+        text: &[char],
+        pos: usize,
+        next: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        alternatives
+            .iter()
+            .any(|pieces| self.match_pieces(pieces, text, pos, next))
+    }
+
+    fn match_pieces(
+        &self,
+        pieces: &[Piece],
+        text: &[char],
+        pos: usize,
+        next: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        let Some((piece, rest)) = pieces.split_first() else {
+            return next(pos);
+        };
+        self.match_piece(piece, 0, text, pos, &mut |end| {
+            self.match_pieces(rest, text, end, next)
+        })
+    }
+
+    /// Matches the rest of the repetitions of a piece after `count` of them,
+    /// greedily.
+    fn match_piece(
+        &self,
+        piece: &Piece,
+        count: usize,
+        text: &[char],
+        pos: usize,
+        next: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        if piece.max.is_none_or(|max| count < max) {
+            let matched = self.match_atom(&piece.atom, text, pos, &mut |end| {
+                // An empty repetition can't lead anywhere new
+                if end == pos && count >= piece.min {
+                    return false;
+                }
+                self.match_piece(piece, count + 1, text, end, next)
+            });
+            if matched {
+                return true;
+            }
+        }
+        count >= piece.min && next(pos)
+    }
+
+    fn match_atom(
+        &self,
+        atom: &Atom,
+        text: &[char],
+        pos: usize,
+        next: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        let c = text.get(pos).copied();
+        match atom {
+            Atom::Char(expected) => c.is_some_and(|c| self.char_eq(c, *expected)) && next(pos + 1),
+            Atom::Any => c.is_some() && next(pos + 1),
+            Atom::Class { negated, items } => {
+                c.is_some_and(|c| self.class_matches(items, c) != *negated) && next(pos + 1)
+            }
+            Atom::Start => pos == 0 && next(pos),
+            Atom::End => pos == text.len() && next(pos),
+            Atom::Group(alternatives) => self.match_alternatives(alternatives, text, pos, next),
+        }
+    }
+
+    fn char_eq(&self, a: char, b: char) -> bool {
+        a == b || self.ignore_case && a.to_lowercase().eq(b.to_lowercase())
+    }
+
+    fn class_matches(&self, items: &[ClassItem], c: char) -> bool {
+        let matches = |c: char| items.iter().any(|item| item.matches(c));
+        matches(c)
+            || self.ignore_case && (c.to_lowercase().any(matches) || c.to_uppercase().any(matches))
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    extended: bool,
+}
+
+impl Parser {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    /// Returns true if the next token is the operator `op`, which basic
+    /// expressions write with a backslash, e.g. `\|`.
+    fn at_operator(&self, op: char) -> bool {
+        match self.extended {
+            true => self.peek(0) == Some(op),
+            false => self.peek(0) == Some('\\') && self.peek(1) == Some(op),
+        }
+    }
+
+    fn skip_operator(&mut self) {
+        self.pos += if self.extended { 1 } else { 2 };
+    }
+
+    fn alternatives(&mut self, depth: usize) -> Result<Alternatives, String> {
+        let mut alternatives = vec![self.pieces(depth)?];
+        while self.at_operator('|') {
+            self.skip_operator();
+            alternatives.push(self.pieces(depth)?);
+        }
+        Ok(alternatives)
+    }
+
+    fn pieces(&mut self, depth: usize) -> Result<Vec<Piece>, String> {
+        let mut pieces: Vec<Piece> = vec![];
+        while let Some(c) = self.peek(0) {
+            if self.at_operator('|') || depth > 0 && self.at_operator(')') {
+                break;
+            }
+            let first = pieces.is_empty();
+
+            let atom = match c {
+                '.' => Atom::Any,
+                '[' => {
+                    self.pos += 1;
+                    self.class()?
+                }
+                '^' if self.extended || first => Atom::Start,
+                '$' if self.extended || self.at_end_of_pieces(1) => Atom::End,
+                '*' if first => Atom::Char('*'),
+                '*' | '+' | '?' if self.extended => {
+                    return Err("Invalid preceding regular expression".to_string())
+                }
+                '(' if self.extended => {
+                    self.pos += 1;
+                    Atom::Group(self.group(depth)?)
+                }
+                '\\' => match self.peek(1) {
+                    Some('(') if !self.extended => {
+                        self.pos += 2;
+                        Atom::Group(self.group(depth)?)
+                    }
+                    Some('w') => named_class(false, is_word),
+                    Some('W') => named_class(true, is_word),
+                    Some('s') => named_class(false, char::is_whitespace),
+                    Some('S') => named_class(true, char::is_whitespace),
+                    Some(c) => {
+                        self.pos += 1;
+                        Atom::Char(c)
+                    }
+                    None => return Err("Trailing backslash".to_string()),
+                },
+                c => Atom::Char(c),
+            };
+            if !matches!(atom, Atom::Group(_) | Atom::Class { .. }) {
+                self.pos += 1;
+            }
+            if matches!(atom, Atom::Class { .. }) && c == '\\' {
+                self.pos += 2;
+            }
+
+            let mut piece = Piece {
+                atom,
+                min: 1,
+                max: Some(1),
+            };
+            while let Some((min, max)) = self.quantifier()? {
+                if piece.min != 1 || piece.max != Some(1) {
+                    piece = Piece {
+                        atom: Atom::Group(vec![vec![piece]]),
+                        min: 1,
+                        max: Some(1),
+                    };
+                }
+                piece.min = min;
+                piece.max = max;
+            }
+            pieces.push(piece);
+        }
+        Ok(pieces)
+    }
+
+    /// Returns true if the pieces being parsed end `offset` characters
+    /// ahead, where a basic expression's `$` is an anchor.
+    fn at_end_of_pieces(&mut self, offset: usize) -> bool {
+        self.pos += offset;
+        let end = self.peek(0).is_none() || self.at_operator('|') || self.at_operator(')');
+        self.pos -= offset;
+        end
+    }
+
+    /// Parses a group after its opening parenthesis, up to and including
+    /// the closing one.
+    fn group(&mut self, depth: usize) -> Result<Alternatives, String> {
+        let alternatives = self.alternatives(depth + 1)?;
+        if !self.at_operator(')') {
+            return Err("Unmatched ( or \\(".to_string());
+        }
+        self.skip_operator();
+        Ok(alternatives)
+    }
+
+    /// Parses the repetition operator following an atom, if any.
+    fn quantifier(&mut self) -> Result<Option<(usize, Option<usize>)>, String> {
+        let quantifier = match self.peek(0) {
+            Some('*') => (0, None),
+            _ if self.at_operator('+') => (1, None),
+            _ if self.at_operator('?') => (0, Some(1)),
+            _ if self.at_operator('{') => {
+                self.skip_operator();
+                return self.interval().map(Some);
+            }
+            _ => return Ok(None),
+        };
+        match quantifier {
+            (0, None) => self.pos += 1,
+            _ => self.skip_operator(),
+        }
+        Ok(Some(quantifier))
+    }
+
+    /// Parses `m}`, `m,}` or `m,n}` of an interval like `{2,3}`.
+    fn interval(&mut self) -> Result<(usize, Option<usize>), String> {
+        let err = || "Invalid content of \\{\\}".to_string();
+        let min = self.number().ok_or_else(err)?;
+        let max = match self.peek(0) {
+            Some(',') => {
+                self.pos += 1;
+                self.number()
+            }
+            _ => Some(min),
+        };
+        if !self.at_operator('}') {
+            return Err("Unmatched \\{".to_string());
+        }
+        self.skip_operator();
+        if max.is_some_and(|max| max < min) {
+            return Err(err());
+        }
+        Ok((min, max))
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek(0).is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        digits.parse().ok()
+    }
+
+    /// Parses a bracket expression after its `[`, up to and including the
+    /// closing `]`.
+    fn class(&mut self) -> Result<Atom, String> {
+        let err = || "Unmatched [, [^, [:, [., or [=".to_string();
+        let negated = self.peek(0) == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut items = vec![];
+        let mut first = true;
+        loop {
+            let c = self.peek(0).ok_or_else(err)?;
+            self.pos += 1;
+            match c {
+                ']' if !first => break,
+                '[' if self.peek(0) == Some(':') => {
+                    let rest: String = self.chars[self.pos + 1..].iter().collect();
+                    let end = rest.find(":]").ok_or_else(err)?;
+                    let f: fn(char) -> bool = match &rest[..end] {
+                        "alpha" => char::is_alphabetic,
+                        "digit" => |c| c.is_ascii_digit(),
+                        "alnum" => char::is_alphanumeric,
+                        "upper" => char::is_uppercase,
+                        "lower" => char::is_lowercase,
+                        "space" => char::is_whitespace,
+                        "blank" => |c| c == ' ' || c == '\t',
+                        "punct" => |c| c.is_ascii_punctuation(),
+                        "xdigit" => |c| c.is_ascii_hexdigit(),
+                        "cntrl" => char::is_control,
+                        "print" => |c| !c.is_control(),
+                        "graph" => |c| !c.is_control() && !c.is_whitespace(),
+                        _ => return Err("Invalid character class name".to_string()),
+                    };
+                    items.push(ClassItem::Named(f));
+                    self.pos += 1 + end + 2;
+                }
+                c if self.peek(0) == Some('-') && self.peek(1).is_some_and(|c| c != ']') => {
+                    let last = self.peek(1).unwrap();
+                    if last < c {
+                        return Err("Invalid range end".to_string());
+                    }
+                    items.push(ClassItem::Range(c, last));
+                    self.pos += 2;
+                }
+                c => items.push(ClassItem::Range(c, c)),
+            }
+            first = false;
+        }
+        Ok(Atom::Class { negated, items })
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn named_class(negated: bool, f: fn(char) -> bool) -> Atom {
+    Atom::Class {
+        negated,
+        items: vec![ClassItem::Named(f)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, syntax: Syntax, text: &str) -> bool {
+        Regex::new(pattern, syntax, false).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn test_regex() {
+        assert!(is_match("fix", Syntax::Basic, "a bugfix"));
+        assert!(is_match("^Merge", Syntax::Basic, "first\nMerge branch"));
+        assert!(!is_match("^fix", Syntax::Basic, "a bugfix"));
+        assert!(is_match("a.*c$", Syntax::Basic, "xabbbc"));
+        assert!(is_match("ab\\+c", Syntax::Basic, "abbc"));
+        assert!(!is_match("ab+c", Syntax::Basic, "abbc"));
+        assert!(is_match("ab+c", Syntax::Extended, "abbc"));
+        assert!(is_match("\\(ab\\)\\{2\\}", Syntax::Basic, "xababx"));
+        assert!(is_match("(foo|bar)baz", Syntax::Extended, "barbaz"));
+        assert!(is_match("[[:digit:]]-[^a-c]", Syntax::Basic, "v1-d"));
+        assert!(!is_match("[[:digit:]]-[^a-c]", Syntax::Basic, "v1-b"));
+        assert!(is_match("a.b", Syntax::Fixed, "xa.b"));
+        assert!(!is_match("a.b", Syntax::Fixed, "axb"));
+        assert!(Regex::new("FIX", Syntax::Basic, true)
+            .unwrap()
+            .is_match("fix it"));
+        assert!(is_match("(x*)*y", Syntax::Extended, "xxy"));
+        assert_eq!(
+            Regex::new("a\\(b", Syntax::Basic, false).unwrap_err(),
+            "Unmatched ( or \\("
+        );
+        assert!(Regex::new("[a", Syntax::Basic, false).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+//! Adding the files of the working tree to the index, as `add` does.
+
+use std::{collections::BTreeSet, fs};
+
+use crate::{
+    object::tree::{default_jobs, file_mode, write_blobs, MODE_GITLINK},
+    pathspec::match_pathspec,
+};
+
+use super::{
+    error::{RepoError, Result},
+    index::{is_missing, Index, IndexEntry},
+    status::{Change, UntrackedFiles},
+    Repository,
+};
+
+impl Repository {
+    /// Updates the index entries of the files matching `pathspecs` to match
+    /// the working tree: changed and untracked files are added, and files
+    /// that are gone are removed. Adding a file with conflicts resolves
+    /// them. The blobs are written by `jobs` threads, by default as many as
+    /// there are cores, or one if `core.preloadIndex` is false. Files whose
+    /// stat data matches their index entry aren't read again. Returns the
+    /// paths that changed in the index.
+    pub fn add_paths(&self, pathspecs: &[String], jobs: Option<usize>) -> Result<Vec<String>> {
+        let status = self.status(UntrackedFiles::All)?;
+        let mut index = Index::lock(self)?;
+
+        let changed: BTreeSet<&str> = status
+            .changes
+            .iter()
+            .filter(|file| file.unstaged != Change::Unmodified)
+            .map(|file| file.path.as_str())
+            .chain(status.unmerged.iter().map(|file| file.path.as_str()))
+            .chain(status.untracked.iter().map(String::as_str))
+            .collect();
+        for spec in pathspecs {
+            let known = index
+                .entries
+                .iter()
+                .map(|e| e.path.as_str())
+                .chain(status.untracked.iter().map(String::as_str))
+                .any(|path| match_pathspec(spec, path).is_some());
+            if !known {
+                Err(RepoError::Other(format!(
+                    "pathspec '{spec}' did not match any files"
+                )))?;
+            }
+        }
+
+        let mut added = vec![];
+        let mut files = vec![];
+        for path in changed {
+            if !pathspecs.iter().any(|s| match_pathspec(s, path).is_some()) {
+                continue;
+            }
+            let meta = match fs::symlink_metadata(self.get_root().join(path)) {
+                Ok(meta) => meta,
+                Err(e) if is_missing(&e) => {
+                    index.remove(path);
+                    added.push(path.to_string());
+                    continue;
+                }
+                Err(e) => Err(e)?,
+            };
+
+            if meta.is_dir() {
+                // A submodule is added at the commit checked out in it
+                let Some(head) = self
+                    .open_submodule(path)?
+                    .map(|submodule| submodule.read_ref("HEAD"))
+                    .transpose()?
+                    .flatten()
+                else {
+                    continue;
+                };
+                index.add(IndexEntry::new(path, MODE_GITLINK, &head));
+                added.push(path.to_string());
+            } else {
+                files.push((path.to_string(), meta));
+            }
+        }
+
+        let jobs = match jobs {
+            Some(jobs) => jobs,
+            None => default_jobs(&self.config()?),
+        };
+        let hashes = write_blobs(&files, self, jobs)?;
+        for ((path, meta), hash) in files.into_iter().zip(hashes) {
+            let mut entry = IndexEntry::new(&path, file_mode(&meta), &hash);
+            entry.update_stat(&meta);
+            index.add(entry);
+            added.push(path);
+        }
+        added.sort();
+        index.commit()?;
+
+        Ok(added)
+    }
+
+    /// Adds all files of the working tree to the index and writes its tree,
+    /// as `git add -A && git write-tree` do, returning the hash of the tree.
+    #[cfg(test)]
+    pub(crate) fn add_all(&self) -> Result<String> {
+        self.add_paths(&[".".to_string()], None)?;
+        let mut index = Index::lock(self)?;
+        let tree = index.write_tree(self)?;
+        index.commit()?;
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{object::Object, repository::filter::write_filter_process};
+    use tempfile::tempdir;
+
+    /// Adds all files of a new repository made by `create` with `jobs`
+    /// threads, returning the tree of the index.
+    fn add_created(create: impl Fn(&Path), jobs: usize) -> String {
+        let temp_dir = tempdir().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        create(temp_dir.path());
+        repo.add_paths(&[".".to_string()], Some(jobs)).unwrap();
+        Index::load(&repo).unwrap().write_tree(&repo).unwrap()
+    }
+
+    #[test]
+    fn test_add_jobs() {
+        let create = |root: &Path| {
+            fs::create_dir(root.join("dir")).unwrap();
+            for i in 0..20 {
+                fs::write(root.join(format!("dir/file{i}")), format!("{i}\n")).unwrap();
+            }
+            fs::write(root.join("top"), "top\n").unwrap();
+        };
+        // The blobs are the same whatever the number of threads
+        let tree = add_created(create, 1);
+        assert_eq!(add_created(create, 4), tree);
+    }
+
+    #[test]
+    fn test_add_filter_process() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+        let filter_dir = tempdir().unwrap();
+        let mut config = repo.config().unwrap();
+        let command = write_filter_process(filter_dir.path(), &["clean"]);
+        config.set("filter.upper.process", &command).unwrap();
+        config.save().unwrap();
+        fs::write(root.join(".gitattributes"), "file* filter=upper\n").unwrap();
+        for i in 0..20 {
+            fs::write(root.join(format!("file{i}")), format!("file {i}\n")).unwrap();
+        }
+
+        // The threads share one process
+        repo.add_paths(&[".".to_string()], Some(4)).unwrap();
+        let launches = fs::read_to_string(filter_dir.path().join("launches")).unwrap();
+        assert_eq!(launches.lines().count(), 1);
+        let index = Index::load(&repo).unwrap();
+        for i in 0..20 {
+            let entry = index.get(&format!("file{i}")).unwrap();
+            let content = Object::read_blob(&entry.hash, &repo).unwrap();
+            assert_eq!(content, format!("FILE {i}\n").as_bytes());
+        }
+    }
+
+    /// Compares adding many small files and a few huge ones with one thread
+    /// and with all cores. Run with `cargo test --release bench_add --
+    /// --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_add() {
+        use std::{thread, time::Instant};
+
+        fn small(root: &Path) {
+            for d in 0..100 {
+                let dir = root.join(format!("dir{d}"));
+                fs::create_dir(&dir).unwrap();
+                for f in 0..200 {
+                    fs::write(
+                        dir.join(format!("file{f}")),
+                        format!("{d} {f}\n").repeat(20),
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        fn huge(root: &Path) {
+            for f in 0..4u8 {
+                let content: Vec<u8> = (0..64 << 20).map(|i: u32| (i % 251) as u8 ^ f).collect();
+                fs::write(root.join(format!("huge{f}")), content).unwrap();
+            }
+        }
+        let jobs = thread::available_parallelism().map_or(1, usize::from);
+        let cases = [
+            ("20000 small files", small as fn(&Path)),
+            ("4 files of 64 MiB", huge),
+        ];
+        for (name, create) in cases {
+            let mut trees = vec![];
+            for jobs in [1, jobs] {
+                let start = Instant::now();
+                trees.push(add_created(create, jobs));
+                println!("{name}, {jobs} jobs: {:?}", start.elapsed());
+            }
+            assert_eq!(trees[0], trees[1]);
+        }
+    }
+}
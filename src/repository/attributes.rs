@@ -0,0 +1,422 @@
+//! Path attributes from `.gitattributes` files, `info/attributes` and
+//! `core.attributesFile`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{object::Object, wildmatch::WM_CASEFOLD};
+
+use super::{
+    error::Result,
+    ignore::{user_file, Pattern},
+    index::{is_missing, Index},
+    Repository,
+};
+
+/// The macros git defines itself.
+const BUILTIN_MACROS: &str = "[attr]binary -diff -merge -text";
+
+/// The state of an attribute for a path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttrState {
+    /// `attr`
+    Set,
+    /// `-attr`
+    Unset,
+    /// `attr=value`
+    Value(String),
+    /// No pattern says anything about the attribute, or `!attr`
+    Unspecified,
+}
+
+/// Shows the state like `check-attr` does.
+impl std::fmt::Display for AttrState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AttrState::Set => write!(f, "set"),
+            AttrState::Unset => write!(f, "unset"),
+            AttrState::Value(value) => write!(f, "{value}"),
+            AttrState::Unspecified => write!(f, "unspecified"),
+        }
+    }
+}
+
+/// A line of an attributes file: a pattern and the attributes it assigns.
+#[derive(Debug)]
+struct AttrLine {
+    pattern: Pattern,
+    attrs: Vec<(String, AttrState)>,
+}
+
+/// The parsed lines and macro definitions of an attributes file.
+#[derive(Debug, Default)]
+struct AttrFile {
+    lines: Vec<AttrLine>,
+    macros: Vec<(String, Vec<(String, AttrState)>)>,
+}
+
+/// Returns true if `name` may be used as an attribute name.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+}
+
+/// Parses an attribute of a line, like `-text` or `eol=lf`.
+fn parse_attr(token: &str) -> Option<(String, AttrState)> {
+    let (name, state) = if let Some(name) = token.strip_prefix('-') {
+        (name, AttrState::Unset)
+    } else if let Some(name) = token.strip_prefix('!') {
+        (name, AttrState::Unspecified)
+    } else if let Some((name, value)) = token.split_once('=') {
+        (name, AttrState::Value(value.to_string()))
+    } else {
+        (token, AttrState::Set)
+    };
+
+    is_valid_name(name).then(|| (name.to_string(), state))
+}
+
+/// Parses an attributes file. Macros may only be defined if
+/// `macros_allowed`, i.e. not in the `.gitattributes` files of
+/// subdirectories. The attribute names are added to `names` in the order
+/// they first appear.
+fn parse_file(
+    content: &str,
+    base: &str,
+    source: &str,
+    macros_allowed: bool,
+    names: &mut Vec<String>,
+) -> AttrFile {
+    let mut file = AttrFile::default();
+    let mut register = |name: &str| {
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    };
+
+    for (i, line) in content.lines().enumerate() {
+        let mut tokens = line.split([' ', '\t', '\r']).filter(|t| !t.is_empty());
+        let Some(first) = tokens.next() else {
+            continue;
+        };
+        if first.starts_with('#') {
+            continue;
+        }
+
+        let macro_name = first.strip_prefix("[attr]");
+        if let Some(name) = macro_name {
+            if !macros_allowed {
+                eprintln!("{} not allowed: {source}:{}", line.trim(), i + 1);
+                continue;
+            }
+            if !is_valid_name(name) {
+                continue;
+            }
+            register(name);
+        }
+        let attrs: Vec<_> = tokens.filter_map(parse_attr).collect();
+        for (name, _) in &attrs {
+            register(name);
+        }
+
+        match macro_name {
+            Some(name) => file.macros.push((name.to_string(), attrs)),
+            // Negated patterns aren't allowed in attributes files
+            None if first.starts_with('!') => eprintln!(
+                "warning: Negative patterns are ignored in git attributes\n\
+                 Use '\\!' for literal leading exclamation."
+            ),
+            None => {
+                if let Some(pattern) = Pattern::parse(first, base, source, i + 1) {
+                    file.lines.push(AttrLine { pattern, attrs });
+                }
+            }
+        }
+    }
+
+    file
+}
+
+/// The attributes of a path, see [`Attributes::check`].
+#[derive(Debug, Default)]
+pub struct PathAttributes {
+    states: HashMap<String, AttrState>,
+}
+
+impl PathAttributes {
+    /// Returns the state of an attribute.
+    pub fn get(&self, name: &str) -> &AttrState {
+        self.states.get(name).unwrap_or(&AttrState::Unspecified)
+    }
+
+    /// Returns true if the attribute is set.
+    pub fn is_set(&self, name: &str) -> bool {
+        self.get(name) == &AttrState::Set
+    }
+
+    /// Returns the value of an attribute set to a value.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        match self.get(name) {
+            AttrState::Value(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// The attributes of the paths of a working tree.
+///
+/// Like git, `info/attributes` takes precedence over the `.gitattributes`
+/// file of a directory, which takes precedence over those of its parents
+/// and then `core.attributesFile`. Within a file the last matching line
+/// wins. A macro attribute that is set also assigns the attributes of its
+/// definition.
+pub struct Attributes {
+    root: PathBuf,
+    /// Whether `.gitattributes` files are read from the working tree
+    worktree: bool,
+    /// The content of the `.gitattributes` files by directory, used if the
+    /// working tree isn't read or has none
+    blobs: HashMap<String, Vec<u8>>,
+    /// The `.gitattributes` files by directory, read when first needed
+    dirs: Mutex<HashMap<String, AttrFile>>,
+    /// `info/attributes`
+    info: AttrFile,
+    /// `core.attributesFile`
+    global: AttrFile,
+    /// The attributes assigned by each macro
+    macros: HashMap<String, Vec<(String, AttrState)>>,
+    /// The attribute names in the order they were first seen
+    names: Mutex<Vec<String>>,
+    flags: u32,
+}
+
+impl Attributes {
+    /// Returns the attributes of `path`, relative to the root of the working
+    /// tree.
+    pub fn check(&self, path: &str) -> Result<PathAttributes> {
+        let dirs: Vec<&str> = std::iter::once("")
+            .chain(path.match_indices('/').map(|(end, _)| &path[..=end]))
+            .collect();
+        for dir in &dirs {
+            self.load(dir)?;
+        }
+
+        let loaded = self.dirs.lock().unwrap_or_else(|e| e.into_inner());
+        let files = std::iter::once(&self.info)
+            .chain(dirs.iter().rev().filter_map(|dir| loaded.get(*dir)))
+            .chain(std::iter::once(&self.global));
+        let mut attributes = PathAttributes::default();
+        for file in files {
+            for line in file.lines.iter().rev() {
+                if line.pattern.matches(path, false, self.flags) {
+                    self.fill(&line.attrs, &mut attributes);
+                }
+            }
+        }
+
+        Ok(attributes)
+    }
+
+    /// Returns the names of all attributes seen so far, in the order git
+    /// lists them.
+    pub fn names(&self) -> Vec<String> {
+        self.names.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Assigns the attributes that have no state yet, the last one first,
+    /// expanding macros that are set.
+    fn fill(&self, attrs: &[(String, AttrState)], attributes: &mut PathAttributes) {
+        for (name, state) in attrs.iter().rev() {
+            if attributes.states.contains_key(name) {
+                continue;
+            }
+            attributes.states.insert(name.clone(), state.clone());
+            if *state == AttrState::Set {
+                if let Some(expansion) = self.macros.get(name) {
+                    self.fill(expansion, attributes);
+                }
+            }
+        }
+    }
+
+    /// Reads the `.gitattributes` file of a directory, if not read yet.
+    fn load(&self, dir: &str) -> Result<()> {
+        if self
+            .dirs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains_key(dir)
+        {
+            return Ok(());
+        }
+        let source = format!("{dir}.gitattributes");
+        let content = match self.worktree {
+            true => match fs::read(self.root.join(&source)) {
+                Ok(content) => Some(content),
+                Err(e) if is_missing(&e) => None,
+                Err(e) => Err(e)?,
+            },
+            false => None,
+        };
+        let content = content.or_else(|| self.blobs.get(dir).cloned());
+        let file = match content {
+            Some(content) => parse_file(
+                &String::from_utf8_lossy(&content),
+                dir,
+                &source,
+                dir.is_empty(),
+                &mut self.names.lock().unwrap_or_else(|e| e.into_inner()),
+            ),
+            None => AttrFile::default(),
+        };
+        self.dirs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(dir.to_string(), file);
+
+        Ok(())
+    }
+}
+
+impl Repository {
+    /// Returns the attributes of the working tree. Where the working tree
+    /// has no `.gitattributes` file, the one in the index is used.
+    pub fn attributes(&self) -> Result<Attributes> {
+        self.load_attributes(true, &Index::load(self)?.files())
+    }
+
+    /// Returns the attributes given by the `.gitattributes` files of
+    /// `files`, by path, such as a tree that is checked out.
+    pub fn attributes_of(&self, files: &BTreeMap<String, (u32, String)>) -> Result<Attributes> {
+        self.load_attributes(false, files)
+    }
+
+    fn load_attributes(
+        &self,
+        worktree: bool,
+        files: &BTreeMap<String, (u32, String)>,
+    ) -> Result<Attributes> {
+        let config = self.config()?;
+        let mut blobs = HashMap::new();
+        for (path, (_, hash)) in files {
+            if let Some(dir) = path.strip_suffix(".gitattributes") {
+                if dir.is_empty() || dir.ends_with('/') {
+                    blobs.insert(dir.to_string(), Object::read_blob(hash, self)?);
+                }
+            }
+        }
+
+        // Read the files in the order git does, for the order of the names
+        let mut names = vec![];
+        let builtin = parse_file(BUILTIN_MACROS, "", "", true, &mut names);
+        let global = match user_file(&config, "core.attributesFile", "attributes") {
+            Some((source, path)) => read_file(&path, &source, &mut names)?,
+            None => AttrFile::default(),
+        };
+        let mut attributes = Attributes {
+            root: self.get_root().to_path_buf(),
+            worktree,
+            blobs,
+            dirs: Mutex::new(HashMap::new()),
+            info: AttrFile::default(),
+            global,
+            macros: HashMap::new(),
+            names: Mutex::new(names),
+            flags: match config.get_bool("core.ignoreCase") {
+                Some(true) => WM_CASEFOLD,
+                _ => 0,
+            },
+        };
+        attributes.load("")?;
+        let info = self.common_dir.join("info/attributes");
+        let names = attributes
+            .names
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner());
+        attributes.info = read_file(&info, ".git/info/attributes", names)?;
+
+        // Later definitions override earlier ones
+        let dirs = attributes.dirs.get_mut().unwrap_or_else(|e| e.into_inner());
+        let root = dirs.get_mut("");
+        let root_macros = root.map(|file| std::mem::take(&mut file.macros));
+        let definitions = builtin
+            .macros
+            .into_iter()
+            .chain(std::mem::take(&mut attributes.global.macros))
+            .chain(root_macros.unwrap_or_default())
+            .chain(std::mem::take(&mut attributes.info.macros));
+        attributes.macros.extend(definitions);
+
+        Ok(attributes)
+    }
+}
+
+/// Reads an attributes file outside the working tree, if it exists.
+fn read_file(path: &Path, source: &str, names: &mut Vec<String>) -> Result<AttrFile> {
+    match fs::read(path) {
+        Ok(content) => Ok(parse_file(
+            &String::from_utf8_lossy(&content),
+            "",
+            source,
+            true,
+            names,
+        )),
+        Err(e) if is_missing(&e) => Ok(AttrFile::default()),
+        Err(e) => Err(e)?,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_attributes() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+
+        fs::write(
+            root.join(".gitattributes"),
+            "# comment\n[attr]mine foo bar=baz\n*.txt text\n*.bin binary\n*.c mine -bar\n\
+             *.txt eol=lf\n",
+        )
+        .unwrap();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub/.gitattributes"), "*.txt -text\nx.c !foo\n").unwrap();
+        fs::create_dir(root.join(".git/info")).unwrap();
+        fs::write(root.join(".git/info/attributes"), "sub/y.txt text=auto\n").unwrap();
+        let attributes = repo.attributes().unwrap();
+
+        let cases = [
+            ("a.txt", "text", AttrState::Set),
+            ("a.txt", "eol", AttrState::Value("lf".to_string())),
+            ("sub/a.txt", "text", AttrState::Unset),
+            ("sub/a.txt", "eol", AttrState::Value("lf".to_string())),
+            ("sub/y.txt", "text", AttrState::Value("auto".to_string())),
+            ("x.bin", "binary", AttrState::Set),
+            ("x.bin", "diff", AttrState::Unset),
+            ("x.c", "foo", AttrState::Set),
+            ("x.c", "bar", AttrState::Unset),
+            ("sub/x.c", "foo", AttrState::Unspecified),
+            ("x", "text", AttrState::Unspecified),
+        ];
+        for (path, name, expected) in cases {
+            let states = attributes.check(path).unwrap();
+            assert_eq!(states.get(name), &expected, "{path}: {name}");
+        }
+
+        assert_eq!(
+            attributes.names()[..6],
+            ["binary", "diff", "merge", "text", "mine", "foo"]
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! The cache tree, the `TREE` extension of the index. It records the tree
+//! hash of every directory of the index, so the trees of unchanged
+//! directories don't have to be written again. Changing an entry
+//! invalidates the trees of its directories.
+
+use std::collections::BTreeMap;
+
+use crate::object::tree::{write_entries, Tree, MODE_TREE};
+
+use super::{
+    error::{RepoError, Result},
+    index::{Index, IndexEntry},
+    Repository,
+};
+
+/// The cached tree of a directory of the index.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheTree {
+    /// The hash of the tree, `None` once an entry in it changed
+    hash: Option<String>,
+    /// The number of index entries in the tree, including its subtrees
+    entry_count: usize,
+    subtrees: BTreeMap<String, CacheTree>,
+}
+
+impl CacheTree {
+    /// Parses the data of a `TREE` extension.
+    pub fn parse(mut data: &[u8]) -> Result<CacheTree> {
+        let (name, tree) = parse_node(&mut data)
+            .ok_or_else(|| RepoError::CorruptIndex("bad TREE extension".to_string()))?;
+        if !name.is_empty() || !data.is_empty() {
+            Err(RepoError::CorruptIndex("bad TREE extension".to_string()))?;
+        }
+        Ok(tree)
+    }
+
+    /// Appends the data of a `TREE` extension, with the trees in pre-order.
+    pub fn serialize(&self, name: &str, out: &mut Vec<u8>) {
+        // format: "<name>\0<entry count> <subtree count>\n<hash>", with an
+        // entry count of -1 and no hash if invalid
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        match &self.hash {
+            Some(hash) => {
+                out.extend(format!("{} {}\n", self.entry_count, self.subtrees.len()).bytes());
+                out.extend(hex::decode(hash).unwrap_or_default());
+            }
+            None => out.extend(format!("-1 {}\n", self.subtrees.len()).bytes()),
+        }
+        for (name, subtree) in &self.subtrees {
+            subtree.serialize(name, out);
+        }
+    }
+
+    /// Returns the cached hash of the tree of a directory, e.g. `src/`, or
+    /// `""` for the root.
+    pub fn get(&self, dir: &str) -> Option<&str> {
+        let mut tree = self;
+        for name in dir.split_terminator('/') {
+            tree = tree.subtrees.get(name)?;
+        }
+        tree.hash.as_deref()
+    }
+
+    /// Invalidates the trees of the directories holding `path`.
+    pub fn invalidate(&mut self, path: &str) {
+        self.hash = None;
+        self.entry_count = 0;
+        if let Some((dir, rest)) = path.split_once('/') {
+            if let Some(subtree) = self.subtrees.get_mut(dir) {
+                subtree.invalidate(rest);
+            }
+        }
+    }
+}
+
+fn parse_node(data: &mut &[u8]) -> Option<(String, CacheTree)> {
+    let nul = data.iter().position(|&b| b == 0)?;
+    let name = String::from_utf8_lossy(&data[..nul]).to_string();
+    let newline = data.iter().position(|&b| b == b'\n')?;
+    let counts = std::str::from_utf8(data.get(nul + 1..newline)?).ok()?;
+    let (entry_count, subtree_count) = counts.split_once(' ')?;
+    let entry_count: i64 = entry_count.parse().ok()?;
+    let subtree_count: usize = subtree_count.parse().ok()?;
+    *data = &data[newline + 1..];
+
+    let mut tree = CacheTree::default();
+    if entry_count >= 0 {
+        tree.hash = Some(hex::encode(data.get(..20)?));
+        tree.entry_count = entry_count as usize;
+        *data = &data[20..];
+    }
+    for _ in 0..subtree_count {
+        let (name, subtree) = parse_node(data)?;
+        tree.subtrees.insert(name, subtree);
+    }
+    Some((name, tree))
+}
+
+impl Index {
+    /// Writes the trees of the index, taking those of unchanged directories
+    /// from the cache tree, and updates the cache tree. Returns the hash of
+    /// the root tree.
+    pub fn write_tree(&mut self, repo: &Repository) -> Result<String> {
+        if let Some(entry) = self.entries.iter().find(|e| e.stage != 0) {
+            Err(RepoError::Other(format!(
+                "{}: unmerged ({})",
+                entry.path, entry.hash
+            )))?;
+        }
+
+        self.invalidate_extensions();
+        let entries: Vec<&IndexEntry> = self.entries.iter().collect();
+        let tree = write_subtree(&entries, "", self.cache_tree(), repo)?;
+        let hash = tree.hash.clone().unwrap_or_default();
+        self.set_cache_tree(tree);
+
+        Ok(hash)
+    }
+
+    /// Reads the files of a tree into `files` by path and returns its cache
+    /// tree. The files of directories whose cached tree is the same are
+    /// taken from the index rather than read again.
+    pub fn read_tree_cached(
+        &self,
+        hash: &str,
+        repo: &Repository,
+        files: &mut BTreeMap<String, (u32, String)>,
+    ) -> Result<CacheTree> {
+        self.read_subtree(hash, "", self.cache_tree(), repo, files)
+    }
+
+    fn read_subtree(
+        &self,
+        hash: &str,
+        prefix: &str,
+        cached: Option<&CacheTree>,
+        repo: &Repository,
+        files: &mut BTreeMap<String, (u32, String)>,
+    ) -> Result<CacheTree> {
+        if let Some(cached) = cached.filter(|cached| cached.hash.as_deref() == Some(hash)) {
+            for entry in self.entries_in(prefix) {
+                files.insert(entry.path.clone(), (entry.mode, entry.hash.clone()));
+            }
+            return Ok(cached.clone());
+        }
+
+        let mut tree = CacheTree {
+            hash: Some(hash.to_string()),
+            ..Default::default()
+        };
+        for entry in Tree::read(hash, repo)?.entries {
+            let path = format!("{prefix}{}", entry.name);
+            if entry.mode == MODE_TREE {
+                let cached = cached.and_then(|cached| cached.subtrees.get(&entry.name));
+                let subtree =
+                    self.read_subtree(&entry.hash, &format!("{path}/"), cached, repo, files)?;
+                tree.entry_count += subtree.entry_count;
+                tree.subtrees.insert(entry.name, subtree);
+            } else {
+                files.insert(path, (entry.mode, entry.hash));
+                tree.entry_count += 1;
+            }
+        }
+
+        Ok(tree)
+    }
+}
+
+/// Writes the tree of the directory `prefix` holding `entries`, unless its
+/// cached tree is still valid.
+fn write_subtree(
+    entries: &[&IndexEntry],
+    prefix: &str,
+    cached: Option<&CacheTree>,
+    repo: &Repository,
+) -> Result<CacheTree> {
+    if let Some(cached) = cached.filter(|cached| cached.hash.is_some()) {
+        return Ok(cached.clone());
+    }
+
+    let mut tree = CacheTree {
+        entry_count: entries.len(),
+        ..Default::default()
+    };
+    let mut tree_entries = vec![];
+    let mut i = 0;
+    while i < entries.len() {
+        let entry = entries[i];
+        let name = &entry.path[prefix.len()..];
+        let Some((dir, _)) = name.split_once('/') else {
+            tree_entries.push((entry.mode, name.to_string(), entry.hash.clone()));
+            i += 1;
+            continue;
+        };
+
+        // The entries of a directory are next to each other
+        let dir_prefix = format!("{prefix}{dir}/");
+        let end = entries[i..]
+            .iter()
+            .position(|e| !e.path.starts_with(&dir_prefix))
+            .map_or(entries.len(), |n| i + n);
+        let cached = cached.and_then(|cached| cached.subtrees.get(dir));
+        let subtree = write_subtree(&entries[i..end], &dir_prefix, cached, repo)?;
+        tree_entries.push((
+            MODE_TREE,
+            dir.to_string(),
+            subtree.hash.clone().unwrap_or_default(),
+        ));
+        tree.subtrees.insert(dir.to_string(), subtree);
+        i = end;
+    }
+    tree.hash = Some(write_entries(tree_entries, repo)?);
+
+    Ok(tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_tree() {
+        let hash = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+        let mut tree = CacheTree {
+            hash: Some(hash.to_string()),
+            entry_count: 3,
+            subtrees: BTreeMap::from([
+                (
+                    "a".to_string(),
+                    CacheTree {
+                        hash: Some(hash.to_string()),
+                        entry_count: 1,
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "b".to_string(),
+                    CacheTree {
+                        hash: Some(hash.to_string()),
+                        entry_count: 1,
+                        ..Default::default()
+                    },
+                ),
+            ]),
+        };
+        let mut data = vec![];
+        tree.serialize("", &mut data);
+        assert!(data.starts_with(b"\x003 2\n"));
+        assert_eq!(CacheTree::parse(&data).unwrap(), tree);
+
+        tree.invalidate("b/file");
+        assert_eq!(tree.get(""), None);
+        assert_eq!(tree.get("a/"), Some(hash));
+        assert_eq!(tree.get("b/"), None);
+        let mut data = vec![];
+        tree.serialize("", &mut data);
+        assert!(data.starts_with(b"\x00-1 2\na\x001 0\n"));
+        assert_eq!(CacheTree::parse(&data).unwrap(), tree);
+        assert!(CacheTree::parse(b"\x001 0\n").is_err());
+    }
+}
@@ -0,0 +1,554 @@
+//! Updating the working tree and the index from one tree to another, as
+//! done when switching branches.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    os::unix::fs::{symlink, PermissionsExt},
+    path::Path,
+};
+
+use crate::{
+    merge::{self, Labels},
+    object::{
+        tree::{Tree, MODE_EXECUTABLE, MODE_GITLINK, MODE_SYMLINK},
+        Object,
+    },
+};
+
+use super::{
+    convert::Convert,
+    error::{RepoError, Result},
+    index::{hash_file, is_missing, Index, IndexEntry},
+    Repository,
+};
+
+/// Options of [`Repository::checkout_tree`].
+#[derive(Debug, Default)]
+pub struct CheckoutOptions<'a> {
+    /// Throw away local changes instead of refusing to overwrite them
+    pub force: bool,
+    /// Merge local changes to files that differ between the trees into the
+    /// new version instead of refusing to overwrite them
+    pub merge: bool,
+    /// The name of the new tree in conflict markers, e.g. the branch name
+    pub label: &'a str,
+}
+
+/// A file whose local changes were merged into the new version.
+#[derive(Debug, PartialEq)]
+pub struct MergedFile {
+    pub path: String,
+    pub conflicts: bool,
+}
+
+/// What happens to a path that differs between the two trees.
+enum Action<'a> {
+    /// Write the file of the new tree
+    Write(&'a (u32, String)),
+    /// Remove the file, it's not in the new tree
+    Remove,
+    /// Merge the local changes with the new version
+    Merge(&'a (u32, String), &'a (u32, String)),
+}
+
+impl Repository {
+    /// Switches the working tree and the index from `old_tree` (the tree of
+    /// HEAD, if any) to `new_tree`. Only files that differ between the two
+    /// trees are touched; local changes to other files are kept. Fails
+    /// without changing anything if local changes or untracked files would
+    /// be overwritten, unless forced or merging. Files outside a sparse
+    /// checkout are only updated in the index.
+    pub fn checkout_tree(
+        &self,
+        old_tree: Option<&str>,
+        new_tree: &str,
+        options: &CheckoutOptions,
+    ) -> Result<Vec<MergedFile>> {
+        let root = self.get_root();
+        let old = match old_tree {
+            Some(hash) => Tree::read_recursive(hash, self)?,
+            None => BTreeMap::new(),
+        };
+        let new = Tree::read_recursive(new_tree, self)?;
+        // Files are compared with the attributes of the working tree, and
+        // written with those of the new tree
+        let convert = self.convert()?;
+        let checkout = self.convert_for(&new)?;
+        let sparse = self.sparse_checkout()?;
+        let skipped = |path: &str| sparse.as_ref().is_some_and(|sparse| !sparse.contains(path));
+
+        let mut index = Index::load(self)?;
+        if !index.exists() {
+            // Without an index, assume the working tree was checked out from HEAD
+            for (path, (mode, hash)) in &old {
+                index.add(IndexEntry::new(path, *mode, hash));
+            }
+        }
+        if options.force {
+            // Conflicts are resolved by taking the new version
+            index.entries.retain(|e| e.stage == 0);
+        } else if index.has_conflicts() {
+            Err(RepoError::UnmergedIndex)?;
+        }
+
+        let indexed_paths: Vec<String> = index.entries.iter().map(|e| e.path.clone()).collect();
+        let paths: BTreeSet<&str> = old
+            .keys()
+            .chain(new.keys())
+            .chain(&indexed_paths)
+            .map(String::as_str)
+            .collect();
+        let mut actions = vec![];
+        let mut local_changes = vec![];
+        let mut untracked = vec![];
+        for path in paths {
+            let (o, n) = (old.get(path), new.get(path));
+            let entry = index.get(path);
+            if o == n {
+                // Forcing also discards the local changes to files that are
+                // the same in both trees, like `reset --hard`
+                let discard = options.force
+                    && match entry {
+                        Some(entry) => {
+                            n != Some(&(entry.mode, entry.hash.clone()))
+                                || index.is_modified(entry, root, &convert)?
+                        }
+                        None => n.is_some(),
+                    };
+                if !discard {
+                    continue;
+                }
+            }
+            let action = match n {
+                Some(n) => Action::Write(n),
+                None => Action::Remove,
+            };
+            if options.force {
+                actions.push((path, action));
+                continue;
+            }
+
+            let indexed = entry.map(|e| (e.mode, e.hash.clone()));
+            if indexed.as_ref() == n {
+                // The index already has the new version, keep any local changes
+                continue;
+            }
+            let Some(entry) = entry else {
+                if o.is_some() {
+                    // Deleted from the index but not yet committed
+                    local_changes.push(path.to_string());
+                } else if !skipped(path) && self.is_in_the_way(root, path, n, &convert)? {
+                    untracked.push(path.to_string());
+                } else {
+                    actions.push((path, action));
+                }
+                continue;
+            };
+
+            let staged = indexed.as_ref() != o;
+            let modified = index.is_modified(entry, root, &convert)?;
+            match (o, n) {
+                _ if !staged && !modified => actions.push((path, action)),
+                (Some(o), Some(n)) if options.merge && !staged => {
+                    actions.push((path, Action::Merge(o, n)))
+                }
+                _ => local_changes.push(path.to_string()),
+            }
+        }
+        if !local_changes.is_empty() {
+            Err(RepoError::LocalChanges(local_changes))?;
+        }
+
+        // Files may have to make way for directories and vice versa
+        let removed: BTreeSet<&str> = actions
+            .iter()
+            .filter(|(_, action)| matches!(action, Action::Remove))
+            .map(|(path, _)| *path)
+            .collect();
+        if !options.force {
+            for (path, action) in &actions {
+                if matches!(action, Action::Write(_))
+                    && !skipped(path)
+                    && self.blocks_path(root, path, &removed, &index)?
+                {
+                    untracked.push(path.to_string());
+                }
+            }
+        }
+        if !untracked.is_empty() {
+            untracked.sort();
+            untracked.dedup();
+            Err(RepoError::UntrackedOverwritten(untracked))?;
+        }
+
+        // Remove files first, so they don't block new directories
+        for path in &removed {
+            if !index.get(path).is_some_and(IndexEntry::skip_worktree) {
+                remove_path(root, path)?;
+            }
+            index.remove(path);
+        }
+        let mut merged = vec![];
+        for (path, action) in actions {
+            match action {
+                Action::Remove => {}
+                Action::Write((mode, hash)) if skipped(path) => {
+                    if index.get(path).is_some_and(|entry| !entry.skip_worktree()) {
+                        remove_path(root, path)?;
+                    }
+                    let mut entry = IndexEntry::new(path, *mode, hash);
+                    entry.set_skip_worktree(true);
+                    index.add(entry);
+                }
+                Action::Write((mode, hash)) => {
+                    let content = Object::read_blob(hash, self)?;
+                    index.add(write_file(root, path, *mode, hash, &content, &checkout)?);
+                }
+                Action::Merge((_, base), (mode, hash)) => {
+                    merged.push(self.merge_file(
+                        path,
+                        base,
+                        (*mode, hash),
+                        options,
+                        &mut index,
+                        &checkout,
+                    )?);
+                }
+            }
+        }
+        index.write()?;
+
+        Ok(merged)
+    }
+
+    /// Makes the index match `tree` without touching the working tree. Stat
+    /// data is kept for unchanged entries and taken from the working tree
+    /// for files that match their new entry. Files left out of a sparse
+    /// checkout stay left out, as do new files outside of it.
+    pub fn reset_index(&self, tree: &str) -> Result<()> {
+        let root = self.get_root();
+        let convert = self.convert()?;
+        let sparse = self.sparse_checkout()?;
+        let mut index = Index::load(self)?;
+        let mut files = BTreeMap::new();
+        let cache_tree = index.read_tree_cached(tree, self, &mut files)?;
+        let mut entries = vec![];
+        for (path, (mode, hash)) in files {
+            let skip = match index.get(&path) {
+                Some(entry) => entry.skip_worktree(),
+                None => sparse
+                    .as_ref()
+                    .is_some_and(|sparse| !sparse.contains(&path)),
+            };
+            let mut entry = match index.get(&path) {
+                Some(entry) if entry.mode == mode && entry.hash == hash => entry.clone(),
+                _ => IndexEntry::new(&path, mode, &hash),
+            };
+            entry.set_skip_worktree(skip);
+            if index.is_modified(&entry, root, &convert)? {
+                entry = IndexEntry::new(&path, mode, &hash);
+            } else if let Ok(meta) = fs::symlink_metadata(root.join(&path)) {
+                entry.update_stat(&meta);
+            }
+            entries.push(entry);
+        }
+        index.entries = entries;
+        index.set_cache_tree(cache_tree);
+
+        index.write()
+    }
+
+    /// Returns true if writing the new file `path` would overwrite an
+    /// untracked file with different content.
+    fn is_in_the_way(
+        &self,
+        root: &Path,
+        path: &str,
+        new: Option<&(u32, String)>,
+        convert: &Convert,
+    ) -> Result<bool> {
+        let Some((_, hash)) = new else {
+            return Ok(false);
+        };
+        let full_path = root.join(path);
+        match fs::symlink_metadata(&full_path) {
+            Ok(meta) if meta.is_dir() => Ok(false),
+            Ok(meta) => Ok(hash_file(path, &meta, convert)? != *hash),
+            Err(e) if is_missing(&e) => Ok(false),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// Returns true if an untracked file or directory is in the way of
+    /// writing `path`: a file where one of its directories should be, or a
+    /// directory with untracked files where the file should be.
+    fn blocks_path(
+        &self,
+        root: &Path,
+        path: &str,
+        removed: &BTreeSet<&str>,
+        index: &Index,
+    ) -> Result<bool> {
+        for (end, _) in path.match_indices('/') {
+            let dir = &path[..end];
+            if let Ok(meta) = fs::symlink_metadata(root.join(dir)) {
+                if !meta.is_dir() && !removed.contains(dir) {
+                    return Ok(index.get(dir).is_none());
+                }
+            }
+        }
+
+        let full_path = root.join(path);
+        if !full_path.is_dir() || fs::symlink_metadata(&full_path)?.is_symlink() {
+            return Ok(false);
+        }
+        let mut files = vec![];
+        collect_files(&full_path, root, &mut files)?;
+        Ok(files.iter().any(|file| !removed.contains(file.as_str())))
+    }
+
+    /// Merges the local changes to `path` with its new version, recording a
+    /// conflict in the index if they overlap.
+    fn merge_file(
+        &self,
+        path: &str,
+        base: &str,
+        (mode, hash): (u32, &str),
+        options: &CheckoutOptions,
+        index: &mut Index,
+        convert: &Convert,
+    ) -> Result<MergedFile> {
+        let root = self.get_root();
+        let base_content = Object::read_blob(base, self)?;
+        let new_content = Object::read_blob(hash, self)?;
+        let local_content = convert.to_git(path, fs::read(root.join(path))?, false)?;
+        if [&base_content, &new_content, &local_content]
+            .iter()
+            .any(|content| content.contains(&0))
+        {
+            Err(RepoError::Other(format!(
+                "Cannot merge binary files: {path}"
+            )))?;
+        }
+
+        let labels = Labels {
+            ours: options.label,
+            theirs: "local",
+        };
+        let result = merge::merge(&base_content, &new_content, &local_content, &labels);
+        write_file(root, path, mode, hash, &result.content, convert)?;
+
+        if result.conflicts {
+            let local = Object::blob_from_bytes(&local_content).write_to_objects(self)?;
+            index.remove(path);
+            for (stage, hash) in [(1, base), (2, hash), (3, local.as_str())] {
+                index.add(IndexEntry {
+                    stage,
+                    ..IndexEntry::new(path, mode, hash)
+                });
+            }
+        } else {
+            // Without stat data the merged file shows as modified
+            index.add(IndexEntry::new(path, mode, hash));
+        }
+
+        Ok(MergedFile {
+            path: path.to_string(),
+            conflicts: result.conflicts,
+        })
+    }
+}
+
+/// Writes a file of a tree to the working tree, returning its index entry.
+pub(super) fn write_file(
+    root: &Path,
+    path: &str,
+    mode: u32,
+    hash: &str,
+    content: &[u8],
+    convert: &Convert,
+) -> Result<IndexEntry> {
+    let full_path = root.join(path);
+    match fs::symlink_metadata(&full_path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(&full_path)?,
+        Ok(_) => fs::remove_file(&full_path)?,
+        Err(e) if is_missing(&e) => {}
+        Err(e) => Err(e)?,
+    }
+    if let Some(parent) = full_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match mode {
+        // Submodules are checked out separately, only create their directory
+        MODE_GITLINK => fs::create_dir_all(&full_path)?,
+        MODE_SYMLINK => symlink(String::from_utf8_lossy(content).as_ref(), &full_path)?,
+        _ => {
+            fs::write(&full_path, convert.to_worktree(path, content)?)?;
+            let mut permissions = fs::metadata(&full_path)?.permissions();
+            let bits = permissions.mode();
+            // Make the file executable for everyone who can read it
+            permissions.set_mode(match mode {
+                MODE_EXECUTABLE => bits | (bits & 0o444) >> 2,
+                _ => bits & !0o111,
+            });
+            fs::set_permissions(&full_path, permissions)?;
+        }
+    }
+
+    let mut entry = IndexEntry::new(path, mode, hash);
+    entry.update_stat(&fs::symlink_metadata(&full_path)?);
+    Ok(entry)
+}
+
+/// Removes a file from the working tree, along with the directories it
+/// leaves empty.
+pub fn remove_path(root: &Path, path: &str) -> Result<()> {
+    let full_path = root.join(path);
+    match fs::symlink_metadata(&full_path) {
+        // A submodule directory is only removed if it's empty
+        Ok(meta) if meta.is_dir() => {
+            let _ = fs::remove_dir(&full_path);
+        }
+        Ok(_) => fs::remove_file(&full_path)?,
+        Err(e) if is_missing(&e) => {}
+        Err(e) => Err(e)?,
+    }
+
+    let mut dir = full_path.parent();
+    while let Some(d) = dir {
+        if d == root || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+
+    Ok(())
+}
+
+/// Collects the paths of all files below `dir`, relative to `root`.
+fn collect_files(dir: &Path, root: &Path, files: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            collect_files(&path, root, files)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            files.push(relative.to_string_lossy().to_string());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::tree::MODE_FILE;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_checkout_tree() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+        let options = CheckoutOptions {
+            label: "other",
+            ..Default::default()
+        };
+
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("dir/a"), "a\n").unwrap();
+        fs::write(root.join("b"), "b\n").unwrap();
+        let old = repo.add_all().unwrap();
+        fs::remove_dir_all(root.join("dir")).unwrap();
+        fs::write(root.join("dir"), "now a file\n").unwrap();
+        fs::write(root.join("b"), "b\nmore\n").unwrap();
+        fs::set_permissions(root.join("b"), fs::Permissions::from_mode(0o755)).unwrap();
+        symlink("b", root.join("link")).unwrap();
+        let new = repo.add_all().unwrap();
+
+        // Back to the old tree, from a working tree matching the new one
+        repo.reset_index(&new).unwrap();
+        repo.checkout_tree(Some(&new), &old, &options).unwrap();
+        assert_eq!(fs::read_to_string(root.join("dir/a")).unwrap(), "a\n");
+        assert_eq!(fs::read_to_string(root.join("b")).unwrap(), "b\n");
+        assert!(!root.join("link").exists());
+        let index = Index::load(&repo).unwrap();
+        assert_eq!(index.entries.len(), 2);
+        let convert = repo.convert().unwrap();
+        assert!(!index
+            .is_modified(index.get("b").unwrap(), root, &convert)
+            .unwrap());
+
+        // Local changes to files that differ are refused, or merged
+        fs::write(root.join("b"), "local\nb\n").unwrap();
+        assert!(matches!(
+            repo.checkout_tree(Some(&old), &new, &options),
+            Err(RepoError::LocalChanges(paths)) if paths == ["b"]
+        ));
+        let merge = CheckoutOptions {
+            merge: true,
+            ..options
+        };
+        let merged = repo.checkout_tree(Some(&old), &new, &merge).unwrap();
+        assert_eq!(
+            merged,
+            [MergedFile {
+                path: "b".to_string(),
+                conflicts: false
+            }]
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("b")).unwrap(),
+            "local\nb\nmore\n"
+        );
+        assert_eq!(fs::read_link(root.join("link")).unwrap(), Path::new("b"));
+        assert_eq!(
+            fs::metadata(root.join("b")).unwrap().permissions().mode() & 0o100,
+            0o100
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("dir")).unwrap(),
+            "now a file\n"
+        );
+
+        // An untracked file in the way of a new file is kept
+        let options = CheckoutOptions {
+            force: true,
+            ..Default::default()
+        };
+        repo.checkout_tree(Some(&new), &old, &options).unwrap();
+        fs::write(root.join("link"), "untracked\n").unwrap();
+        assert!(matches!(
+            repo.checkout_tree(Some(&old), &new, &CheckoutOptions::default()),
+            Err(RepoError::UntrackedOverwritten(paths)) if paths == ["link"]
+        ));
+        repo.checkout_tree(Some(&old), &new, &options).unwrap();
+        assert!(fs::symlink_metadata(root.join("link"))
+            .unwrap()
+            .is_symlink());
+
+        // Forcing discards local changes to files the trees agree on, and
+        // staged files that are in neither
+        fs::write(root.join("dir"), "local\n").unwrap();
+        fs::write(root.join("staged"), "staged\n").unwrap();
+        let mut index = Index::load(&repo).unwrap();
+        let meta = fs::metadata(root.join("staged")).unwrap();
+        let hash = hash_file("staged", &meta, &convert).unwrap();
+        index.add(IndexEntry::new("staged", MODE_FILE, &hash));
+        index.write().unwrap();
+        repo.checkout_tree(Some(&new), &new, &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("dir")).unwrap(),
+            "now a file\n"
+        );
+        assert!(!root.join("staged").exists());
+        let index = Index::load(&repo).unwrap();
+        assert!(index.get("staged").is_none());
+        assert!(!index
+            .is_modified(index.get("dir").unwrap(), root, &convert)
+            .unwrap());
+    }
+}
@@ -0,0 +1,285 @@
+//! Finding the untracked files to remove, as done by `clean`.
+
+use std::{fs, path::Path};
+
+use crate::pathspec::match_pathspec;
+
+use super::{
+    error::Result,
+    ignore::{Ignore, TrackedPaths, Tracking},
+    index::Index,
+    Repository,
+};
+
+/// Which files `clean` removes with regard to the ignore rules.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CleanIgnored {
+    /// Only untracked files that aren't ignored
+    #[default]
+    Keep,
+    /// Ignored files too, the ignore files aren't read (`-x`)
+    Also,
+    /// Only ignored files (`-X`)
+    Only,
+}
+
+/// Options of [`Repository::clean_entries`].
+#[derive(Debug, Default)]
+pub struct CleanOptions<'a> {
+    /// Remove untracked directories as a whole
+    pub directories: bool,
+    /// Remove directories holding other repositories too
+    pub repositories: bool,
+    pub ignored: CleanIgnored,
+    /// Extra ignore patterns
+    pub excludes: &'a [String],
+    pub pathspecs: &'a [String],
+}
+
+/// A path found by [`Repository::clean_entries`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum CleanEntry {
+    /// A file or a directory to remove
+    Remove(String),
+    /// A repository inside a directory to remove, which is kept
+    SkipRepository(String),
+}
+
+impl Repository {
+    /// Returns the untracked files and directories to remove, sorted by
+    /// path. Without [`CleanOptions::directories`], untracked directories
+    /// are only removed if a pathspec names them. A directory is removed as
+    /// a whole unless it holds files to keep; the repositories inside are
+    /// reported as skipped.
+    pub fn clean_entries(&self, options: &CleanOptions) -> Result<Vec<CleanEntry>> {
+        let mut ignore = match options.ignored {
+            CleanIgnored::Also => self.ignore_none()?,
+            _ => self.ignore()?,
+        };
+        ignore.add_patterns(options.excludes);
+        let index = Index::load(self)?;
+        let mut walk = CleanWalk {
+            root: self.get_root(),
+            tracked: TrackedPaths::new(index.entries.iter().map(|e| e.path.as_str())),
+            ignore,
+            options,
+        };
+
+        Ok(walk.scan("", false)?.entries)
+    }
+}
+
+/// What a directory holds.
+struct Scan {
+    /// The paths to remove below the directory, unless it is removed as a
+    /// whole
+    entries: Vec<CleanEntry>,
+    /// Whether every file below the directory is to be removed
+    all_removed: bool,
+    /// Whether any file below the directory is to be removed
+    any_removed: bool,
+    /// Whether there is a repository below the directory
+    has_repository: bool,
+}
+
+/// The state of collecting the files to clean.
+struct CleanWalk<'a> {
+    root: &'a Path,
+    tracked: TrackedPaths,
+    ignore: Ignore,
+    options: &'a CleanOptions<'a>,
+}
+
+impl CleanWalk<'_> {
+    /// Returns true if `path` matches the pathspecs, or there are none. A
+    /// pathspec with a trailing slash matches the directory itself too.
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        self.options.pathspecs.is_empty()
+            || self.options.pathspecs.iter().any(|spec| {
+                match_pathspec(spec, path).is_some()
+                    || (is_dir && spec.strip_suffix('/') == Some(path))
+            })
+    }
+
+    /// Scans the directory `prefix` of the working tree, e.g. `src/`, for
+    /// files to remove. Inside an ignored directory every file is ignored.
+    fn scan(&mut self, prefix: &str, ignored_dir: bool) -> Result<Scan> {
+        let keep_mode = self.options.ignored != CleanIgnored::Only;
+        let mut scan = Scan {
+            entries: vec![],
+            all_removed: true,
+            any_removed: false,
+            has_repository: false,
+        };
+
+        let mut entries: Vec<_> =
+            fs::read_dir(self.root.join(prefix))?.collect::<std::io::Result<_>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name == ".git" {
+                continue;
+            }
+            let path = format!("{prefix}{name}");
+            let is_dir = entry.file_type()?.is_dir();
+
+            let (tracking, ignored) =
+                self.tracked
+                    .classify(&path, is_dir, &mut self.ignore, ignored_dir)?;
+            match tracking {
+                Tracking::Tracked => {
+                    scan.all_removed = false;
+                    continue;
+                }
+                Tracking::HoldsTracked => {
+                    let sub = self.scan(&format!("{path}/"), ignored)?;
+                    scan.entries.extend(sub.entries);
+                    scan.all_removed = false;
+                    continue;
+                }
+                Tracking::Untracked => {}
+            }
+
+            // Files to remove are untracked files that aren't ignored, or
+            // only ignored ones
+            let removed = ignored != keep_mode;
+            if !is_dir {
+                match removed {
+                    true => {
+                        scan.any_removed = true;
+                        if self.matches(&path, false) {
+                            scan.entries.push(CleanEntry::Remove(path));
+                        }
+                    }
+                    false => scan.all_removed = false,
+                }
+                continue;
+            }
+
+            if !self.options.repositories && entry.path().join(".git").exists() {
+                scan.has_repository = true;
+                continue;
+            }
+            if !removed && keep_mode {
+                // Nothing inside an ignored directory is removed
+                scan.all_removed = false;
+                continue;
+            }
+
+            let sub = self.scan(&format!("{path}/"), ignored)?;
+            // Empty directories aren't removed when cleaning ignored files
+            let whole = sub.all_removed && (keep_mode || sub.any_removed);
+            scan.all_removed &= whole;
+            scan.any_removed |= sub.any_removed;
+            scan.has_repository |= sub.has_repository;
+
+            // Without -d, only directories named by a pathspec are removed
+            let matched = self.matches(&path, true);
+            let allowed =
+                matched && (self.options.directories || !self.options.pathspecs.is_empty());
+            if whole && allowed {
+                match sub.has_repository {
+                    true => self.list_removal(&format!("{path}/"), &mut scan.entries)?,
+                    false => scan.entries.push(CleanEntry::Remove(format!("{path}/"))),
+                }
+            } else if matched && !allowed && (whole || keep_mode) {
+                // An untracked directory that may not be removed
+            } else {
+                scan.entries.extend(sub.entries);
+            }
+        }
+
+        Ok(scan)
+    }
+
+    /// Lists the contents of the directory `prefix` to remove, which holds
+    /// repositories, keeping the directories leading to them.
+    fn list_removal(&self, prefix: &str, entries: &mut Vec<CleanEntry>) -> Result<()> {
+        let mut dir: Vec<_> =
+            fs::read_dir(self.root.join(prefix))?.collect::<std::io::Result<_>>()?;
+        dir.sort_by_key(|entry| entry.file_name());
+        for entry in dir {
+            let path = format!("{prefix}{}", entry.file_name().to_string_lossy());
+            if !entry.file_type()?.is_dir() {
+                entries.push(CleanEntry::Remove(path));
+            } else if entry.path().join(".git").exists() {
+                entries.push(CleanEntry::SkipRepository(path));
+            } else if has_repository(&entry.path())? {
+                self.list_removal(&format!("{path}/"), entries)?;
+            } else {
+                // Like git, without a trailing slash
+                entries.push(CleanEntry::Remove(path));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns true if there is a repository below the directory `dir`.
+fn has_repository(dir: &Path) -> Result<bool> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir()
+            && (entry.path().join(".git").exists() || has_repository(&entry.path())?)
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_clean_entries() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+
+        fs::write(root.join(".gitignore"), "*.o\n").unwrap();
+        fs::write(root.join("tracked"), "").unwrap();
+        let tree = repo.add_all().unwrap();
+        repo.reset_index(&tree).unwrap();
+        for path in ["u", "a.o", "dir/x", "mixed/y", "mixed/y.o"] {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let entries = |options: &CleanOptions| -> Vec<String> {
+            repo.clean_entries(options)
+                .unwrap()
+                .into_iter()
+                .map(|entry| match entry {
+                    CleanEntry::Remove(path) | CleanEntry::SkipRepository(path) => path,
+                })
+                .collect()
+        };
+        assert_eq!(entries(&CleanOptions::default()), ["u"]);
+        let options = CleanOptions {
+            directories: true,
+            ..Default::default()
+        };
+        // A directory holding ignored files isn't removed as a whole
+        assert_eq!(entries(&options), ["dir/", "mixed/y", "u"]);
+        let options = CleanOptions {
+            ignored: CleanIgnored::Only,
+            ..Default::default()
+        };
+        assert_eq!(entries(&options), ["a.o", "mixed/y.o"]);
+        let excludes = ["u".to_string()];
+        let pathspecs = ["dir".to_string(), "u".to_string(), "*.o".to_string()];
+        let options = CleanOptions {
+            ignored: CleanIgnored::Also,
+            excludes: &excludes,
+            pathspecs: &pathspecs,
+            ..Default::default()
+        };
+        assert_eq!(entries(&options), ["a.o", "dir/", "mixed/y.o"]);
+    }
+}
@@ -0,0 +1,92 @@
+//! Recording the index as a new commit on top of HEAD, as `commit` does.
+
+use crate::object::write_commit;
+
+use super::{
+    error::{RepoError, Result},
+    index::Index,
+    Repository,
+};
+
+impl Repository {
+    /// Writes the tree of the index and a commit of it whose parent is
+    /// HEAD, and moves HEAD to it. Returns the hash of the commit.
+    pub fn commit(&self, message: &str) -> Result<String> {
+        let parent = self.read_ref("HEAD")?;
+        let mut index = Index::lock(self)?;
+        if index.has_conflicts() {
+            Err(RepoError::Other(
+                "Committing is not possible because you have unmerged files.".to_string(),
+            ))?;
+        }
+
+        let tree = index.write_tree(self)?;
+        let commit = write_commit(&tree, parent.as_deref().as_slice(), message, self)?;
+        // Keep the trees written in the cache tree
+        index.commit()?;
+
+        let subject = message.lines().next().unwrap_or_default();
+        let reflog_message = match parent {
+            Some(_) => format!("commit: {subject}"),
+            None => format!("commit (initial): {subject}"),
+        };
+        self.update_ref("HEAD", &commit, &reflog_message)?;
+
+        Ok(commit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::object::{tree::Tree, Commit};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_commit() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("dir/a"), "a\n").unwrap();
+        fs::write(root.join("secret"), "secret\n").unwrap();
+        repo.add_paths(&[".".to_string()], None).unwrap();
+        let first = repo.commit("one").unwrap();
+        let files = |commit: &str| {
+            let tree = Commit::read(commit, &repo).unwrap().tree;
+            Tree::read_recursive(&tree, &repo).unwrap()
+        };
+        assert_eq!(
+            files(&first).keys().collect::<Vec<_>>(),
+            ["dir/a", "secret"]
+        );
+
+        // Unstaged changes and untracked files aren't committed, and
+        // `rm --cached` leaves the file out of the commit
+        fs::write(root.join("dir/a"), "changed\n").unwrap();
+        fs::write(root.join("untracked"), "untracked\n").unwrap();
+        let mut index = Index::lock(&repo).unwrap();
+        index.remove("secret");
+        index.commit().unwrap();
+        let second = repo.commit("two").unwrap();
+        assert_eq!(
+            Commit::read(&second, &repo).unwrap().parents,
+            [first.as_str()]
+        );
+        assert_eq!(repo.read_ref("HEAD").unwrap(), Some(second.clone()));
+        let second_files = files(&second);
+        assert_eq!(second_files.keys().collect::<Vec<_>>(), ["dir/a"]);
+        assert_eq!(second_files["dir/a"], files(&first)["dir/a"]);
+        assert!(root.join("secret").exists());
+
+        // The trees written are cached in the index
+        let index = Index::load(&repo).unwrap();
+        let tree = Commit::read(&second, &repo).unwrap().tree;
+        assert_eq!(
+            index.cache_tree().and_then(|t| t.get("")),
+            Some(tree.as_str())
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use super::error::{RepoError, Result};
+
+#[derive(Debug, Clone)]
+struct Section {
+    name: String,
+    subsection: Option<String>,
+    entries: Vec<(String, String)>,
+}
+
+impl Section {
+    fn matches(&self, name: &str, subsection: Option<&str>) -> bool {
+        self.name.eq_ignore_ascii_case(name) && self.subsection.as_deref() == subsection
+    }
+}
+
+/// Git configuration, read from the user's `~/.gitconfig` and the
+/// repository's `.git/config`. Only the repository config is ever written.
+#[derive(Debug, Default)]
+pub struct Config {
+    global: Vec<Section>,
+    local: Vec<Section>,
+    path: PathBuf,
+}
+
+/// Splits a key like `branch.feature/login.remote` into its section,
+/// subsection and variable name.
+fn split_key(key: &str) -> Result<(&str, Option<&str>, &str)> {
+    let (section, rest) = key
+        .split_once('.')
+        .ok_or_else(|| RepoError::Config(format!("key does not contain a section: {key}")))?;
+    match rest.rsplit_once('.') {
+        Some((subsection, name)) => Ok((section, Some(subsection), name)),
+        None => Ok((section, None, rest)),
+    }
+}
+
+impl Config {
+    /// Loads the configuration of the repository at the given git directory.
+    pub fn load(git_dir: &Path) -> Result<Config> {
+        let global = match env::var_os("HOME") {
+            Some(home) => Self::read_file(&Path::new(&home).join(".gitconfig"))?,
+            None => vec![],
+        };
+        let path = git_dir.join("config");
+        let local = Self::read_file(&path)?;
+
+        Ok(Config {
+            global,
+            local,
+            path,
+        })
+    }
+
+    /// Loads a single file in the config format, such as `.gitmodules`.
+    pub fn load_file(path: &Path) -> Result<Config> {
+        Ok(Config {
+            global: vec![],
+            local: Self::read_file(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn read_file(path: &Path) -> Result<Vec<Section>> {
+        match fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn parse(content: &str) -> Result<Vec<Section>> {
+        let mut sections: Vec<Section> = vec![];
+
+        for (n, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let bad_line = || RepoError::Config(format!("bad config line {}", n + 1));
+
+            if let Some(header) = line.strip_prefix('[') {
+                // format: `[section]` or `[section "subsection"]`
+                let header = &header[..header.find(']').ok_or_else(bad_line)?];
+                let section = match header.split_once(' ') {
+                    Some((name, subsection)) => Section {
+                        name: name.to_lowercase(),
+                        subsection: Some(
+                            subsection
+                                .trim()
+                                .trim_matches('"')
+                                .replace("\\\"", "\"")
+                                .replace("\\\\", "\\"),
+                        ),
+                        entries: vec![],
+                    },
+                    // Legacy `[section.subsection]` syntax
+                    None => match header.split_once('.') {
+                        Some((name, subsection)) => Section {
+                            name: name.to_lowercase(),
+                            subsection: Some(subsection.to_lowercase()),
+                            entries: vec![],
+                        },
+                        None => Section {
+                            name: header.to_lowercase(),
+                            subsection: None,
+                            entries: vec![],
+                        },
+                    },
+                };
+                sections.push(section);
+                continue;
+            }
+
+            let section = sections.last_mut().ok_or_else(bad_line)?;
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), parse_value(value)),
+                // A key without a value is a boolean true
+                None => (line, "true".to_string()),
+            };
+            section.entries.push((key.to_lowercase(), value));
+        }
+
+        Ok(sections)
+    }
+
+    fn all_sections(&self) -> impl Iterator<Item = &Section> {
+        self.global.iter().chain(self.local.iter())
+    }
+
+    /// Returns all values of the given key, in the order they were read.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let Ok((section, subsection, name)) = split_key(key) else {
+            return vec![];
+        };
+        self.all_sections()
+            .filter(|s| s.matches(section, subsection))
+            .flat_map(|s| s.entries.iter())
+            .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Returns the last value of the given key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).pop()
+    }
+
+    /// Returns the value of the given key interpreted as a boolean.
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)?.to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Some(true),
+            "false" | "no" | "off" | "0" | "" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the given key interpreted as an integer,
+    /// accepting the `k`, `m` and `g` suffixes.
+    pub fn get_int(&self, key: &str) -> Option<i64> {
+        let value = self.get(key)?.to_lowercase();
+        let (number, factor) = match value.chars().last()? {
+            'k' => (&value[..value.len() - 1], 1024),
+            'm' => (&value[..value.len() - 1], 1024 * 1024),
+            'g' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+            _ => (value.as_str(), 1),
+        };
+        number.parse::<i64>().ok().map(|n| n * factor)
+    }
+
+    /// Returns the names of all subsections of the given section, e.g. the
+    /// remote names for `remote`.
+    pub fn subsections(&self, section: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = vec![];
+        for s in self.all_sections() {
+            if let Some(subsection) = s.subsection.as_deref() {
+                if s.name.eq_ignore_ascii_case(section) && !names.contains(&subsection) {
+                    names.push(subsection);
+                }
+            }
+        }
+        names
+    }
+
+    /// Sets the given key in the repository config, replacing any existing value.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let (section, subsection, name) = split_key(key)?;
+        let index = match self
+            .local
+            .iter()
+            .rposition(|s| s.matches(section, subsection))
+        {
+            Some(index) => index,
+            None => {
+                self.local.push(Section {
+                    name: section.to_lowercase(),
+                    subsection: subsection.map(str::to_string),
+                    entries: vec![],
+                });
+                self.local.len() - 1
+            }
+        };
+
+        let entries = &mut self.local[index].entries;
+        match entries
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        {
+            Some(entry) => entry.1 = value.to_string(),
+            None => entries.push((name.to_lowercase(), value.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Removes the given key from the repository config.
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        let (section, subsection, name) = split_key(key)?;
+        for s in self.local.iter_mut() {
+            if s.matches(section, subsection) {
+                s.entries.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+            }
+        }
+        self.local.retain(|s| !s.entries.is_empty());
+
+        Ok(())
+    }
+
+    /// Renames a section such as `branch.old` to `branch.new`.
+    pub fn rename_section(&mut self, old: &str, new: &str) -> Result<()> {
+        let (old_name, old_sub) = split_section(old);
+        let (new_name, new_sub) = split_section(new);
+        for s in self.local.iter_mut() {
+            if s.matches(old_name, old_sub) {
+                s.name = new_name.to_lowercase();
+                s.subsection = new_sub.map(str::to_string);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copies a section such as `branch.old` to `branch.new`.
+    pub fn copy_section(&mut self, old: &str, new: &str) -> Result<()> {
+        let (old_name, old_sub) = split_section(old);
+        let (new_name, new_sub) = split_section(new);
+        let copies: Vec<Section> = self
+            .local
+            .iter()
+            .filter(|s| s.matches(old_name, old_sub))
+            .map(|s| Section {
+                name: new_name.to_lowercase(),
+                subsection: new_sub.map(str::to_string),
+                entries: s.entries.clone(),
+            })
+            .collect();
+        self.local.extend(copies);
+
+        Ok(())
+    }
+
+    /// Removes a section such as `branch.old` from the repository config.
+    pub fn remove_section(&mut self, section: &str) -> Result<()> {
+        let (name, subsection) = split_section(section);
+        self.local.retain(|s| !s.matches(name, subsection));
+
+        Ok(())
+    }
+
+    /// Writes the repository config back to `.git/config`.
+    pub fn save(&self) -> Result<()> {
+        let mut content = String::new();
+        for section in &self.local {
+            match &section.subsection {
+                Some(subsection) => content.push_str(&format!(
+                    "[{} \"{}\"]\n",
+                    section.name,
+                    subsection.replace('\\', "\\\\").replace('"', "\\\"")
+                )),
+                None => content.push_str(&format!("[{}]\n", section.name)),
+            }
+            for (key, value) in &section.entries {
+                content.push_str(&format!("\t{} = {}\n", key, quote_value(value)));
+            }
+        }
+        fs::write(&self.path, content)?;
+
+        Ok(())
+    }
+}
+
+fn split_section(section: &str) -> (&str, Option<&str>) {
+    match section.split_once('.') {
+        Some((name, subsection)) => (name, Some(subsection)),
+        None => (section, None),
+    }
+}
+
+/// Parses a config value, handling quotes, escapes and trailing comments.
+fn parse_value(value: &str) -> String {
+    let mut result = String::new();
+    let mut quoted = false;
+    let mut chars = value.trim().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(c) => result.push(c),
+                None => {}
+            },
+            '#' | ';' if !quoted => break,
+            c => result.push(c),
+        }
+    }
+    if !quoted {
+        let len = result.trim_end().len();
+        result.truncate(len);
+    }
+    result
+}
+
+fn quote_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    if value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';']) {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_config() {
+        let sections = Config::parse(
+            "[core]\n\
+             \tbare = false\n\
+             \tfilemode\n\
+             # comment\n\
+             [branch \"feature/login\"]\n\
+             \tremote = origin ; trailing\n\
+             \tmerge = refs/heads/login\n\
+             [alias]\n\
+             \tst = \"status -s # not a comment\"\n",
+        )
+        .unwrap();
+        let config = Config {
+            local: sections,
+            ..Default::default()
+        };
+
+        assert_eq!(config.get_bool("core.bare"), Some(false));
+        assert_eq!(config.get_bool("core.fileMode"), Some(true));
+        assert_eq!(config.get("branch.feature/login.remote"), Some("origin"));
+        assert_eq!(
+            config.get("branch.feature/login.merge"),
+            Some("refs/heads/login")
+        );
+        assert_eq!(config.get("alias.st"), Some("status -s # not a comment"));
+        assert_eq!(config.get("branch.main.remote"), None);
+    }
+
+    #[test]
+    fn test_write_config() {
+        let temp_dir = tempdir().unwrap();
+        let mut config = Config {
+            path: temp_dir.path().join("config"),
+            ..Default::default()
+        };
+        config.set("branch.old.remote", "origin").unwrap();
+        config.set("core.bare", "false").unwrap();
+        config.rename_section("branch.old", "branch.new").unwrap();
+        config.save().unwrap();
+
+        let config = Config::load(temp_dir.path()).unwrap();
+        assert_eq!(config.get("branch.new.remote"), Some("origin"));
+        assert_eq!(config.get("branch.old.remote"), None);
+        assert_eq!(config.get_bool("core.bare"), Some(false));
+    }
+}
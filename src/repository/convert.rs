@@ -0,0 +1,399 @@
+//! Converting file content between the working tree and the repository, as
+//! the `filter`, `text`, `eol` and `crlf` attributes and `core.autocrlf` say.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    os::unix::ffi::OsStrExt,
+};
+
+use crate::object::Object;
+
+use super::{
+    attributes::{AttrState, Attributes, PathAttributes},
+    error::{RepoError, Result},
+    filter::{FilterKind, Filters},
+    index::Index,
+    Repository,
+};
+
+/// `core.autocrlf`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AutoCrlf {
+    False,
+    True,
+    Input,
+}
+
+/// `core.safecrlf`, what to do if converting a file isn't reversible.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SafeCrlf {
+    False,
+    Warn,
+    Fail,
+}
+
+/// How the line endings of a file are converted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CrlfAction {
+    /// Not at all
+    Binary,
+    /// Text, with the line endings of `core.eol`
+    Text,
+    /// Text, with LF in the working tree
+    TextInput,
+    /// Text, with CRLF in the working tree
+    TextCrlf,
+    /// Text if it looks like text, with the line endings of `core.eol`
+    Auto,
+    /// Text if it looks like text, with LF in the working tree
+    AutoInput,
+    /// Text if it looks like text, with CRLF in the working tree
+    AutoCrlf,
+}
+
+impl CrlfAction {
+    fn is_auto(self) -> bool {
+        matches!(
+            self,
+            CrlfAction::Auto | CrlfAction::AutoInput | CrlfAction::AutoCrlf
+        )
+    }
+}
+
+/// Counts of the kinds of characters in a file, to tell text from binary.
+#[derive(Debug, Clone, Default)]
+struct TextStats {
+    nul: usize,
+    lone_cr: usize,
+    lone_lf: usize,
+    crlf: usize,
+    printable: usize,
+    nonprintable: usize,
+}
+
+impl TextStats {
+    fn gather(content: &[u8]) -> TextStats {
+        let mut stats = TextStats::default();
+        let mut i = 0;
+        while i < content.len() {
+            match content[i] {
+                b'\r' if content.get(i + 1) == Some(&b'\n') => {
+                    stats.crlf += 1;
+                    i += 1;
+                }
+                b'\r' => stats.lone_cr += 1,
+                b'\n' => stats.lone_lf += 1,
+                127 => stats.nonprintable += 1,
+                // Backspace, tab, form feed and escape are common in text
+                b'\x08' | b'\t' | b'\x0c' | b'\x1b' => stats.printable += 1,
+                0 => {
+                    stats.nul += 1;
+                    stats.nonprintable += 1;
+                }
+                b if b < 32 => stats.nonprintable += 1,
+                _ => stats.printable += 1,
+            }
+            i += 1;
+        }
+        // A trailing DOS end-of-file marker is fine
+        if content.last() == Some(&0x1a) {
+            stats.nonprintable -= 1;
+        }
+
+        stats
+    }
+
+    /// Returns true if the content doesn't look like text, like git guesses.
+    fn is_binary(&self) -> bool {
+        self.lone_cr > 0 || self.nul > 0 || (self.printable >> 7) < self.nonprintable
+    }
+}
+
+/// The conversion of files between the working tree and the repository.
+pub struct Convert<'a> {
+    repo: &'a Repository,
+    attributes: Attributes,
+    auto_crlf: AutoCrlf,
+    /// Whether `core.eol` asks for CRLF
+    eol_crlf: bool,
+    safe_crlf: SafeCrlf,
+    /// The blobs of the index by path
+    indexed: HashMap<String, String>,
+    filters: Filters,
+}
+
+impl Repository {
+    /// Returns the conversion of the files of the working tree, with its
+    /// attributes.
+    pub fn convert(&self) -> Result<Convert<'_>> {
+        self.load_convert(self.attributes()?)
+    }
+
+    /// Returns the conversion for checking out `files`, with the attributes
+    /// they define.
+    pub fn convert_for(&self, files: &BTreeMap<String, (u32, String)>) -> Result<Convert<'_>> {
+        self.load_convert(self.attributes_of(files)?)
+    }
+
+    fn load_convert(&self, attributes: Attributes) -> Result<Convert<'_>> {
+        let config = self.config()?;
+        let auto_crlf = match config.get("core.autocrlf") {
+            Some(value) if value.eq_ignore_ascii_case("input") => AutoCrlf::Input,
+            _ => match config.get_bool("core.autocrlf") {
+                Some(true) => AutoCrlf::True,
+                _ => AutoCrlf::False,
+            },
+        };
+        let safe_crlf = match config.get("core.safecrlf") {
+            Some(value) if value.eq_ignore_ascii_case("warn") => SafeCrlf::Warn,
+            _ => match config.get_bool("core.safecrlf") {
+                Some(true) => SafeCrlf::Fail,
+                Some(false) => SafeCrlf::False,
+                None => SafeCrlf::Warn,
+            },
+        };
+        let eol_crlf = config
+            .get("core.eol")
+            .is_some_and(|eol| eol.eq_ignore_ascii_case("crlf"));
+        let index = Index::load(self)?;
+
+        Ok(Convert {
+            repo: self,
+            attributes,
+            auto_crlf,
+            eol_crlf,
+            safe_crlf,
+            indexed: index
+                .files()
+                .into_iter()
+                .map(|(path, (_, hash))| (path, hash))
+                .collect(),
+            filters: Filters::load(&config),
+        })
+    }
+}
+
+impl Convert<'_> {
+    /// Reads a file of the working tree, by its path relative to the root,
+    /// as it is stored in the repository. For a symlink that is the path it
+    /// points to. If `write` is set the content is about to be written as a
+    /// blob, and a conversion that can't be reversed is warned about, or
+    /// fails with `core.safecrlf`.
+    pub fn read_file(&self, path: &str, meta: &fs::Metadata, write: bool) -> Result<Vec<u8>> {
+        let full_path = self.repo.get_root().join(path);
+        if meta.is_symlink() {
+            return Ok(fs::read_link(full_path)?.as_os_str().as_bytes().to_vec());
+        }
+        self.to_git(path, fs::read(full_path)?, write)
+    }
+
+    /// Converts the content of a file in the working tree to what is stored
+    /// in the repository, see [`Convert::read_file`]. The clean filter runs
+    /// first.
+    pub fn to_git(&self, path: &str, content: Vec<u8>, write: bool) -> Result<Vec<u8>> {
+        let attributes = self.attributes.check(path)?;
+        let content = match attributes.value("filter") {
+            Some(driver) => self
+                .filters
+                .apply(driver, FilterKind::Clean, path, content)?,
+            None => content,
+        };
+
+        let action = self.crlf_action(&attributes);
+        if action == CrlfAction::Binary {
+            return Ok(content);
+        }
+
+        let stats = TextStats::gather(&content);
+        let mut convert = true;
+        if action.is_auto() {
+            if stats.is_binary() {
+                return Ok(content);
+            }
+            // Files committed with CRs are left alone, so they don't change
+            // by merely being touched
+            if self.has_cr_in_index(path)? {
+                convert = false;
+            }
+        }
+
+        if write && self.safe_crlf != SafeCrlf::False {
+            // Would checking out what is stored give back the same file?
+            let mut new = stats.clone();
+            if convert {
+                new.lone_lf += new.crlf;
+                new.crlf = 0;
+            }
+            if self.will_convert_lf_to_crlf(&new, action) {
+                new.crlf += new.lone_lf;
+                new.lone_lf = 0;
+            }
+            let fail = self.safe_crlf == SafeCrlf::Fail;
+            if stats.crlf > 0 && new.crlf == 0 {
+                match fail {
+                    true => Err(RepoError::CrlfWouldBeReplaced(path.to_string()))?,
+                    false => eprintln!(
+                        "warning: in the working copy of '{path}', CRLF will be replaced by \
+                         LF the next time Git touches it"
+                    ),
+                }
+            } else if stats.lone_lf > 0 && new.lone_lf == 0 {
+                match fail {
+                    true => Err(RepoError::LfWouldBeReplaced(path.to_string()))?,
+                    false => eprintln!(
+                        "warning: in the working copy of '{path}', LF will be replaced by \
+                         CRLF the next time Git touches it"
+                    ),
+                }
+            }
+        }
+
+        if !convert || stats.crlf == 0 {
+            return Ok(content);
+        }
+        // Drop the CR of every CRLF
+        let mut converted = Vec::with_capacity(content.len() - stats.crlf);
+        for (i, &b) in content.iter().enumerate() {
+            if b != b'\r' || content.get(i + 1) != Some(&b'\n') {
+                converted.push(b);
+            }
+        }
+
+        Ok(converted)
+    }
+
+    /// Converts the content of a regular file's blob to what is written to
+    /// the working tree. The smudge filter runs last.
+    pub fn to_worktree(&self, path: &str, content: &[u8]) -> Result<Vec<u8>> {
+        let attributes = self.attributes.check(path)?;
+        let action = self.crlf_action(&attributes);
+        let stats = TextStats::gather(content);
+        let mut converted = content.to_vec();
+        if self.will_convert_lf_to_crlf(&stats, action) {
+            converted = Vec::with_capacity(content.len() + stats.lone_lf);
+            for (i, &b) in content.iter().enumerate() {
+                if b == b'\n' && (i == 0 || content[i - 1] != b'\r') {
+                    converted.push(b'\r');
+                }
+                converted.push(b);
+            }
+        }
+
+        match attributes.value("filter") {
+            Some(driver) => self
+                .filters
+                .apply(driver, FilterKind::Smudge, path, converted),
+            None => Ok(converted),
+        }
+    }
+
+    /// Returns how the line endings of a file are converted, like git's
+    /// `convert_attrs`.
+    fn crlf_action(&self, attributes: &PathAttributes) -> CrlfAction {
+        let from_attr = |state: &AttrState| match state {
+            AttrState::Set => Some(CrlfAction::Text),
+            AttrState::Unset => Some(CrlfAction::Binary),
+            AttrState::Value(value) if value == "input" => Some(CrlfAction::TextInput),
+            AttrState::Value(value) if value == "auto" => Some(CrlfAction::Auto),
+            _ => None,
+        };
+        // The crlf attribute is the older form of text
+        let mut action =
+            from_attr(attributes.get("text")).or_else(|| from_attr(attributes.get("crlf")));
+
+        if action != Some(CrlfAction::Binary) {
+            match (action, attributes.value("eol")) {
+                (Some(CrlfAction::Auto), Some("lf")) => action = Some(CrlfAction::AutoInput),
+                (Some(CrlfAction::Auto), Some("crlf")) => action = Some(CrlfAction::AutoCrlf),
+                (_, Some("lf")) => action = Some(CrlfAction::TextInput),
+                (_, Some("crlf")) => action = Some(CrlfAction::TextCrlf),
+                _ => {}
+            }
+        }
+
+        match action {
+            Some(CrlfAction::Text) if self.text_eol_is_crlf() => CrlfAction::TextCrlf,
+            Some(CrlfAction::Text) => CrlfAction::TextInput,
+            Some(action) => action,
+            None => match self.auto_crlf {
+                AutoCrlf::False => CrlfAction::Binary,
+                AutoCrlf::True => CrlfAction::AutoCrlf,
+                AutoCrlf::Input => CrlfAction::AutoInput,
+            },
+        }
+    }
+
+    /// Returns true if text files get CRLF in the working tree by default.
+    fn text_eol_is_crlf(&self) -> bool {
+        match self.auto_crlf {
+            AutoCrlf::True => true,
+            AutoCrlf::Input => false,
+            AutoCrlf::False => self.eol_crlf,
+        }
+    }
+
+    /// Returns true if checking out a file with these stats converts its
+    /// LFs to CRLFs.
+    fn will_convert_lf_to_crlf(&self, stats: &TextStats, action: CrlfAction) -> bool {
+        let crlf = match action {
+            CrlfAction::Binary | CrlfAction::TextInput | CrlfAction::AutoInput => false,
+            CrlfAction::TextCrlf | CrlfAction::AutoCrlf => true,
+            CrlfAction::Text | CrlfAction::Auto => self.text_eol_is_crlf(),
+        };
+        if !crlf || stats.lone_lf == 0 {
+            return false;
+        }
+        // Files that have CRs already, or aren't text, are left alone
+        !action.is_auto() || (stats.lone_cr == 0 && stats.crlf == 0 && !stats.is_binary())
+    }
+
+    /// Returns true if the version of `path` in the index contains a CR.
+    fn has_cr_in_index(&self, path: &str) -> Result<bool> {
+        match self.indexed.get(path) {
+            Some(hash) => Ok(Object::read_blob(hash, self.repo)?.contains(&b'\r')),
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_convert() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+
+        let mut config = repo.config().unwrap();
+        config.set("core.autocrlf", "true").unwrap();
+        config.save().unwrap();
+        fs::write(root.join(".gitattributes"), "*.lf eol=lf\n*.bin -text\n").unwrap();
+        let convert = repo.convert().unwrap();
+
+        let cases: [(&str, &[u8], &[u8]); 4] = [
+            ("a.txt", b"a\nb\n", b"a\r\nb\r\n"),
+            ("a.lf", b"a\nb\n", b"a\nb\n"),
+            ("a.bin", b"a\nb\n", b"a\nb\n"),
+            // Binary content isn't converted by autocrlf
+            ("a.dat", b"a\n\0\n", b"a\n\0\n"),
+        ];
+        for (path, blob, worktree) in cases {
+            assert_eq!(convert.to_worktree(path, blob).unwrap(), worktree, "{path}");
+        }
+
+        let cases: [(&str, &[u8], &[u8]); 4] = [
+            ("a.txt", b"a\r\nb\r\n", b"a\nb\n"),
+            ("a.lf", b"a\r\nb\n", b"a\nb\n"),
+            ("a.bin", b"a\r\nb\r\n", b"a\r\nb\r\n"),
+            ("a.dat", b"a\r\n\0\r\n", b"a\r\n\0\r\n"),
+        ];
+        for (path, worktree, blob) in cases {
+            let content = convert.to_git(path, worktree.to_vec(), false).unwrap();
+            assert_eq!(content, blob, "{path}");
+        }
+    }
+}
@@ -0,0 +1,613 @@
+//! Differences between two sets of files, e.g. two trees, written as
+//! patches or as a diffstat like `git diff` does.
+
+use std::{collections::BTreeMap, fs, io::Write};
+
+use crate::{
+    diff::{self, count_changes, is_binary, lines, write_unified, DiffColors},
+    object::{
+        commit::merge_bases,
+        tree::{file_mode, Tree, EMPTY_TREE, MODE_GITLINK, MODE_SYMLINK},
+        Commit, Object, ObjectType,
+    },
+    pathspec::match_pathspec,
+};
+
+use super::{
+    error::{RepoError, Result},
+    index::{hash_file, is_missing, Index},
+    Repository,
+};
+
+/// The lines of context around changes in patches.
+const CONTEXT: usize = 3;
+
+/// How to write patches.
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// The lines of context around changes
+    pub context: usize,
+    pub colors: DiffColors,
+    /// Read the new side of the changes from the working tree rather than
+    /// from their blobs, which aren't written
+    pub worktree: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> DiffOptions {
+        DiffOptions {
+            context: CONTEXT,
+            colors: DiffColors::default(),
+            worktree: false,
+        }
+    }
+}
+
+/// The width diffstats are fitted into.
+const STAT_WIDTH: usize = 80;
+
+/// A file that differs between two sets of files, with its `(mode, hash)`
+/// on both sides. A file is added if it has no old side and deleted if it
+/// has no new side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileChange {
+    pub path: String,
+    pub old: Option<(u32, String)>,
+    pub new: Option<(u32, String)>,
+}
+
+/// What the changes of `diff` are between.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffSides {
+    /// The index and the working tree
+    Worktree,
+    /// A tree and the index
+    Cached(String),
+    /// A tree and the working tree
+    TreeWorktree(String),
+    Trees(String, String),
+}
+
+impl DiffSides {
+    /// Returns true if the new side is the working tree.
+    pub fn worktree(&self) -> bool {
+        matches!(self, DiffSides::Worktree | DiffSides::TreeWorktree(_))
+    }
+}
+
+/// Returns the files that differ between `old` and `new`, by path. A file
+/// that became a symlink or vice versa is deleted and added again.
+pub fn diff_files(
+    old: &BTreeMap<String, (u32, String)>,
+    new: &BTreeMap<String, (u32, String)>,
+) -> Vec<FileChange> {
+    let mut paths: Vec<&String> = old.keys().chain(new.keys()).collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut changes = vec![];
+    for path in paths {
+        let (o, n) = (old.get(path), new.get(path));
+        if o == n {
+            continue;
+        }
+        let change = |old: Option<&(u32, String)>, new: Option<&(u32, String)>| FileChange {
+            path: path.clone(),
+            old: old.cloned(),
+            new: new.cloned(),
+        };
+        match (o, n) {
+            (Some(o), Some(n)) if (o.0 == MODE_SYMLINK) != (n.0 == MODE_SYMLINK) => {
+                changes.push(change(Some(o), None));
+                changes.push(change(None, Some(n)));
+            }
+            _ => changes.push(change(o, n)),
+        }
+    }
+
+    changes
+}
+
+impl Repository {
+    /// Returns the files that differ between two trees.
+    pub fn diff_trees(&self, old: &str, new: &str) -> Result<Vec<FileChange>> {
+        Ok(diff_files(
+            &Tree::read_recursive(old, self)?,
+            &Tree::read_recursive(new, self)?,
+        ))
+    }
+
+    /// Returns what `diff` compares given its revisions: the index and the
+    /// working tree without any, or HEAD and the index if `cached`; a commit
+    /// and the working tree, or the index if `cached`; two commits, given as
+    /// `<commit> <commit>` or `<commit>..<commit>`; or the merge base of two
+    /// commits and the second one, for `<commit>...<commit>`.
+    pub fn diff_sides(&self, cached: bool, revisions: &[String]) -> Result<DiffSides> {
+        Ok(match (cached, revisions) {
+            (false, []) => DiffSides::Worktree,
+            (true, []) => match self.read_ref("HEAD")? {
+                Some(head) => DiffSides::Cached(Commit::read(&head, self)?.tree),
+                None => DiffSides::Cached(EMPTY_TREE.to_string()),
+            },
+            (true, [rev]) => DiffSides::Cached(self.tree_of(rev)?),
+            (false, [rev]) => match rev.split_once("..") {
+                Some((old, new)) => self.diff_range(old, new)?,
+                None => DiffSides::TreeWorktree(self.tree_of(rev)?),
+            },
+            (false, [old, new]) => DiffSides::Trees(self.tree_of(old)?, self.tree_of(new)?),
+            _ => Err(RepoError::Other("too many revisions".to_string()))?,
+        })
+    }
+
+    /// Returns the changes between the sides of a diff to the files
+    /// matching `pathspecs`, or to all files if there are none, and the
+    /// unmerged paths, which have no single version in the index to compare
+    /// with.
+    pub fn diff_changes(
+        &self,
+        sides: &DiffSides,
+        pathspecs: &[String],
+    ) -> Result<(Vec<FileChange>, Vec<String>)> {
+        let index = Index::load(self)?;
+        let changes = match sides {
+            DiffSides::Worktree => {
+                let mut files = self.worktree_files(&index)?;
+                files.retain(|path, _| index.get(path).is_some());
+                diff_files(&index.files(), &files)
+            }
+            DiffSides::Cached(tree) => {
+                diff_files(&Tree::read_recursive(tree, self)?, &index.files())
+            }
+            DiffSides::TreeWorktree(tree) => diff_files(
+                &Tree::read_recursive(tree, self)?,
+                &self.worktree_files(&index)?,
+            ),
+            DiffSides::Trees(old, new) => self.diff_trees(old, new)?,
+        };
+        let matches = |path: &str| {
+            pathspecs.is_empty() || pathspecs.iter().any(|s| match_pathspec(s, path).is_some())
+        };
+
+        let mut unmerged: Vec<String> = match sides {
+            DiffSides::Worktree | DiffSides::Cached(_) => index
+                .entries
+                .iter()
+                .filter(|e| e.stage != 0 && matches(&e.path))
+                .map(|e| e.path.clone())
+                .collect(),
+            _ => vec![],
+        };
+        unmerged.dedup();
+        let changes = changes
+            .into_iter()
+            .filter(|c| matches(&c.path) && !unmerged.contains(&c.path))
+            .collect();
+
+        Ok((changes, unmerged))
+    }
+
+    /// Returns the sides of `<old>..<new>`, or of `<old>...<new>` comparing
+    /// the merge base of both with `<new>`. An empty side means HEAD.
+    fn diff_range(&self, old: &str, new: &str) -> Result<DiffSides> {
+        let old = if old.is_empty() { "HEAD" } else { old };
+        let Some(new) = new.strip_prefix('.') else {
+            let new = if new.is_empty() { "HEAD" } else { new };
+            return Ok(DiffSides::Trees(self.tree_of(old)?, self.tree_of(new)?));
+        };
+
+        let new = if new.is_empty() { "HEAD" } else { new };
+        let (old_commit, new_commit) = (self.commit_of(old)?, self.commit_of(new)?);
+        let base = merge_bases(&old_commit, &new_commit, self)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| RepoError::Other(format!("{old}...{new}: no merge base")))?;
+
+        Ok(DiffSides::Trees(
+            Commit::read(&base, self)?.tree,
+            Commit::read(&new_commit, self)?.tree,
+        ))
+    }
+
+    /// Resolves a revision to a commit.
+    fn commit_of(&self, rev: &str) -> Result<String> {
+        let hash = self.peel_tags(&self.rev_parse(rev)?)?;
+        match Object::read(&hash, self)?.kind {
+            ObjectType::Commit => Ok(hash),
+            _ => Err(RepoError::Other(format!("{rev} is not a commit"))),
+        }
+    }
+
+    /// Resolves a revision to a tree, that of the commit it names if it
+    /// isn't one.
+    fn tree_of(&self, rev: &str) -> Result<String> {
+        let hash = self.peel_tags(&self.rev_parse(rev)?)?;
+        match Object::read(&hash, self)?.kind {
+            ObjectType::Commit => Ok(Commit::read(&hash, self)?.tree),
+            ObjectType::Tree => Ok(hash),
+            _ => Err(RepoError::Other(format!("{rev} is not a tree-ish"))),
+        }
+    }
+
+    /// Writes the changes as a patch in git's format.
+    pub fn write_patch(
+        &self,
+        out: &mut impl Write,
+        changes: &[FileChange],
+        options: &DiffOptions,
+    ) -> Result<()> {
+        let (meta, reset) = (&options.colors.meta, &options.colors.reset);
+        for change in changes {
+            let path = &change.path;
+            writeln!(out, "{meta}diff --git a/{path} b/{path}{reset}")?;
+            let old_hash = change.old.as_ref().map_or("0000000", |(_, h)| &h[..7]);
+            let new_hash = change.new.as_ref().map_or("0000000", |(_, h)| &h[..7]);
+            match (&change.old, &change.new) {
+                (None, Some((mode, _))) => {
+                    writeln!(out, "{meta}new file mode {mode:o}{reset}")?;
+                    writeln!(out, "{meta}index {old_hash}..{new_hash}{reset}")?;
+                }
+                (Some((mode, _)), None) => {
+                    writeln!(out, "{meta}deleted file mode {mode:o}{reset}")?;
+                    writeln!(out, "{meta}index {old_hash}..{new_hash}{reset}")?;
+                }
+                (Some((old_mode, old)), Some((new_mode, new))) => {
+                    if old_mode != new_mode {
+                        writeln!(out, "{meta}old mode {old_mode:o}{reset}")?;
+                        writeln!(out, "{meta}new mode {new_mode:o}{reset}")?;
+                    }
+                    if old == new {
+                        continue;
+                    }
+                    match old_mode == new_mode {
+                        true => writeln!(
+                            out,
+                            "{meta}index {old_hash}..{new_hash} {new_mode:o}{reset}"
+                        )?,
+                        false => writeln!(out, "{meta}index {old_hash}..{new_hash}{reset}")?,
+                    }
+                }
+                (None, None) => continue,
+            }
+
+            let old = self.read_side(&change.old)?;
+            let new = match &change.new {
+                Some((mode, _)) if options.worktree && *mode != MODE_GITLINK => {
+                    let meta = fs::symlink_metadata(self.get_root().join(path))?;
+                    self.convert()?.read_file(path, &meta, false)?
+                }
+                new => self.read_side(new)?,
+            };
+            if old.is_empty() && new.is_empty() {
+                continue;
+            }
+            let old_name = match change.old {
+                Some(_) => format!("a/{path}"),
+                None => "/dev/null".to_string(),
+            };
+            let new_name = match change.new {
+                Some(_) => format!("b/{path}"),
+                None => "/dev/null".to_string(),
+            };
+            if is_binary(&old) || is_binary(&new) {
+                writeln!(out, "Binary files {old_name} and {new_name} differ")?;
+                continue;
+            }
+            writeln!(out, "{meta}--- {old_name}{reset}")?;
+            writeln!(out, "{meta}+++ {new_name}{reset}")?;
+            write_unified(out, &old, &new, options.context, &options.colors)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the `(mode, hash)` of the files of the index as they are in
+    /// the working tree, only hashing those whose stat data changed. Files
+    /// missing from the working tree are left out, unless a sparse checkout
+    /// left them out, and submodules are at the commit they have checked
+    /// out.
+    pub fn worktree_files(&self, index: &Index) -> Result<BTreeMap<String, (u32, String)>> {
+        let convert = self.convert()?;
+        let mut files = BTreeMap::new();
+        for entry in &index.entries {
+            // The stages of a conflict are one file
+            if files.contains_key(&entry.path) {
+                continue;
+            }
+            let meta = match fs::symlink_metadata(self.get_root().join(&entry.path)) {
+                Ok(meta) => meta,
+                Err(e) if is_missing(&e) => {
+                    if entry.skip_worktree() {
+                        files.insert(entry.path.clone(), (entry.mode, entry.hash.clone()));
+                    }
+                    continue;
+                }
+                Err(e) => Err(e)?,
+            };
+
+            let version = if entry.mode == MODE_GITLINK {
+                let head = match self.open_submodule(&entry.path)? {
+                    Some(submodule) => submodule.read_ref("HEAD")?,
+                    None => None,
+                };
+                (MODE_GITLINK, head.unwrap_or_else(|| entry.hash.clone()))
+            } else if meta.is_dir() {
+                continue;
+            } else if entry.stage == 0 && entry.stat_matches(&meta) && !index.is_racy(entry) {
+                (entry.mode, entry.hash.clone())
+            } else {
+                (file_mode(&meta), hash_file(&entry.path, &meta, &convert)?)
+            };
+            files.insert(entry.path.clone(), version);
+        }
+
+        Ok(files)
+    }
+
+    /// Writes a diffstat of the changes: a line with the number of changed
+    /// lines and a graph of `+` and `-` per file, and a summary line.
+    pub fn write_stat(&self, out: &mut impl Write, changes: &[FileChange]) -> Result<()> {
+        // The number of added and removed lines, or the sizes of binary files
+        let mut stats = vec![];
+        for change in changes {
+            let old = self.read_side(&change.old)?;
+            let new = self.read_side(&change.new)?;
+            let stat = match is_binary(&old) || is_binary(&new) {
+                true => Stat::Binary(old.len(), new.len()),
+                false => {
+                    let (added, removed) = count_changes(&diff::diff(&lines(&old), &lines(&new)));
+                    Stat::Text(added, removed)
+                }
+            };
+            stats.push((change.path.as_str(), stat));
+        }
+
+        let max_change = stats
+            .iter()
+            .map(|(_, stat)| match stat {
+                Stat::Text(added, removed) => added + removed,
+                Stat::Binary(..) => 0,
+            })
+            .max()
+            .unwrap_or(0);
+        let max_len = stats.iter().map(|(path, _)| path.len()).max().unwrap_or(0);
+        let has_binary = stats
+            .iter()
+            .any(|(_, stat)| matches!(stat, Stat::Binary(..)));
+        let number_width = max_change
+            .to_string()
+            .len()
+            .max(if has_binary { 3 } else { 0 });
+
+        // Fit the names and the graph into the width like git does
+        let mut graph_width = max_change;
+        let mut name_width = max_len;
+        let width = STAT_WIDTH.max(16 + 6 + number_width);
+        if name_width + number_width + 6 + graph_width > width {
+            if graph_width + number_width + 6 > width * 3 / 8 {
+                graph_width = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+            }
+            if name_width > width - number_width - 6 - graph_width {
+                name_width = width - number_width - 6 - graph_width;
+            } else {
+                graph_width = width - number_width - 6 - name_width;
+            }
+        }
+
+        let (mut insertions, mut deletions) = (0, 0);
+        for (path, stat) in &stats {
+            let name = match path.len() > name_width {
+                // Long names are cut at the start, at a directory if possible
+                true => {
+                    let tail = &path[path.len() - (name_width - 3)..];
+                    let tail = tail.find('/').map_or(tail, |slash| &tail[slash..]);
+                    format!("...{tail}")
+                }
+                false => path.to_string(),
+            };
+            write!(out, " {name:<name_width$} |")?;
+            match *stat {
+                Stat::Binary(old, new) => {
+                    writeln!(out, " {:>number_width$} {old} -> {new} bytes", "Bin")?
+                }
+                Stat::Text(added, removed) => {
+                    insertions += added;
+                    deletions += removed;
+                    let total = added + removed;
+                    let (plus, minus) = match graph_width < max_change {
+                        true => scale(added, removed, graph_width, max_change),
+                        false => (added, removed),
+                    };
+                    writeln!(
+                        out,
+                        " {total:>number_width$}{}{}{}",
+                        if total > 0 { " " } else { "" },
+                        "+".repeat(plus),
+                        "-".repeat(minus)
+                    )?;
+                }
+            }
+        }
+
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        write!(out, " {} file{} changed", stats.len(), plural(stats.len()))?;
+        if insertions > 0 || deletions == 0 {
+            write!(out, ", {insertions} insertion{}(+)", plural(insertions))?;
+        }
+        if deletions > 0 || insertions == 0 {
+            write!(out, ", {deletions} deletion{}(-)", plural(deletions))?;
+        }
+        writeln!(out)?;
+
+        Ok(())
+    }
+
+    /// Reads the content of one side of a change, empty if it doesn't exist.
+    /// Submodules read as the commit they're at.
+    fn read_side(&self, side: &Option<(u32, String)>) -> Result<Vec<u8>> {
+        match side {
+            Some((MODE_GITLINK, hash)) => Ok(format!("Subproject commit {hash}\n").into_bytes()),
+            Some((_, hash)) => Ok(Object::read_blob(hash, self)?),
+            None => Ok(vec![]),
+        }
+    }
+}
+
+/// The changes to a file in a diffstat.
+enum Stat {
+    /// The number of added and removed lines
+    Text(usize, usize),
+    /// The old and new size of a binary file
+    Binary(usize, usize),
+}
+
+/// Scales the added and removed lines of a file to the width of the graph,
+/// showing at least one of each if there are any.
+fn scale(added: usize, removed: usize, width: usize, max_change: usize) -> (usize, usize) {
+    let scale = |n: usize| match n {
+        0 => 0,
+        n => 1 + n * (width - 1) / max_change,
+    };
+    let mut total = scale(added + removed);
+    if total < 2 && added > 0 && removed > 0 {
+        total = 2;
+    }
+    if added < removed {
+        let plus = scale(added);
+        (plus, total - plus)
+    } else {
+        let minus = scale(removed);
+        (total - minus, minus)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::tree::MODE_FILE;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_diff_files() {
+        let file = |mode, hash: &str| (mode, hash.to_string());
+        let old = BTreeMap::from([
+            ("a".to_string(), file(MODE_FILE, "1")),
+            ("b".to_string(), file(MODE_FILE, "2")),
+            ("link".to_string(), file(MODE_FILE, "3")),
+        ]);
+        let new = BTreeMap::from([
+            ("b".to_string(), file(MODE_FILE, "2")),
+            ("c".to_string(), file(MODE_FILE, "4")),
+            ("link".to_string(), file(MODE_SYMLINK, "3")),
+        ]);
+        let changes: Vec<_> = diff_files(&old, &new)
+            .into_iter()
+            .map(|c| (c.path, c.old.is_some(), c.new.is_some()))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("a".to_string(), true, false),
+                ("c".to_string(), false, true),
+                ("link".to_string(), true, false),
+                ("link".to_string(), false, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_changes() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+        let function = |e: u32| {
+            format!(
+                "#include <stdio.h>\n\nint main(void)\n{{\n\tint a = 1;\n\tint b = 2;\n\t\
+                 int c = 3;\n\tint d = 4;\n\tint e = {e};\n\treturn a + b + c + d + e;\n}}\n"
+            )
+        };
+        fs::write(root.join("f.c"), function(5)).unwrap();
+        fs::write(root.join("bin"), b"a\0b").unwrap();
+        fs::write(root.join("other"), "other\n").unwrap();
+        let all = [".".to_string()];
+        repo.add_paths(&all, None).unwrap();
+        let first = repo.commit("one").unwrap();
+        fs::write(root.join("f.c"), function(6)).unwrap();
+        fs::write(root.join("bin"), b"a\0c").unwrap();
+        repo.add_paths(&all, None).unwrap();
+        let second = repo.commit("two").unwrap();
+
+        // A staged new file and an unstaged change
+        fs::write(root.join("new"), "new\n").unwrap();
+        repo.add_paths(&["new".to_string()], None).unwrap();
+        fs::write(root.join("other"), "changed\n").unwrap();
+
+        let paths = |sides: &DiffSides, pathspecs: &[String]| -> Vec<String> {
+            let (changes, unmerged) = repo.diff_changes(sides, pathspecs).unwrap();
+            assert!(unmerged.is_empty());
+            changes.into_iter().map(|c| c.path).collect()
+        };
+        let sides = |cached: bool, revisions: &[&str]| {
+            let revisions: Vec<String> = revisions.iter().map(|r| r.to_string()).collect();
+            repo.diff_sides(cached, &revisions).unwrap()
+        };
+        assert_eq!(sides(false, &[]), DiffSides::Worktree);
+        assert_eq!(paths(&DiffSides::Worktree, &[]), ["other"]);
+        assert_eq!(paths(&sides(true, &[]), &[]), ["new"]);
+        assert_eq!(paths(&sides(true, &[&first]), &[]), ["bin", "f.c", "new"]);
+        assert_eq!(paths(&sides(false, &["HEAD"]), &[]), ["new", "other"]);
+
+        let trees = sides(false, &[&first, &second]);
+        assert!(!trees.worktree());
+        assert_eq!(sides(false, &[&format!("{first}..{second}")]), trees);
+        assert_eq!(sides(false, &[&format!("{first}..")]), trees);
+        assert_eq!(paths(&trees, &[]), ["bin", "f.c"]);
+        assert_eq!(paths(&trees, &["*.c".to_string()]), ["f.c"]);
+        // Since the merge base, which is the first commit itself
+        assert!(paths(&sides(false, &[&format!("{second}...{first}")]), &[]).is_empty());
+        assert!(repo
+            .diff_sides(true, &[first.clone(), second.clone()])
+            .is_err());
+
+        // Binary files aren't diffed, and hunk headers name the function
+        let (changes, _) = repo.diff_changes(&trees, &[]).unwrap();
+        let mut patch = vec![];
+        repo.write_patch(&mut patch, &changes, &DiffOptions::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(patch).unwrap(),
+            "diff --git a/bin b/bin\n\
+             index 20b5be9..88f3700 100644\n\
+             Binary files a/bin and b/bin differ\n\
+             diff --git a/f.c b/f.c\n\
+             index 9ea7da7..189f7b9 100644\n\
+             --- a/f.c\n\
+             +++ b/f.c\n\
+             @@ -6,6 +6,6 @@ int main(void)\n \
+             \tint b = 2;\n \
+             \tint c = 3;\n \
+             \tint d = 4;\n\
+             -\tint e = 5;\n\
+             +\tint e = 6;\n \
+             \treturn a + b + c + d + e;\n \
+             }\n"
+        );
+
+        // The new side of the working tree is read from the files
+        let (changes, _) = repo.diff_changes(&DiffSides::Worktree, &[]).unwrap();
+        let options = DiffOptions {
+            worktree: true,
+            ..Default::default()
+        };
+        let mut patch = vec![];
+        repo.write_patch(&mut patch, &changes, &options).unwrap();
+        let patch = String::from_utf8(patch).unwrap();
+        assert!(patch.ends_with("@@ -1 +1 @@\n-other\n+changed\n"));
+    }
+
+    #[test]
+    fn test_scale() {
+        assert_eq!(scale(100, 0, 10, 100), (10, 0));
+        assert_eq!(scale(50, 50, 10, 100), (5, 5));
+        assert_eq!(scale(1, 1, 10, 1000), (1, 1));
+    }
+}
@@ -11,7 +11,7 @@ pub enum RepoError {
     #[error(transparent)]
     Io(#[from] io::Error),
 
-    #[error("{0}")]
+    #[error(transparent)]
     Object(#[from] ObjectError),
 
     #[error("branch '{0}' not found")]
@@ -20,9 +20,106 @@ pub enum RepoError {
     #[error("a branch named '{0}' already exists")]
     BranchAlreadyExists(String),
 
+    #[error("'{0}' is not a valid branch name")]
+    InvalidBranchName(String),
+
+    #[error("'{existing}' exists; cannot create '{name}'")]
+    RefConflict { name: String, existing: String },
+
+    #[error("ref '{0}' not found")]
+    RefNotFound(String),
+
+    #[error("{0}")]
+    Transaction(String),
+
+    #[error("corrupt reftable: {0}")]
+    CorruptReftable(String),
+
+    #[error("index file corrupt: {0}")]
+    CorruptIndex(String),
+
+    #[error(
+        "Your local changes to the following files would be overwritten by checkout:\n{}\n\
+         Please commit your changes or stash them before you switch branches.\nAborting",
+        indent(.0)
+    )]
+    LocalChanges(Vec<String>),
+
+    #[error(
+        "The following untracked working tree files would be overwritten by checkout:\n{}\n\
+         Please move or remove them before you switch branches.\nAborting",
+        indent(.0)
+    )]
+    UntrackedOverwritten(Vec<String>),
+
+    #[error(
+        "Your local changes to the following files would be overwritten by merge:\n{}\n\
+         Please commit your changes or stash them before you merge.\nAborting",
+        indent(.0)
+    )]
+    MergeLocalChanges(Vec<String>),
+
+    #[error(
+        "The following untracked working tree files would be overwritten by merge:\n{}\n\
+         Please move or remove them before you merge.\nAborting",
+        indent(.0)
+    )]
+    MergeUntrackedOverwritten(Vec<String>),
+
+    #[error("you need to resolve your current index first")]
+    UnmergedIndex,
+
+    #[error("Entry '{0}' not uptodate. Cannot merge.")]
+    NotUptodate(String),
+
+    #[error("Entry '{0}' would be overwritten by merge. Cannot merge.")]
+    WouldBeOverwritten(String),
+
+    #[error("Untracked working tree file '{0}' would be overwritten by merge.")]
+    UntrackedWouldBeOverwritten(String),
+
+    #[error("path '{0}' is unmerged")]
+    Unmerged(String),
+
+    #[error("pathspec '{0}' did not match any file(s) known to git")]
+    PathspecNotMatched(String),
+
+    #[error("CRLF would be replaced by LF in {0}")]
+    CrlfWouldBeReplaced(String),
+
+    #[error("LF would be replaced by CRLF in {0}")]
+    LfWouldBeReplaced(String),
+
+    #[error("{path}: clean filter '{driver}' failed")]
+    CleanFilterFailed { path: String, driver: String },
+
+    #[error("{path}: smudge filter {driver} failed")]
+    SmudgeFilterFailed { path: String, driver: String },
+
+    #[error("unknown revision '{0}'")]
+    UnknownRevision(String),
+
+    #[error("short object ID {0} is ambiguous")]
+    AmbiguousRevision(String),
+
+    #[error("bad config: {0}")]
+    Config(String),
+
     #[error("not a git repository")]
     NotGitRepository,
 
     #[error("repository already initialized")]
     AlreadyInitialized,
+
+    #[error("{0}")]
+    Other(String),
+}
+
+/// Formats a list of paths one per line, indented with a tab.
+fn indent(paths: &[String]) -> String {
+    paths
+        .iter()
+        .map(|path| format!("\t{path}"))
+        .collect::<Vec<_>>()
+        .join("\n")
 }
@@ -0,0 +1,113 @@
+//! Git's EWAH compressed bitmaps, used by index extensions to flag entries.
+//!
+//! A bitmap is stored as the number of bits and of 64-bit words, the words,
+//! and the position of the last marker word. Each marker word holds a bit,
+//! how many words of that bit follow, and how many literal words follow
+//! those.
+
+/// Reads a bitmap, returning it along with the number of bytes it took.
+pub fn read(data: &[u8]) -> Option<(Vec<bool>, usize)> {
+    let be32 = |pos: usize| Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?));
+    let bit_size = be32(0)? as usize;
+    let word_count = be32(4)? as usize;
+    let words: Vec<u64> = data
+        .get(8..8 + 8 * word_count)?
+        .chunks(8)
+        .map(|word| u64::from_be_bytes(word.try_into().unwrap()))
+        .collect();
+    // The position of the last marker word, which isn't needed for reading
+    be32(8 + 8 * word_count)?;
+
+    // The bitmap can't be longer than its words say, and may be much
+    // shorter than the size claims if it's corrupt
+    let mut described = 0usize;
+    let mut i = 0;
+    while i < words.len() {
+        let run = ((words[i] >> 1) & 0xffff_ffff) as usize;
+        let literals = (words[i] >> 33) as usize;
+        described = described.saturating_add(run.saturating_add(literals).saturating_mul(64));
+        i = i.saturating_add(1 + literals);
+    }
+    if bit_size > described {
+        return None;
+    }
+
+    let mut bits = Vec::with_capacity(bit_size.min(words.len() * 64));
+    let mut i = 0;
+    while i < words.len() && bits.len() < bit_size {
+        let marker = words[i];
+        let run = ((marker >> 1) & 0xffff_ffff) as usize * 64;
+        let literals = (marker >> 33) as usize;
+        let run = run.min(bit_size - bits.len());
+        bits.resize(bits.len() + run, marker & 1 == 1);
+        for word in words.get(i + 1..i + 1 + literals)? {
+            bits.extend((0..64).map(|bit| word >> bit & 1 == 1));
+        }
+        i += 1 + literals;
+    }
+    bits.truncate(bit_size);
+
+    Some((bits, 12 + 8 * word_count))
+}
+
+/// Writes a bitmap, compressing the runs of empty words. Like git, the
+/// bitmap ends with its last set bit.
+pub fn write(bits: &[bool], out: &mut Vec<u8>) {
+    let bit_size = bits.iter().rposition(|&bit| bit).map_or(0, |last| last + 1);
+    let literals: Vec<u64> = bits[..bit_size]
+        .chunks(64)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0, |word, (bit, &set)| word | u64::from(set) << bit)
+        })
+        .collect();
+
+    // The last literal word isn't empty, so every marker is followed by some
+    let mut words = vec![];
+    let mut marker = 0;
+    let mut i = 0;
+    while i < literals.len() {
+        let run = literals[i..].iter().take_while(|&&word| word == 0).count();
+        let count = literals[i + run..]
+            .iter()
+            .take_while(|&&word| word != 0)
+            .count();
+        marker = words.len();
+        words.push((run as u64) << 1 | (count as u64) << 33);
+        words.extend_from_slice(&literals[i + run..i + run + count]);
+        i += run + count;
+    }
+    if words.is_empty() {
+        words.push(0);
+    }
+
+    out.extend_from_slice(&(bit_size as u32).to_be_bytes());
+    out.extend_from_slice(&(words.len() as u32).to_be_bytes());
+    for word in &words {
+        out.extend_from_slice(&word.to_be_bytes());
+    }
+    out.extend_from_slice(&(marker as u32).to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ewah() {
+        let mut bits = vec![false; 1000];
+        bits[2] = true;
+        bits[999] = true;
+        let mut data = vec![];
+        write(&bits, &mut data);
+        assert_eq!(read(&data), Some((bits, data.len())));
+
+        // A size larger than the words hold is rejected
+        let mut data = vec![];
+        write(&[true], &mut data);
+        data[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(read(&data), None);
+    }
+}
@@ -0,0 +1,528 @@
+//! Content filters, run on the files whose `filter` attribute names a driver
+//! configured with `filter.<driver>.clean`, `.smudge` or `.process`.
+//!
+//! A `clean` or `smudge` command is run once per file, with the content on
+//! its standard input and `%f` replaced by the quoted path. A `process`
+//! command is started once and serves every file of an operation, speaking
+//! git's long-running filter protocol over pkt-lines.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, BufWriter, Read, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::Mutex,
+    thread,
+};
+
+use crate::pkt_line;
+
+use super::{
+    config::Config,
+    error::{RepoError, Result},
+};
+
+/// The direction a filter converts content in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum FilterKind {
+    /// From the working tree to the repository
+    Clean,
+    /// From the repository to the working tree
+    Smudge,
+}
+
+impl FilterKind {
+    fn name(self) -> &'static str {
+        match self {
+            FilterKind::Clean => "clean",
+            FilterKind::Smudge => "smudge",
+        }
+    }
+}
+
+/// A filter driver, `filter.<name>` in the config.
+#[derive(Debug, Default)]
+struct Driver {
+    clean: Option<String>,
+    smudge: Option<String>,
+    process: Option<String>,
+    /// Whether content that can't be filtered is an error, rather than
+    /// passed through as is
+    required: bool,
+}
+
+/// The filter drivers, and the filter processes started so far. A process
+/// filters one file at a time, for whichever thread asks.
+pub(super) struct Filters {
+    drivers: HashMap<String, Driver>,
+    /// The filter processes by command, `None` if one failed to start
+    processes: Mutex<HashMap<String, Option<FilterProcess>>>,
+}
+
+impl Filters {
+    pub(super) fn load(config: &Config) -> Filters {
+        let drivers = config
+            .subsections("filter")
+            .into_iter()
+            .map(|name| {
+                let get = |key: &str| config.get(&format!("filter.{name}.{key}"));
+                let driver = Driver {
+                    clean: get("clean").map(str::to_string),
+                    smudge: get("smudge").map(str::to_string),
+                    process: get("process").map(str::to_string),
+                    required: config
+                        .get_bool(&format!("filter.{name}.required"))
+                        .unwrap_or(false),
+                };
+                (name.to_string(), driver)
+            })
+            .collect();
+
+        Filters {
+            drivers,
+            processes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs the filter of `driver` on the content of `path`. Without a
+    /// filter, or if it fails, the content is returned as is, unless the
+    /// driver is required.
+    pub(super) fn apply(
+        &self,
+        driver: &str,
+        kind: FilterKind,
+        path: &str,
+        content: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        let Some(config) = self.drivers.get(driver) else {
+            return Ok(content);
+        };
+        let command = match kind {
+            FilterKind::Clean => &config.clean,
+            FilterKind::Smudge => &config.smudge,
+        };
+        let filtered = match (&config.process, command) {
+            (Some(process), _) => self.run_process(process, kind, path, &content),
+            (None, Some(command)) => run_command(command, path, &content).map(Some),
+            (None, None) => Ok(None),
+        };
+
+        match filtered {
+            Ok(Some(filtered)) => Ok(filtered),
+            Ok(None) | Err(_) if config.required => {
+                let (path, driver) = (path.to_string(), driver.to_string());
+                Err(match kind {
+                    FilterKind::Clean => RepoError::CleanFilterFailed { path, driver },
+                    FilterKind::Smudge => RepoError::SmudgeFilterFailed { path, driver },
+                })
+            }
+            Ok(None) | Err(_) => Ok(content),
+        }
+    }
+
+    /// Filters content with a long-running filter process, starting it if
+    /// needed. Returns `None` if the process doesn't filter the content.
+    fn run_process(
+        &self,
+        command: &str,
+        kind: FilterKind,
+        path: &str,
+        content: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let mut processes = self.processes.lock().unwrap_or_else(|e| e.into_inner());
+        if !processes.contains_key(command) {
+            let process = FilterProcess::start(command);
+            let failed = process.is_err();
+            processes.insert(command.to_string(), process.ok());
+            if failed {
+                Err(RepoError::Other(format!(
+                    "initialization for subprocess '{command}' failed"
+                )))?;
+            }
+        }
+        let Some(process) = processes.get_mut(command).and_then(Option::as_mut) else {
+            return Ok(None);
+        };
+        if !process.capabilities.contains(&kind) {
+            return Ok(None);
+        }
+        match process.filter(kind, path, content) {
+            Ok(FilterStatus::Success(filtered)) => Ok(Some(filtered)),
+            Ok(FilterStatus::Error) => Ok(None),
+            Ok(FilterStatus::Abort) => {
+                // The process won't filter anything else in this direction
+                process
+                    .capabilities
+                    .retain(|capability| *capability != kind);
+                Ok(None)
+            }
+            Err(_) => {
+                processes.insert(command.to_string(), None);
+                Err(RepoError::Other(format!(
+                    "external filter '{command}' failed"
+                )))
+            }
+        }
+    }
+}
+
+/// Runs a `clean` or `smudge` command on content, returning its output.
+fn run_command(command: &str, path: &str, content: &[u8]) -> Result<Vec<u8>> {
+    let command = command.replace("%f", &quote_shell(path));
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|_| RepoError::Other(format!("cannot fork to run external filter '{command}'")))?;
+
+    // Write the content while reading the output, so neither side blocks
+    let (mut stdin, mut stdout) = match (child.stdin.take(), child.stdout.take()) {
+        (Some(stdin), Some(stdout)) => (stdin, stdout),
+        _ => Err(RepoError::Other(format!(
+            "external filter '{command}' failed"
+        )))?,
+    };
+    let (read, written, output) = thread::scope(|scope| {
+        let writer = scope.spawn(move || match stdin.write_all(content) {
+            // A filter may not read all of its input
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        });
+        let mut output = vec![];
+        let read = stdout.read_to_end(&mut output);
+        let written = writer.join().expect("filter input thread panicked");
+        (read, written, output)
+    });
+    let status = child.wait()?;
+
+    if written.is_err() {
+        Err(RepoError::Other(format!(
+            "cannot feed the input to external filter '{command}'"
+        )))?;
+    }
+    if read.is_err() {
+        Err(RepoError::Other(format!(
+            "read from external filter '{command}' failed"
+        )))?;
+    }
+    if !status.success() {
+        Err(RepoError::Other(match status.code() {
+            Some(code) => format!("external filter '{command}' failed {code}"),
+            None => format!("external filter '{command}' died of a signal"),
+        }))?;
+    }
+
+    Ok(output)
+}
+
+/// Quotes a string for the shell, like git's `sq_quote_buf`.
+fn quote_shell(s: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in s.chars() {
+        match c {
+            '\'' => quoted.push_str("'\\''"),
+            '!' => quoted.push_str("'\\!'"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// The outcome of a filter process request.
+enum FilterStatus {
+    Success(Vec<u8>),
+    /// The process couldn't filter the file
+    Error,
+    /// The process can't filter any more files
+    Abort,
+}
+
+/// A long-running filter process, `filter.<driver>.process`.
+struct FilterProcess {
+    child: Child,
+    stdin: Option<BufWriter<ChildStdin>>,
+    stdout: BufReader<ChildStdout>,
+    /// What the process offered to do
+    capabilities: Vec<FilterKind>,
+}
+
+impl FilterProcess {
+    /// Starts a filter process and negotiates the protocol version and
+    /// capabilities with it.
+    fn start(command: &str) -> io::Result<FilterProcess> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut process = FilterProcess {
+            stdin: child.stdin.take().map(BufWriter::new),
+            stdout: BufReader::new(child.stdout.take().ok_or(io::ErrorKind::BrokenPipe)?),
+            child,
+            capabilities: vec![],
+        };
+
+        let stdin = process.stdin()?;
+        pkt_line::write_text(stdin, "git-filter-client")?;
+        pkt_line::write_text(stdin, "version=2")?;
+        pkt_line::write_flush(stdin)?;
+        stdin.flush()?;
+        let welcome = pkt_line::read_text_list(&mut process.stdout)?;
+        if welcome.first().map(String::as_str) != Some("git-filter-server")
+            || !welcome.iter().any(|line| line == "version=2")
+        {
+            Err(protocol_error("bad welcome from filter process"))?;
+        }
+
+        // Like git, delaying is offered too, but never asked for
+        let stdin = process.stdin()?;
+        for capability in ["clean", "smudge", "delay"] {
+            pkt_line::write_text(stdin, &format!("capability={capability}"))?;
+        }
+        pkt_line::write_flush(stdin)?;
+        stdin.flush()?;
+        for line in pkt_line::read_text_list(&mut process.stdout)? {
+            match line.strip_prefix("capability=") {
+                Some("clean") => process.capabilities.push(FilterKind::Clean),
+                Some("smudge") => process.capabilities.push(FilterKind::Smudge),
+                Some(_) => {}
+                None => Err(protocol_error("bad capability from filter process"))?,
+            }
+        }
+
+        Ok(process)
+    }
+
+    fn stdin(&mut self) -> io::Result<&mut BufWriter<ChildStdin>> {
+        self.stdin
+            .as_mut()
+            .ok_or_else(|| io::ErrorKind::BrokenPipe.into())
+    }
+
+    /// Sends the content of a file to filter, and reads the result.
+    fn filter(&mut self, kind: FilterKind, path: &str, content: &[u8]) -> io::Result<FilterStatus> {
+        let stdin = self.stdin()?;
+        pkt_line::write_text(stdin, &format!("command={}", kind.name()))?;
+        pkt_line::write_text(stdin, &format!("pathname={path}"))?;
+        pkt_line::write_flush(stdin)?;
+        pkt_line::write_content(stdin, content)?;
+        stdin.flush()?;
+
+        let mut status = self.read_status(None)?;
+        if status == "success" {
+            let filtered = pkt_line::read_content(&mut self.stdout)?;
+            // An empty list keeps the status
+            status = self.read_status(Some(status))?;
+            if status == "success" {
+                return Ok(FilterStatus::Success(filtered));
+            }
+        }
+
+        Ok(match status.as_str() {
+            "abort" => FilterStatus::Abort,
+            _ => FilterStatus::Error,
+        })
+    }
+
+    /// Reads a list of `status=<status>` lines, returning the last status.
+    fn read_status(&mut self, status: Option<String>) -> io::Result<String> {
+        let lines = pkt_line::read_text_list(&mut self.stdout)?;
+        let last = lines
+            .iter()
+            .rev()
+            .find_map(|line| line.strip_prefix("status="))
+            .map(str::to_string);
+
+        last.or(status)
+            .ok_or_else(|| protocol_error("no status from filter process"))
+    }
+}
+
+/// Closes the process's input, which tells it to exit, and waits for it.
+impl Drop for FilterProcess {
+    fn drop(&mut self) {
+        self.stdin.take();
+        let _ = self.child.wait();
+    }
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Writes a filter process script to `dir`, returning the command running
+/// it. The process answers the handshake with `capabilities`, uppercases the
+/// files it cleans and lowercases those it smudges, but answers
+/// `status=error` or `status=abort` for paths ending in that word. It
+/// appends a line to `dir/launches` when it starts, and `<command> <path>`
+/// to `dir/requests` for every file.
+#[cfg(test)]
+pub(crate) fn write_filter_process(dir: &std::path::Path, capabilities: &[&str]) -> String {
+    let script = dir.join("filter.sh");
+    let content = r#"
+echo started >> launches
+nl='
+'
+read_pkt() {
+	len=$(dd bs=1 count=4 2>/dev/null)
+	[ -n "$len" ] || exit 0
+	[ "$len" != 0000 ] || return 1
+	pkt=$(dd bs=1 count=$((0x$len - 4)) 2>/dev/null; echo x)
+	pkt=${pkt%x}
+}
+text() {
+	printf '%04x%s\n' $((${#1} + 5)) "$1"
+}
+flush() {
+	printf 0000
+}
+
+read_pkt && [ "$pkt" = "git-filter-client$nl" ] || exit 1
+while read_pkt; do :; done
+text git-filter-server
+text version=2
+flush
+while read_pkt; do :; done
+for capability in "$@"; do
+	text "capability=$capability"
+done
+flush
+
+while :; do
+	while read_pkt; do
+		case $pkt in
+		command=*) command=${pkt#command=} ;;
+		pathname=*) path=${pkt#pathname=} ;;
+		esac
+	done
+	content=
+	while read_pkt; do content=$content$pkt; done
+	command=${command%"$nl"} path=${path%"$nl"}
+	echo "$command $path" >> requests
+	case $path in
+	*error|*abort)
+		text "status=${path##*[!a-z]}"
+		flush
+		continue
+		;;
+	esac
+	case $command in
+	clean) result=$(printf '%s' "$content" | tr a-z A-Z; echo x) ;;
+	*) result=$(printf '%s' "$content" | tr A-Z a-z; echo x) ;;
+	esac
+	result=${result%x}
+	text status=success
+	flush
+	[ -z "$result" ] || printf '%04x%s' $((${#result} + 4)) "$result"
+	flush
+	flush
+done
+"#;
+    std::fs::write(&script, content).unwrap();
+    let dir = quote_shell(&dir.to_string_lossy());
+    let script = quote_shell(&script.to_string_lossy());
+    format!("cd {dir} && sh {script} {}", capabilities.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::repository::Repository;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_filters() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+
+        let mut config = repo.config().unwrap();
+        config.set("filter.upper.clean", "tr a-z A-Z").unwrap();
+        config.set("filter.upper.smudge", "tr A-Z a-z").unwrap();
+        config.set("filter.path.clean", "printf '%s' %f").unwrap();
+        config.set("filter.broken.clean", "false").unwrap();
+        config.set("filter.needed.clean", "false").unwrap();
+        config.set("filter.needed.required", "true").unwrap();
+        config.save().unwrap();
+        let filters = Filters::load(&repo.config().unwrap());
+
+        let apply = |driver, kind, path| filters.apply(driver, kind, path, b"abc".to_vec());
+        assert_eq!(apply("upper", FilterKind::Clean, "a").unwrap(), b"ABC");
+        assert_eq!(apply("upper", FilterKind::Smudge, "a").unwrap(), b"abc");
+        assert_eq!(apply("path", FilterKind::Clean, "it's").unwrap(), b"it's");
+        assert_eq!(apply("path", FilterKind::Smudge, "a").unwrap(), b"abc");
+        assert_eq!(apply("broken", FilterKind::Clean, "a").unwrap(), b"abc");
+        assert_eq!(apply("unknown", FilterKind::Clean, "a").unwrap(), b"abc");
+        assert!(apply("needed", FilterKind::Clean, "a").is_err());
+    }
+
+    #[test]
+    fn test_filter_process() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+        let both_dir = tempdir().unwrap();
+        let clean_dir = tempdir().unwrap();
+
+        let mut config = repo.config().unwrap();
+        let both = write_filter_process(both_dir.path(), &["clean", "smudge"]);
+        let clean = write_filter_process(clean_dir.path(), &["clean"]);
+        config.set("filter.both.process", &both).unwrap();
+        // The process wins over the command
+        config.set("filter.both.clean", "false").unwrap();
+        config.set("filter.clean.process", &clean).unwrap();
+        config.set("filter.needed.process", &both).unwrap();
+        config.set("filter.needed.required", "true").unwrap();
+        config.set("filter.mute.process", "true").unwrap();
+        config
+            .set("filter.garbled.process", "printf 0008oops")
+            .unwrap();
+        config.set("filter.garbled.required", "true").unwrap();
+        config.save().unwrap();
+        let filters = Filters::load(&repo.config().unwrap());
+
+        let apply = |driver, kind, path| filters.apply(driver, kind, path, b"aBc\n".to_vec());
+        assert_eq!(apply("both", FilterKind::Clean, "a").unwrap(), b"ABC\n");
+        assert_eq!(apply("both", FilterKind::Smudge, "b").unwrap(), b"abc\n");
+        // Only what the process asked for is sent to it
+        assert_eq!(apply("clean", FilterKind::Smudge, "c").unwrap(), b"aBc\n");
+        assert_eq!(apply("clean", FilterKind::Clean, "c").unwrap(), b"ABC\n");
+
+        // A file the process can't filter is kept as is, unless required
+        assert_eq!(apply("both", FilterKind::Clean, "error").unwrap(), b"aBc\n");
+        assert_eq!(apply("both", FilterKind::Clean, "d").unwrap(), b"ABC\n");
+        assert!(matches!(
+            apply("needed", FilterKind::Smudge, "dir/error"),
+            Err(RepoError::SmudgeFilterFailed { .. })
+        ));
+        // After an abort nothing else is sent in that direction
+        assert_eq!(apply("both", FilterKind::Clean, "abort").unwrap(), b"aBc\n");
+        assert_eq!(apply("both", FilterKind::Clean, "e").unwrap(), b"aBc\n");
+        assert_eq!(apply("both", FilterKind::Smudge, "e").unwrap(), b"abc\n");
+
+        // One process served every file of its command
+        let read = |dir: &tempfile::TempDir, name| {
+            fs::read_to_string(dir.path().join(name)).unwrap_or_default()
+        };
+        assert_eq!(read(&both_dir, "launches"), "started\n");
+        assert_eq!(
+            read(&both_dir, "requests"),
+            "clean a\nsmudge b\nclean error\nclean d\nsmudge dir/error\nclean abort\n\
+             smudge e\n"
+        );
+        assert_eq!(read(&clean_dir, "launches"), "started\n");
+        assert_eq!(read(&clean_dir, "requests"), "clean c\n");
+
+        // A process that fails the handshake filters nothing
+        assert_eq!(apply("mute", FilterKind::Clean, "f").unwrap(), b"aBc\n");
+        assert!(matches!(
+            apply("garbled", FilterKind::Clean, "f"),
+            Err(RepoError::CleanFilterFailed { .. })
+        ));
+    }
+}
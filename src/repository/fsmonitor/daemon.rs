@@ -0,0 +1,363 @@
+//! The fsmonitor daemon, watching the working tree with inotify, which only
+//! Linux has.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    ffi::CString,
+    fs::{self, File},
+    io::{self, Read},
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        raw::{c_char, c_int},
+        unix::{
+            ffi::OsStrExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::pkt_line;
+
+use super::super::{
+    error::{RepoError, Result},
+    index::is_missing,
+    worktree::absolute,
+    Repository,
+};
+
+/// The directory of the files the daemon creates to know when it has seen
+/// all events up to a query, in the git directory
+const COOKIES: &str = "fsmonitor--daemon/cookies";
+/// The most changes the daemon remembers; older tokens get a trivial answer
+const MAX_CHANGES: usize = 100_000;
+
+extern "C" {
+    fn inotify_init1(flags: c_int) -> c_int;
+    fn inotify_add_watch(fd: c_int, path: *const c_char, mask: u32) -> c_int;
+}
+
+const IN_CLOEXEC: c_int = 0o2000000;
+const IN_MODIFY: u32 = 0x2;
+const IN_ATTRIB: u32 = 0x4;
+const IN_MOVED_FROM: u32 = 0x40;
+const IN_MOVED_TO: u32 = 0x80;
+const IN_CREATE: u32 = 0x100;
+const IN_DELETE: u32 = 0x200;
+const IN_Q_OVERFLOW: u32 = 0x4000;
+const IN_IGNORED: u32 = 0x8000;
+const IN_ONLYDIR: u32 = 0x0100_0000;
+const IN_ISDIR: u32 = 0x4000_0000;
+const WATCH_MASK: u32 =
+    IN_MODIFY | IN_ATTRIB | IN_MOVED_FROM | IN_MOVED_TO | IN_CREATE | IN_DELETE | IN_ONLYDIR;
+/// Size of an event without its name
+const EVENT_SIZE: usize = 16;
+
+/// Watches the working tree of `repo` and answers queries on the socket in
+/// the git directory, until asked to stop.
+pub fn run(repo: &Repository) -> Result<()> {
+    if repo.fsmonitor_running() {
+        Err(RepoError::Other(format!(
+            "fsmonitor--daemon is already running '{}'",
+            absolute(repo.get_root())?.display()
+        )))?;
+    }
+    let cookies = repo.get_path().join(COOKIES);
+    fs::create_dir_all(&cookies)?;
+    let mut watcher = Watcher::new(repo.get_root(), &cookies)?;
+
+    // A socket left behind by a daemon that didn't stop cleanly
+    let socket = repo.fsmonitor_socket();
+    let _ = fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket)?;
+    let shared = Arc::new(Shared::default());
+    {
+        let shared = shared.clone();
+        let socket = socket.clone();
+        thread::spawn(move || {
+            let error = watcher.run(&shared);
+            shared.changes.lock().unwrap().error = Some(error);
+            // Wake up the listener, so it stops
+            let _ = UnixStream::connect(&socket)
+                .and_then(|mut stream| pkt_line::write_content(&mut stream, b"quit"));
+        });
+    }
+
+    let mut cookie = 0;
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+        let Ok(request) = pkt_line::read_content(&mut stream) else {
+            continue;
+        };
+        if request == b"quit" {
+            let _ = pkt_line::write_content(&mut stream, b"ok");
+            break;
+        }
+
+        cookie += 1;
+        shared.sync(&cookies.join(format!("{}-{cookie}", std::process::id())));
+        let response = shared
+            .changes
+            .lock()
+            .unwrap()
+            .since(&String::from_utf8_lossy(&request));
+        let _ = pkt_line::write_content(&mut stream, &response);
+    }
+    let _ = fs::remove_file(&socket);
+
+    let error = shared.changes.lock().unwrap().error.take();
+    match error {
+        Some(e) => Err(RepoError::Other(format!("fsmonitor--daemon failed: {e}"))),
+        None => Ok(()),
+    }
+}
+
+/// The state the watcher shares with the listener.
+#[derive(Default)]
+struct Shared {
+    changes: Mutex<Changes>,
+    /// Signalled when cookie files are seen
+    cookie_seen: Condvar,
+}
+
+impl Shared {
+    /// Waits until the watcher has seen all events up to now, by creating a
+    /// cookie file and waiting for the event of that.
+    fn sync(&self, cookie: &Path) {
+        let Some(name) = cookie
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+        else {
+            return;
+        };
+        if File::create(cookie).is_err() {
+            return;
+        }
+        let changes = self.changes.lock().unwrap();
+        let (mut changes, _) = self
+            .cookie_seen
+            .wait_timeout_while(changes, Duration::from_secs(1), |changes| {
+                !changes.cookies.contains(&name)
+            })
+            .unwrap();
+        changes.cookies.remove(&name);
+        drop(changes);
+        let _ = fs::remove_file(cookie);
+    }
+}
+
+/// The changes the daemon has seen.
+#[derive(Default)]
+struct Changes {
+    /// Identifies this run of the daemon in tokens; a new one starts when
+    /// events were lost
+    session: String,
+    /// The number of the last change
+    seq: u64,
+    /// The number of the last change that was forgotten; earlier tokens get
+    /// a trivial answer
+    forgotten: u64,
+    paths: VecDeque<(u64, String)>,
+    /// The names of the cookie files seen
+    cookies: HashSet<String>,
+    /// Why the watcher stopped
+    error: Option<io::Error>,
+}
+
+impl Changes {
+    /// Forgets all changes, so all tokens get a trivial answer.
+    fn restart(&mut self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.session = format!("{}.{}", std::process::id(), now.as_nanos());
+        self.paths.clear();
+        self.forgotten = self.seq;
+    }
+
+    fn add(&mut self, path: String) {
+        if self.session.is_empty() {
+            self.restart();
+        }
+        self.seq += 1;
+        self.paths.push_back((self.seq, path));
+        if self.paths.len() > MAX_CHANGES {
+            self.forgotten = self.paths[MAX_CHANGES / 2 - 1].0;
+            self.paths.drain(..MAX_CHANGES / 2);
+        }
+    }
+
+    /// Returns the response to a query: the new token, followed by the paths
+    /// changed since `token` or `/` if it's not one we can answer, each
+    /// terminated by a NUL.
+    fn since(&mut self, token: &str) -> Vec<u8> {
+        if self.session.is_empty() {
+            self.restart();
+        }
+        let since = token
+            .strip_prefix("builtin:")
+            .and_then(|token| token.rsplit_once(':'))
+            .filter(|(session, _)| *session == self.session)
+            .and_then(|(_, seq)| seq.parse::<u64>().ok())
+            .filter(|seq| (self.forgotten..=self.seq).contains(seq));
+
+        let mut response = format!("builtin:{}:{}\0", self.session, self.seq).into_bytes();
+        match since {
+            Some(since) => {
+                let paths: BTreeSet<&str> = self
+                    .paths
+                    .iter()
+                    .filter(|(seq, _)| *seq > since)
+                    .map(|(_, path)| path.as_str())
+                    .collect();
+                for path in paths {
+                    response.extend_from_slice(path.as_bytes());
+                    response.push(0);
+                }
+            }
+            None => response.extend_from_slice(b"/\0"),
+        }
+        response
+    }
+}
+
+/// The inotify watches of the directories of a working tree.
+struct Watcher {
+    inotify: File,
+    root: PathBuf,
+    /// The directories by watch descriptor, e.g. `src/`
+    dirs: HashMap<c_int, String>,
+    /// The watch descriptor of the cookie directory
+    cookies: c_int,
+}
+
+impl Watcher {
+    /// Watches all directories of the working tree but `.git`.
+    fn new(root: &Path, cookies: &Path) -> io::Result<Watcher> {
+        // SAFETY: takes no pointers, and the descriptor is checked
+        let fd = unsafe { inotify_init1(IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut watcher = Watcher {
+            // SAFETY: the descriptor is new and owned by nothing else
+            inotify: unsafe { File::from_raw_fd(fd) },
+            root: root.to_path_buf(),
+            dirs: HashMap::new(),
+            cookies: -1,
+        };
+        watcher.cookies = watcher.add_watch(cookies)?;
+        watcher.watch_dir("", &mut vec![])?;
+
+        Ok(watcher)
+    }
+
+    fn add_watch(&self, dir: &Path) -> io::Result<c_int> {
+        let dir = CString::new(dir.as_os_str().as_bytes())?;
+        // SAFETY: the path is a valid C string for the duration of the call
+        let wd = unsafe { inotify_add_watch(self.inotify.as_raw_fd(), dir.as_ptr(), WATCH_MASK) };
+        match wd {
+            ..0 => Err(io::Error::last_os_error()),
+            wd => Ok(wd),
+        }
+    }
+
+    /// Watches the directory `prefix`, e.g. `src/`, and the directories
+    /// below it, adding the files in them to `found`.
+    fn watch_dir(&mut self, prefix: &str, found: &mut Vec<String>) -> io::Result<()> {
+        let dir = self.root.join(prefix);
+        let wd = match self.add_watch(&dir) {
+            Ok(wd) => wd,
+            // Gone again already
+            Err(e) if !prefix.is_empty() && is_missing(&e) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        self.dirs.insert(wd, prefix.to_string());
+
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if is_missing(&e) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name == ".git" {
+                continue;
+            }
+            let path = format!("{prefix}{name}");
+            if entry.file_type()?.is_dir() {
+                self.watch_dir(&format!("{path}/"), found)?;
+            }
+            found.push(path);
+        }
+        Ok(())
+    }
+
+    /// Records the changed paths until reading events fails.
+    fn run(&mut self, shared: &Shared) -> io::Error {
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let len = match self.inotify.read(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return e,
+            };
+
+            let (mut paths, mut cookies, mut overflow) = (vec![], vec![], false);
+            let mut pos = 0;
+            while pos + EVENT_SIZE <= len {
+                let field = |i: usize| {
+                    let start = pos + 4 * i;
+                    u32::from_ne_bytes(buf[start..start + 4].try_into().unwrap())
+                };
+                let (wd, mask, name_len) = (field(0) as c_int, field(1), field(3) as usize);
+                let name = &buf[pos + EVENT_SIZE..(pos + EVENT_SIZE + name_len).min(len)];
+                // The name is padded with NULs
+                let name = String::from_utf8_lossy(name.split(|&b| b == 0).next().unwrap_or(name))
+                    .to_string();
+                pos += EVENT_SIZE + name_len;
+
+                if mask & IN_Q_OVERFLOW != 0 {
+                    overflow = true;
+                } else if wd == self.cookies {
+                    if mask & IN_CREATE != 0 {
+                        cookies.push(name);
+                    }
+                } else if mask & IN_IGNORED != 0 {
+                    self.dirs.remove(&wd);
+                } else if let Some(prefix) = self.dirs.get(&wd) {
+                    if name.is_empty() || name == ".git" {
+                        continue;
+                    }
+                    let path = format!("{prefix}{name}");
+                    // Files may have been created in a new directory before
+                    // it was watched
+                    if mask & IN_ISDIR != 0 && mask & (IN_CREATE | IN_MOVED_TO) != 0 {
+                        if let Err(e) = self.watch_dir(&format!("{path}/"), &mut paths) {
+                            return e;
+                        }
+                    }
+                    paths.push(path);
+                }
+            }
+
+            let mut changes = shared.changes.lock().unwrap();
+            if overflow {
+                changes.restart();
+            }
+            for path in paths {
+                changes.add(path);
+            }
+            changes.cookies.extend(cookies);
+            drop(changes);
+            shared.cookie_seen.notify_all();
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! The fsmonitor daemon, which watches the working tree with inotify so
+//! `status`, and `add` which goes through it, only have to look at the
+//! files that changed since they last asked. Each answer comes with a token
+//! to ask for the changes after it, which the index keeps in its `FSMN`
+//! extension along with the entries known to be unchanged since. Other
+//! platforms have no daemon, so all files are looked at there.
+
+#[cfg(target_os = "linux")]
+mod daemon;
+
+use std::{io, os::unix::net::UnixStream, path::PathBuf, thread, time::Duration};
+
+use crate::pkt_line;
+
+use super::{
+    error::{RepoError, Result},
+    ewah, Repository,
+};
+
+/// The socket of the daemon, in the git directory
+const SOCKET: &str = "fsmonitor--daemon.ipc";
+const EXTENSION_VERSION: u32 = 2;
+
+/// The daemon's answer to a query.
+#[derive(Debug, PartialEq)]
+pub struct FsmonitorChanges {
+    /// The token to ask for the changes after this answer with
+    pub token: String,
+    /// The paths that changed since the token of the query, or `None` if
+    /// anything may have changed, e.g. because the daemon was restarted
+    pub paths: Option<Vec<String>>,
+}
+
+impl Repository {
+    fn fsmonitor_socket(&self) -> PathBuf {
+        self.get_path().join(SOCKET)
+    }
+
+    /// Returns true if a daemon is watching the working tree.
+    pub fn fsmonitor_running(&self) -> bool {
+        UnixStream::connect(self.fsmonitor_socket()).is_ok()
+    }
+
+    /// Asks the daemon which paths changed since `token`. Returns `None` if
+    /// no daemon is running or it doesn't answer.
+    pub fn query_fsmonitor(&self, token: Option<&str>) -> Option<FsmonitorChanges> {
+        let response = self.send_fsmonitor(token.unwrap_or("")).ok()?;
+        let mut fields = response.split(|&b| b == 0);
+        let token = String::from_utf8(fields.next()?.to_vec()).ok()?;
+        let paths: Vec<String> = fields
+            .filter(|path| !path.is_empty())
+            .map(|path| String::from_utf8_lossy(path).to_string())
+            .collect();
+        let paths = match paths.first().map(String::as_str) {
+            Some("/") => None,
+            _ => Some(paths),
+        };
+
+        Some(FsmonitorChanges { token, paths })
+    }
+
+    /// Sends a request to the daemon and returns its response.
+    fn send_fsmonitor(&self, request: &str) -> io::Result<Vec<u8>> {
+        let mut stream = UnixStream::connect(self.fsmonitor_socket())?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        pkt_line::write_content(&mut stream, request.as_bytes())?;
+        pkt_line::read_content(&mut stream)
+    }
+
+    /// Asks the daemon to stop and waits until it has. Returns false if none
+    /// is running.
+    pub fn stop_fsmonitor(&self) -> Result<bool> {
+        if self.send_fsmonitor("quit").is_err() {
+            return Ok(false);
+        }
+        while self.fsmonitor_running() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(true)
+    }
+
+    /// Watches the working tree and answers queries on the socket in the git
+    /// directory, until asked to stop.
+    pub fn run_fsmonitor(&self) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        return daemon::run(self);
+        #[cfg(not(target_os = "linux"))]
+        Err(RepoError::Other(
+            "fsmonitor--daemon not supported on this platform".to_string(),
+        ))
+    }
+}
+
+/// Parses the data of an `FSMN` extension of the index: the token of the
+/// last query, and for each entry whether it may have changed since.
+/// Entries past the end of the bitmap haven't.
+pub fn parse_extension(data: &[u8]) -> Result<(String, Vec<bool>)> {
+    let bad = || RepoError::CorruptIndex("bad FSMN extension".to_string());
+    let be32 = |pos: usize| {
+        data.get(pos..pos + 4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(bad)
+    };
+    let version = be32(0)?;
+    if version != EXTENSION_VERSION {
+        Err(RepoError::CorruptIndex(format!(
+            "bad fsmonitor version {version}"
+        )))?;
+    }
+    let token_end = data[4..]
+        .iter()
+        .position(|&b| b == 0)
+        .map(|len| 4 + len)
+        .ok_or_else(bad)?;
+    let token = String::from_utf8_lossy(&data[4..token_end]).to_string();
+    let size = be32(token_end + 1)? as usize;
+    let bitmap = data
+        .get(token_end + 5..token_end + 5 + size)
+        .and_then(ewah::read)
+        .map(|(bitmap, _)| bitmap)
+        .ok_or_else(bad)?;
+
+    Ok((token, bitmap))
+}
+
+/// Appends the data of an `FSMN` extension, with the entries that may have
+/// changed since the token marked in `dirty`.
+pub fn serialize_extension(token: &str, dirty: &[bool], out: &mut Vec<u8>) {
+    out.extend_from_slice(&EXTENSION_VERSION.to_be_bytes());
+    out.extend_from_slice(token.as_bytes());
+    out.push(0);
+    let mut bitmap = vec![];
+    ewah::write(dirty, &mut bitmap);
+    out.extend_from_slice(&(bitmap.len() as u32).to_be_bytes());
+    out.extend_from_slice(&bitmap);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fsmonitor() {
+        let mut dirty = vec![false; 300];
+        dirty[3] = true;
+        dirty[250] = true;
+        let mut data = vec![];
+        serialize_extension("builtin:1:2", &dirty, &mut data);
+        let (token, bitmap) = parse_extension(&data).unwrap();
+        assert_eq!(token, "builtin:1:2");
+        assert_eq!(bitmap, dirty[..251]);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_daemon() {
+        use std::fs;
+        use tempfile::tempdir;
+
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path().to_path_buf();
+        let repo = Repository::init(&root).unwrap();
+        fs::create_dir(root.join("dir")).unwrap();
+        let daemon = thread::spawn(move || Repository::from_path(root).unwrap().run_fsmonitor());
+        while !repo.fsmonitor_running() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // A token from another daemon gets a trivial answer
+        let changes = repo.query_fsmonitor(Some("builtin:0:0")).unwrap();
+        assert_eq!(changes.paths, None);
+        fs::write(repo.get_root().join("dir/a"), "a").unwrap();
+        fs::create_dir_all(repo.get_root().join("new/sub")).unwrap();
+        fs::write(repo.get_root().join("new/sub/b"), "b").unwrap();
+        let changes = repo.query_fsmonitor(Some(&changes.token)).unwrap();
+        let paths = changes.paths.unwrap();
+        assert!(["dir/a", "new", "new/sub/b"]
+            .iter()
+            .all(|path| paths.contains(&path.to_string())));
+        let changes = repo.query_fsmonitor(Some(&changes.token)).unwrap();
+        assert_eq!(changes.paths, Some(vec![]));
+
+        assert!(repo.stop_fsmonitor().unwrap());
+        daemon.join().unwrap().unwrap();
+        assert!(!repo.stop_fsmonitor().unwrap());
+    }
+}
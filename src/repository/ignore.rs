@@ -0,0 +1,424 @@
+//! Ignore rules from `.gitignore` files, `info/exclude` and
+//! `core.excludesFile`, and patterns given on the command line.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    env, fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::wildmatch::{wildmatch, WM_CASEFOLD, WM_PATHNAME};
+
+use super::{config::Config, error::Result, index::is_missing, Repository};
+
+/// A pattern of an ignore file.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    /// The pattern as written, without a leading `!` or a trailing `/`
+    pub pattern: String,
+    /// Whether the pattern re-includes paths, `!pattern`
+    pub negated: bool,
+    /// Whether the pattern only matches directories, `pattern/`
+    pub dir_only: bool,
+    /// The file the pattern was read from, e.g. `src/.gitignore`
+    pub source: String,
+    /// The line number of the pattern in its file
+    pub line: usize,
+    /// The directory of the `.gitignore` file, e.g. `src/`, which the
+    /// pattern is relative to
+    base: String,
+    /// Whether the pattern contains a slash, so it matches the path relative
+    /// to `base` instead of the file name
+    anchored: bool,
+}
+
+impl Pattern {
+    /// Parses a line of an ignore file, returning `None` for blank lines and
+    /// comments.
+    pub(super) fn parse(
+        line: &str,
+        base: &str,
+        source: &str,
+        line_number: usize,
+    ) -> Option<Pattern> {
+        if line.starts_with('#') {
+            return None;
+        }
+        let line = trim_trailing_spaces(line);
+        if line.is_empty() {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, pattern) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+
+        Some(Pattern {
+            pattern: pattern.to_string(),
+            negated,
+            dir_only,
+            source: source.to_string(),
+            line: line_number,
+            base: base.to_string(),
+            anchored: pattern.contains('/'),
+        })
+    }
+
+    /// Returns true if the pattern matches `path`, relative to the root of
+    /// the working tree.
+    pub(super) fn matches(&self, path: &str, is_dir: bool, flags: u32) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Some(path) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+
+        if self.anchored {
+            let pattern = self.pattern.strip_prefix('/').unwrap_or(&self.pattern);
+            wildmatch(pattern, path, flags | WM_PATHNAME)
+        } else {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            wildmatch(&self.pattern, name, flags)
+        }
+    }
+}
+
+/// Formats the pattern as written in its file.
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bang = if self.negated { "!" } else { "" };
+        let slash = if self.dir_only { "/" } else { "" };
+        write!(f, "{bang}{}{slash}", self.pattern)
+    }
+}
+
+/// Removes trailing spaces, unless they are escaped with a backslash.
+fn trim_trailing_spaces(line: &str) -> &str {
+    let mut end = line.len();
+    while line[..end].ends_with(' ') {
+        let backslashes = line[..end - 1]
+            .bytes()
+            .rev()
+            .take_while(|&b| b == b'\\')
+            .count();
+        if backslashes % 2 == 1 {
+            break;
+        }
+        end -= 1;
+    }
+    &line[..end]
+}
+
+/// Parses the patterns of an ignore file.
+fn parse_patterns(content: &str, base: &str, source: &str) -> Vec<Pattern> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| Pattern::parse(line, base, source, i + 1))
+        .collect()
+}
+
+/// Reads the patterns of an ignore file, if it exists.
+fn read_patterns(path: &Path, base: &str, source: &str) -> Result<Vec<Pattern>> {
+    match fs::read(path) {
+        Ok(content) => Ok(parse_patterns(
+            &String::from_utf8_lossy(&content),
+            base,
+            source,
+        )),
+        Err(e) if is_missing(&e) => Ok(vec![]),
+        Err(e) => Err(e)?,
+    }
+}
+
+/// Returns the path of a per-user file set with `key`, like
+/// `core.excludesFile`, defaulting to `$XDG_CONFIG_HOME/git/<name>`, along
+/// with the path as written.
+pub(super) fn user_file(config: &Config, key: &str, name: &str) -> Option<(String, PathBuf)> {
+    let source = match config.get(key) {
+        Some(path) => path.to_string(),
+        None => match env::var("XDG_CONFIG_HOME") {
+            Ok(dir) if !dir.is_empty() => format!("{dir}/git/{name}"),
+            _ => format!("~/.config/git/{name}"),
+        },
+    };
+    let path = match source.strip_prefix("~/") {
+        Some(rest) => Path::new(&env::var("HOME").ok()?).join(rest),
+        None => PathBuf::from(&source),
+    };
+
+    Some((source, path))
+}
+
+/// The ignore rules of a working tree.
+///
+/// Like git, patterns given on the command line take precedence over the
+/// `.gitignore` file of a directory, which takes precedence over those of
+/// its parents, which take precedence over `info/exclude` and then
+/// `core.excludesFile`. Within a file the last matching pattern wins. Once a
+/// directory is ignored, nothing inside it can be re-included.
+pub struct Ignore {
+    root: PathBuf,
+    /// The patterns of the `.gitignore` files by directory, e.g. `src/`,
+    /// read when first needed
+    dirs: HashMap<String, Vec<Pattern>>,
+    /// The patterns of `info/exclude` and `core.excludesFile`
+    global: Vec<Vec<Pattern>>,
+    /// The patterns given on the command line, e.g. `clean -e`
+    command_line: Vec<Pattern>,
+    /// Whether to read the `.gitignore` files
+    read_files: bool,
+    flags: u32,
+}
+
+impl Ignore {
+    /// Adds patterns given on the command line.
+    pub fn add_patterns(&mut self, patterns: &[String]) {
+        self.command_line.extend(
+            patterns
+                .iter()
+                .filter_map(|pattern| Pattern::parse(pattern, "", "", 0)),
+        );
+    }
+
+    /// Returns the pattern deciding whether `path` is ignored, if any. The
+    /// path is ignored if the pattern isn't negated.
+    pub fn matching_pattern(&mut self, path: &str, is_dir: bool) -> Result<Option<&Pattern>> {
+        let path = path.trim_end_matches('/');
+
+        // An ignored leading directory ignores everything inside it
+        let mut dirs = vec![String::new()];
+        for (end, _) in path.match_indices('/') {
+            let dir = &path[..end];
+            self.load(dirs.last().unwrap())?;
+            if self.find(dir, true).is_some_and(|pattern| !pattern.negated) {
+                return Ok(self.find(dir, true));
+            }
+            dirs.push(format!("{dir}/"));
+        }
+        self.load(dirs.last().unwrap())?;
+
+        Ok(self.find(path, is_dir))
+    }
+
+    /// Returns true if `path` is ignored.
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> Result<bool> {
+        Ok(self
+            .matching_pattern(path, is_dir)?
+            .is_some_and(|pattern| !pattern.negated))
+    }
+
+    /// Reads the `.gitignore` file of a directory, if not read yet.
+    fn load(&mut self, dir: &str) -> Result<()> {
+        if !self.dirs.contains_key(dir) {
+            let source = format!("{dir}.gitignore");
+            let patterns = match self.read_files {
+                true => read_patterns(&self.root.join(&source), dir, &source)?,
+                false => vec![],
+            };
+            self.dirs.insert(dir.to_string(), patterns);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the last matching pattern of the list with the highest
+    /// precedence, looking at the `.gitignore` files that are loaded.
+    fn find(&self, path: &str, is_dir: bool) -> Option<&Pattern> {
+        let mut dirs: Vec<&str> = path
+            .match_indices('/')
+            .map(|(end, _)| &path[..=end])
+            .collect();
+        dirs.insert(0, "");
+
+        let dir_patterns = dirs.into_iter().rev().filter_map(|dir| self.dirs.get(dir));
+        let lists = [&self.command_line].into_iter().chain(dir_patterns);
+        lists.chain(&self.global).find_map(|patterns| {
+            patterns
+                .iter()
+                .rev()
+                .find(|pattern| pattern.matches(path, is_dir, self.flags))
+        })
+    }
+}
+
+/// How a path of the working tree relates to the index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tracking {
+    /// The path is in the index
+    Tracked,
+    /// A directory that isn't in the index but holds tracked files
+    HoldsTracked,
+    Untracked,
+}
+
+/// The paths of the index, to tell tracked files from untracked and ignored
+/// ones when walking the working tree.
+#[derive(Debug, Default)]
+pub struct TrackedPaths(BTreeSet<String>);
+
+impl TrackedPaths {
+    pub fn new<'a>(paths: impl IntoIterator<Item = &'a str>) -> TrackedPaths {
+        TrackedPaths(paths.into_iter().map(str::to_string).collect())
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.0.contains(path)
+    }
+
+    /// Returns the tracked paths below the directory `prefix`, e.g. `src/`.
+    pub fn below<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a str> {
+        self.0
+            .range(prefix.to_string()..)
+            .take_while(move |path| path.starts_with(prefix))
+            .map(String::as_str)
+    }
+
+    /// Returns true if there are tracked files below the directory `prefix`.
+    pub fn has_tracked(&self, prefix: &str) -> bool {
+        self.below(prefix).next().is_some()
+    }
+
+    /// Returns how `path` relates to the index, and whether it is ignored.
+    /// Inside an ignored directory everything is ignored; tracked files are
+    /// ignored too if they match, which walks keep anyway.
+    pub fn classify(
+        &self,
+        path: &str,
+        is_dir: bool,
+        ignore: &mut Ignore,
+        ignored_dir: bool,
+    ) -> Result<(Tracking, bool)> {
+        let tracking = if self.contains(path) {
+            Tracking::Tracked
+        } else if is_dir && self.has_tracked(&format!("{path}/")) {
+            Tracking::HoldsTracked
+        } else {
+            Tracking::Untracked
+        };
+        let ignored = ignored_dir || ignore.is_ignored(path, is_dir)?;
+
+        Ok((tracking, ignored))
+    }
+}
+
+impl Repository {
+    /// Returns the ignore rules of the working tree.
+    pub fn ignore(&self) -> Result<Ignore> {
+        let config = self.config()?;
+        let exclude = self.common_dir.join("info/exclude");
+        let mut global = vec![read_patterns(&exclude, "", ".git/info/exclude")?];
+
+        if let Some((source, path)) = user_file(&config, "core.excludesFile", "ignore") {
+            global.push(read_patterns(&path, "", &source)?);
+        }
+
+        let flags = match config.get_bool("core.ignoreCase") {
+            Some(true) => WM_CASEFOLD,
+            _ => 0,
+        };
+
+        Ok(Ignore {
+            root: self.get_root().to_path_buf(),
+            dirs: HashMap::new(),
+            global,
+            command_line: vec![],
+            read_files: true,
+            flags,
+        })
+    }
+
+    /// Returns ignore rules without any of the ignore files, for patterns
+    /// given on the command line.
+    pub fn ignore_none(&self) -> Result<Ignore> {
+        let mut ignore = self.ignore()?;
+        ignore.global.clear();
+        ignore.read_files = false;
+        Ok(ignore)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_ignore() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+
+        fs::write(
+            root.join(".gitignore"),
+            "# comment\n*.log\n!keep.log\nbuild/\n/root.txt\ndoc/**/*.pdf\ntrail  \n",
+        )
+        .unwrap();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub/.gitignore"), "!*.o\nlocal\n").unwrap();
+        fs::create_dir(root.join(".git/info")).unwrap();
+        fs::write(root.join(".git/info/exclude"), "*.o\n").unwrap();
+        let mut ignore = repo.ignore().unwrap();
+
+        let cases = [
+            ("a.log", false, true),
+            ("sub/a.log", false, true),
+            ("keep.log", false, false),
+            ("build", true, true),
+            ("build", false, false),
+            ("build/x/file", false, true),
+            ("root.txt", false, true),
+            ("sub/root.txt", false, false),
+            ("doc/c.pdf", false, true),
+            ("doc/a/b/c.pdf", false, true),
+            ("trail", false, true),
+            ("x.o", false, true),
+            ("sub/x.o", false, false),
+            ("sub/local", false, true),
+            ("local", false, false),
+        ];
+        for (path, is_dir, expected) in cases {
+            assert_eq!(ignore.is_ignored(path, is_dir).unwrap(), expected, "{path}");
+        }
+
+        let pattern = ignore.matching_pattern("sub/x.o", false).unwrap().unwrap();
+        assert_eq!(
+            (pattern.source.as_str(), pattern.line, pattern.to_string()),
+            ("sub/.gitignore", 1, "!*.o".to_string())
+        );
+    }
+
+    #[test]
+    fn test_classify() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+        fs::write(root.join(".gitignore"), "*.o\nbuild/\n").unwrap();
+        let mut ignore = repo.ignore().unwrap();
+        let tracked = TrackedPaths::new(["a.o", "build/keep", "src/main.c"]);
+
+        assert!(tracked.has_tracked("src/"));
+        assert!(!tracked.has_tracked("sr/"));
+        assert_eq!(tracked.below("build/").collect::<Vec<_>>(), ["build/keep"]);
+        let cases = [
+            ("a.o", false, false, (Tracking::Tracked, true)),
+            ("b.o", false, false, (Tracking::Untracked, true)),
+            ("src", true, false, (Tracking::HoldsTracked, false)),
+            ("build", true, false, (Tracking::HoldsTracked, true)),
+            ("build/new", false, true, (Tracking::Untracked, true)),
+            ("src/main.h", false, false, (Tracking::Untracked, false)),
+            // Only directories hold tracked files
+            ("src", false, false, (Tracking::Untracked, false)),
+        ];
+        for (path, is_dir, ignored_dir, expected) in cases {
+            let class = tracked.classify(path, is_dir, &mut ignore, ignored_dir);
+            assert_eq!(class.unwrap(), expected, "{path}");
+        }
+    }
+}
@@ -0,0 +1,731 @@
+//! The index (`.git/index`), git's staging area. Besides the object hash of
+//! every tracked file it caches the file's stat data, so unchanged files
+//! can be recognized without hashing them.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    ops::{Deref, DerefMut},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::object::{
+    tree::{file_mode, MODE_GITLINK},
+    Object,
+};
+
+use super::{
+    cache_tree::CacheTree,
+    convert::Convert,
+    error::{RepoError, Result},
+    fsmonitor,
+    untracked_cache::UntrackedCache,
+    Repository,
+};
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+const TREE_SIGNATURE: &[u8; 4] = b"TREE";
+const UNTRACKED_SIGNATURE: &[u8; 4] = b"UNTR";
+const FSMONITOR_SIGNATURE: &[u8; 4] = b"FSMN";
+const HASH_SIZE: usize = 20;
+/// Size of an entry up to the path, without extended flags
+const ENTRY_HEADER_SIZE: usize = 62;
+
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_MASK: u16 = 0x3000;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+
+/// Extended flag of entries left out of the working tree by a sparse
+/// checkout
+const EXTENDED_FLAG_SKIP_WORKTREE: u16 = 0x4000;
+
+fn corrupt(what: &str) -> RepoError {
+    RepoError::CorruptIndex(what.to_string())
+}
+
+/// A tracked file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IndexEntry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub hash: String,
+    /// 0 for a merged entry, 1-3 for the base, ours and theirs versions of
+    /// a file with a merge conflict
+    pub stage: u8,
+    /// Flags of index version 3, such as skip-worktree
+    pub extended_flags: u16,
+    pub path: String,
+    /// Whether the fsmonitor saw no change to the file since the entry was
+    /// found up to date, so it needn't even be stat'ed. Only to be trusted
+    /// after [`Index::refresh_fsmonitor`].
+    pub fsmonitor_valid: bool,
+}
+
+impl IndexEntry {
+    /// Returns an entry without stat data, so it's never considered up to date.
+    pub fn new(path: &str, mode: u32, hash: &str) -> IndexEntry {
+        IndexEntry {
+            mode,
+            hash: hash.to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Updates the cached stat data from the file's metadata.
+    pub fn update_stat(&mut self, meta: &fs::Metadata) {
+        // The index stores the lower 32 bits only
+        self.ctime = (meta.ctime() as u32, meta.ctime_nsec() as u32);
+        self.mtime = (meta.mtime() as u32, meta.mtime_nsec() as u32);
+        self.dev = meta.dev() as u32;
+        self.ino = meta.ino() as u32;
+        self.uid = meta.uid();
+        self.gid = meta.gid();
+        self.size = meta.size() as u32;
+    }
+
+    /// Returns true if the file is left out of the working tree.
+    pub fn skip_worktree(&self) -> bool {
+        self.extended_flags & EXTENDED_FLAG_SKIP_WORKTREE != 0
+    }
+
+    pub fn set_skip_worktree(&mut self, skip: bool) {
+        match skip {
+            true => self.extended_flags |= EXTENDED_FLAG_SKIP_WORKTREE,
+            false => self.extended_flags &= !EXTENDED_FLAG_SKIP_WORKTREE,
+        }
+    }
+
+    /// Returns true if the file's metadata still matches the cached stat data.
+    pub fn stat_matches(&self, meta: &fs::Metadata) -> bool {
+        self.mtime == (meta.mtime() as u32, meta.mtime_nsec() as u32)
+            && self.ctime == (meta.ctime() as u32, meta.ctime_nsec() as u32)
+            && self.ino == meta.ino() as u32
+            && self.size == meta.size() as u32
+            && self.mode == file_mode(meta)
+    }
+}
+
+/// The parsed index file.
+#[derive(Debug, Default)]
+pub struct Index {
+    path: PathBuf,
+    /// Entries sorted by path and stage
+    pub entries: Vec<IndexEntry>,
+    /// Modification time of the index file when it was read, to detect
+    /// files changed in the same second ("racy git")
+    mtime: Option<(u32, u32)>,
+    cache_tree: Option<CacheTree>,
+    untracked_cache: Option<UntrackedCache>,
+    /// The token of the last fsmonitor query, which the valid flags of the
+    /// entries are relative to
+    fsmonitor_token: Option<String>,
+    /// The entries as they were read, to find the changed ones and
+    /// invalidate their cached trees and untracked files when writing
+    loaded: Vec<IndexEntry>,
+}
+
+impl Index {
+    /// Reads the index of the repository, which is empty if it doesn't exist.
+    pub fn load(repo: &Repository) -> Result<Index> {
+        let path = repo.get_path().join("index");
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Index {
+                    path,
+                    ..Default::default()
+                })
+            }
+            Err(e) => Err(e)?,
+        };
+        let meta = fs::metadata(&path)?;
+
+        Ok(Index {
+            mtime: Some((meta.mtime() as u32, meta.mtime_nsec() as u32)),
+            path,
+            ..parse(&data)?
+        })
+    }
+
+    /// Returns true if the index file exists.
+    pub fn exists(&self) -> bool {
+        self.mtime.is_some()
+    }
+
+    /// Returns the merged (stage 0) entry of a path.
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.position(path, 0).ok().map(|i| &self.entries[i])
+    }
+
+    /// Returns the `(mode, hash)` of the merged entries by path.
+    pub fn files(&self) -> BTreeMap<String, (u32, String)> {
+        self.entries
+            .iter()
+            .filter(|e| e.stage == 0)
+            .map(|e| (e.path.clone(), (e.mode, e.hash.clone())))
+            .collect()
+    }
+
+    /// Returns the merged entries below a directory, e.g. `src/`.
+    pub fn entries_in<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a IndexEntry> {
+        let start = self.entries.partition_point(|e| e.path.as_str() < prefix);
+        self.entries[start..]
+            .iter()
+            .take_while(move |e| e.path.starts_with(prefix))
+            .filter(|e| e.stage == 0)
+    }
+
+    pub fn cache_tree(&self) -> Option<&CacheTree> {
+        self.cache_tree.as_ref()
+    }
+
+    /// Sets the cache tree, which must match the entries as they are.
+    pub fn set_cache_tree(&mut self, cache_tree: CacheTree) {
+        self.invalidate_extensions();
+        self.cache_tree = Some(cache_tree);
+        self.loaded = self.entries.clone();
+    }
+
+    pub fn untracked_cache(&self) -> Option<&UntrackedCache> {
+        self.untracked_cache.as_ref()
+    }
+
+    /// Sets the untracked cache, which must match the entries as they are,
+    /// or removes it.
+    pub fn set_untracked_cache(&mut self, untracked_cache: Option<UntrackedCache>) {
+        self.invalidate_extensions();
+        self.untracked_cache = untracked_cache;
+        self.loaded = self.entries.clone();
+    }
+
+    /// Asks the fsmonitor daemon, if `core.fsmonitor` is set, which files
+    /// changed since the token of the index, and clears the valid flag of
+    /// their entries. Without an answer no entry is valid. Returns true if
+    /// the token changed.
+    pub fn refresh_fsmonitor(&mut self, repo: &Repository) -> Result<bool> {
+        let changes = match repo.config()?.get_bool("core.fsmonitor") {
+            Some(true) => repo.query_fsmonitor(self.fsmonitor_token.as_deref()),
+            _ => None,
+        };
+        let token = changes.as_ref().map(|changes| changes.token.clone());
+
+        match changes.and_then(|changes| changes.paths) {
+            Some(paths) => {
+                // A path may be a directory, changing all files below it
+                for path in paths {
+                    let start = self.entries.partition_point(|e| e.path < path);
+                    for e in self.entries[start..].iter_mut() {
+                        if e.path != path {
+                            break;
+                        }
+                        e.fsmonitor_valid = false;
+                    }
+                    let dir = format!("{path}/");
+                    let start = self.entries.partition_point(|e| e.path < dir);
+                    for e in self.entries[start..].iter_mut() {
+                        if !e.path.starts_with(&dir) {
+                            break;
+                        }
+                        e.fsmonitor_valid = false;
+                    }
+                }
+            }
+            None => self
+                .entries
+                .iter_mut()
+                .for_each(|e| e.fsmonitor_valid = false),
+        }
+
+        let changed = token != self.fsmonitor_token;
+        self.fsmonitor_token = token;
+        Ok(changed)
+    }
+
+    /// Returns true if the index has an fsmonitor token, so the valid flags
+    /// of its entries are kept.
+    pub fn uses_fsmonitor(&self) -> bool {
+        self.fsmonitor_token.is_some()
+    }
+
+    /// Invalidates the cached trees of the entries that were added, removed
+    /// or changed since the index was read, and the cached untracked files
+    /// of the directories of those added or removed.
+    pub(super) fn invalidate_extensions(&mut self) {
+        let (mut cache_tree, mut untracked_cache) =
+            (self.cache_tree.as_mut(), self.untracked_cache.as_mut());
+        if cache_tree.is_none() && untracked_cache.is_none() {
+            return;
+        }
+        let mut invalidate = |path: &str, added_or_removed: bool| {
+            if let Some(cache_tree) = &mut cache_tree {
+                cache_tree.invalidate(path);
+            }
+            if let Some(untracked_cache) = &mut untracked_cache {
+                if added_or_removed {
+                    untracked_cache.invalidate(path);
+                }
+            }
+        };
+        fn key(e: &IndexEntry) -> (&str, u8) {
+            (e.path.as_str(), e.stage)
+        }
+        let (mut old, mut new) = (
+            self.loaded.iter().peekable(),
+            self.entries.iter().peekable(),
+        );
+        loop {
+            match (old.peek(), new.peek()) {
+                (Some(a), Some(b)) if key(a) == key(b) => {
+                    if a.mode != b.mode || a.hash != b.hash {
+                        invalidate(&a.path, false);
+                    }
+                    old.next();
+                    new.next();
+                }
+                (Some(a), Some(b)) if key(a) < key(b) => {
+                    invalidate(&a.path, true);
+                    old.next();
+                }
+                (Some(a), None) => {
+                    invalidate(&a.path, true);
+                    old.next();
+                }
+                (_, Some(b)) => {
+                    invalidate(&b.path, true);
+                    new.next();
+                }
+                (None, None) => break,
+            }
+        }
+    }
+
+    /// Returns all entries of a path, more than one if it has conflicts.
+    pub fn get_all<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'a IndexEntry> {
+        self.entries.iter().filter(move |e| e.path == path)
+    }
+
+    fn position(&self, path: &str, stage: u8) -> std::result::Result<usize, usize> {
+        self.entries
+            .binary_search_by(|e| (e.path.as_str(), e.stage).cmp(&(path, stage)))
+    }
+
+    /// Adds an entry, replacing the entry of the same path and stage. Adding
+    /// a merged entry resolves any conflict of the path. The entry isn't
+    /// fsmonitor valid until the file is found up to date again.
+    pub fn add(&mut self, mut entry: IndexEntry) {
+        entry.fsmonitor_valid = false;
+        if entry.stage == 0 {
+            self.entries
+                .retain(|e| e.path != entry.path || e.stage == 0);
+        }
+        match self.position(&entry.path, entry.stage) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    /// Removes all entries of a path.
+    pub fn remove(&mut self, path: &str) {
+        self.entries.retain(|e| e.path != path);
+    }
+
+    /// Returns true if any path has a merge conflict.
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|e| e.stage != 0)
+    }
+
+    /// Returns true if the file may have changed since its stat data was
+    /// cached without that showing in the stat data, because it was
+    /// modified in the same instant the index was written.
+    pub fn is_racy(&self, entry: &IndexEntry) -> bool {
+        self.is_racy_mtime(entry.mtime)
+    }
+
+    /// Returns true if something modified at `mtime` may have changed again
+    /// in the same instant the index was written.
+    pub fn is_racy_mtime(&self, mtime: (u32, u32)) -> bool {
+        self.mtime.is_some_and(|index_mtime| index_mtime <= mtime)
+    }
+
+    /// Returns true if the file in the working tree differs from the entry,
+    /// hashing its content if the stat data doesn't settle it. A file left
+    /// out of a sparse checkout isn't modified by being missing.
+    pub fn is_modified(&self, entry: &IndexEntry, root: &Path, convert: &Convert) -> Result<bool> {
+        let path = root.join(&entry.path);
+        let meta = match fs::symlink_metadata(&path) {
+            Ok(meta) => meta,
+            Err(e) if is_missing(&e) => return Ok(!entry.skip_worktree()),
+            Err(e) => Err(e)?,
+        };
+        if entry.mode == MODE_GITLINK {
+            return Ok(!meta.is_dir());
+        }
+        if file_mode(&meta) != entry.mode {
+            return Ok(true);
+        }
+        if entry.stat_matches(&meta) && !self.is_racy(entry) {
+            return Ok(false);
+        }
+
+        Ok(hash_file(&entry.path, &meta, convert)? != entry.hash)
+    }
+
+    /// Writes the index through `index.lock`, which also keeps concurrent
+    /// writers out.
+    pub fn write(&mut self) -> Result<()> {
+        let file = create_lock(&self.path)?;
+        self.write_to_lock(file)
+    }
+
+    /// Locks the index and reads it. Changes are written with
+    /// [`LockedIndex::commit`]; no one else can update the index until then.
+    pub fn lock(repo: &Repository) -> Result<LockedIndex> {
+        let path = repo.get_path().join("index");
+        let file = create_lock(&path)?;
+        let mut lock = LockedIndex {
+            index: Index {
+                path,
+                ..Default::default()
+            },
+            file: Some(file),
+        };
+        // Dropping the lock releases it if the index can't be read
+        lock.index = Index::load(repo)?;
+
+        Ok(lock)
+    }
+
+    /// Writes the entries to the lock file and moves it into place.
+    fn write_to_lock(&mut self, mut file: File) -> Result<()> {
+        let lock = self.path.with_extension("lock");
+        self.invalidate_extensions();
+        let result = file
+            .write_all(&serialize(self))
+            .map_err(RepoError::from)
+            .and_then(|_| Ok(fs::rename(&lock, &self.path)?));
+        if result.is_err() {
+            let _ = fs::remove_file(&lock);
+        }
+        result?;
+
+        let meta = fs::metadata(&self.path)?;
+        self.mtime = Some((meta.mtime() as u32, meta.mtime_nsec() as u32));
+        self.loaded = self.entries.clone();
+
+        Ok(())
+    }
+}
+
+/// Creates the lock file of the index at `path`.
+fn create_lock(path: &Path) -> Result<File> {
+    let lock = path.with_extension("lock");
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock)
+        .map_err(|e| RepoError::Other(format!("unable to create '{}': {e}", lock.display())))
+}
+
+/// The index, locked from reading it until the changes are committed. The
+/// lock is released without changes when it's dropped.
+pub struct LockedIndex {
+    index: Index,
+    file: Option<File>,
+}
+
+impl LockedIndex {
+    /// Writes the index and releases the lock.
+    pub fn commit(mut self) -> Result<()> {
+        let file = self.file.take().expect("index lock already released");
+        self.index.write_to_lock(file)
+    }
+}
+
+impl Deref for LockedIndex {
+    type Target = Index;
+
+    fn deref(&self) -> &Index {
+        &self.index
+    }
+}
+
+impl DerefMut for LockedIndex {
+    fn deref_mut(&mut self) -> &mut Index {
+        &mut self.index
+    }
+}
+
+impl Drop for LockedIndex {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(self.index.path.with_extension("lock"));
+        }
+    }
+}
+
+/// Returns true if an error means that a file doesn't exist, also when
+/// one of its parent directories is a file.
+pub fn is_missing(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory)
+}
+
+/// Returns the blob hash of a file in the working tree, by its path
+/// relative to the root; for a symlink the blob holds the path it points to.
+pub fn hash_file(path: &str, meta: &fs::Metadata, convert: &Convert) -> Result<String> {
+    let content = convert.read_file(path, meta, false)?;
+    Ok(Object::blob_from_bytes(&content).write(std::io::sink())?)
+}
+
+fn be16(data: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes([data[pos], data[pos + 1]])
+}
+
+fn be32(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap_or_default())
+}
+
+/// Parses an index file of version 2 or 3, with its extensions.
+fn parse(data: &[u8]) -> Result<Index> {
+    if data.len() < 12 + HASH_SIZE || &data[..4] != SIGNATURE {
+        Err(corrupt("bad signature"))?;
+    }
+    let (content, checksum) = data.split_at(data.len() - HASH_SIZE);
+    if Sha1::digest(content).as_slice() != checksum {
+        Err(corrupt("bad index file sha1 signature"))?;
+    }
+    let version = be32(data, 4);
+    if !(2..=3).contains(&version) {
+        Err(RepoError::CorruptIndex(format!(
+            "index file version {version} is not supported"
+        )))?;
+    }
+    let count = be32(data, 8) as usize;
+
+    let mut entries = Vec::with_capacity(count);
+    let mut pos = 12;
+    for _ in 0..count {
+        if pos + ENTRY_HEADER_SIZE > content.len() {
+            Err(corrupt("truncated entry"))?;
+        }
+        let field = |i: usize| be32(data, pos + 4 * i);
+        let flags = be16(data, pos + 60);
+        let mut name_start = pos + ENTRY_HEADER_SIZE;
+        let mut extended_flags = 0;
+        if flags & FLAG_EXTENDED != 0 {
+            extended_flags = be16(data, name_start);
+            name_start += 2;
+        }
+        let name_end = content[name_start..]
+            .iter()
+            .position(|&b| b == 0)
+            .map(|len| name_start + len)
+            .ok_or_else(|| corrupt("unterminated path"))?;
+
+        entries.push(IndexEntry {
+            ctime: (field(0), field(1)),
+            mtime: (field(2), field(3)),
+            dev: field(4),
+            ino: field(5),
+            mode: field(6),
+            uid: field(7),
+            gid: field(8),
+            size: field(9),
+            hash: hex::encode(&data[pos + 40..pos + 40 + HASH_SIZE]),
+            stage: ((flags & FLAG_STAGE_MASK) >> 12) as u8,
+            extended_flags,
+            path: String::from_utf8_lossy(&content[name_start..name_end]).to_string(),
+            fsmonitor_valid: false,
+        });
+        // Entries are padded with 1-8 NUL bytes to a multiple of 8
+        pos += (name_end - pos + 8) & !7;
+    }
+
+    // Extensions follow the entries, each with a signature and the size of
+    // its data
+    let mut cache_tree = None;
+    let mut untracked_cache = None;
+    let mut fsmonitor_token = None;
+    while pos + 8 <= content.len() {
+        let signature = &content[pos..pos + 4];
+        let size = be32(data, pos + 4) as usize;
+        let extension = content
+            .get(pos + 8..pos + 8 + size)
+            .ok_or_else(|| corrupt("truncated extension"))?;
+        match signature {
+            _ if signature == TREE_SIGNATURE => cache_tree = Some(CacheTree::parse(extension)?),
+            _ if signature == UNTRACKED_SIGNATURE => {
+                untracked_cache = Some(UntrackedCache::parse(extension)?)
+            }
+            _ if signature == FSMONITOR_SIGNATURE => {
+                let (token, dirty) = fsmonitor::parse_extension(extension)?;
+                if dirty.len() > entries.len() {
+                    Err(corrupt("fsmonitor bitmap larger than the index"))?;
+                }
+                for (i, entry) in entries.iter_mut().enumerate() {
+                    entry.fsmonitor_valid = !dirty.get(i).copied().unwrap_or(false);
+                }
+                fsmonitor_token = Some(token);
+            }
+            // Extensions starting with an uppercase letter are optional
+            [b'A'..=b'Z', ..] => {}
+            _ => Err(RepoError::CorruptIndex(format!(
+                "index uses {} extension, which we do not understand",
+                signature.escape_ascii()
+            )))?,
+        }
+        pos += 8 + size;
+    }
+
+    Ok(Index {
+        loaded: entries.clone(),
+        entries,
+        cache_tree,
+        untracked_cache,
+        fsmonitor_token,
+        ..Default::default()
+    })
+}
+
+fn serialize(index: &Index) -> Vec<u8> {
+    let entries = &index.entries;
+    let version: u32 = if entries.iter().any(|e| e.extended_flags != 0) {
+        3
+    } else {
+        2
+    };
+    let mut out = SIGNATURE.to_vec();
+    out.extend_from_slice(&version.to_be_bytes());
+    out.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+    for entry in entries {
+        let start = out.len();
+        for field in [
+            entry.ctime.0,
+            entry.ctime.1,
+            entry.mtime.0,
+            entry.mtime.1,
+            entry.dev,
+            entry.ino,
+            entry.mode,
+            entry.uid,
+            entry.gid,
+            entry.size,
+        ] {
+            out.extend_from_slice(&field.to_be_bytes());
+        }
+        out.extend_from_slice(&hex::decode(&entry.hash).unwrap_or_else(|_| vec![0; HASH_SIZE]));
+        let mut flags = (u16::from(entry.stage) << 12) | entry.path.len().min(0xfff) as u16;
+        flags &= FLAG_STAGE_MASK | FLAG_NAME_MASK;
+        if entry.extended_flags != 0 {
+            flags |= FLAG_EXTENDED;
+        }
+        out.extend_from_slice(&flags.to_be_bytes());
+        if entry.extended_flags != 0 {
+            out.extend_from_slice(&entry.extended_flags.to_be_bytes());
+        }
+        out.extend_from_slice(entry.path.as_bytes());
+        let len = out.len() - start;
+        out.resize(start + ((len + 8) & !7), 0);
+    }
+
+    if let Some(cache_tree) = &index.cache_tree {
+        let mut extension = vec![];
+        cache_tree.serialize("", &mut extension);
+        out.extend_from_slice(TREE_SIGNATURE);
+        out.extend_from_slice(&(extension.len() as u32).to_be_bytes());
+        out.extend_from_slice(&extension);
+    }
+    if let Some(untracked_cache) = &index.untracked_cache {
+        let mut extension = vec![];
+        untracked_cache.serialize(&mut extension);
+        out.extend_from_slice(UNTRACKED_SIGNATURE);
+        out.extend_from_slice(&(extension.len() as u32).to_be_bytes());
+        out.extend_from_slice(&extension);
+    }
+    if let Some(token) = &index.fsmonitor_token {
+        let dirty: Vec<bool> = entries.iter().map(|e| !e.fsmonitor_valid).collect();
+        let mut extension = vec![];
+        fsmonitor::serialize_extension(token, &dirty, &mut extension);
+        out.extend_from_slice(FSMONITOR_SIGNATURE);
+        out.extend_from_slice(&(extension.len() as u32).to_be_bytes());
+        out.extend_from_slice(&extension);
+    }
+
+    let checksum = Sha1::digest(&out);
+    out.extend_from_slice(&checksum);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_index_roundtrip() {
+        let mut index = Index::default();
+        let hash = "3b18e512dba79e4c8300dd08aeb37f8e728b8dad";
+        index.add(IndexEntry::new("src/main.rs", 0o100644, hash));
+        index.add(IndexEntry::new("README", 0o100755, hash));
+        index.add(IndexEntry {
+            stage: 2,
+            ..IndexEntry::new("conflict", 0o100644, hash)
+        });
+        assert_eq!(
+            index
+                .entries
+                .iter()
+                .map(|e| e.path.as_str())
+                .collect::<Vec<_>>(),
+            ["README", "conflict", "src/main.rs"]
+        );
+
+        let data = serialize(&index);
+        // 12 byte header, entries padded to 8 bytes, 20 byte checksum
+        assert_eq!(data.len(), 12 + 72 + 72 + 80 + 20);
+        let entries = parse(&data).unwrap().entries;
+        assert_eq!(entries, index.entries);
+        assert!(Index {
+            entries,
+            ..Default::default()
+        }
+        .has_conflicts());
+
+        index.add(IndexEntry::new("conflict", 0o100644, hash));
+        assert_eq!(index.get_all("conflict").count(), 1);
+    }
+
+    #[test]
+    fn test_index_lock() {
+        let temp_dir = tempdir().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let hash = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
+        let mut index = Index::lock(&repo).unwrap();
+        index.add(IndexEntry::new("a", 0o100644, hash));
+        // Nobody else can lock or write the index meanwhile
+        assert!(Index::lock(&repo).is_err());
+        assert!(Index::load(&repo).unwrap().write().is_err());
+        index.commit().unwrap();
+        assert!(Index::load(&repo).unwrap().get("a").is_some());
+
+        // Dropping the lock discards the changes
+        let mut index = Index::lock(&repo).unwrap();
+        index.remove("a");
+        drop(index);
+        let mut index = Index::load(&repo).unwrap();
+        assert!(index.get("a").is_some());
+        index.write().unwrap();
+    }
+}
@@ -1,108 +1,413 @@
+pub mod add;
+pub mod attributes;
+pub mod cache_tree;
+pub mod checkout;
+pub mod clean;
+pub mod commit;
+pub mod config;
+pub mod convert;
+pub mod diff;
 pub mod error;
+pub mod ewah;
+pub mod filter;
+pub mod fsmonitor;
+pub mod ignore;
+pub mod index;
+pub mod patch;
+pub mod refname;
+pub mod refs;
+pub mod reset;
+pub mod sparse;
+pub mod stash;
+pub mod status;
+pub mod submodule;
+pub mod transaction;
+pub mod untracked_cache;
+pub mod worktree;
 
 use std::{
     fs,
     path::{Path, PathBuf},
 };
 
+use config::Config;
 use error::{RepoError, Result};
+use refs::{reftable::ReftableStore, RefFormat, RefStore, RefValue, RefWrite};
 
 pub struct Repository {
+    /// The git directory: `.git`, or `.git/worktrees/<name>` for a linked
+    /// worktree
     dir: PathBuf,
+    /// The directory shared by all worktrees, with the objects, refs and
+    /// config
+    common_dir: PathBuf,
+    /// The root of the working tree
+    root: PathBuf,
+    refs: Box<dyn RefStore>,
 }
 
 impl Repository {
-    /// Returns a new Repository instance from the given path.
+    /// Returns a new Repository instance from the given path. In a linked
+    /// worktree `.git` is a file pointing to its git directory.
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let git_dir = path.as_ref().join(".git");
-        if !git_dir.exists() {
+        let dot_git = path.as_ref().join(".git");
+        if dot_git.is_file() {
+            let git_dir = worktree::read_gitfile(&dot_git)?;
+            let common_dir = worktree::read_common_dir(&git_dir)?;
+            return Self::open(git_dir, common_dir, path.as_ref().to_path_buf());
+        }
+        if !dot_git.exists() {
             Err(RepoError::NotGitRepository)?;
         }
 
-        Ok(Self { dir: git_dir })
+        Self::open(dot_git.clone(), dot_git, path.as_ref().to_path_buf())
+    }
+
+    /// Opens the worktree at `root` with the given git and common
+    /// directories.
+    fn open(dir: PathBuf, common_dir: PathBuf, root: PathBuf) -> Result<Self> {
+        let format = RefFormat::from_config(&Config::load(&common_dir)?)?;
+        let refs = format.open_worktree(&dir, &common_dir);
+
+        Ok(Self {
+            dir,
+            common_dir,
+            root,
+            refs,
+        })
     }
 
     /// Initializes a new Git repository at the given directory.
     pub fn init(directory: &Path) -> Result<Repository> {
+        Self::init_with_format(directory, RefFormat::Files)
+    }
+
+    /// Initializes a new Git repository at the given directory, storing its
+    /// refs in the given format.
+    pub fn init_with_format(directory: &Path, format: RefFormat) -> Result<Repository> {
         let dir = directory.join(".git");
         if dir.exists() {
             Err(RepoError::AlreadyInitialized)?;
         }
-        fs::create_dir_all(dir.join("objects"))?;
-        fs::create_dir_all(dir.join("refs"))?;
-        fs::write(dir.join("HEAD"), "ref: refs/heads/main\n")?;
+        create_git_dir(&dir, format)?;
 
-        Ok(Repository { dir })
+        let repo = Repository {
+            refs: format.open(&dir),
+            common_dir: dir.clone(),
+            root: directory.to_path_buf(),
+            dir,
+        };
+        if format == RefFormat::Reftable {
+            repo.set_symbolic_ref("HEAD", "refs/heads/main", "")?;
+        }
+
+        Ok(repo)
     }
 
-    /// Creates a new branch with the given name.
+    /// Creates a new branch with the given name, pointing at the current HEAD.
     pub fn create_branch(&self, branch: &str) -> Result<()> {
-        if self.branch_exists(branch)? {
+        let head_commit = self.rev_parse("HEAD")?;
+        self.create_branch_at(branch, &head_commit, false)
+    }
+
+    /// Creates a new branch with the given name pointing at `hash`. With
+    /// `force`, an existing branch is reset to `hash`.
+    pub fn create_branch_at(&self, branch: &str, hash: &str, force: bool) -> Result<()> {
+        if !refname::is_valid_branch_name(branch) {
+            Err(RepoError::InvalidBranchName(branch.to_string()))?;
+        }
+        let exists = self.branch_exists(branch)?;
+        if exists && !force {
             Err(RepoError::BranchAlreadyExists(branch.to_string()))?;
         }
+        let ref_path = format!("refs/heads/{branch}");
+        if !exists {
+            self.check_ref_conflict(&ref_path)?;
+        }
+
+        let message = if exists {
+            "branch: Reset"
+        } else {
+            "branch: Created"
+        };
+        self.update_ref(&ref_path, hash, message)?;
+
+        Ok(())
+    }
+
+    /// Deletes the branch with the given name and its reflog.
+    pub fn delete_branch(&self, branch: &str) -> Result<()> {
+        if !self.branch_exists(branch)? {
+            Err(RepoError::BranchNotFound(branch.to_string()))?;
+        }
+        self.delete_ref(&format!("refs/heads/{branch}"))
+    }
+
+    /// Renames a branch, moving its reflog and config section and updating
+    /// HEAD if it points to the branch. With `force`, an existing branch
+    /// named `new` is overwritten.
+    pub fn rename_branch(&self, old: &str, new: &str, force: bool) -> Result<()> {
+        let hash = self.copy_branch_ref(old, new, force)?;
+        let old_ref = format!("refs/heads/{old}");
+        let new_ref = format!("refs/heads/{new}");
+        if old != new {
+            let reflog = self.read_reflog(&old_ref)?;
+            self.delete_branch(old)?;
+            // Recreate the ref now that `old` can no longer conflict with `new`
+            self.copy_ref_with_reflog(&new_ref, &hash, reflog)?;
+        }
+        let message = format!("Branch: renamed {old_ref} to {new_ref}");
+        self.append_reflog(&new_ref, &hash, &hash, &message)?;
+
+        if self.read_symbolic_ref("HEAD")?.as_deref() == Some(&old_ref) {
+            self.set_symbolic_ref("HEAD", &new_ref, "")?;
+            self.append_reflog("HEAD", &hash, &hash, &message)?;
+        }
+
+        let mut config = self.config()?;
+        config.remove_section(&format!("branch.{new}"))?;
+        config.rename_section(&format!("branch.{old}"), &format!("branch.{new}"))?;
+        config.save()?;
 
-        // Get the commit of the current HEAD and write it to the new branch
-        let head_ref = fs::read_to_string(self.dir.join("HEAD"))?
-            .trim_start_matches("ref: ")
-            .trim()
-            .to_string();
-        let head_commit = fs::read_to_string(self.dir.join(head_ref))?;
-        fs::write(self.dir.join("refs/heads").join(branch), head_commit)?;
+        Ok(())
+    }
+
+    /// Copies a branch along with its reflog and config section. With
+    /// `force`, an existing branch named `new` is overwritten.
+    pub fn copy_branch(&self, old: &str, new: &str, force: bool) -> Result<()> {
+        let hash = self.copy_branch_ref(old, new, force)?;
+        let old_ref = format!("refs/heads/{old}");
+        let new_ref = format!("refs/heads/{new}");
+        if old != new {
+            let reflog = self.read_reflog(&old_ref)?;
+            self.copy_ref_with_reflog(&new_ref, &hash, reflog)?;
+
+            let mut config = self.config()?;
+            config.remove_section(&format!("branch.{new}"))?;
+            config.copy_section(&format!("branch.{old}"), &format!("branch.{new}"))?;
+            config.save()?;
+        }
+        let message = format!("Branch: copied {old_ref} to {new_ref}");
+        self.append_reflog(&new_ref, &hash, &hash, &message)?;
 
         Ok(())
     }
 
+    /// Sets `refname` to `hash`, giving it the reflog entries of the ref it
+    /// was renamed or copied from.
+    fn copy_ref_with_reflog(
+        &self,
+        refname: &str,
+        hash: &str,
+        reflog: Vec<refs::ReflogEntry>,
+    ) -> Result<()> {
+        let lock = self.refs.lock(&[refname.to_string()])?;
+        let mut writes = vec![RefWrite::Ref {
+            refname: refname.to_string(),
+            value: Some(RefValue::Direct(hash.to_string())),
+        }];
+        writes.extend(reflog.into_iter().map(|entry| RefWrite::Log {
+            refname: refname.to_string(),
+            entry,
+        }));
+        lock.commit(writes)
+    }
+
+    /// Validates a branch rename or copy from `old` to `new`, returning the
+    /// hash `old` points to.
+    fn copy_branch_ref(&self, old: &str, new: &str, force: bool) -> Result<String> {
+        let old_ref = format!("refs/heads/{old}");
+        let hash = self
+            .read_ref(&old_ref)?
+            .ok_or_else(|| RepoError::BranchNotFound(old.to_string()))?;
+        if !refname::is_valid_branch_name(new) {
+            Err(RepoError::InvalidBranchName(new.to_string()))?;
+        }
+        if old != new && self.branch_exists(new)? {
+            if !force {
+                Err(RepoError::BranchAlreadyExists(new.to_string()))?;
+            }
+            self.delete_branch(new)?;
+        }
+
+        // `old` itself may be in the way of `new`, e.g. `foo` -> `foo/bar`
+        let new_ref = format!("refs/heads/{new}");
+        let conflict = self.check_ref_conflict(&new_ref);
+        match conflict {
+            Err(RepoError::RefConflict { ref existing, .. }) if *existing == old_ref => {}
+            conflict => conflict?,
+        }
+
+        Ok(hash)
+    }
+
+    /// Returns the full name of the upstream branch configured for the given
+    /// branch, e.g. `refs/remotes/origin/main`.
+    pub fn upstream(&self, branch: &str) -> Result<Option<String>> {
+        let config = self.config()?;
+        let remote = config.get(&format!("branch.{branch}.remote"));
+        let merge = config.get(&format!("branch.{branch}.merge"));
+        let (Some(remote), Some(merge)) = (remote, merge) else {
+            return Ok(None);
+        };
+
+        let upstream = match (remote, merge.strip_prefix("refs/heads/")) {
+            // A remote of "." means the upstream is a local branch
+            (".", _) => merge.to_string(),
+            (remote, Some(name)) => format!("refs/remotes/{remote}/{name}"),
+            (_, None) => return Ok(None),
+        };
+
+        Ok(Some(upstream))
+    }
+
+    /// Returns the configuration of the repository.
+    pub fn config(&self) -> Result<Config> {
+        Config::load(&self.common_dir)
+    }
+
     /// Switches to the branch with the given name.
     pub fn switch_branch(&self, branch: &str) -> Result<()> {
         if !self.branch_exists(branch)? {
             Err(RepoError::BranchNotFound(branch.to_string()))?;
         }
         // Update HEAD to reference the new branch
-        let branch_ref = format!("ref: refs/heads/{}\n", branch);
-        fs::write(self.dir.join("HEAD"), branch_ref)?;
+        let from = self.get_head()?;
+        let from = from.strip_prefix("refs/heads/").unwrap_or(&from);
+        let message = format!("checkout: moving from {from} to {branch}");
+        self.set_symbolic_ref("HEAD", &format!("refs/heads/{branch}"), &message)?;
 
         Ok(())
     }
 
     /// Checks if a branch with the given name exists.
     pub fn branch_exists(&self, branch: &str) -> Result<bool> {
-        let entries = fs::read_dir(self.dir.join("refs/heads"))?;
+        if !refname::is_valid_branch_name(branch) {
+            return Ok(false);
+        }
+
+        Ok(self.read_ref(&format!("refs/heads/{branch}"))?.is_some())
+    }
 
-        let exists = entries
-            .filter_map(|e| e.ok())
-            .any(|entry| entry.file_name() == branch);
+    /// Checks that creating `ref_path` would not clash with an existing ref,
+    /// since `refs/heads/foo` and `refs/heads/foo/bar` can't both exist.
+    pub(crate) fn check_ref_conflict(&self, ref_path: &str) -> Result<()> {
+        let conflict = |existing: &str| RepoError::RefConflict {
+            name: ref_path.to_string(),
+            existing: existing.to_string(),
+        };
 
-        Ok(exists)
+        // An existing ref is a prefix of the new ref
+        let mut prefix = String::new();
+        let components: Vec<&str> = ref_path.split('/').collect();
+        for component in &components[..components.len() - 1] {
+            if !prefix.is_empty() {
+                prefix.push('/');
+            }
+            prefix.push_str(component);
+            if self.refs.read(&prefix)?.is_some() {
+                Err(conflict(&prefix))?;
+            }
+        }
+
+        // The new ref is a prefix of existing refs
+        if let Some((existing, _)) = self.refs.list(&format!("{ref_path}/"))?.first() {
+            Err(conflict(existing))?;
+        }
+
+        Ok(())
     }
 
     /// Returns the hash of the commit referenced by the given ref path.
     pub fn get_ref(&self, ref_path: &str) -> Result<String> {
-        Ok(fs::read_to_string(self.dir.join(ref_path))?)
+        self.read_ref(ref_path)?
+            .ok_or_else(|| RepoError::RefNotFound(ref_path.to_string()))
     }
 
     /// Sets the hash of the commit referenced by the given ref path.
     pub fn set_ref(&self, ref_path: &str, hash: &str) -> Result<()> {
-        Ok(fs::write(self.dir.join(ref_path), hash)?)
+        let lock = self.refs.lock(&[ref_path.to_string()])?;
+        lock.commit(vec![RefWrite::Ref {
+            refname: ref_path.to_string(),
+            value: Some(RefValue::Direct(hash.trim().to_string())),
+        }])
     }
 
-    /// Returns the ref path of the current HEAD.
+    /// Returns the ref path of the current HEAD, or its hash if detached.
     pub fn get_head(&self) -> Result<String> {
-        let head_ref = fs::read_to_string(self.dir.join("HEAD"))?
-            .trim_start_matches("ref: ")
-            .trim()
-            .to_string();
-        Ok(head_ref)
+        match self.refs.read("HEAD")? {
+            Some(RefValue::Symbolic(target) | RefValue::Direct(target)) => Ok(target),
+            None => Err(RepoError::RefNotFound("HEAD".to_string())),
+        }
     }
 
-    /// Returns the root directory of the repository.
+    /// Returns the root directory of the working tree.
     pub fn get_root(&self) -> &Path {
-        self.dir.parent().unwrap_or(Path::new("."))
+        &self.root
     }
 
-    /// Returns the path of the repository (`.git` directory)
+    /// Returns the path of the repository (`.git` directory), or of the git
+    /// directory of a linked worktree
     pub fn get_path(&self) -> &Path {
         self.dir.as_path()
     }
+
+    /// Returns the directory shared by all worktrees, which has the objects,
+    /// the refs and the config
+    pub fn get_common_path(&self) -> &Path {
+        self.common_dir.as_path()
+    }
+}
+
+/// Creates the objects directory, the refs and HEAD of a new git
+/// directory.
+fn create_git_dir(dir: &Path, format: RefFormat) -> Result<()> {
+    fs::create_dir_all(dir.join("objects"))?;
+
+    match format {
+        RefFormat::Files => {
+            fs::create_dir_all(dir.join("refs/heads"))?;
+            fs::create_dir_all(dir.join("refs/tags"))?;
+            fs::write(dir.join("HEAD"), "ref: refs/heads/main\n")?;
+        }
+        RefFormat::Reftable => {
+            ReftableStore::create(dir)?;
+            let mut config = Config::load(dir)?;
+            config.set("core.repositoryformatversion", "1")?;
+            config.set("extensions.refStorage", "reftable")?;
+            config.save()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a file, creating its parent directories if needed.
+fn write_creating_parents(path: &Path, content: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(fs::write(path, content)?)
+}
+
+/// Removes a file and then its parent directories up to (but excluding)
+/// `stop`, for as long as they are empty.
+fn remove_file_and_empty_parents(path: &Path, stop: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => Err(e)?,
+    }
+
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if d == stop || !d.starts_with(stop) || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -127,13 +432,112 @@ mod tests {
         );
     }
 
+    const COMMIT: &str = "3b18e512dba79e4c8300dd08aeb37f8e728b8dad";
+
     #[test]
     fn test_create_branch() {
-        //let temp_dir = tempdir().unwrap();
-        //let temp_dir_path = temp_dir.path().to_path_buf();
-        //let repo = Repository::init(&temp_dir_path).unwrap();
-        //
-        //repo.create_branch("test").unwrap();
-        //assert!(repo.branch_exists("test").unwrap());
+        let temp_dir = tempdir().unwrap();
+        let temp_dir_path = temp_dir.path().to_path_buf();
+        let repo = Repository::init(&temp_dir_path).unwrap();
+        repo.set_ref("refs/heads/main", COMMIT).unwrap();
+
+        repo.create_branch("test").unwrap();
+        assert!(repo.branch_exists("test").unwrap());
+    }
+
+    #[test]
+    fn test_hierarchical_branches() {
+        let temp_dir = tempdir().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        repo.set_ref("refs/heads/main", COMMIT).unwrap();
+
+        repo.create_branch("feature/login").unwrap();
+        assert!(repo.branch_exists("feature/login").unwrap());
+        assert!(!repo.branch_exists("feature").unwrap());
+        repo.switch_branch("feature/login").unwrap();
+        assert_eq!(repo.get_head().unwrap(), "refs/heads/feature/login");
+
+        // `feature` is a directory, `feature/login/x` would be below a file
+        assert!(matches!(
+            repo.create_branch("feature"),
+            Err(RepoError::RefConflict { .. })
+        ));
+        assert!(matches!(
+            repo.create_branch("feature/login/x"),
+            Err(RepoError::RefConflict { .. })
+        ));
+        assert!(matches!(
+            repo.create_branch("feature/../main"),
+            Err(RepoError::InvalidBranchName(_))
+        ));
+
+        repo.switch_branch("main").unwrap();
+        repo.delete_branch("feature/login").unwrap();
+        assert!(!repo.dir.join("refs/heads/feature").exists());
+        assert!(repo.dir.join("refs/heads").exists());
+        repo.create_branch("feature").unwrap();
+    }
+
+    #[test]
+    fn test_rename_branch() {
+        let temp_dir = tempdir().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        repo.set_ref("refs/heads/main", COMMIT).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set("branch.main.remote", "origin").unwrap();
+        config.save().unwrap();
+
+        repo.rename_branch("main", "main/old", false).unwrap();
+        assert!(!repo.branch_exists("main").unwrap());
+        assert_eq!(repo.get_head().unwrap(), "refs/heads/main/old");
+        assert_eq!(repo.get_ref("refs/heads/main/old").unwrap(), COMMIT);
+        assert!(repo.has_reflog("refs/heads/main/old").unwrap());
+        assert_eq!(
+            repo.config().unwrap().get("branch.main/old.remote"),
+            Some("origin")
+        );
+
+        repo.copy_branch("main/old", "copy", false).unwrap();
+        assert_eq!(repo.get_ref("refs/heads/copy").unwrap(), COMMIT);
+        assert!(repo.branch_exists("main/old").unwrap());
+        assert!(matches!(
+            repo.rename_branch("copy", "main/old", false),
+            Err(RepoError::BranchAlreadyExists(_))
+        ));
+        repo.rename_branch("copy", "main/old", true).unwrap();
+        assert!(!repo.branch_exists("copy").unwrap());
+    }
+
+    #[test]
+    fn test_reftable_branches() {
+        let temp_dir = tempdir().unwrap();
+        Repository::init_with_format(temp_dir.path(), RefFormat::Reftable).unwrap();
+        let repo = Repository::from_path(temp_dir.path()).unwrap();
+        assert_eq!(repo.get_head().unwrap(), "refs/heads/main");
+        assert!(!repo.dir.join("refs/heads").is_dir());
+
+        repo.update_ref("HEAD", COMMIT, "commit (initial): test")
+            .unwrap();
+        repo.create_branch("feature/login").unwrap();
+        assert!(matches!(
+            repo.create_branch("feature"),
+            Err(RepoError::RefConflict { .. })
+        ));
+        assert_eq!(
+            repo.list_refs("refs/heads/").unwrap(),
+            [
+                ("refs/heads/feature/login".to_string(), COMMIT.to_string()),
+                ("refs/heads/main".to_string(), COMMIT.to_string()),
+            ]
+        );
+
+        repo.rename_branch("main", "trunk", false).unwrap();
+        assert_eq!(repo.get_head().unwrap(), "refs/heads/trunk");
+        assert_eq!(repo.read_reflog("refs/heads/trunk").unwrap().len(), 2);
+        assert!(!repo.has_reflog("refs/heads/main").unwrap());
+
+        repo.delete_branch("feature/login").unwrap();
+        assert!(!repo.branch_exists("feature/login").unwrap());
+        repo.create_branch("feature").unwrap();
     }
 }
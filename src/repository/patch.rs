@@ -0,0 +1,723 @@
+//! Applying parts of the changes to files, as `add -p`, `reset -p` and
+//! `restore -p` do. The changes between two versions of a file are split
+//! into hunks to select, which can be split further or edited. The
+//! selected hunks are applied to one version, or undone in the other, and
+//! the result is written to the index and/or the working tree.
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, Write},
+};
+
+use crate::{
+    diff::{diff, function_name, hunk_range, is_binary, lines, unified},
+    object::{
+        tree::{file_mode, Tree, MODE_GITLINK},
+        Object,
+    },
+    pathspec::match_pathspec,
+};
+
+use super::{
+    checkout::{remove_path, write_file},
+    error::Result,
+    index::{is_missing, Index, IndexEntry},
+    Repository,
+};
+
+const CONTEXT: usize = 3;
+
+/// A version of a file: its mode and content.
+pub type FileVersion = (u32, Vec<u8>);
+
+/// What a patch session compares, and where the selected hunks go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatchMode<'a> {
+    /// Stage changes of the working tree in the index
+    Stage,
+    /// Reset the index to a tree, HEAD's if `head`
+    Reset { tree: &'a str, head: bool },
+    /// Restore the working tree, and the index if `index`, from a tree or
+    /// from the index
+    Restore {
+        tree: Option<&'a str>,
+        head: bool,
+        index: bool,
+    },
+}
+
+impl PatchMode<'_> {
+    /// Returns true if the changes are shown from the target to the source,
+    /// to be applied, rather than from the source to the target, to be
+    /// undone. That is the case when resetting or restoring from a tree
+    /// other than HEAD's.
+    pub fn reverse_diff(&self) -> bool {
+        matches!(
+            self,
+            PatchMode::Reset { head: false, .. }
+                | PatchMode::Restore {
+                    tree: Some(_),
+                    head: false,
+                    ..
+                }
+        )
+    }
+
+    /// Returns true if selected hunks are undone rather than applied.
+    pub fn reverse_apply(&self) -> bool {
+        *self != PatchMode::Stage && !self.reverse_diff()
+    }
+
+    fn writes_index(&self) -> bool {
+        !matches!(self, PatchMode::Restore { index: false, .. })
+    }
+
+    fn writes_worktree(&self) -> bool {
+        matches!(self, PatchMode::Restore { .. })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HunkKind {
+    ModeChange,
+    /// The whole file is deleted
+    Deletion,
+    /// The whole file is added
+    Addition,
+    /// Some lines change
+    Lines,
+}
+
+/// A hunk of a file's changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatchHunk {
+    pub kind: HunkKind,
+    /// The first line of the hunk in the old and the new version, from 0
+    old_start: usize,
+    new_start: usize,
+    /// The lines of the hunk with their ` `, `-` or `+` prefix, without a
+    /// line terminator at the end of a file that has none
+    lines: Vec<Vec<u8>>,
+    pub selected: bool,
+}
+
+impl PatchHunk {
+    /// Returns the number of lines of the hunk in the old and the new
+    /// version.
+    fn len(&self) -> (usize, usize) {
+        let count = |tag: u8| {
+            self.lines
+                .iter()
+                .filter(|line| line[0] == b' ' || line[0] == tag)
+                .count()
+        };
+        (count(b'-'), count(b'+'))
+    }
+
+    /// Returns the lines of the version the hunk is applied to, `-` lines
+    /// and context for the old one, `+` lines and context for the new one.
+    fn base_lines(&self, reverse: bool) -> impl Iterator<Item = &[u8]> {
+        let tag = if reverse { b'+' } else { b'-' };
+        self.lines
+            .iter()
+            .filter(move |line| line[0] == b' ' || line[0] == tag)
+            .map(|line| &line[1..])
+    }
+
+    /// Returns the hunks the hunk splits into, at every run of context
+    /// lines between changes. The context between two hunks is part of
+    /// both.
+    fn split(&self) -> Vec<PatchHunk> {
+        let mut hunks: Vec<PatchHunk> = vec![];
+        let (mut old, mut new) = (self.old_start, self.new_start);
+        let mut i = 0;
+        while i < self.lines.len() {
+            let context = i;
+            while i < self.lines.len() && self.lines[i][0] == b' ' {
+                i += 1;
+            }
+            if let Some(last) = hunks.last_mut() {
+                last.lines.extend_from_slice(&self.lines[context..i]);
+            }
+            if i == self.lines.len() {
+                break;
+            }
+
+            let (start_old, start_new) = (old, new);
+            (old, new) = (old + i - context, new + i - context);
+            while i < self.lines.len() && self.lines[i][0] != b' ' {
+                match self.lines[i][0] {
+                    b'-' => old += 1,
+                    _ => new += 1,
+                }
+                i += 1;
+            }
+            hunks.push(PatchHunk {
+                kind: HunkKind::Lines,
+                old_start: start_old,
+                new_start: start_new,
+                lines: self.lines[context..i].to_vec(),
+                selected: self.selected,
+            });
+        }
+        hunks
+    }
+}
+
+/// The result of editing a hunk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edit {
+    /// The edited hunk replaces the hunk, and is selected
+    Applied,
+    /// All lines were removed, the hunk is left as it was
+    Empty,
+    /// The edited hunk doesn't apply to the version it's for
+    DoesNotApply,
+}
+
+/// The changes to a file, split into hunks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePatch {
+    pub path: String,
+    /// The version the changes are shown from, `None` if the file is added
+    pub old: Option<FileVersion>,
+    /// The version the changes are shown to, `None` if the file is deleted
+    pub new: Option<FileVersion>,
+    /// Whether the changes are shown from `b/` to `a/`, see
+    /// [`PatchMode::reverse_diff`]
+    swapped: bool,
+    /// Whether the file is binary, which has no hunks
+    pub binary: bool,
+    pub hunks: Vec<PatchHunk>,
+}
+
+impl FilePatch {
+    /// Returns the changes between two versions of a file.
+    pub fn new(
+        path: &str,
+        old: Option<FileVersion>,
+        new: Option<FileVersion>,
+        swapped: bool,
+    ) -> FilePatch {
+        let content = |version: &Option<FileVersion>| {
+            version
+                .as_ref()
+                .map_or(vec![], |(_, content)| content.clone())
+        };
+        let (old_content, new_content) = (content(&old), content(&new));
+        let binary = is_binary(&old_content) || is_binary(&new_content);
+
+        let mut hunks = vec![];
+        if let (Some((old_mode, _)), Some((new_mode, _))) = (&old, &new) {
+            if old_mode != new_mode {
+                hunks.push(PatchHunk {
+                    kind: HunkKind::ModeChange,
+                    old_start: 0,
+                    new_start: 0,
+                    lines: vec![],
+                    selected: false,
+                });
+            }
+        }
+        if !binary {
+            let kind = match (&old, &new) {
+                (None, _) => HunkKind::Addition,
+                (_, None) => HunkKind::Deletion,
+                _ => HunkKind::Lines,
+            };
+            let (old_lines, new_lines) = (lines(&old_content), lines(&new_content));
+            let changes = unified(&diff(&old_lines, &new_lines), old_lines.len(), CONTEXT);
+            for hunk in &changes {
+                let mut lines = vec![];
+                let mut pos = hunk.old.start;
+                let mut push = |tag: u8, content: &[&[u8]]| {
+                    lines.extend(content.iter().map(|line| [&[tag], *line].concat()))
+                };
+                for change in &hunk.changes {
+                    push(b' ', &old_lines[pos..change.old.start]);
+                    push(b'-', &old_lines[change.old.clone()]);
+                    push(b'+', &new_lines[change.new.clone()]);
+                    pos = change.old.end;
+                }
+                push(b' ', &old_lines[pos..hunk.old.end]);
+                hunks.push(PatchHunk {
+                    kind,
+                    old_start: hunk.old.start,
+                    new_start: hunk.new.start,
+                    lines,
+                    selected: false,
+                });
+            }
+            // An empty file that is added or deleted has no lines
+            if changes.is_empty() && kind != HunkKind::Lines {
+                hunks.push(PatchHunk {
+                    kind,
+                    old_start: 0,
+                    new_start: 0,
+                    lines: vec![],
+                    selected: false,
+                });
+            }
+        }
+
+        FilePatch {
+            path: path.to_string(),
+            old,
+            new,
+            swapped,
+            binary,
+            hunks,
+        }
+    }
+
+    fn names(&self) -> (String, String) {
+        let (a, b) = if self.swapped { ("b", "a") } else { ("a", "b") };
+        (format!("{a}/{}", self.path), format!("{b}/{}", self.path))
+    }
+
+    fn abbrev(version: &Option<FileVersion>) -> String {
+        match version {
+            Some((_, content)) => {
+                let hash = Object::blob_from_bytes(content)
+                    .write(io::sink())
+                    .unwrap_or_default();
+                hash[..7.min(hash.len())].to_string()
+            }
+            None => "0000000".to_string(),
+        }
+    }
+
+    /// Writes the header of the file's diff. For an added or deleted file
+    /// it is only the `diff --git` line, the rest is part of its hunk.
+    pub fn write_header(&self, out: &mut impl Write) -> io::Result<()> {
+        let (a, b) = self.names();
+        writeln!(out, "diff --git {a} {b}")?;
+        let (Some((old_mode, old)), Some((new_mode, new))) = (&self.old, &self.new) else {
+            return Ok(());
+        };
+        if old == new {
+            return Ok(());
+        }
+        let (old_hash, new_hash) = (Self::abbrev(&self.old), Self::abbrev(&self.new));
+        match old_mode == new_mode {
+            true => writeln!(out, "index {old_hash}..{new_hash} {new_mode:o}")?,
+            false => writeln!(out, "index {old_hash}..{new_hash}")?,
+        }
+        writeln!(out, "--- {a}\n+++ {b}")
+    }
+
+    /// Writes the `i`th hunk.
+    pub fn write_hunk(&self, i: usize, out: &mut impl Write) -> io::Result<()> {
+        let hunk = &self.hunks[i];
+        let (a, b) = self.names();
+        let mode = |version: &Option<FileVersion>| version.as_ref().map_or(0, |(mode, _)| *mode);
+        let (old_hash, new_hash) = (Self::abbrev(&self.old), Self::abbrev(&self.new));
+        match hunk.kind {
+            HunkKind::ModeChange => {
+                writeln!(out, "old mode {:o}", mode(&self.old))?;
+                return writeln!(out, "new mode {:o}", mode(&self.new));
+            }
+            HunkKind::Deletion => {
+                writeln!(out, "deleted file mode {:o}", mode(&self.old))?;
+                writeln!(out, "index {old_hash}..{new_hash}")?;
+                if !hunk.lines.is_empty() {
+                    writeln!(out, "--- {a}\n+++ /dev/null")?;
+                }
+            }
+            HunkKind::Addition => {
+                writeln!(out, "new file mode {:o}", mode(&self.new))?;
+                writeln!(out, "index {old_hash}..{new_hash}")?;
+                if !hunk.lines.is_empty() {
+                    writeln!(out, "--- /dev/null\n+++ {b}")?;
+                }
+            }
+            HunkKind::Lines => {}
+        }
+        if hunk.lines.is_empty() {
+            return Ok(());
+        }
+
+        let old_content = self.old.as_ref().map_or(&[][..], |(_, c)| c);
+        let (old_len, new_len) = hunk.len();
+        let name = function_name(&lines(old_content)[..hunk.old_start])
+            .map(|name| format!(" {name}"))
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "@@ -{} +{} @@{name}",
+            hunk_range(&(hunk.old_start..hunk.old_start + old_len)),
+            hunk_range(&(hunk.new_start..hunk.new_start + new_len)),
+        )?;
+        for line in &hunk.lines {
+            out.write_all(line)?;
+            if !line.ends_with(b"\n") {
+                out.write_all(b"\n\\ No newline at end of file\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the `i`th hunk can be split into smaller hunks.
+    pub fn can_split(&self, i: usize) -> bool {
+        self.hunks[i].kind == HunkKind::Lines && self.hunks[i].split().len() > 1
+    }
+
+    /// Splits the `i`th hunk into smaller hunks, returning how many.
+    pub fn split(&mut self, i: usize) -> usize {
+        let hunks = self.hunks[i].split();
+        let count = hunks.len();
+        self.hunks.splice(i..=i, hunks);
+        count
+    }
+
+    /// Returns true if the `i`th hunk can be edited.
+    pub fn can_edit(&self, i: usize) -> bool {
+        self.hunks[i].kind == HunkKind::Lines
+    }
+
+    /// Replaces the `i`th hunk by an edited version of what
+    /// [`FilePatch::write_hunk`] wrote, selecting it. Lines starting with
+    /// `#` are left out. The edited hunk has to apply to the version it's
+    /// applied to, see [`PatchMode::reverse_apply`].
+    pub fn edit(&mut self, i: usize, text: &[u8], reverse: bool) -> Edit {
+        let mut edited: Vec<Vec<u8>> = vec![];
+        for line in text.split_inclusive(|&b| b == b'\n') {
+            match line.first() {
+                Some(b'#') => {}
+                Some(b'@') if line.starts_with(b"@@") => {}
+                Some(b'\\') => {
+                    if let Some(last) = edited.last_mut() {
+                        if last.ends_with(b"\n") {
+                            last.pop();
+                        }
+                    }
+                }
+                Some(b' ' | b'-' | b'+') => edited.push(line.to_vec()),
+                // Editors may strip the space of an empty context line
+                Some(b'\n') => edited.push(b" \n".to_vec()),
+                _ => return Edit::DoesNotApply,
+            }
+        }
+        if edited.is_empty() {
+            return Edit::Empty;
+        }
+
+        let hunk = PatchHunk {
+            lines: edited,
+            selected: true,
+            ..self.hunks[i].clone()
+        };
+        let (base, start) = match reverse {
+            false => (&self.old, hunk.old_start),
+            true => (&self.new, hunk.new_start),
+        };
+        let base = lines(base.as_ref().map_or(&[][..], |(_, c)| c));
+        let base = &base[start.min(base.len())..];
+        let matches = hunk.base_lines(reverse).count() <= base.len()
+            && hunk.base_lines(reverse).zip(base).all(|(a, b)| a == *b);
+        if !matches {
+            return Edit::DoesNotApply;
+        }
+        self.hunks[i] = hunk;
+        Edit::Applied
+    }
+
+    /// Returns true if any hunk is selected.
+    pub fn is_selected(&self) -> bool {
+        self.hunks.iter().any(|hunk| hunk.selected)
+    }
+
+    /// Returns the version of the file with the selected hunks applied to
+    /// the old version, or undone in the new one if `reverse`. `None` if
+    /// the file is deleted.
+    pub fn result(&self, reverse: bool) -> Option<FileVersion> {
+        // Whether each part of the file is taken from the new version
+        let take_new = |hunk: &PatchHunk| hunk.selected != reverse;
+        let (old_mode, new_mode) = (
+            self.old.as_ref().map(|(mode, _)| *mode),
+            self.new.as_ref().map(|(mode, _)| *mode),
+        );
+        let mut mode = match reverse {
+            false => old_mode.or(new_mode),
+            true => new_mode.or(old_mode),
+        }?;
+
+        for hunk in &self.hunks {
+            match hunk.kind {
+                HunkKind::ModeChange => {
+                    mode = match take_new(hunk) {
+                        true => new_mode?,
+                        false => old_mode?,
+                    }
+                }
+                HunkKind::Deletion if take_new(hunk) => return None,
+                HunkKind::Deletion => return self.old.clone(),
+                HunkKind::Addition if take_new(hunk) => return self.new.clone(),
+                HunkKind::Addition => return None,
+                HunkKind::Lines => {}
+            }
+        }
+
+        // The hunks are applied to one version, or undone in the other
+        let (base, other_tag) = match reverse {
+            false => (&self.old, b'+'),
+            true => (&self.new, b'-'),
+        };
+        let base = base.as_ref().map_or(&[][..], |(_, c)| c);
+        let base = lines(base);
+        let mut content = vec![];
+        let mut pos = 0;
+        for hunk in self.hunks.iter().filter(|h| h.kind == HunkKind::Lines) {
+            let start = if reverse {
+                hunk.new_start
+            } else {
+                hunk.old_start
+            };
+            if start > pos {
+                content.extend(base[pos..start].concat());
+                pos = start;
+            }
+            // Context shared with the previous hunk is there already
+            let mut line_no = start;
+            for line in &hunk.lines {
+                let (tag, text) = (line[0], &line[1..]);
+                if tag == other_tag {
+                    if hunk.selected {
+                        content.extend_from_slice(text);
+                    }
+                    continue;
+                }
+                if line_no >= pos && (tag == b' ' || !hunk.selected) {
+                    content.extend_from_slice(text);
+                }
+                line_no += 1;
+            }
+            pos = pos.max(line_no);
+        }
+        content.extend(base[pos.min(base.len())..].concat());
+
+        Some((mode, content))
+    }
+}
+
+impl Repository {
+    /// Returns the changes of the files matching `pathspecs`, or of all
+    /// files if there are none, as shown by a patch session. Submodules
+    /// and files with conflicts are left out.
+    pub fn patch_files(&self, mode: PatchMode, pathspecs: &[String]) -> Result<Vec<FilePatch>> {
+        let convert = self.convert()?;
+        let index = Index::load(self)?;
+        let indexed = index.files();
+
+        // The source of the changes, and whether their target is the index
+        let (source, target_index) = match mode {
+            PatchMode::Stage => (indexed.clone(), false),
+            PatchMode::Reset { tree, .. } => (Tree::read_recursive(tree, self)?, true),
+            PatchMode::Restore { tree: None, .. } => (indexed.clone(), false),
+            PatchMode::Restore {
+                tree: Some(tree), ..
+            } => (Tree::read_recursive(tree, self)?, false),
+        };
+        let unmerged: BTreeSet<&str> = index
+            .entries
+            .iter()
+            .filter(|e| e.stage != 0)
+            .map(|e| e.path.as_str())
+            .collect();
+        let paths: BTreeSet<&String> = source.keys().chain(indexed.keys()).collect();
+        let matches = |path: &str| {
+            pathspecs.is_empty() || pathspecs.iter().any(|s| match_pathspec(s, path).is_some())
+        };
+
+        let read_blob = |(mode, hash): &(u32, String)| -> Result<FileVersion> {
+            Ok((*mode, Object::read_blob(hash, self)?))
+        };
+        let mut files = vec![];
+        for path in paths {
+            if !matches(path) || unmerged.contains(path.as_str()) {
+                continue;
+            }
+            let from = source.get(path);
+            let to = match target_index {
+                true => indexed.get(path).map(read_blob).transpose()?,
+                false => {
+                    let meta = match fs::symlink_metadata(self.get_root().join(path)) {
+                        Ok(meta) => Some(meta),
+                        Err(e) if is_missing(&e) => None,
+                        Err(e) => Err(e)?,
+                    };
+                    // Files that are missing outside a sparse checkout are unchanged
+                    let skipped = index.get(path).is_some_and(|e| e.skip_worktree());
+                    match meta {
+                        None if skipped => continue,
+                        None => None,
+                        Some(meta) if meta.is_dir() => continue,
+                        Some(meta) => {
+                            let unchanged = index.get(path).is_some_and(|e| {
+                                Some(&(e.mode, e.hash.clone())) == from
+                                    && e.stat_matches(&meta)
+                                    && !index.is_racy(e)
+                            });
+                            if unchanged {
+                                continue;
+                            }
+                            Some((file_mode(&meta), convert.read_file(path, &meta, false)?))
+                        }
+                    }
+                }
+            };
+            let from = from.map(read_blob).transpose()?;
+            let is_gitlink =
+                |v: &Option<FileVersion>| v.as_ref().is_some_and(|v| v.0 == MODE_GITLINK);
+            if from == to || is_gitlink(&from) || is_gitlink(&to) {
+                continue;
+            }
+
+            files.push(match mode.reverse_diff() {
+                true => FilePatch::new(path, to, from, true),
+                false => FilePatch::new(path, from, to, false),
+            });
+        }
+
+        Ok(files)
+    }
+
+    /// Writes the files with selected hunks where `mode` says.
+    pub fn apply_patches(&self, mode: PatchMode, files: &[FilePatch]) -> Result<()> {
+        let files: Vec<&FilePatch> = files.iter().filter(|f| f.is_selected()).collect();
+        if files.is_empty() {
+            return Ok(());
+        }
+        let root = self.get_root();
+        let convert = self.convert()?;
+        let mut index = Index::lock(self)?;
+
+        for file in files {
+            let path = &file.path;
+            let result = file.result(mode.reverse_apply());
+            let Some((new_mode, content)) = result else {
+                if mode.writes_worktree() {
+                    remove_path(root, path)?;
+                }
+                if mode.writes_index() {
+                    index.remove(path);
+                }
+                continue;
+            };
+
+            let blob = Object::blob_from_bytes(&content);
+            let hash = match mode.writes_index() {
+                true => blob.write_to_objects(self)?,
+                false => blob.write(io::sink())?,
+            };
+            if mode.writes_worktree() {
+                let entry = write_file(root, path, new_mode, &hash, &content, &convert)?;
+                if mode.writes_index() {
+                    index.add(entry);
+                }
+            } else {
+                let mut entry = IndexEntry::new(path, new_mode, &hash);
+                // Keep the stat data if the file matches the new entry
+                if let Some(old) = index.get(path) {
+                    if let Ok(meta) = fs::symlink_metadata(root.join(path)) {
+                        if old.mode == new_mode && old.hash == hash && old.stat_matches(&meta) {
+                            entry.update_stat(&meta);
+                        }
+                    }
+                }
+                index.add(entry);
+            }
+        }
+
+        index.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Commit;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_file_patch() {
+        let old: Vec<u8> = (1..=20)
+            .map(|i| format!("{i}\n"))
+            .collect::<String>()
+            .into();
+        let new = String::from_utf8(old.clone())
+            .unwrap()
+            .replace("\n2\n", "\ntwo\n")
+            .replace("\n4\n", "\nfour\n")
+            .replace("\n18\n", "\neighteen\n");
+        let mut patch = FilePatch::new(
+            "f",
+            Some((0o100644, old.clone())),
+            Some((0o100644, new.clone().into())),
+            false,
+        );
+        assert_eq!(patch.hunks.len(), 2);
+        assert!(patch.can_split(0) && !patch.can_split(1));
+        assert_eq!(patch.split(0), 2);
+
+        let mut out = vec![];
+        patch.write_hunk(1, &mut out).unwrap();
+        assert_eq!(out, b"@@ -3,5 +3,5 @@\n 3\n-4\n+four\n 5\n 6\n 7\n");
+
+        // Applying the second and third hunk, or undoing the first
+        patch.hunks[1].selected = true;
+        patch.hunks[2].selected = true;
+        let expected = new.replace("two", "2");
+        assert_eq!(patch.result(false).unwrap().1, expected.as_bytes());
+        patch
+            .hunks
+            .iter_mut()
+            .for_each(|h| h.selected = !h.selected);
+        assert_eq!(patch.result(true).unwrap().1, expected.as_bytes());
+
+        // An edited hunk has to apply
+        let edit = b"@@ -15,6 +15,6 @@\n 15\n 16\n 17\n-18\n+EIGHTEEN\n 19\n 20\n";
+        assert_eq!(patch.edit(2, edit, false), Edit::Applied);
+        assert!(patch
+            .result(false)
+            .unwrap()
+            .1
+            .ends_with(b"17\nEIGHTEEN\n19\n20\n"));
+        assert_eq!(patch.edit(2, b" 15\n-17\n", false), Edit::DoesNotApply);
+        assert_eq!(patch.edit(2, b"# nothing\n", false), Edit::Empty);
+
+        let patch = FilePatch::new("f", Some((0o100644, old)), None, false);
+        assert_eq!(patch.hunks[0].kind, HunkKind::Deletion);
+        assert_eq!(patch.result(false), patch.old);
+    }
+
+    #[test]
+    fn test_stage_hunk() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+        let old: String = (1..=20).map(|i| format!("{i}\n")).collect();
+        fs::write(root.join("f"), &old).unwrap();
+        repo.add_paths(&["f".to_string()], None).unwrap();
+        repo.commit("one").unwrap();
+
+        // Only the first of the two hunks is staged, and committed
+        let new = old
+            .replace("\n2\n", "\ntwo\n")
+            .replace("\n18\n", "\neighteen\n");
+        fs::write(root.join("f"), &new).unwrap();
+        let mut files = repo.patch_files(PatchMode::Stage, &[]).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].hunks.len(), 2);
+        files[0].hunks[0].selected = true;
+        repo.apply_patches(PatchMode::Stage, &files).unwrap();
+        let commit = repo.commit("two").unwrap();
+
+        let tree = Commit::read(&commit, &repo).unwrap().tree;
+        let (_, hash) = &Tree::read_recursive(&tree, &repo).unwrap()["f"];
+        let committed = Object::read_blob(hash, &repo).unwrap();
+        assert_eq!(committed, old.replace("\n2\n", "\ntwo\n").as_bytes());
+        assert_eq!(fs::read_to_string(root.join("f")).unwrap(), new);
+    }
+}
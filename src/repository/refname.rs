@@ -0,0 +1,136 @@
+/// Options for [`check_ref_format`], mirroring the flags of `git check-ref-format`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RefFormatOptions {
+    /// Allow a refname with only one component (e.g. `HEAD` or `main`).
+    pub allow_onelevel: bool,
+    /// Allow a single `*` to be used as a component wildcard.
+    pub refspec_pattern: bool,
+    /// Collapse consecutive slashes and strip a leading slash before checking.
+    pub normalize: bool,
+}
+
+/// Checks a refname against git's `check-ref-format` rules, returning the
+/// (possibly normalized) refname if it is valid.
+pub fn check_ref_format(name: &str, opts: RefFormatOptions) -> Option<String> {
+    let name = if opts.normalize {
+        let collapsed: Vec<&str> = name.split('/').filter(|c| !c.is_empty()).collect();
+        collapsed.join("/")
+    } else {
+        name.to_string()
+    };
+
+    if name.is_empty() || name == "@" {
+        return None;
+    }
+    if name.starts_with('/') || name.ends_with('/') || name.ends_with('.') {
+        return None;
+    }
+    if name.contains("..") || name.contains("@{") || name.contains("//") {
+        return None;
+    }
+
+    let mut wildcards = 0;
+    for c in name.chars() {
+        match c {
+            '\0'..='\x1f' | '\x7f' | ' ' | '~' | '^' | ':' | '?' | '[' | '\\' => return None,
+            '*' => wildcards += 1,
+            _ => {}
+        }
+    }
+    if wildcards > usize::from(opts.refspec_pattern) {
+        return None;
+    }
+
+    let components: Vec<&str> = name.split('/').collect();
+    if components.len() < 2 && !opts.allow_onelevel {
+        return None;
+    }
+    for component in components {
+        if component.starts_with('.') || component.ends_with(".lock") {
+            return None;
+        }
+    }
+
+    Some(name)
+}
+
+/// Returns true if `name` can be used as a branch name (`refs/heads/<name>`).
+pub fn is_valid_branch_name(name: &str) -> bool {
+    if name.starts_with('-') || name == "HEAD" {
+        return false;
+    }
+    check_ref_format(&format!("refs/heads/{name}"), RefFormatOptions::default()).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(name: &str) -> bool {
+        check_ref_format(name, RefFormatOptions::default()).is_some()
+    }
+
+    #[test]
+    fn test_valid_refnames() {
+        assert!(check("refs/heads/main"));
+        assert!(check("refs/heads/feature/login"));
+        assert!(check("refs/tags/v1.0"));
+        assert!(check("heads/foo.bar"));
+    }
+
+    #[test]
+    fn test_invalid_refnames() {
+        assert!(!check("main"));
+        assert!(!check("refs/heads/.hidden"));
+        assert!(!check("refs/heads/foo.lock"));
+        assert!(!check("refs/heads/a..b"));
+        assert!(!check("refs/heads/a b"));
+        assert!(!check("refs/heads/a~1"));
+        assert!(!check("refs/heads/a^"));
+        assert!(!check("refs/heads/a:b"));
+        assert!(!check("refs/heads/a?"));
+        assert!(!check("refs/heads/a*"));
+        assert!(!check("refs/heads/a[b"));
+        assert!(!check("refs/heads/a\\b"));
+        assert!(!check("refs/heads/a@{1}"));
+        assert!(!check("refs/heads/foo/"));
+        assert!(!check("/refs/heads/foo"));
+        assert!(!check("refs//heads/foo"));
+        assert!(!check("refs/heads/foo."));
+        assert!(!check("@"));
+    }
+
+    #[test]
+    fn test_options() {
+        let onelevel = RefFormatOptions {
+            allow_onelevel: true,
+            ..Default::default()
+        };
+        assert_eq!(check_ref_format("main", onelevel), Some("main".to_string()));
+
+        let pattern = RefFormatOptions {
+            refspec_pattern: true,
+            ..Default::default()
+        };
+        assert!(check_ref_format("refs/heads/*", pattern).is_some());
+        assert!(check_ref_format("refs/*/*", pattern).is_none());
+
+        let normalize = RefFormatOptions {
+            normalize: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            check_ref_format("/refs//heads/foo", normalize),
+            Some("refs/heads/foo".to_string())
+        );
+    }
+
+    #[test]
+    fn test_branch_names() {
+        assert!(is_valid_branch_name("feature/login"));
+        assert!(is_valid_branch_name("main"));
+        assert!(!is_valid_branch_name("-main"));
+        assert!(!is_valid_branch_name("HEAD"));
+        assert!(!is_valid_branch_name("feature/"));
+    }
+}
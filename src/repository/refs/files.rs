@@ -0,0 +1,318 @@
+//! The files ref backend: every ref is a file below `.git/refs` (a loose
+//! ref) or a line in `.git/packed-refs`, reflogs live in `.git/logs`.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::object::Signature;
+
+use super::{
+    super::{
+        error::{RepoError, Result},
+        remove_file_and_empty_parents, write_creating_parents,
+    },
+    RefLock, RefStore, RefValue, RefWrite, ReflogEntry,
+};
+
+pub struct FilesStore {
+    dir: PathBuf,
+}
+
+impl FilesStore {
+    pub fn new(git_dir: &Path) -> FilesStore {
+        FilesStore {
+            dir: git_dir.to_path_buf(),
+        }
+    }
+
+    /// Returns the entries of `.git/packed-refs` as `(refname, hash)` pairs.
+    pub fn packed_refs(&self) -> Result<Vec<(String, String)>> {
+        let content = match fs::read_to_string(self.dir.join("packed-refs")) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => Err(e)?,
+        };
+
+        // format: "<hash> <refname>", with '#' header lines and '^' peeled tag lines
+        Ok(content
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+            .filter_map(|line| line.split_once(' '))
+            .map(|(hash, refname)| (refname.to_string(), hash.to_string()))
+            .collect())
+    }
+
+    /// Removes a ref from `.git/packed-refs`, if it's there.
+    fn remove_packed_ref(&self, refname: &str) -> Result<()> {
+        let path = self.dir.join("packed-refs");
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Ok(());
+        };
+        if !content
+            .lines()
+            .any(|line| line.ends_with(&format!(" {refname}")))
+        {
+            return Ok(());
+        }
+
+        let mut lines = vec![];
+        let mut removed = false;
+        for line in content.lines() {
+            if line.split_once(' ').map(|(_, name)| name) == Some(refname) {
+                removed = true;
+            } else if !(removed && line.starts_with('^')) {
+                removed = false;
+                lines.push(line);
+            }
+        }
+        fs::write(path, lines.join("\n") + "\n")?;
+
+        Ok(())
+    }
+
+    /// Returns the path of the reflog of the given ref.
+    fn reflog_path(&self, refname: &str) -> PathBuf {
+        self.dir.join("logs").join(refname)
+    }
+
+    /// Returns the directory empty parents are removed up to when deleting
+    /// a ref or reflog, e.g. `refs/heads` for `refs/heads/feature/login`.
+    fn namespace(refname: &str) -> &str {
+        match refname.match_indices('/').nth(1) {
+            Some((end, _)) if refname.starts_with("refs/") => &refname[..end],
+            _ => "",
+        }
+    }
+
+    fn write(&self, refname: &str, value: &RefValue, lock: &Path) -> Result<()> {
+        let content = match value {
+            RefValue::Direct(hash) => format!("{hash}\n"),
+            RefValue::Symbolic(target) => format!("ref: {target}\n"),
+        };
+        write_creating_parents(lock, content.as_bytes())?;
+
+        // Empty directories left behind by deleted refs are in the way
+        let path = self.dir.join(refname);
+        if path.is_dir() {
+            remove_empty_dirs(&path)?;
+        }
+        fs::rename(lock, path)?;
+
+        Ok(())
+    }
+
+    fn delete(&self, refname: &str) -> Result<()> {
+        self.remove_packed_ref(refname)?;
+        let namespace = Self::namespace(refname);
+        remove_file_and_empty_parents(&self.dir.join(refname), &self.dir.join(namespace))?;
+        self.delete_reflog(refname)
+    }
+
+    fn delete_reflog(&self, refname: &str) -> Result<()> {
+        let namespace = Self::namespace(refname);
+        remove_file_and_empty_parents(&self.reflog_path(refname), &self.reflog_path(namespace))
+    }
+
+    fn append_reflog(&self, refname: &str, entry: &ReflogEntry) -> Result<()> {
+        let path = self.reflog_path(refname);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut log = OpenOptions::new().create(true).append(true).open(path)?;
+        let ReflogEntry {
+            old,
+            new,
+            signature,
+            message,
+        } = entry;
+        match message.trim() {
+            "" => writeln!(log, "{old} {new} {signature}")?,
+            message => writeln!(log, "{old} {new} {signature}\t{message}")?,
+        }
+
+        Ok(())
+    }
+}
+
+impl RefStore for FilesStore {
+    fn read(&self, refname: &str) -> Result<Option<RefValue>> {
+        let path = self.dir.join(refname);
+        if !path.is_file() {
+            return Ok(self
+                .packed_refs()?
+                .into_iter()
+                .find(|(name, _)| name == refname)
+                .map(|(_, hash)| RefValue::Direct(hash)));
+        }
+
+        let content = fs::read_to_string(path)?;
+        Ok(Some(match content.trim().strip_prefix("ref: ") {
+            Some(target) => RefValue::Symbolic(target.to_string()),
+            None => RefValue::Direct(content.trim().to_string()),
+        }))
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<(String, RefValue)>> {
+        let mut refs: Vec<(String, RefValue)> = self
+            .packed_refs()?
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, hash)| (name, RefValue::Direct(hash)))
+            .collect();
+
+        // Only walk the directory the prefix points into
+        let start = match prefix.rfind('/') {
+            Some(end) if prefix.starts_with("refs/") => &prefix[..end],
+            _ => "refs",
+        };
+        let mut loose = vec![];
+        collect_loose_refs(&self.dir.join(start), &self.dir, &mut loose)?;
+        for refname in loose {
+            if !refname.starts_with(prefix) || refname.ends_with(".lock") {
+                continue;
+            }
+            if let Some(value) = self.read(&refname)? {
+                // Loose refs take precedence over packed refs
+                refs.retain(|(name, _)| name != &refname);
+                refs.push((refname, value));
+            }
+        }
+        refs.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Ok(refs)
+    }
+
+    fn lock(&self, refnames: &[String]) -> Result<Box<dyn RefLock + '_>> {
+        let mut lock = FilesLock {
+            store: self,
+            locks: vec![],
+        };
+        for refname in refnames {
+            lock.lock(refname)?;
+        }
+
+        Ok(Box::new(lock))
+    }
+
+    fn read_reflog(&self, refname: &str) -> Result<Vec<ReflogEntry>> {
+        match fs::read_to_string(self.reflog_path(refname)) {
+            Ok(content) => Ok(content.lines().filter_map(parse_reflog_line).collect()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    fn has_reflog(&self, refname: &str) -> Result<bool> {
+        Ok(self.reflog_path(refname).is_file())
+    }
+}
+
+/// Parses a reflog line: `<old> <new> <signature>\t<message>`.
+fn parse_reflog_line(line: &str) -> Option<ReflogEntry> {
+    let (info, message) = line.split_once('\t').unwrap_or((line, ""));
+    let (old, rest) = info.split_once(' ')?;
+    let (new, signature) = rest.split_once(' ')?;
+
+    Some(ReflogEntry {
+        old: old.to_string(),
+        new: new.to_string(),
+        signature: Signature::parse(signature).ok()?,
+        message: message.to_string(),
+    })
+}
+
+/// Refs locked by creating `<ref>.lock` next to them.
+struct FilesLock<'a> {
+    store: &'a FilesStore,
+    locks: Vec<(String, PathBuf)>,
+}
+
+impl FilesLock<'_> {
+    fn lock(&mut self, refname: &str) -> Result<PathBuf> {
+        if let Some((_, path)) = self.locks.iter().find(|(name, _)| name == refname) {
+            return Ok(path.clone());
+        }
+
+        let path = self.store.dir.join(format!("{refname}.lock"));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| RepoError::Other(format!("unable to create '{}': {e}", path.display())))?;
+        self.locks.push((refname.to_string(), path.clone()));
+
+        Ok(path)
+    }
+}
+
+impl RefLock for FilesLock<'_> {
+    fn commit(mut self: Box<Self>, writes: Vec<RefWrite>) -> Result<()> {
+        for write in writes {
+            match write {
+                RefWrite::Ref {
+                    refname,
+                    value: Some(value),
+                } => {
+                    let lock = self.lock(&refname)?;
+                    self.store.write(&refname, &value, &lock)?;
+                }
+                RefWrite::Ref {
+                    refname,
+                    value: None,
+                } => {
+                    // Release the lock first so it doesn't keep parent directories alive
+                    let lock = self.lock(&refname)?;
+                    fs::remove_file(lock)?;
+                    self.store.delete(&refname)?;
+                }
+                RefWrite::Log { refname, entry } => self.store.append_reflog(&refname, &entry)?,
+                RefWrite::DeleteLog { refname } => self.store.delete_reflog(&refname)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for FilesLock<'_> {
+    fn drop(&mut self) {
+        // Committed locks have been renamed into place already
+        for (_, path) in &self.locks {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Recursively collects the names of all loose refs below `dir`.
+fn collect_loose_refs(dir: &Path, git_dir: &Path, refs: &mut Vec<String>) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_loose_refs(&path, git_dir, refs)?;
+        } else if let Ok(name) = path.strip_prefix(git_dir) {
+            refs.push(name.to_string_lossy().to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes a directory tree that contains no files.
+fn remove_empty_dirs(dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            remove_empty_dirs(&path)?;
+        }
+    }
+    Ok(fs::remove_dir(dir)?)
+}
@@ -0,0 +1,511 @@
+//! Ref storage. [`RefStore`] is implemented by the classic files backend
+//! (loose refs plus `packed-refs`) and by the reftable backend, the
+//! [`Repository`] methods in this module work on top of either.
+
+pub mod files;
+pub mod reftable;
+pub mod worktree;
+
+use std::{fs, path::Path};
+
+use crate::object::{Commit, Object, ObjectType, Signature, Tag};
+
+use super::{
+    config::Config,
+    error::{RepoError, Result},
+    transaction::{RefChange, RefTransaction, RefUpdate},
+    Repository,
+};
+
+pub const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// An entry of a ref's reflog.
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub signature: Signature,
+    pub message: String,
+}
+
+/// The value stored in a ref.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefValue {
+    /// An object hash
+    Direct(String),
+    /// The name of another ref, e.g. `refs/heads/main` for HEAD
+    Symbolic(String),
+}
+
+/// A change applied by [`RefLock::commit`].
+#[derive(Debug, Clone)]
+pub enum RefWrite {
+    /// Sets a ref, or deletes it along with its reflog if the value is `None`
+    Ref {
+        refname: String,
+        value: Option<RefValue>,
+    },
+    /// Appends an entry to the reflog of a ref
+    Log { refname: String, entry: ReflogEntry },
+    /// Deletes the reflog of a ref
+    DeleteLog { refname: String },
+}
+
+/// A backend storing refs and their reflogs.
+pub trait RefStore: Send + Sync {
+    /// Returns the value of a ref without following symbolic refs, or
+    /// `None` if the ref doesn't exist.
+    fn read(&self, refname: &str) -> Result<Option<RefValue>>;
+
+    /// Returns all refs below `refs/` whose name starts with `prefix`,
+    /// sorted by name.
+    fn list(&self, prefix: &str) -> Result<Vec<(String, RefValue)>>;
+
+    /// Locks the given refs against concurrent writers. The lock is
+    /// released when it is committed or dropped.
+    fn lock(&self, refnames: &[String]) -> Result<Box<dyn RefLock + '_>>;
+
+    /// Returns the reflog of a ref, oldest entry first.
+    fn read_reflog(&self, refname: &str) -> Result<Vec<ReflogEntry>>;
+
+    /// Returns true if the ref has a reflog, even an empty one.
+    fn has_reflog(&self, refname: &str) -> Result<bool>;
+}
+
+/// Refs locked by [`RefStore::lock`].
+pub trait RefLock {
+    /// Applies all writes at once and releases the lock.
+    fn commit(self: Box<Self>, writes: Vec<RefWrite>) -> Result<()>;
+}
+
+/// The on-disk format refs are stored in, set by `extensions.refStorage`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RefFormat {
+    #[default]
+    Files,
+    Reftable,
+}
+
+impl RefFormat {
+    /// Returns the format configured for a repository.
+    pub fn from_config(config: &Config) -> Result<RefFormat> {
+        match config.get("extensions.refStorage") {
+            None | Some("files") => Ok(RefFormat::Files),
+            Some("reftable") => Ok(RefFormat::Reftable),
+            Some(format) => Err(RepoError::Config(format!(
+                "unknown ref storage format '{format}'"
+            ))),
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<RefFormat> {
+        match name {
+            "files" => Some(RefFormat::Files),
+            "reftable" => Some(RefFormat::Reftable),
+            _ => None,
+        }
+    }
+
+    /// Opens the ref store of the repository at `git_dir`.
+    pub(super) fn open(self, git_dir: &Path) -> Box<dyn RefStore> {
+        match self {
+            RefFormat::Files => Box::new(files::FilesStore::new(git_dir)),
+            RefFormat::Reftable => Box::new(reftable::ReftableStore::new(git_dir)),
+        }
+    }
+
+    /// Opens the ref store of a worktree with the git directory `git_dir`,
+    /// sharing all but the per-worktree refs with `common_dir`.
+    pub(super) fn open_worktree(self, git_dir: &Path, common_dir: &Path) -> Box<dyn RefStore> {
+        if git_dir == common_dir {
+            return self.open(git_dir);
+        }
+        Box::new(worktree::WorktreeStore::new(
+            self.open(git_dir),
+            self.open(common_dir),
+        ))
+    }
+}
+
+/// Returns whether `name` is a ref outside of `refs/` like `HEAD` or
+/// `ORIG_HEAD`, which are named in capitals.
+fn is_pseudo_ref(name: &str) -> bool {
+    name.ends_with("HEAD") && name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_')
+}
+
+impl Repository {
+    /// Returns the hash the given ref points to, following symbolic refs, or
+    /// `None` if the ref doesn't exist.
+    pub fn read_ref(&self, refname: &str) -> Result<Option<String>> {
+        let mut refname = refname.to_string();
+        // Guard against symbolic ref loops
+        for _ in 0..5 {
+            match self.refs.read(&refname)? {
+                Some(RefValue::Symbolic(target)) => refname = target,
+                Some(RefValue::Direct(hash)) => return Ok(Some(hash)),
+                None => return Ok(None),
+            }
+        }
+
+        Err(RepoError::Other(format!(
+            "symbolic ref loop at '{refname}'"
+        )))
+    }
+
+    /// Returns the target of a symbolic ref such as `HEAD`, or `None` if the
+    /// ref is not symbolic.
+    pub fn read_symbolic_ref(&self, refname: &str) -> Result<Option<String>> {
+        match self.refs.read(refname)? {
+            Some(RefValue::Symbolic(target)) => Ok(Some(target)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns all refs starting with the given prefix (e.g. `refs/heads/`)
+    /// as `(refname, hash)` pairs, sorted by refname. Symbolic refs are
+    /// resolved, dangling ones left out.
+    pub fn list_refs(&self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let mut refs = vec![];
+        for (refname, value) in self.refs.list(prefix)? {
+            let hash = match value {
+                RefValue::Direct(hash) => Some(hash),
+                RefValue::Symbolic(target) => self.read_ref(&target)?,
+            };
+            if let Some(hash) = hash {
+                refs.push((refname, hash));
+            }
+        }
+
+        Ok(refs)
+    }
+
+    /// Expands a short ref name like `main` or `origin/main` to the full ref
+    /// name, using the same rules as git.
+    pub fn expand_ref(&self, name: &str) -> Result<Option<String>> {
+        let candidates = [
+            name.to_string(),
+            format!("refs/{name}"),
+            format!("refs/tags/{name}"),
+            format!("refs/heads/{name}"),
+            format!("refs/remotes/{name}"),
+            format!("refs/remotes/{name}/HEAD"),
+        ];
+        for candidate in candidates {
+            if (is_pseudo_ref(&candidate) || candidate.starts_with("refs/"))
+                && self.read_ref(&candidate)?.is_some()
+            {
+                return Ok(Some(candidate));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Resolves a revision such as `HEAD`, `main~2`, `v1.0^2`, `main@{1}` or
+    /// an abbreviated hash to the full object hash.
+    pub fn rev_parse(&self, rev: &str) -> Result<String> {
+        let unknown = || RepoError::UnknownRevision(rev.to_string());
+
+        // Split off `~<n>` and `^<n>` suffixes
+        let base_end = rev.find(['~', '^']).unwrap_or(rev.len());
+        let (base, mut suffix) = rev.split_at(base_end);
+        let base = if base == "@" { "HEAD" } else { base };
+
+        // `<ref>@{<n>}` is the value of the ref `n` updates ago
+        let mut hash = match base.strip_suffix('}').and_then(|b| b.split_once("@{")) {
+            Some((name, n)) => {
+                let n: usize = n.parse().map_err(|_| unknown())?;
+                let name = if name.is_empty() { "HEAD" } else { name };
+                let refname = self.expand_ref(name)?.ok_or_else(unknown)?;
+                let reflog = self.read_reflog(&refname)?;
+                match reflog.len().checked_sub(n + 1) {
+                    Some(i) => reflog[i].new.clone(),
+                    None => Err(RepoError::Other(format!(
+                        "log for '{name}' only has {} entries",
+                        reflog.len()
+                    )))?,
+                }
+            }
+            None => match self.expand_ref(base)? {
+                Some(refname) => self.read_ref(&refname)?.ok_or_else(unknown)?,
+                None => self.expand_hash(base)?.ok_or_else(unknown)?,
+            },
+        };
+
+        while !suffix.is_empty() {
+            let op = suffix.as_bytes()[0];
+            suffix = &suffix[1..];
+            if op == b'^' && suffix.starts_with('{') {
+                // `^{<type>}` peels to an object of that type
+                let end = suffix.find('}').ok_or_else(unknown)?;
+                hash = self.peel_to(&hash, &suffix[1..end])?.ok_or_else(unknown)?;
+                suffix = &suffix[end + 1..];
+                continue;
+            }
+            // Other operators like `^@` or `^!` name more than one commit
+            if op != b'~' && op != b'^' {
+                Err(unknown())?;
+            }
+            // Annotated tags are peeled to the commit they point to
+            hash = self.peel_tags(&hash)?;
+            let digits = suffix
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(suffix.len());
+            let n = match &suffix[..digits] {
+                "" => 1,
+                n => n.parse::<usize>().map_err(|_| unknown())?,
+            };
+            suffix = &suffix[digits..];
+
+            if op == b'~' {
+                for _ in 0..n {
+                    hash = Commit::read(&hash, self)?
+                        .parents
+                        .first()
+                        .cloned()
+                        .ok_or_else(unknown)?;
+                }
+            } else if n > 0 {
+                hash = Commit::read(&hash, self)?
+                    .parents
+                    .get(n - 1)
+                    .cloned()
+                    .ok_or_else(unknown)?;
+            }
+        }
+
+        Ok(hash)
+    }
+
+    /// Peels an object as `<rev>^{<kind>}` does: `^{}` follows annotated
+    /// tags, `^{tree}` also takes the tree of a commit, and `^{commit}`,
+    /// `^{blob}`, `^{tag}` and `^{object}` require that type. Returns
+    /// `None` if the object doesn't peel to the type or it's unknown.
+    fn peel_to(&self, hash: &str, kind: &str) -> Result<Option<String>> {
+        let kind_of = |hash: &str| -> Result<ObjectType> { Ok(Object::read(hash, self)?.kind) };
+        let peeled = match kind {
+            "" => Some(self.peel_tags(hash)?),
+            "object" => Some(hash.to_string()),
+            "tag" => (kind_of(hash)? == ObjectType::Tag).then(|| hash.to_string()),
+            "commit" | "blob" | "tree" => {
+                let hash = self.peel_tags(hash)?;
+                match (kind, kind_of(&hash)?) {
+                    ("commit", ObjectType::Commit)
+                    | ("blob", ObjectType::Blob)
+                    | ("tree", ObjectType::Tree) => Some(hash),
+                    ("tree", ObjectType::Commit) => Some(Commit::read(&hash, self)?.tree),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        Ok(peeled)
+    }
+
+    /// Follows annotated tags to the object they point to.
+    pub fn peel_tags(&self, hash: &str) -> Result<String> {
+        let mut hash = hash.to_string();
+        while Object::read(&hash, self)?.kind == ObjectType::Tag {
+            hash = Tag::read(&hash, self)?.object;
+        }
+        Ok(hash)
+    }
+
+    /// Expands a (possibly abbreviated) hex object name to a full hash.
+    pub fn expand_hash(&self, prefix: &str) -> Result<Option<String>> {
+        if prefix.len() < 4 || prefix.len() > 40 || !prefix.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(None);
+        }
+        let prefix = prefix.to_lowercase();
+        let dir = self.common_dir.join("objects").join(&prefix[..2]);
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Ok(None);
+        };
+
+        let mut matches = entries
+            .filter_map(|e| e.ok())
+            .map(|e| format!("{}{}", &prefix[..2], e.file_name().to_string_lossy()))
+            .filter(|hash| hash.starts_with(&prefix));
+        let hash = matches.next();
+        if matches.next().is_some() {
+            Err(RepoError::AmbiguousRevision(prefix.to_string()))?;
+        }
+
+        Ok(hash)
+    }
+
+    /// Updates a ref to point to the given hash and records the update in
+    /// the ref's reflog (and in HEAD's, if HEAD points to the ref).
+    pub fn update_ref(&self, refname: &str, hash: &str, message: &str) -> Result<()> {
+        let mut transaction = RefTransaction::new(self);
+        transaction.update(RefUpdate {
+            refname: refname.to_string(),
+            change: RefChange::Set(hash.to_string()),
+            old: None,
+            no_deref: false,
+            message: message.to_string(),
+        })?;
+        transaction.commit()
+    }
+
+    /// Points the symbolic ref `refname` (e.g. `HEAD`) at the ref `target`,
+    /// recording the change in its reflog if a message is given.
+    pub fn set_symbolic_ref(&self, refname: &str, target: &str, message: &str) -> Result<()> {
+        let old = self.read_ref(refname)?;
+        let lock = self.refs.lock(&[refname.to_string()])?;
+        let mut writes = vec![RefWrite::Ref {
+            refname: refname.to_string(),
+            value: Some(RefValue::Symbolic(target.to_string())),
+        }];
+        if !message.is_empty() && self.should_log(refname)? {
+            let new = self.read_ref(target)?;
+            writes.push(RefWrite::Log {
+                refname: refname.to_string(),
+                entry: ReflogEntry {
+                    old: old.unwrap_or_else(|| NULL_HASH.to_string()),
+                    new: new.unwrap_or_else(|| NULL_HASH.to_string()),
+                    signature: Signature::now()?,
+                    message: message.to_string(),
+                },
+            });
+        }
+
+        lock.commit(writes)
+    }
+
+    /// Deletes a ref along with its reflog.
+    pub fn delete_ref(&self, refname: &str) -> Result<()> {
+        let lock = self.refs.lock(&[refname.to_string()])?;
+        lock.commit(vec![RefWrite::Ref {
+            refname: refname.to_string(),
+            value: None,
+        }])
+    }
+
+    /// Returns true if updates of the ref should be logged: branches,
+    /// remote-tracking branches, HEAD and `refs/stash` (whose reflog is the
+    /// list of stashes) always get a reflog, other refs only if their reflog
+    /// already exists.
+    pub(crate) fn should_log(&self, refname: &str) -> Result<bool> {
+        let always_log = refname == "HEAD"
+            || refname == "refs/stash"
+            || refname.starts_with("refs/heads/")
+            || refname.starts_with("refs/remotes/");
+        Ok(always_log || self.refs.has_reflog(refname)?)
+    }
+
+    /// Appends an entry to the reflog of the given ref, if it should be logged.
+    pub fn append_reflog(&self, refname: &str, old: &str, new: &str, message: &str) -> Result<()> {
+        if !self.should_log(refname)? {
+            return Ok(());
+        }
+        let lock = self.refs.lock(&[])?;
+        lock.commit(vec![RefWrite::Log {
+            refname: refname.to_string(),
+            entry: ReflogEntry {
+                old: old.to_string(),
+                new: new.to_string(),
+                signature: Signature::now()?,
+                message: message.to_string(),
+            },
+        }])
+    }
+
+    /// Deletes the entry `<refname>@{n}` from a reflog, pointing the ref at
+    /// the newest remaining entry, or deleting it if none remain. The entry
+    /// after the deleted one gets its old value, so the log stays connected.
+    pub fn delete_reflog_entry(&self, refname: &str, n: usize) -> Result<()> {
+        let mut reflog = self.read_reflog(refname)?;
+        let i = reflog
+            .len()
+            .checked_sub(n + 1)
+            .ok_or_else(|| RepoError::Other(format!("reflog entry {refname}@{{{n}}} not found")))?;
+        let removed = reflog.remove(i);
+        if let Some(next) = reflog.get_mut(i) {
+            next.old = removed.old;
+        }
+
+        let lock = self.refs.lock(&[refname.to_string()])?;
+        let Some(newest) = reflog.last() else {
+            return lock.commit(vec![RefWrite::Ref {
+                refname: refname.to_string(),
+                value: None,
+            }]);
+        };
+        let mut writes = vec![
+            RefWrite::DeleteLog {
+                refname: refname.to_string(),
+            },
+            RefWrite::Ref {
+                refname: refname.to_string(),
+                value: Some(RefValue::Direct(newest.new.clone())),
+            },
+        ];
+        writes.extend(reflog.into_iter().map(|entry| RefWrite::Log {
+            refname: refname.to_string(),
+            entry,
+        }));
+        lock.commit(writes)
+    }
+
+    /// Returns the entries of the given ref's reflog, oldest first.
+    pub fn read_reflog(&self, refname: &str) -> Result<Vec<ReflogEntry>> {
+        self.refs.read_reflog(refname)
+    }
+
+    /// Returns true if the given ref has a reflog.
+    pub fn has_reflog(&self, refname: &str) -> Result<bool> {
+        self.refs.has_reflog(refname)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::object::{tree::write_tree_from_files, write_commit};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_rev_parse() {
+        let temp_dir = tempdir().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let tree = write_tree_from_files(&BTreeMap::new(), &repo).unwrap();
+        let one = write_commit(&tree, &[], "one", &repo).unwrap();
+        let two = write_commit(&tree, &[&one], "two", &repo).unwrap();
+        let side = write_commit(&tree, &[&one], "side", &repo).unwrap();
+        let merge = write_commit(&tree, &[&two, &side], "merge", &repo).unwrap();
+        repo.update_ref("HEAD", &merge, "merge").unwrap();
+
+        let parse = |rev: &str| repo.rev_parse(rev);
+        assert_eq!(parse("HEAD").unwrap(), merge);
+        assert_eq!(parse("HEAD^2").unwrap(), side);
+        assert_eq!(parse("HEAD~2").unwrap(), one);
+        assert_eq!(parse("HEAD^^").unwrap(), one);
+        assert_eq!(parse("HEAD^0").unwrap(), merge);
+        assert_eq!(parse("HEAD^{commit}~1").unwrap(), two);
+        assert_eq!(parse("HEAD^{tree}").unwrap(), tree);
+        assert_eq!(parse("HEAD^{}").unwrap(), merge);
+
+        // Operators naming several commits, and objects that don't peel to
+        // the type or unknown types, are no single revision
+        for rev in [
+            "HEAD^@",
+            "HEAD^!",
+            "HEAD^-",
+            "HEAD~1^@",
+            "HEAD^{blob}",
+            "HEAD^{tag}",
+            "HEAD^{tree}^{commit}",
+            "HEAD^{/merge}",
+            "HEAD^{commit",
+        ] {
+            assert!(
+                matches!(parse(rev), Err(RepoError::UnknownRevision(_))),
+                "{rev}"
+            );
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! The reftable ref backend (`extensions.refStorage = reftable`).
+//!
+//! Refs and reflogs are stored in a stack of immutable tables in
+//! `.git/reftable`, listed oldest first in `tables.list`. Every transaction
+//! appends a table, where records override those of older tables. To keep
+//! the stack short, tables are merged whenever a table isn't at least twice
+//! as large as all newer tables combined.
+
+pub mod record;
+pub mod table;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use record::{LogRecord, Record, RefRecord};
+use table::Table;
+
+use super::{
+    super::error::{RepoError, Result},
+    RefLock, RefStore, RefValue, RefWrite, ReflogEntry,
+};
+
+pub struct ReftableStore {
+    dir: PathBuf,
+    /// Tables are immutable, so they can be cached by file name
+    tables: Mutex<HashMap<String, Arc<Table>>>,
+}
+
+impl ReftableStore {
+    pub fn new(git_dir: &Path) -> ReftableStore {
+        ReftableStore {
+            dir: git_dir.join("reftable"),
+            tables: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Creates the layout of a reftable repository. `HEAD` and `refs/heads`
+    /// are only there for older git versions to detect the repository and
+    /// refuse to work with it.
+    pub fn create(git_dir: &Path) -> Result<()> {
+        fs::create_dir_all(git_dir.join("reftable"))?;
+        fs::write(git_dir.join("reftable/tables.list"), "")?;
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/.invalid\n")?;
+        fs::create_dir_all(git_dir.join("refs"))?;
+        fs::write(
+            git_dir.join("refs/heads"),
+            "this repository uses the reftable format\n",
+        )?;
+        Ok(())
+    }
+
+    fn table_names(&self) -> Result<Vec<String>> {
+        match fs::read_to_string(self.dir.join("tables.list")) {
+            Ok(content) => Ok(content.lines().map(str::to_string).collect()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// Returns the current tables, oldest first.
+    fn tables(&self) -> Result<Vec<(String, Arc<Table>)>> {
+        let names = self.table_names()?;
+        let mut cache = self.tables.lock().unwrap_or_else(|e| e.into_inner());
+        cache.retain(|name, _| names.contains(name));
+
+        let mut tables = vec![];
+        for name in names {
+            let table = match cache.get(&name) {
+                Some(table) => table.clone(),
+                None => {
+                    let table = Arc::new(Table::open(&self.dir.join(&name))?);
+                    cache.insert(name.clone(), table.clone());
+                    table
+                }
+            };
+            tables.push((name, table));
+        }
+
+        Ok(tables)
+    }
+
+    /// Returns the log records of a ref by update index, including deletions.
+    fn log_records(&self, refname: &str) -> Result<BTreeMap<u64, Option<ReflogEntry>>> {
+        let mut records = BTreeMap::new();
+        for (_, table) in self.tables()? {
+            for record in table.logs(Some(refname))? {
+                records.insert(record.update_index, record.entry);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Writes a table to a temporary file and renames it to its final name,
+    /// `<min update index>-<max update index>-<random>.ref`.
+    fn write_table(
+        &self,
+        refs: &[RefRecord],
+        logs: &[LogRecord],
+        min_update_index: u64,
+        max_update_index: u64,
+    ) -> Result<String> {
+        let data = table::write_table(refs, logs, min_update_index, max_update_index)?;
+        let random = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default()
+            ^ std::process::id().rotate_left(16);
+        let name = format!("0x{min_update_index:012x}-0x{max_update_index:012x}-{random:08x}.ref");
+
+        let temp = self.dir.join(format!("tmp_{name}"));
+        fs::write(&temp, data)?;
+        fs::rename(&temp, self.dir.join(&name))?;
+
+        Ok(name)
+    }
+
+    /// Merges the tables from `start` on into one. Deletions are only kept
+    /// if there are older tables left they could apply to.
+    fn compact(&self, tables: &[(String, Arc<Table>)], start: usize) -> Result<String> {
+        let mut refs = BTreeMap::new();
+        let mut logs = BTreeMap::new();
+        for (_, table) in &tables[start..] {
+            for record in table.refs("")? {
+                refs.insert(record.refname.clone(), record);
+            }
+            for record in table.logs(None)? {
+                logs.insert(record.key(), record);
+            }
+        }
+        let keep_deletions = start > 0;
+        let refs: Vec<RefRecord> = refs
+            .into_values()
+            .filter(|record| keep_deletions || record.value.is_some())
+            .collect();
+        let logs: Vec<LogRecord> = logs
+            .into_values()
+            .filter(|record| keep_deletions || record.entry.is_some())
+            .collect();
+
+        let min = tables[start].1.min_update_index;
+        let max = tables[tables.len() - 1].1.max_update_index;
+        self.write_table(&refs, &logs, min, max)
+    }
+
+    /// Returns the index of the first table to merge so the table sizes
+    /// form a geometric sequence again, if any.
+    fn compaction_start(&self, names: &[String]) -> Result<Option<usize>> {
+        let sizes = names
+            .iter()
+            .map(|name| Ok(fs::metadata(self.dir.join(name))?.len()))
+            .collect::<Result<Vec<_>>>()?;
+        let Some(&last) = sizes.last() else {
+            return Ok(None);
+        };
+
+        let mut start = sizes.len() - 1;
+        let mut total = last;
+        while start > 0 && sizes[start - 1] < 2 * total {
+            start -= 1;
+            total += sizes[start];
+        }
+        Ok((start < sizes.len() - 1).then_some(start))
+    }
+}
+
+impl RefStore for ReftableStore {
+    fn read(&self, refname: &str) -> Result<Option<RefValue>> {
+        for (_, table) in self.tables()?.iter().rev() {
+            if let Some(record) = table.read_ref(refname)? {
+                return Ok(record.value);
+            }
+        }
+        Ok(None)
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<(String, RefValue)>> {
+        let mut refs = BTreeMap::new();
+        for (_, table) in self.tables()? {
+            for record in table.refs(prefix)? {
+                refs.insert(record.refname, record.value);
+            }
+        }
+
+        Ok(refs
+            .into_iter()
+            .filter(|(refname, _)| refname.starts_with("refs/"))
+            .filter_map(|(refname, value)| Some((refname, value?)))
+            .collect())
+    }
+
+    fn lock(&self, _refnames: &[String]) -> Result<Box<dyn RefLock + '_>> {
+        // The whole stack is locked at once
+        let path = self.dir.join("tables.list.lock");
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| RepoError::Other(format!("unable to create '{}': {e}", path.display())))?;
+
+        Ok(Box::new(ReftableLock { store: self, path }))
+    }
+
+    fn read_reflog(&self, refname: &str) -> Result<Vec<ReflogEntry>> {
+        Ok(self.log_records(refname)?.into_values().flatten().collect())
+    }
+
+    fn has_reflog(&self, refname: &str) -> Result<bool> {
+        Ok(!self.read_reflog(refname)?.is_empty())
+    }
+}
+
+/// The lock on `tables.list`, held while adding a table to the stack.
+struct ReftableLock<'a> {
+    store: &'a ReftableStore,
+    path: PathBuf,
+}
+
+impl RefLock for ReftableLock<'_> {
+    fn commit(self: Box<Self>, writes: Vec<RefWrite>) -> Result<()> {
+        let store = self.store;
+        let tables = store.tables()?;
+        let next = tables
+            .last()
+            .map_or(1, |(_, table)| table.max_update_index + 1);
+
+        // Every reflog entry needs its own update index, so a transaction
+        // writing several entries for one ref spans several indices
+        let mut log_counts: HashMap<&str, u64> = HashMap::new();
+        for write in &writes {
+            if let RefWrite::Log { refname, .. } = write {
+                *log_counts.entry(refname).or_default() += 1;
+            }
+        }
+        let max = next + log_counts.values().max().copied().unwrap_or(1).max(1) - 1;
+
+        let mut refs = BTreeMap::new();
+        let mut logs = BTreeMap::new();
+        let mut log_indices: HashMap<String, u64> = HashMap::new();
+        for write in &writes {
+            let delete_log = match write {
+                RefWrite::Ref { refname, value } => {
+                    let record = RefRecord {
+                        refname: refname.clone(),
+                        update_index: max,
+                        value: value.clone(),
+                    };
+                    refs.insert(refname.clone(), record);
+                    value.is_none().then_some(refname)
+                }
+                RefWrite::Log { refname, entry } => {
+                    let index = log_indices.entry(refname.clone()).or_insert(next);
+                    let record = LogRecord {
+                        refname: refname.clone(),
+                        update_index: *index,
+                        entry: Some(entry.clone()),
+                    };
+                    *index += 1;
+                    logs.insert(record.key(), record);
+                    None
+                }
+                RefWrite::DeleteLog { refname } => Some(refname),
+            };
+
+            // Deleting a reflog means writing a tombstone for each of its entries
+            if let Some(refname) = delete_log {
+                for (update_index, entry) in store.log_records(refname)? {
+                    if entry.is_some() {
+                        let record = LogRecord {
+                            refname: refname.clone(),
+                            update_index,
+                            entry: None,
+                        };
+                        logs.entry(record.key()).or_insert(record);
+                    }
+                }
+            }
+        }
+
+        let refs: Vec<RefRecord> = refs.into_values().collect();
+        let logs: Vec<LogRecord> = logs.into_values().collect();
+        let name = store.write_table(&refs, &logs, next, max)?;
+
+        let mut names: Vec<String> = tables.iter().map(|(name, _)| name.clone()).collect();
+        names.push(name.clone());
+        let mut obsolete = vec![];
+        if let Some(start) = store.compaction_start(&names)? {
+            let mut tables = tables;
+            tables.push((name.clone(), Arc::new(Table::open(&store.dir.join(&name))?)));
+            let compacted = store.compact(&tables, start)?;
+            obsolete = names.split_off(start);
+            names.push(compacted);
+        }
+
+        let mut list = OpenOptions::new().write(true).open(&self.path)?;
+        for name in &names {
+            writeln!(list, "{name}")?;
+        }
+        drop(list);
+        fs::rename(&self.path, store.dir.join("tables.list"))?;
+
+        for name in obsolete {
+            fs::remove_file(store.dir.join(name))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ReftableLock<'_> {
+    fn drop(&mut self) {
+        // A committed lock has been renamed to tables.list already
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Signature;
+    use tempfile::tempdir;
+
+    fn hash(n: usize) -> String {
+        format!("{n:040x}")
+    }
+
+    fn log(n: usize) -> ReflogEntry {
+        ReflogEntry {
+            old: hash(n - 1),
+            new: hash(n),
+            signature: Signature::parse("A U Thor <a@example.com> 1700000000 +0200").unwrap(),
+            message: format!("update {n}"),
+        }
+    }
+
+    #[test]
+    fn test_reftable_store() {
+        let temp_dir = tempdir().unwrap();
+        ReftableStore::create(temp_dir.path()).unwrap();
+        let store = ReftableStore::new(temp_dir.path());
+
+        for n in 1..=40 {
+            let refname = format!("refs/heads/b{}", n % 4);
+            store
+                .lock(std::slice::from_ref(&refname))
+                .unwrap()
+                .commit(vec![
+                    RefWrite::Ref {
+                        refname: refname.clone(),
+                        value: Some(RefValue::Direct(hash(n))),
+                    },
+                    RefWrite::Log {
+                        refname,
+                        entry: log(n),
+                    },
+                ])
+                .unwrap();
+        }
+        // Compaction keeps the stack short
+        assert!(store.table_names().unwrap().len() < 8);
+        assert_eq!(
+            store.read("refs/heads/b1").unwrap(),
+            Some(RefValue::Direct(hash(37)))
+        );
+        assert_eq!(store.list("refs/heads/").unwrap().len(), 4);
+        let reflog = store.read_reflog("refs/heads/b2").unwrap();
+        assert_eq!(reflog.len(), 10);
+        assert_eq!(reflog[0].message, "update 2");
+        assert_eq!(reflog[9].new, hash(38));
+
+        // Locking is exclusive
+        let lock = store.lock(&[]).unwrap();
+        assert!(store.lock(&[]).is_err());
+        drop(lock);
+
+        store
+            .lock(&[])
+            .unwrap()
+            .commit(vec![RefWrite::Ref {
+                refname: String::from("refs/heads/b2"),
+                value: None,
+            }])
+            .unwrap();
+        assert_eq!(store.read("refs/heads/b2").unwrap(), None);
+        assert!(!store.has_reflog("refs/heads/b2").unwrap());
+        assert_eq!(store.list("refs/").unwrap().len(), 3);
+    }
+}
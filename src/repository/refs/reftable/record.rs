@@ -0,0 +1,307 @@
+//! The records stored in reftable blocks and their binary encoding.
+
+use crate::object::Signature;
+
+use super::super::{
+    super::error::{RepoError, Result},
+    RefValue, ReflogEntry,
+};
+
+const HASH_SIZE: usize = 20;
+
+fn corrupt(what: &str) -> RepoError {
+    RepoError::CorruptReftable(what.to_string())
+}
+
+/// Appends a varint. Unlike LEB128, every continuation adds one to the
+/// value, so each number has exactly one encoding (as in pack files).
+pub fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    loop {
+        value >>= 7;
+        if value == 0 {
+            break;
+        }
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+    }
+    buf.extend(bytes.iter().rev());
+}
+
+/// Reads a varint written by [`put_varint`], advancing `pos`.
+pub fn get_varint(data: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut byte = *data.get(*pos).ok_or_else(|| corrupt("truncated varint"))?;
+    *pos += 1;
+    let mut value = u64::from(byte & 0x7f);
+    while byte & 0x80 != 0 {
+        byte = *data.get(*pos).ok_or_else(|| corrupt("truncated varint"))?;
+        *pos += 1;
+        value = ((value + 1) << 7) | u64::from(byte & 0x7f);
+    }
+    Ok(value)
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8]> {
+    let bytes = data
+        .get(*pos..*pos + len)
+        .ok_or_else(|| corrupt("truncated record"))?;
+    *pos += len;
+    Ok(bytes)
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    put_varint(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn get_string(data: &[u8], pos: &mut usize) -> Result<String> {
+    let len = get_varint(data, pos)? as usize;
+    Ok(String::from_utf8_lossy(take(data, pos, len)?).to_string())
+}
+
+fn put_hash(buf: &mut Vec<u8>, hash: &str) -> Result<()> {
+    let bytes = hex::decode(hash).map_err(|_| corrupt("invalid object name"))?;
+    if bytes.len() != HASH_SIZE {
+        Err(corrupt("invalid object name"))?;
+    }
+    buf.extend_from_slice(&bytes);
+    Ok(())
+}
+
+fn get_hash(data: &[u8], pos: &mut usize) -> Result<String> {
+    Ok(hex::encode(take(data, pos, HASH_SIZE)?))
+}
+
+/// A record in a reftable block. Records are stored sorted by key, with
+/// the key prefix shared with the previous record left out.
+pub trait Record: Sized {
+    const BLOCK_TYPE: u8;
+
+    fn key(&self) -> Vec<u8>;
+
+    /// The 3 bits stored along with the key, e.g. whether the record is a deletion
+    fn value_type(&self) -> u8;
+
+    fn encode_value(&self, min_update_index: u64, buf: &mut Vec<u8>) -> Result<()>;
+
+    fn decode(
+        key: &[u8],
+        value_type: u8,
+        data: &[u8],
+        pos: &mut usize,
+        min_update_index: u64,
+    ) -> Result<Self>;
+}
+
+/// Encodes a record, sharing `prefix_len` bytes of its key with the previous one.
+pub fn encode_record<R: Record>(
+    record: &R,
+    prefix_len: usize,
+    min_update_index: u64,
+    buf: &mut Vec<u8>,
+) -> Result<()> {
+    let key = record.key();
+    let suffix = &key[prefix_len..];
+    put_varint(buf, prefix_len as u64);
+    put_varint(
+        buf,
+        (suffix.len() as u64) << 3 | u64::from(record.value_type()),
+    );
+    buf.extend_from_slice(suffix);
+    record.encode_value(min_update_index, buf)
+}
+
+/// Decodes the key of the record at `pos`, given the key of the previous
+/// record. Returns the key and the value type.
+pub fn decode_key(data: &[u8], pos: &mut usize, last_key: &[u8]) -> Result<(Vec<u8>, u8)> {
+    let prefix_len = get_varint(data, pos)? as usize;
+    let suffix_and_type = get_varint(data, pos)?;
+    let suffix_len = (suffix_and_type >> 3) as usize;
+    if prefix_len > last_key.len() {
+        Err(corrupt("key prefix too long"))?;
+    }
+    let mut key = last_key[..prefix_len].to_vec();
+    key.extend_from_slice(take(data, pos, suffix_len)?);
+
+    Ok((key, (suffix_and_type & 0x7) as u8))
+}
+
+/// A ref and its value at `update_index`; a value of `None` records the
+/// deletion of the ref.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefRecord {
+    pub refname: String,
+    pub update_index: u64,
+    pub value: Option<RefValue>,
+}
+
+impl Record for RefRecord {
+    const BLOCK_TYPE: u8 = b'r';
+
+    fn key(&self) -> Vec<u8> {
+        self.refname.as_bytes().to_vec()
+    }
+
+    fn value_type(&self) -> u8 {
+        match self.value {
+            None => 0,
+            Some(RefValue::Direct(_)) => 1,
+            Some(RefValue::Symbolic(_)) => 3,
+        }
+    }
+
+    fn encode_value(&self, min_update_index: u64, buf: &mut Vec<u8>) -> Result<()> {
+        put_varint(buf, self.update_index - min_update_index);
+        match &self.value {
+            None => {}
+            Some(RefValue::Direct(hash)) => put_hash(buf, hash)?,
+            Some(RefValue::Symbolic(target)) => put_string(buf, target),
+        }
+        Ok(())
+    }
+
+    fn decode(
+        key: &[u8],
+        value_type: u8,
+        data: &[u8],
+        pos: &mut usize,
+        min_update_index: u64,
+    ) -> Result<RefRecord> {
+        let update_index = min_update_index + get_varint(data, pos)?;
+        let value = match value_type {
+            0 => None,
+            1 => Some(RefValue::Direct(get_hash(data, pos)?)),
+            // The peeled value of a tag is only a cache, skip it
+            2 => {
+                let hash = get_hash(data, pos)?;
+                get_hash(data, pos)?;
+                Some(RefValue::Direct(hash))
+            }
+            3 => Some(RefValue::Symbolic(get_string(data, pos)?)),
+            _ => Err(corrupt("unknown ref value type"))?,
+        };
+
+        Ok(RefRecord {
+            refname: String::from_utf8_lossy(key).to_string(),
+            update_index,
+            value,
+        })
+    }
+}
+
+/// A reflog entry of a ref; an entry of `None` deletes the entry with the
+/// same `update_index` in older tables.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub refname: String,
+    pub update_index: u64,
+    pub entry: Option<ReflogEntry>,
+}
+
+impl Record for LogRecord {
+    const BLOCK_TYPE: u8 = b'l';
+
+    /// `<refname> NUL <reversed update index>`, so the newest entry comes first
+    fn key(&self) -> Vec<u8> {
+        let mut key = self.refname.as_bytes().to_vec();
+        key.push(0);
+        key.extend_from_slice(&(u64::MAX - self.update_index).to_be_bytes());
+        key
+    }
+
+    fn value_type(&self) -> u8 {
+        u8::from(self.entry.is_some())
+    }
+
+    fn encode_value(&self, _min_update_index: u64, buf: &mut Vec<u8>) -> Result<()> {
+        let Some(entry) = &self.entry else {
+            return Ok(());
+        };
+        put_hash(buf, &entry.old)?;
+        put_hash(buf, &entry.new)?;
+        put_string(buf, &entry.signature.name);
+        put_string(buf, &entry.signature.email);
+        put_varint(buf, entry.signature.time.max(0) as u64);
+        // The offset is stored like it's written, `-0130` as -130
+        let tz: i16 = entry.signature.tz.parse().unwrap_or(0);
+        buf.extend_from_slice(&tz.to_be_bytes());
+        // Messages are single lines, stored with a trailing newline
+        put_string(buf, &format!("{}\n", entry.message.trim_end_matches('\n')));
+        Ok(())
+    }
+
+    fn decode(
+        key: &[u8],
+        value_type: u8,
+        data: &[u8],
+        pos: &mut usize,
+        _min_update_index: u64,
+    ) -> Result<LogRecord> {
+        if key.len() < 9 || key[key.len() - 9] != 0 {
+            Err(corrupt("invalid log key"))?;
+        }
+        let (refname, index) = key.split_at(key.len() - 9);
+        let reversed = u64::from_be_bytes(index[1..].try_into().unwrap_or_default());
+
+        let entry = match value_type {
+            0 => None,
+            1 => {
+                let old = get_hash(data, pos)?;
+                let new = get_hash(data, pos)?;
+                let name = get_string(data, pos)?;
+                let email = get_string(data, pos)?;
+                let time = get_varint(data, pos)? as i64;
+                let tz = i16::from_be_bytes(take(data, pos, 2)?.try_into().unwrap_or_default());
+                let message = get_string(data, pos)?;
+                let sign = if tz < 0 { '-' } else { '+' };
+                Some(ReflogEntry {
+                    old,
+                    new,
+                    signature: Signature {
+                        name,
+                        email,
+                        time,
+                        tz: format!("{sign}{:04}", tz.unsigned_abs()),
+                    },
+                    message: message.trim_end_matches('\n').to_string(),
+                })
+            }
+            _ => Err(corrupt("unknown log value type"))?,
+        };
+
+        Ok(LogRecord {
+            refname: String::from_utf8_lossy(refname).to_string(),
+            update_index: u64::MAX - reversed,
+            entry,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        for value in [
+            0,
+            1,
+            127,
+            128,
+            255,
+            16511,
+            16512,
+            u64::from(u32::MAX),
+            u64::MAX,
+        ] {
+            let mut buf = vec![];
+            put_varint(&mut buf, value);
+            let mut pos = 0;
+            assert_eq!(get_varint(&buf, &mut pos).unwrap(), value);
+            assert_eq!(pos, buf.len());
+        }
+        let mut buf = vec![];
+        put_varint(&mut buf, 128);
+        assert_eq!(buf, [0x80, 0x00]);
+    }
+}
@@ -0,0 +1,562 @@
+//! Reading and writing single reftable files.
+//!
+//! A table starts with a header, followed by blocks of ref records, blocks
+//! of zlib compressed log records and a footer pointing at the sections.
+//! Each block ends with the offsets of its restart points, records that
+//! store their full key, which allow binary searching within the block.
+
+use std::{borrow::Cow, fs, io::Read, path::Path};
+
+use flate2::{bufread::ZlibDecoder, write::ZlibEncoder, Compression, Crc};
+
+use super::{
+    super::super::error::{RepoError, Result},
+    record::{decode_key, encode_record, LogRecord, Record, RefRecord},
+};
+
+const MAGIC: &[u8; 4] = b"REFT";
+/// Size of the header of the version 1 tables we write
+const HEADER_SIZE: usize = 24;
+pub const BLOCK_SIZE: u32 = 4096;
+/// A record with its full key is written every this many records
+const RESTART_INTERVAL: usize = 16;
+
+fn corrupt(what: &str) -> RepoError {
+    RepoError::CorruptReftable(what.to_string())
+}
+
+fn be24(data: &[u8]) -> usize {
+    usize::from(data[0]) << 16 | usize::from(data[1]) << 8 | usize::from(data[2])
+}
+
+fn put_be24(buf: &mut [u8], value: usize) {
+    buf[0] = (value >> 16) as u8;
+    buf[1] = (value >> 8) as u8;
+    buf[2] = value as u8;
+}
+
+fn be64(data: &[u8]) -> u64 {
+    u64::from_be_bytes(data[..8].try_into().unwrap_or_default())
+}
+
+/// Returns the header and footer sizes for a table format version. Version
+/// 2 adds a hash function id.
+fn sizes(version: u8) -> Result<(usize, usize)> {
+    match version {
+        1 => Ok((24, 68)),
+        2 => Ok((28, 72)),
+        _ => Err(corrupt("unsupported version")),
+    }
+}
+
+/// A block of a table, with log blocks already decompressed.
+struct Block<'a> {
+    data: Cow<'a, [u8]>,
+    kind: u8,
+    /// Offset of the first record, past the file header in the first block
+    records_start: usize,
+    restarts: Vec<usize>,
+    /// Offset of the next block in the file
+    next: usize,
+}
+
+impl Block<'_> {
+    fn restarts_start(&self) -> usize {
+        self.data.len() - 2 - 3 * self.restarts.len()
+    }
+
+    /// Decodes the records starting at `offset`, which has to be a restart
+    /// point, calling `f` for each until it returns false.
+    fn scan<R: Record>(
+        &self,
+        offset: usize,
+        min_update_index: u64,
+        mut f: impl FnMut(R) -> bool,
+    ) -> Result<bool> {
+        let end = self.restarts_start();
+        let mut pos = offset;
+        let mut last_key = vec![];
+        while pos < end {
+            let (key, value_type) = decode_key(&self.data, &mut pos, &last_key)?;
+            let record = R::decode(&key, value_type, &self.data, &mut pos, min_update_index)?;
+            last_key = key;
+            if !f(record) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn key_at(&self, offset: usize) -> Result<Vec<u8>> {
+        let mut pos = offset;
+        Ok(decode_key(&self.data, &mut pos, &[])?.0)
+    }
+
+    /// Returns the offset of the last restart point whose key is not greater
+    /// than `key`, where scanning for it has to start.
+    fn seek(&self, key: &[u8]) -> Result<usize> {
+        let (mut low, mut high) = (0, self.restarts.len());
+        while high - low > 1 {
+            let mid = (low + high) / 2;
+            if self.key_at(self.restarts[mid])?.as_slice() <= key {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(self
+            .restarts
+            .get(low)
+            .copied()
+            .unwrap_or(self.records_start))
+    }
+}
+
+/// A reftable file loaded into memory.
+pub struct Table {
+    data: Vec<u8>,
+    header_size: usize,
+    footer_start: usize,
+    block_size: usize,
+    pub min_update_index: u64,
+    pub max_update_index: u64,
+    log_offset: usize,
+}
+
+impl Table {
+    pub fn open(path: &Path) -> Result<Table> {
+        Table::parse(fs::read(path)?)
+    }
+
+    pub fn parse(data: Vec<u8>) -> Result<Table> {
+        if data.len() < 5 || &data[..4] != MAGIC {
+            Err(corrupt("bad magic"))?;
+        }
+        let (header_size, footer_size) = sizes(data[4])?;
+        if data.len() < header_size + footer_size {
+            Err(corrupt("truncated table"))?;
+        }
+        let footer_start = data.len() - footer_size;
+        let footer = &data[footer_start..];
+        if footer[..header_size] != data[..header_size] {
+            Err(corrupt("footer does not match header"))?;
+        }
+        let mut crc = Crc::new();
+        crc.update(&footer[..footer_size - 4]);
+        if crc.sum().to_be_bytes() != footer[footer_size - 4..] {
+            Err(corrupt("footer checksum mismatch"))?;
+        }
+        if data[4] == 2 && &data[8..12] != b"sha1" {
+            Err(corrupt("unsupported hash function"))?;
+        }
+
+        // footer: header, ref index, obj section and index, log section and index
+        let log_offset = be64(&footer[header_size + 24..]) as usize;
+        Ok(Table {
+            header_size,
+            footer_start,
+            block_size: be24(&data[5..]),
+            min_update_index: be64(&data[header_size - 16..]),
+            max_update_index: be64(&data[header_size - 8..]),
+            log_offset,
+            data,
+        })
+    }
+
+    /// Reads the block at `offset`, or `None` at the end of the blocks.
+    fn block(&self, offset: usize) -> Result<Option<Block<'_>>> {
+        let header_off = if offset == 0 { self.header_size } else { 0 };
+        let start = offset + header_off;
+        if start + 4 > self.footer_start {
+            return Ok(None);
+        }
+        let kind = self.data[start];
+        let len = be24(&self.data[start + 1..]);
+
+        let (data, next) = if kind == LogRecord::BLOCK_TYPE {
+            // Everything after the block header is compressed, `len` is the inflated size
+            let mut decoder = ZlibDecoder::new(&self.data[start + 4..self.footer_start]);
+            let mut data = self.data[offset..start + 4].to_vec();
+            decoder.read_to_end(&mut data)?;
+            let next = start + 4 + decoder.total_in() as usize;
+            (Cow::Owned(data), next)
+        } else {
+            let end = offset + len;
+            if end > self.footer_start || len < header_off + 6 {
+                Err(corrupt("block exceeds table"))?;
+            }
+            // Blocks are either padded with zeros to the block size or unaligned
+            let padded =
+                self.block_size > 0 && len < self.block_size && self.data.get(end) == Some(&0);
+            let next = if padded {
+                offset + self.block_size
+            } else {
+                end
+            };
+            (Cow::Borrowed(&self.data[offset..end]), next)
+        };
+        if data.len() != len {
+            Err(corrupt("block length mismatch"))?;
+        }
+
+        let count = usize::from(u16::from_be_bytes([data[len - 2], data[len - 1]]));
+        let restarts_start = (len - 2)
+            .checked_sub(3 * count)
+            .filter(|&start| start >= header_off + 4)
+            .ok_or_else(|| corrupt("bad restart count"))?;
+        let restarts = (0..count)
+            .map(|i| be24(&data[restarts_start + 3 * i..]))
+            .collect();
+
+        Ok(Some(Block {
+            data,
+            kind,
+            records_start: header_off + 4,
+            restarts,
+            next,
+        }))
+    }
+
+    /// Calls `f` for the records of the blocks of type `R` in the section
+    /// starting at `offset`, beginning with the block that may contain `key`.
+    fn scan<R: Record>(
+        &self,
+        offset: usize,
+        key: &[u8],
+        mut f: impl FnMut(R) -> bool,
+    ) -> Result<()> {
+        // Find the last block whose first key is not greater than `key`
+        let mut current = match self.block(offset)? {
+            Some(block) if block.kind == R::BLOCK_TYPE => block,
+            _ => return Ok(()),
+        };
+        while let Some(next) = self.block(current.next)? {
+            if next.kind != R::BLOCK_TYPE || next.key_at(next.records_start)?.as_slice() > key {
+                break;
+            }
+            current = next;
+        }
+
+        let mut start = current.seek(key)?;
+        loop {
+            if !current.scan(start, self.min_update_index, &mut f)? {
+                return Ok(());
+            }
+            current = match self.block(current.next)? {
+                Some(block) if block.kind == R::BLOCK_TYPE => block,
+                _ => return Ok(()),
+            };
+            start = current.records_start;
+        }
+    }
+
+    /// Returns the record of the given ref, which may be a deletion.
+    pub fn read_ref(&self, refname: &str) -> Result<Option<RefRecord>> {
+        let mut found = None;
+        self.scan(0, refname.as_bytes(), |record: RefRecord| {
+            match record.refname.as_str().cmp(refname) {
+                std::cmp::Ordering::Less => true,
+                std::cmp::Ordering::Equal => {
+                    found = Some(record);
+                    false
+                }
+                std::cmp::Ordering::Greater => false,
+            }
+        })?;
+        Ok(found)
+    }
+
+    /// Returns the records of all refs starting with `prefix`.
+    pub fn refs(&self, prefix: &str) -> Result<Vec<RefRecord>> {
+        let mut records = vec![];
+        self.scan(0, prefix.as_bytes(), |record: RefRecord| {
+            if record.refname.starts_with(prefix) {
+                records.push(record);
+                true
+            } else {
+                record.refname.as_str() < prefix
+            }
+        })?;
+        Ok(records)
+    }
+
+    /// Returns the log records of the given ref, or of all refs if `None`.
+    pub fn logs(&self, refname: Option<&str>) -> Result<Vec<LogRecord>> {
+        let offset = match self.log_offset {
+            0 if self.data[self.header_size] == LogRecord::BLOCK_TYPE => 0,
+            0 => return Ok(vec![]),
+            offset => offset,
+        };
+        let mut key = refname.unwrap_or_default().as_bytes().to_vec();
+        if refname.is_some() {
+            key.push(0);
+        }
+
+        let mut records = vec![];
+        self.scan(offset, &key, |record: LogRecord| match refname {
+            Some(refname) if record.refname.as_str() < refname => true,
+            Some(refname) if record.refname != refname => false,
+            _ => {
+                records.push(record);
+                true
+            }
+        })?;
+        Ok(records)
+    }
+}
+
+/// Writes the records of one section into blocks.
+struct BlockWriter<'a> {
+    out: &'a mut Vec<u8>,
+    kind: u8,
+    min_update_index: u64,
+    /// The uncompressed block being filled
+    block: Vec<u8>,
+    header_off: usize,
+    restarts: Vec<usize>,
+    count: usize,
+    last_key: Vec<u8>,
+}
+
+impl<'a> BlockWriter<'a> {
+    fn new(out: &'a mut Vec<u8>, kind: u8, min_update_index: u64) -> BlockWriter<'a> {
+        let mut writer = BlockWriter {
+            out,
+            kind,
+            min_update_index,
+            block: vec![],
+            header_off: 0,
+            restarts: vec![],
+            count: 0,
+            last_key: vec![],
+        };
+        writer.start_block();
+        writer
+    }
+
+    fn start_block(&mut self) {
+        // The first block of the file contains the file header
+        self.header_off = if self.out.len() == HEADER_SIZE {
+            HEADER_SIZE
+        } else {
+            0
+        };
+        self.block = self.out[self.out.len() - self.header_off..].to_vec();
+        self.block.extend([self.kind, 0, 0, 0]);
+        self.restarts.clear();
+        self.count = 0;
+        self.last_key.clear();
+    }
+
+    fn add<R: Record>(&mut self, record: &R) -> Result<()> {
+        let key = record.key();
+        for attempt in 0..2 {
+            let restart = self.count.is_multiple_of(RESTART_INTERVAL);
+            let prefix_len = if restart {
+                0
+            } else {
+                key.iter()
+                    .zip(&self.last_key)
+                    .take_while(|(a, b)| a == b)
+                    .count()
+            };
+            let mut encoded = vec![];
+            encode_record(record, prefix_len, self.min_update_index, &mut encoded)?;
+
+            let restarts = self.restarts.len() + usize::from(restart);
+            let size = self.block.len() + encoded.len() + 3 * restarts + 2;
+            // A record that doesn't fit into an empty block gets one of its own
+            if size > BLOCK_SIZE as usize && self.count > 0 && attempt == 0 {
+                self.flush(true)?;
+                continue;
+            }
+
+            if restart {
+                self.restarts.push(self.block.len());
+            }
+            self.block.extend(encoded);
+            self.count += 1;
+            self.last_key = key;
+            return Ok(());
+        }
+        Ok(())
+    }
+
+    /// Finishes the current block. Ref blocks are padded to the block size,
+    /// unless they are the last of their section.
+    fn flush(&mut self, more: bool) -> Result<()> {
+        if self.count == 0 {
+            return Ok(());
+        }
+        for &restart in &self.restarts {
+            let mut bytes = [0; 3];
+            put_be24(&mut bytes, restart);
+            self.block.extend(bytes);
+        }
+        self.block
+            .extend((self.restarts.len() as u16).to_be_bytes());
+        let len = self.block.len();
+        put_be24(&mut self.block[self.header_off + 1..], len);
+
+        self.out.truncate(self.out.len() - self.header_off);
+        if self.kind == LogRecord::BLOCK_TYPE {
+            let body_start = self.header_off + 4;
+            self.out.extend_from_slice(&self.block[..body_start]);
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            std::io::Write::write_all(&mut encoder, &self.block[body_start..])?;
+            self.out.extend(encoder.finish()?);
+        } else {
+            self.out.extend_from_slice(&self.block);
+            if more && len < BLOCK_SIZE as usize {
+                self.out
+                    .resize(self.out.len() + BLOCK_SIZE as usize - len, 0);
+            }
+        }
+
+        if more {
+            self.start_block();
+        }
+        Ok(())
+    }
+}
+
+/// Serializes a table with the given records, which have to be sorted by key.
+pub fn write_table(
+    refs: &[RefRecord],
+    logs: &[LogRecord],
+    min_update_index: u64,
+    max_update_index: u64,
+) -> Result<Vec<u8>> {
+    let mut header = MAGIC.to_vec();
+    header.push(1);
+    header.extend(&BLOCK_SIZE.to_be_bytes()[1..]);
+    header.extend(min_update_index.to_be_bytes());
+    header.extend(max_update_index.to_be_bytes());
+    let mut out = header.clone();
+
+    if !refs.is_empty() {
+        let mut writer = BlockWriter::new(&mut out, RefRecord::BLOCK_TYPE, min_update_index);
+        for record in refs {
+            writer.add(record)?;
+        }
+        writer.flush(false)?;
+    }
+
+    let mut log_offset = 0;
+    if !logs.is_empty() {
+        if out.len() > header.len() {
+            log_offset = out.len();
+        }
+        let mut writer = BlockWriter::new(&mut out, LogRecord::BLOCK_TYPE, min_update_index);
+        for record in logs {
+            writer.add(record)?;
+        }
+        writer.flush(false)?;
+    }
+
+    let mut footer = header;
+    footer.extend(0u64.to_be_bytes()); // ref index
+    footer.extend(0u64.to_be_bytes()); // obj section and object id length
+    footer.extend(0u64.to_be_bytes()); // obj index
+    footer.extend((log_offset as u64).to_be_bytes());
+    footer.extend(0u64.to_be_bytes()); // log index
+    let mut crc = Crc::new();
+    crc.update(&footer);
+    footer.extend(crc.sum().to_be_bytes());
+    out.extend(footer);
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        object::Signature,
+        repository::refs::{RefValue, ReflogEntry},
+    };
+
+    fn hash(n: usize) -> String {
+        format!("{n:040x}")
+    }
+
+    #[test]
+    fn test_write_and_read_table() {
+        let mut refs: Vec<RefRecord> = (0..2000)
+            .map(|n| RefRecord {
+                refname: format!("refs/heads/branch-{n:05}"),
+                update_index: 3,
+                value: Some(RefValue::Direct(hash(n))),
+            })
+            .chain([RefRecord {
+                refname: String::from("refs/heads/deleted"),
+                update_index: 4,
+                value: None,
+            }])
+            .collect();
+        refs.push(RefRecord {
+            refname: String::from("HEAD"),
+            update_index: 4,
+            value: Some(RefValue::Symbolic(String::from("refs/heads/branch-00001"))),
+        });
+        refs.sort_by(|a, b| a.refname.cmp(&b.refname));
+        let mut logs: Vec<LogRecord> = (1..=3)
+            .map(|n| LogRecord {
+                refname: String::from("HEAD"),
+                update_index: n,
+                entry: Some(ReflogEntry {
+                    old: hash(n as usize - 1),
+                    new: hash(n as usize),
+                    signature: Signature::parse("A U Thor <a@example.com> 1700000000 -0130")
+                        .unwrap(),
+                    message: format!("commit: {n}"),
+                }),
+            })
+            .collect();
+        logs.sort_by_key(|record| record.key());
+
+        let data = write_table(&refs, &logs, 3, 4).unwrap();
+        // Padded ref blocks followed by an unaligned log block
+        assert!(data.len() > 4 * BLOCK_SIZE as usize);
+        let table = Table::parse(data).unwrap();
+        assert_eq!(table.min_update_index, 3);
+        assert_eq!(table.max_update_index, 4);
+
+        assert_eq!(table.refs("").unwrap(), refs);
+        assert_eq!(table.refs("refs/heads/branch-019").unwrap().len(), 100);
+        let head = table.read_ref("HEAD").unwrap().unwrap();
+        assert_eq!(
+            head.value,
+            Some(RefValue::Symbolic(String::from("refs/heads/branch-00001")))
+        );
+        let record = table.read_ref("refs/heads/branch-01234").unwrap().unwrap();
+        assert_eq!(record.value, Some(RefValue::Direct(hash(1234))));
+        assert_eq!(record.update_index, 3);
+        assert_eq!(
+            table.read_ref("refs/heads/deleted").unwrap().unwrap().value,
+            None
+        );
+        assert!(table.read_ref("refs/heads/branch").unwrap().is_none());
+
+        let logs = table.logs(Some("HEAD")).unwrap();
+        assert_eq!(logs.len(), 3);
+        // Newest first
+        assert_eq!(logs[0].update_index, 3);
+        let entry = logs[0].entry.as_ref().unwrap();
+        assert_eq!(entry.message, "commit: 3");
+        assert_eq!(entry.signature.tz, "-0130");
+        assert_eq!(entry.new, hash(3));
+        assert!(table
+            .logs(Some("refs/heads/branch-00001"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_empty_table() {
+        let table = Table::parse(write_table(&[], &[], 1, 1).unwrap()).unwrap();
+        assert!(table.refs("").unwrap().is_empty());
+        assert!(table.logs(None).unwrap().is_empty());
+        assert!(Table::parse(b"REFT\x01".to_vec()).is_err());
+    }
+}
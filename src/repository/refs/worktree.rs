@@ -0,0 +1,99 @@
+//! The refs of a linked worktree. `HEAD`, the other pseudo refs and the refs
+//! below `refs/worktree/`, `refs/bisect/` and `refs/rewritten/` belong to
+//! the worktree and are kept in its git directory, all other refs are shared
+//! with the main worktree.
+
+use super::{super::error::Result, RefLock, RefStore, RefValue, RefWrite, ReflogEntry};
+
+pub struct WorktreeStore {
+    worktree: Box<dyn RefStore>,
+    common: Box<dyn RefStore>,
+}
+
+impl WorktreeStore {
+    pub fn new(worktree: Box<dyn RefStore>, common: Box<dyn RefStore>) -> WorktreeStore {
+        WorktreeStore { worktree, common }
+    }
+
+    /// Returns the store the given ref is kept in.
+    fn store(&self, refname: &str) -> &dyn RefStore {
+        match is_per_worktree(refname) {
+            true => self.worktree.as_ref(),
+            false => self.common.as_ref(),
+        }
+    }
+}
+
+/// Returns whether a ref belongs to a single worktree.
+pub fn is_per_worktree(refname: &str) -> bool {
+    !refname.starts_with("refs/")
+        || ["refs/worktree/", "refs/bisect/", "refs/rewritten/"]
+            .iter()
+            .any(|prefix| refname.starts_with(prefix))
+}
+
+impl RefStore for WorktreeStore {
+    fn read(&self, refname: &str) -> Result<Option<RefValue>> {
+        self.store(refname).read(refname)
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<(String, RefValue)>> {
+        let mut refs: Vec<(String, RefValue)> = self
+            .worktree
+            .list(prefix)?
+            .into_iter()
+            .filter(|(refname, _)| is_per_worktree(refname))
+            .collect();
+        refs.extend(
+            self.common
+                .list(prefix)?
+                .into_iter()
+                .filter(|(refname, _)| !is_per_worktree(refname)),
+        );
+        refs.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Ok(refs)
+    }
+
+    fn lock(&self, refnames: &[String]) -> Result<Box<dyn RefLock + '_>> {
+        let (worktree, common): (Vec<String>, Vec<String>) = refnames
+            .iter()
+            .cloned()
+            .partition(|refname| is_per_worktree(refname));
+
+        Ok(Box::new(WorktreeLock {
+            worktree: self.worktree.lock(&worktree)?,
+            common: self.common.lock(&common)?,
+        }))
+    }
+
+    fn read_reflog(&self, refname: &str) -> Result<Vec<ReflogEntry>> {
+        self.store(refname).read_reflog(refname)
+    }
+
+    fn has_reflog(&self, refname: &str) -> Result<bool> {
+        self.store(refname).has_reflog(refname)
+    }
+}
+
+/// The locks on the refs of both stores.
+struct WorktreeLock<'a> {
+    worktree: Box<dyn RefLock + 'a>,
+    common: Box<dyn RefLock + 'a>,
+}
+
+impl RefLock for WorktreeLock<'_> {
+    fn commit(self: Box<Self>, writes: Vec<RefWrite>) -> Result<()> {
+        let (worktree, common): (Vec<RefWrite>, Vec<RefWrite>) =
+            writes.into_iter().partition(|write| {
+                let (RefWrite::Ref { refname, .. }
+                | RefWrite::Log { refname, .. }
+                | RefWrite::DeleteLog { refname }) = write;
+                is_per_worktree(refname)
+            });
+
+        // Shared refs first, so HEAD's reflog isn't updated if they fail
+        self.common.commit(common)?;
+        self.worktree.commit(worktree)
+    }
+}
@@ -0,0 +1,367 @@
+//! Resetting the index and the working tree to a tree, as done by `reset`
+//! and `restore`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+};
+
+use crate::{
+    object::{
+        tree::{file_mode, Tree},
+        Object,
+    },
+    pathspec::match_pathspec,
+};
+
+use super::{
+    checkout::{remove_path, write_file},
+    error::{RepoError, Result},
+    index::{hash_file, Index, IndexEntry},
+    Repository,
+};
+
+/// How much of the state `reset` changes besides the current branch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetMode {
+    /// Only move the branch
+    Soft,
+    /// Reset the index too
+    Mixed,
+    /// Reset the index and the working tree, discarding local changes
+    Hard,
+    /// Reset the index and the files that differ between the commits,
+    /// refusing to touch files with local changes
+    Keep,
+    /// Reset the index and the files that differ between the index and the
+    /// commit, refusing to touch files with unstaged changes
+    Merge,
+}
+
+/// Options of [`Repository::restore_paths`].
+#[derive(Debug, Default)]
+pub struct RestoreOptions<'a> {
+    /// The tree to restore from, or `None` for the index
+    pub source: Option<&'a str>,
+    /// Restore the index
+    pub staged: bool,
+    /// Restore the working tree
+    pub worktree: bool,
+    /// Don't fail when a pathspec matches no files
+    pub ignore_unmatch: bool,
+    /// Match the pathspecs against the source only, keeping the files that
+    /// aren't in it, like `checkout <tree-ish> -- <paths>`
+    pub overlay: bool,
+}
+
+impl Repository {
+    /// Resets the index to `tree`, and the working tree as `mode` says.
+    /// `head_tree` is the tree of HEAD before the reset. Nothing is changed
+    /// if local changes are in the way. Files outside a sparse checkout are
+    /// only reset in the index.
+    pub fn reset_tree(&self, head_tree: Option<&str>, tree: &str, mode: ResetMode) -> Result<()> {
+        match mode {
+            ResetMode::Soft => return Ok(()),
+            ResetMode::Mixed => return self.reset_index(tree),
+            _ => {}
+        }
+
+        let root = self.get_root();
+        let head = match head_tree {
+            Some(hash) => Tree::read_recursive(hash, self)?,
+            None => BTreeMap::new(),
+        };
+        let target = Tree::read_recursive(tree, self)?;
+        let convert = self.convert()?;
+        let checkout = self.convert_for(&target)?;
+        let sparse = self.sparse_checkout()?;
+        let skipped = |path: &str| sparse.as_ref().is_some_and(|sparse| !sparse.contains(path));
+        let mut index = Index::lock(self)?;
+
+        let indexed_paths: Vec<String> = index.entries.iter().map(|e| e.path.clone()).collect();
+        let paths: BTreeSet<&str> = head
+            .keys()
+            .chain(target.keys())
+            .chain(&indexed_paths)
+            .map(String::as_str)
+            .collect();
+        let mut updates = vec![];
+        for path in paths {
+            let (h, t) = (head.get(path), target.get(path));
+            let unmerged = index.get_all(path).any(|e| e.stage != 0);
+            let entry = index.get(path);
+            let indexed = entry.map(|e| (e.mode, e.hash.clone()));
+            let modified = match entry {
+                Some(entry) => index.is_modified(entry, root, &convert)?,
+                None => false,
+            };
+
+            let update = match mode {
+                ResetMode::Hard => unmerged || indexed.as_ref() != t || modified,
+                ResetMode::Keep if unmerged => Err(RepoError::NotUptodate(path.to_string()))?,
+                ResetMode::Keep if h == t => false,
+                ResetMode::Keep if indexed.as_ref() != h => {
+                    Err(RepoError::WouldBeOverwritten(path.to_string()))?
+                }
+                ResetMode::Merge if unmerged => true,
+                ResetMode::Merge if indexed.as_ref() == t => false,
+                _ if modified => Err(RepoError::NotUptodate(path.to_string()))?,
+                _ => true,
+            };
+            if !update {
+                continue;
+            }
+            // Unlike checkout, untracked files are in the way even if they
+            // have the new content
+            if mode != ResetMode::Hard
+                && entry.is_none()
+                && !unmerged
+                && t.is_some()
+                && !skipped(path)
+                && fs::symlink_metadata(root.join(path)).is_ok_and(|meta| !meta.is_dir())
+            {
+                Err(RepoError::UntrackedWouldBeOverwritten(path.to_string()))?;
+            }
+            updates.push((path, t, entry.is_some() || unmerged));
+        }
+
+        // Files tracked in the index but not in the new tree are removed
+        let in_worktree = |path: &str| index.get(path).is_some_and(|e| !e.skip_worktree());
+        for (path, t, tracked) in &updates {
+            if t.is_none() && *tracked && (in_worktree(path) || index.get(path).is_none()) {
+                remove_path(root, path)?;
+            }
+        }
+        let mut written = BTreeMap::new();
+        for (path, t, _) in &updates {
+            if let Some((mode, hash)) = t {
+                if skipped(path) {
+                    if in_worktree(path) {
+                        remove_path(root, path)?;
+                    }
+                    let mut entry = IndexEntry::new(path, *mode, hash);
+                    entry.set_skip_worktree(true);
+                    written.insert(*path, entry);
+                    continue;
+                }
+                let content = Object::read_blob(hash, self)?;
+                let entry = write_file(root, path, *mode, hash, &content, &checkout)?;
+                written.insert(*path, entry);
+            }
+        }
+
+        let mut entries = vec![];
+        for (path, (mode, hash)) in &target {
+            let entry = match (written.remove(path.as_str()), index.get(path)) {
+                (Some(entry), _) => entry,
+                (None, Some(entry)) if entry.mode == *mode && entry.hash == *hash => entry.clone(),
+                _ => IndexEntry::new(path, *mode, hash),
+            };
+            entries.push(entry);
+        }
+        index.entries = entries;
+
+        index.commit()
+    }
+
+    /// Restores the files matching `pathspecs` in the index and/or the
+    /// working tree, from a tree or from the index. Files that aren't in the
+    /// source are removed, unless in overlay mode. Returns the restored
+    /// paths.
+    pub fn restore_paths(
+        &self,
+        options: &RestoreOptions,
+        pathspecs: &[String],
+    ) -> Result<Vec<String>> {
+        let root = self.get_root();
+        let convert = self.convert()?;
+        let mut index = Index::lock(self)?;
+        let source = match options.source {
+            Some(tree) => Some(Tree::read_recursive(tree, self)?),
+            None => None,
+        };
+
+        // The candidates are the paths of the source and of the index
+        let mut paths: BTreeSet<&str> = match (&source, options.overlay) {
+            (Some(_), true) => BTreeSet::new(),
+            _ => index.entries.iter().map(|e| e.path.as_str()).collect(),
+        };
+        if let Some(source) = &source {
+            paths.extend(source.keys().map(String::as_str));
+        }
+        let mut matched = BTreeSet::new();
+        for spec in pathspecs {
+            let matches: Vec<&str> = paths
+                .iter()
+                .copied()
+                .filter(|path| match_pathspec(spec, path).is_some())
+                .collect();
+            if matches.is_empty() && !options.ignore_unmatch {
+                Err(RepoError::PathspecNotMatched(spec.clone()))?;
+            }
+            matched.extend(matches.into_iter().map(str::to_string));
+        }
+
+        if source.is_none() {
+            if let Some(path) = matched.iter().find(|path| index.get(path).is_none()) {
+                Err(RepoError::Unmerged(path.clone()))?;
+            }
+        }
+
+        for path in &matched {
+            let version = match &source {
+                Some(source) => source.get(path).cloned(),
+                None => index.get(path).map(|e| (e.mode, e.hash.clone())),
+            };
+
+            if options.worktree {
+                let unchanged = match index.get(path) {
+                    Some(entry) if Some((entry.mode, entry.hash.clone())) == version => {
+                        !index.is_modified(entry, root, &convert)?
+                    }
+                    _ => false,
+                };
+                match &version {
+                    Some(_) if unchanged => {}
+                    Some((mode, hash)) => {
+                        let content = Object::read_blob(hash, self)?;
+                        let entry = write_file(root, path, *mode, hash, &content, &convert)?;
+                        if options.staged || source.is_none() {
+                            index.add(entry);
+                        } else if let Some(indexed) = index.get(path) {
+                            // Refresh the stat data if the file now matches the index
+                            if indexed.mode == *mode && indexed.hash == *hash {
+                                index.add(entry);
+                            }
+                        }
+                    }
+                    None => remove_path(root, path)?,
+                }
+            }
+            if options.staged {
+                match &version {
+                    Some((mode, hash)) => match index.get(path) {
+                        Some(entry) if entry.mode == *mode && entry.hash == *hash => {}
+                        _ => {
+                            let mut entry = IndexEntry::new(path, *mode, hash);
+                            let full_path = root.join(path);
+                            if let Ok(meta) = fs::symlink_metadata(&full_path) {
+                                // Cache the stat data if the file matches the new entry
+                                if file_mode(&meta) == *mode
+                                    && hash_file(path, &meta, &convert)? == *hash
+                                {
+                                    entry.update_stat(&meta);
+                                }
+                            }
+                            index.add(entry);
+                        }
+                    },
+                    None => index.remove(path),
+                }
+            }
+        }
+        index.commit()?;
+
+        Ok(matched.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_reset_tree() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+
+        fs::write(root.join("a"), "a\n").unwrap();
+        fs::write(root.join("b"), "b\n").unwrap();
+        let old = repo.add_all().unwrap();
+        fs::write(root.join("a"), "a2\n").unwrap();
+        fs::write(root.join("c"), "c\n").unwrap();
+        let new = repo.add_all().unwrap();
+        repo.reset_index(&new).unwrap();
+
+        // Keep refuses to touch files with local changes that differ
+        fs::write(root.join("a"), "local\n").unwrap();
+        assert!(matches!(
+            repo.reset_tree(Some(&new), &old, ResetMode::Keep),
+            Err(RepoError::NotUptodate(path)) if path == "a"
+        ));
+        // but keeps local changes to other files
+        fs::write(root.join("a"), "a2\n").unwrap();
+        fs::write(root.join("b"), "local\n").unwrap();
+        repo.reset_tree(Some(&new), &old, ResetMode::Keep).unwrap();
+        assert_eq!(fs::read_to_string(root.join("a")).unwrap(), "a\n");
+        assert_eq!(fs::read_to_string(root.join("b")).unwrap(), "local\n");
+        assert!(!root.join("c").exists());
+
+        // Untracked files are in the way
+        fs::write(root.join("c"), "c\n").unwrap();
+        assert!(matches!(
+            repo.reset_tree(Some(&old), &new, ResetMode::Merge),
+            Err(RepoError::UntrackedWouldBeOverwritten(path)) if path == "c"
+        ));
+
+        // Hard discards everything
+        repo.reset_tree(Some(&old), &new, ResetMode::Hard).unwrap();
+        assert_eq!(fs::read_to_string(root.join("b")).unwrap(), "b\n");
+        let index = Index::load(&repo).unwrap();
+        assert_eq!(index.entries.len(), 3);
+        let convert = repo.convert().unwrap();
+        assert!(index
+            .entries
+            .iter()
+            .all(|entry| !index.is_modified(entry, root, &convert).unwrap()));
+
+        // Restoring a path from a tree into the index only
+        let options = RestoreOptions {
+            source: Some(&old),
+            staged: true,
+            ..Default::default()
+        };
+        let paths = repo.restore_paths(&options, &["a".to_string()]).unwrap();
+        assert_eq!(paths, ["a"]);
+        assert_eq!(fs::read_to_string(root.join("a")).unwrap(), "a2\n");
+        let index = Index::load(&repo).unwrap();
+        assert_eq!(
+            index.get("a").unwrap().hash,
+            Tree::read_recursive(&old, &repo).unwrap()["a"].1
+        );
+
+        // and back from the index into the working tree
+        let options = RestoreOptions {
+            worktree: true,
+            ..Default::default()
+        };
+        repo.restore_paths(&options, &["a".to_string()]).unwrap();
+        assert_eq!(fs::read_to_string(root.join("a")).unwrap(), "a\n");
+        assert!(matches!(
+            repo.restore_paths(&options, &["nope".to_string()]),
+            Err(RepoError::PathspecNotMatched(_))
+        ));
+
+        // In overlay mode, like `checkout <tree-ish> -- <paths>`, files that
+        // aren't in the source are kept
+        let options = RestoreOptions {
+            source: Some(&old),
+            staged: true,
+            worktree: true,
+            overlay: true,
+            ..Default::default()
+        };
+        let paths = repo.restore_paths(&options, &[".".to_string()]).unwrap();
+        assert_eq!(paths, ["a", "b"]);
+        assert!(root.join("c").exists());
+        assert!(Index::load(&repo).unwrap().get("c").is_some());
+        let options = RestoreOptions {
+            overlay: false,
+            ..options
+        };
+        repo.restore_paths(&options, &[".".to_string()]).unwrap();
+        assert!(!root.join("c").exists());
+    }
+}
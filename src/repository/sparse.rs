@@ -0,0 +1,410 @@
+//! Sparse checkout: only the files matching the patterns of
+//! `info/sparse-checkout` are in the working tree. The index entries of the
+//! others have the skip-worktree bit set, so they aren't reported as
+//! deleted and are committed as they are in the index.
+
+use std::{collections::BTreeSet, fs};
+
+use crate::{object::Object, wildmatch::is_glob};
+
+use super::{
+    checkout::{remove_path, write_file},
+    error::Result,
+    ignore::Pattern,
+    index::{is_missing, Index},
+    Repository,
+};
+
+const SOURCE: &str = ".git/info/sparse-checkout";
+
+/// The patterns that make up a sparse checkout without any directories:
+/// only the files at the root.
+const DEFAULT_PATTERNS: &str = "/*\n!/*/\n";
+
+/// How the patterns of a sparse checkout are matched.
+#[derive(Debug)]
+enum SparsePatterns {
+    /// Cone mode: the files at the root, every file below the recursive
+    /// directories and the files directly in their parents
+    Cone {
+        recursive: BTreeSet<String>,
+        parents: BTreeSet<String>,
+    },
+    /// Gitignore-style patterns, where a file is included if the last
+    /// pattern matching it or one of its directories isn't negated
+    Full(Vec<Pattern>),
+}
+
+/// The files that are checked out in a sparse checkout.
+#[derive(Debug)]
+pub struct SparseCheckout {
+    patterns: SparsePatterns,
+}
+
+impl SparseCheckout {
+    /// Returns a cone of the given directories, relative to the root.
+    pub fn from_dirs(dirs: &[String]) -> SparseCheckout {
+        let recursive: BTreeSet<String> = dirs.iter().cloned().collect();
+        // Directories inside the recursive ones are included already
+        let in_recursive = |dir: &str| {
+            recursive.contains(dir) || ancestors(dir).any(|parent| recursive.contains(parent))
+        };
+        let parents = recursive
+            .iter()
+            .flat_map(|dir| ancestors(dir))
+            .filter(|dir| !in_recursive(dir))
+            .map(str::to_string)
+            .collect();
+        let recursive = recursive
+            .iter()
+            .filter(|dir| !ancestors(dir).any(|parent| recursive.contains(parent)))
+            .cloned()
+            .collect();
+
+        SparseCheckout {
+            patterns: SparsePatterns::Cone { recursive, parents },
+        }
+    }
+
+    /// Parses the content of `info/sparse-checkout`. Patterns that don't
+    /// make up a cone are matched as full patterns, with a warning.
+    pub fn parse(content: &str, cone: bool) -> SparseCheckout {
+        let patterns: Vec<Pattern> = content
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| Pattern::parse(line, "", SOURCE, i + 1))
+            .collect();
+        if cone {
+            match parse_cone(&patterns) {
+                Ok(patterns) => return SparseCheckout { patterns },
+                Err(pattern) => {
+                    eprintln!("warning: unrecognized pattern: '{pattern}'");
+                    eprintln!("warning: disabling cone pattern matching");
+                }
+            }
+        }
+
+        SparseCheckout {
+            patterns: SparsePatterns::Full(patterns),
+        }
+    }
+
+    /// Returns full patterns, or the default ones if there are none.
+    pub fn from_patterns(patterns: &[String]) -> SparseCheckout {
+        if patterns.is_empty() {
+            return SparseCheckout::parse(DEFAULT_PATTERNS, false);
+        }
+        let content: String = patterns
+            .iter()
+            .map(|pattern| format!("{pattern}\n"))
+            .collect();
+        SparseCheckout::parse(&content, false)
+    }
+
+    pub fn is_cone(&self) -> bool {
+        matches!(self.patterns, SparsePatterns::Cone { .. })
+    }
+
+    /// Returns true if the file at `path` is checked out.
+    pub fn contains(&self, path: &str) -> bool {
+        match &self.patterns {
+            SparsePatterns::Cone { recursive, parents } => {
+                let Some((dir, _)) = path.rsplit_once('/') else {
+                    return true;
+                };
+                parents.contains(dir)
+                    || recursive.contains(dir)
+                    || ancestors(dir).any(|parent| recursive.contains(parent))
+            }
+            SparsePatterns::Full(patterns) => {
+                // Paths no pattern matches take the decision of their
+                // directory
+                let mut path = path;
+                let mut is_dir = false;
+                loop {
+                    let matched = patterns
+                        .iter()
+                        .rev()
+                        .find(|pattern| pattern.matches(path, is_dir, 0));
+                    if let Some(pattern) = matched {
+                        return !pattern.negated;
+                    }
+                    match path.rsplit_once('/') {
+                        Some((dir, _)) => path = dir,
+                        None => return false,
+                    }
+                    is_dir = true;
+                }
+            }
+        }
+    }
+
+    /// Returns the outermost directory of `path` outside a cone, if any.
+    fn outside_dir<'p>(&self, path: &'p str) -> Option<&'p str> {
+        let SparsePatterns::Cone { recursive, parents } = &self.patterns else {
+            return None;
+        };
+        let mut dirs: Vec<&str> = ancestors(path).collect();
+        dirs.reverse();
+        for dir in dirs {
+            if recursive.contains(dir) {
+                return None;
+            }
+            if !parents.contains(dir) {
+                return Some(dir);
+            }
+        }
+        None
+    }
+
+    /// Returns the recursive directories of a cone, or the patterns.
+    pub fn list(&self) -> Vec<String> {
+        match &self.patterns {
+            SparsePatterns::Cone { recursive, .. } => recursive.iter().cloned().collect(),
+            SparsePatterns::Full(patterns) => patterns.iter().map(Pattern::to_string).collect(),
+        }
+    }
+
+    /// Returns the content of `info/sparse-checkout`.
+    fn serialize(&self) -> String {
+        match &self.patterns {
+            SparsePatterns::Cone { recursive, parents } => {
+                let mut content = DEFAULT_PATTERNS.to_string();
+                for dir in parents {
+                    let dir = escape(dir);
+                    content.push_str(&format!("/{dir}/\n!/{dir}/*/\n"));
+                }
+                for dir in recursive {
+                    content.push_str(&format!("/{}/\n", escape(dir)));
+                }
+                content
+            }
+            SparsePatterns::Full(patterns) => patterns
+                .iter()
+                .map(|pattern| format!("{pattern}\n"))
+                .collect(),
+        }
+    }
+}
+
+/// Returns the parent directories of `dir`, the innermost first.
+fn ancestors(dir: &str) -> impl Iterator<Item = &str> {
+    dir.rmatch_indices('/').map(|(end, _)| &dir[..end])
+}
+
+/// Escapes the glob characters of a directory name with backslashes.
+fn escape(dir: &str) -> String {
+    let mut escaped = String::new();
+    for c in dir.chars() {
+        if is_glob(c.encode_utf8(&mut [0; 4])) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Parses the patterns of a cone, returning the first pattern that doesn't
+/// fit if they aren't one.
+fn parse_cone(patterns: &[Pattern]) -> std::result::Result<SparsePatterns, String> {
+    let mut recursive = BTreeSet::new();
+    let mut parents = BTreeSet::new();
+    for pattern in patterns {
+        let Some(dir) = pattern.pattern.strip_prefix('/') else {
+            return Err(pattern.to_string());
+        };
+        match (pattern.negated, pattern.dir_only, dir) {
+            // `/*` and `!/*/`, the files at the root
+            (false, false, "*") | (true, true, "*") => {}
+            // `!/dir/*/` leaves out the subdirectories of a parent
+            (true, true, dir) if dir.ends_with("/*") => {
+                let dir = unescape(&dir[..dir.len() - 2]);
+                recursive.remove(&dir);
+                parents.insert(dir);
+            }
+            (false, true, dir) if !has_unescaped_glob(dir) => {
+                recursive.insert(unescape(dir));
+            }
+            _ => return Err(pattern.to_string()),
+        }
+    }
+
+    Ok(SparsePatterns::Cone { recursive, parents })
+}
+
+fn has_unescaped_glob(s: &str) -> bool {
+    let mut escaped = false;
+    for c in s.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+impl Repository {
+    /// Returns the patterns of the sparse checkout, if enabled with
+    /// `core.sparseCheckout` and `info/sparse-checkout` exists.
+    pub fn sparse_checkout(&self) -> Result<Option<SparseCheckout>> {
+        let config = self.config()?;
+        if config.get_bool("core.sparseCheckout") != Some(true) {
+            return Ok(None);
+        }
+        let cone = config.get_bool("core.sparseCheckoutCone") == Some(true);
+        self.read_sparse_checkout(cone)
+    }
+
+    /// Reads `info/sparse-checkout`, if it exists, whether or not sparse
+    /// checkout is enabled.
+    pub fn read_sparse_checkout(&self, cone: bool) -> Result<Option<SparseCheckout>> {
+        match fs::read(self.dir.join("info/sparse-checkout")) {
+            Ok(content) => Ok(Some(SparseCheckout::parse(
+                &String::from_utf8_lossy(&content),
+                cone,
+            ))),
+            Err(e) if is_missing(&e) => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    /// Enables sparse checkout with the given patterns, writing them to
+    /// `info/sparse-checkout`, or disables it, and updates the working
+    /// tree.
+    pub fn set_sparse_checkout(&self, sparse: Option<&SparseCheckout>) -> Result<()> {
+        if let Some(sparse) = sparse {
+            fs::create_dir_all(self.dir.join("info"))?;
+            fs::write(self.dir.join("info/sparse-checkout"), sparse.serialize())?;
+        }
+        self.update_sparse_worktree(sparse)?;
+
+        let mut config = self.config()?;
+        let cone = sparse.is_some_and(SparseCheckout::is_cone);
+        config.set("core.sparseCheckout", &sparse.is_some().to_string())?;
+        config.set("core.sparseCheckoutCone", &cone.to_string())?;
+        config.save()?;
+
+        Ok(())
+    }
+
+    /// Removes the files outside the sparse checkout from the working tree,
+    /// unless they have local changes, which are reported, and writes the files inside it that
+    /// are missing, updating their skip-worktree bits. Without a sparse
+    /// checkout every file is written.
+    pub fn update_sparse_worktree(&self, sparse: Option<&SparseCheckout>) -> Result<()> {
+        let root = self.get_root();
+        let convert = self.convert()?;
+        let mut index = Index::lock(self)?;
+        let mut left = vec![];
+        for i in 0..index.entries.len() {
+            let entry = &index.entries[i];
+            if entry.stage != 0 {
+                continue;
+            }
+            let wanted = sparse.is_none_or(|sparse| sparse.contains(&entry.path));
+            let present = fs::symlink_metadata(root.join(&entry.path)).is_ok();
+            if wanted && entry.skip_worktree() {
+                if present {
+                    index.entries[i].set_skip_worktree(false);
+                } else {
+                    let content = Object::read_blob(&entry.hash, self)?;
+                    let (path, mode, hash) = (&entry.path, entry.mode, &entry.hash);
+                    index.entries[i] = write_file(root, path, mode, hash, &content, &convert)?;
+                }
+            } else if !wanted && !entry.skip_worktree() {
+                if present && index.is_modified(entry, root, &convert)? {
+                    left.push(entry.path.clone());
+                    continue;
+                }
+                remove_path(root, &entry.path)?;
+                index.entries[i].set_skip_worktree(true);
+            }
+        }
+        if !left.is_empty() {
+            eprintln!("warning: The following paths are not up to date and were left despite sparse patterns:");
+            for path in &left {
+                eprintln!("\t{path}");
+            }
+            eprintln!();
+            eprintln!(
+                "After fixing the above paths, you may want to run `git sparse-checkout reapply`."
+            );
+        }
+
+        // The directories outside a cone are only left if there are other
+        // files in them
+        if let Some(sparse) = sparse {
+            let (skipped, present): (Vec<_>, Vec<_>) = index
+                .entries
+                .iter()
+                .partition(|entry| entry.skip_worktree());
+            let present: BTreeSet<&str> = present
+                .iter()
+                .filter_map(|entry| sparse.outside_dir(&entry.path))
+                .collect();
+            let dirs: BTreeSet<&str> = skipped
+                .iter()
+                .filter_map(|entry| sparse.outside_dir(&entry.path))
+                .filter(|dir| !present.contains(dir))
+                .collect();
+            for dir in dirs {
+                if root.join(dir).is_dir() {
+                    eprintln!("warning: directory '{dir}/' contains untracked files, but is not in the sparse-checkout cone");
+                }
+            }
+        }
+        index.commit()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_checkout() {
+        let cone = SparseCheckout::from_dirs(&["a/b".to_string(), "d".to_string()]);
+        assert_eq!(cone.serialize(), "/*\n!/*/\n/a/\n!/a/*/\n/a/b/\n/d/\n");
+        let cases = [
+            ("top", true),
+            ("a/x", true),
+            ("a/b/c/z", true),
+            ("a/c/y", false),
+            ("d/w", true),
+            ("e/v", false),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(cone.contains(path), expected, "{path}");
+            let parsed = SparseCheckout::parse(&cone.serialize(), true);
+            assert!(parsed.is_cone());
+            assert_eq!(parsed.contains(path), expected, "{path}");
+            // The same patterns give the same result in full mode
+            let full = SparseCheckout::parse(&cone.serialize(), false);
+            assert_eq!(full.contains(path), expected, "{path}");
+        }
+
+        let full = SparseCheckout::parse("/a/\n!/a/b/c/\n*.txt\n", true);
+        assert!(!full.is_cone());
+        assert!(full.contains("a/b/y"));
+        assert!(!full.contains("a/b/c/z"));
+        assert!(full.contains("a/b/c/z.txt"));
+        assert!(!full.contains("top"));
+        assert_eq!(full.list(), ["/a/", "!/a/b/c/", "*.txt"]);
+    }
+}
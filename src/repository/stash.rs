@@ -0,0 +1,574 @@
+//! Stashing local changes away, stored the way git stores them so stashes
+//! are shared with git. A stash is a commit of the working tree (W) whose
+//! parents are the commit HEAD pointed to, a commit of the index (I), and
+//! optionally a commit of the untracked files (U). `refs/stash` points at
+//! the newest stash, and its reflog lists all of them.
+
+use std::{collections::BTreeMap, fs};
+
+use crate::{
+    merge::{self, Labels},
+    object::{
+        tree::{file_mode, write_blob, write_tree_from_files, Tree, MODE_SYMLINK},
+        write_commit, Commit, Object,
+    },
+    pathspec::match_pathspec,
+};
+
+use super::{
+    checkout::{remove_path, write_file},
+    diff::diff_files,
+    error::{RepoError, Result},
+    index::{is_missing, Index, IndexEntry},
+    refs::ReflogEntry,
+    reset::{ResetMode, RestoreOptions},
+    status::UntrackedFiles,
+    Repository,
+};
+
+pub const STASH_REF: &str = "refs/stash";
+
+/// The names of the sides of the merge when applying a stash.
+const LABELS: Labels = Labels {
+    ours: "Updated upstream",
+    theirs: "Stashed changes",
+};
+
+/// Files by path, as `(mode, hash)`.
+type Files = BTreeMap<String, (u32, String)>;
+
+/// Options of [`Repository::stash_push`].
+#[derive(Debug, Default)]
+pub struct StashOptions<'a> {
+    /// The description of the stash, instead of the HEAD commit
+    pub message: Option<&'a str>,
+    /// Only stash the files matching these, or all files if empty
+    pub pathspecs: &'a [String],
+    /// Stash untracked files too, and remove them
+    pub include_untracked: bool,
+    /// Leave the changes in the index in place
+    pub keep_index: bool,
+}
+
+/// The commits a stash is made of.
+#[derive(Debug)]
+pub struct Stash {
+    /// The commit the stash was made on
+    pub base: String,
+    pub base_tree: String,
+    pub index_tree: String,
+    pub worktree_tree: String,
+    pub untracked_tree: Option<String>,
+}
+
+impl Stash {
+    /// Reads the stash with the given W commit.
+    pub fn read(hash: &str, repo: &Repository) -> Result<Stash> {
+        let commit = Commit::read(hash, repo)?;
+        let [base, index, untracked @ ..] = commit.parents.as_slice() else {
+            return Err(RepoError::Other(format!(
+                "'{hash}' is not a stash-like commit"
+            )));
+        };
+        let untracked_tree = match untracked.first() {
+            Some(untracked) => Some(Commit::read(untracked, repo)?.tree),
+            None => None,
+        };
+
+        Ok(Stash {
+            base: base.clone(),
+            base_tree: Commit::read(base, repo)?.tree,
+            index_tree: Commit::read(index, repo)?.tree,
+            worktree_tree: commit.tree,
+            untracked_tree,
+        })
+    }
+}
+
+/// The outcome of applying a stash.
+#[derive(Debug, Default)]
+pub struct ApplyOutcome {
+    /// What happened during the merge, e.g. `CONFLICT (content): ...`
+    pub messages: Vec<String>,
+    /// Whether some files have conflicts
+    pub conflicts: bool,
+}
+
+/// The result of merging one path of two trees.
+#[derive(Debug)]
+enum MergedPath {
+    Clean(Option<(u32, String)>),
+    /// The path conflicts, the working tree gets `content` in `mode`
+    Conflict {
+        versions: [Option<(u32, String)>; 3],
+        mode: u32,
+        content: Vec<u8>,
+    },
+}
+
+impl Repository {
+    /// Returns the stashes, newest first.
+    pub fn stash_list(&self) -> Result<Vec<ReflogEntry>> {
+        let mut stashes = self.read_reflog(STASH_REF)?;
+        stashes.reverse();
+        Ok(stashes)
+    }
+
+    /// Saves the local changes to a new stash and reverts them to HEAD,
+    /// returning the message of the stash, or `None` if there were no local
+    /// changes to save.
+    pub fn stash_push(&self, options: &StashOptions) -> Result<Option<String>> {
+        let root = self.get_root();
+        let head = self.read_ref("HEAD")?.ok_or_else(|| {
+            RepoError::Other("You do not have the initial commit yet".to_string())
+        })?;
+        let head_commit = Commit::read(&head, self)?;
+        let head_files = Tree::read_recursive(&head_commit.tree, self)?;
+        let convert = self.convert()?;
+        let index = Index::load(self)?;
+        if index.has_conflicts() {
+            Err(RepoError::UnmergedIndex)?;
+        }
+
+        let matches = |path: &str| {
+            options.pathspecs.is_empty()
+                || options
+                    .pathspecs
+                    .iter()
+                    .any(|spec| match_pathspec(spec, path).is_some())
+        };
+        let untracked: Vec<String> = match options.include_untracked {
+            true => self.status(UntrackedFiles::All)?.untracked,
+            false => vec![],
+        };
+        for spec in options.pathspecs {
+            let known = index.entries.iter().map(|e| &e.path).chain(&untracked);
+            if !known
+                .chain(head_files.keys())
+                .any(|path| match_pathspec(spec, path).is_some())
+            {
+                Err(RepoError::PathspecNotMatched(spec.clone()))?;
+            }
+        }
+        let untracked: Vec<String> = untracked.into_iter().filter(|p| matches(p)).collect();
+
+        // The working tree is the index with the local changes to matching files
+        let index_files = index.files();
+        let mut worktree_files = index_files.clone();
+        for entry in index.entries.iter().filter(|e| matches(&e.path)) {
+            let path = root.join(&entry.path);
+            match fs::symlink_metadata(&path) {
+                Err(e) if is_missing(&e) => {
+                    worktree_files.remove(&entry.path);
+                }
+                Err(e) => Err(e)?,
+                Ok(meta) if index.is_modified(entry, root, &convert)? => {
+                    let hash = write_blob(&entry.path, &meta, self, &convert)?;
+                    worktree_files.insert(entry.path.clone(), (file_mode(&meta), hash));
+                }
+                Ok(_) => {}
+            }
+        }
+
+        let staged = diff_files(&head_files, &index_files)
+            .iter()
+            .any(|change| matches(&change.path));
+        if !staged && worktree_files == index_files && untracked.is_empty() {
+            return Ok(None);
+        }
+
+        let branch = self.read_symbolic_ref("HEAD")?;
+        let branch = branch
+            .as_deref()
+            .map_or("(no branch)", |b| b.trim_start_matches("refs/heads/"));
+        let description = format!("{branch}: {} {}", &head[..7], head_commit.subject());
+
+        let index_tree = write_tree_from_files(&index_files, self)?;
+        let index_commit = write_commit(
+            &index_tree,
+            &[&head],
+            &format!("index on {description}"),
+            self,
+        )?;
+        let mut parents = vec![head.as_str(), index_commit.as_str()];
+        let untracked_commit;
+        if !untracked.is_empty() {
+            let mut files = Files::new();
+            for path in &untracked {
+                let full_path = root.join(path);
+                let meta = fs::symlink_metadata(&full_path)?;
+                files.insert(
+                    path.clone(),
+                    (file_mode(&meta), write_blob(path, &meta, self, &convert)?),
+                );
+            }
+            untracked_commit = write_commit(
+                &write_tree_from_files(&files, self)?,
+                &[],
+                &format!("untracked files on {description}"),
+                self,
+            )?;
+            parents.push(&untracked_commit);
+        }
+        let message = match options.message {
+            Some(message) => format!("On {branch}: {message}"),
+            None => format!("WIP on {description}"),
+        };
+        let worktree_commit = write_commit(
+            &write_tree_from_files(&worktree_files, self)?,
+            &parents,
+            &message,
+            self,
+        )?;
+        self.update_ref(STASH_REF, &worktree_commit, &message)?;
+
+        // Revert the stashed changes
+        if options.pathspecs.is_empty() {
+            self.reset_tree(Some(&head_commit.tree), &head_commit.tree, ResetMode::Hard)?;
+        } else {
+            let restore = RestoreOptions {
+                source: Some(&head_commit.tree),
+                staged: true,
+                worktree: true,
+                ignore_unmatch: true,
+                overlay: false,
+            };
+            self.restore_paths(&restore, options.pathspecs)?;
+        }
+        for path in &untracked {
+            remove_path(root, path)?;
+        }
+        if options.keep_index {
+            let restore = RestoreOptions {
+                source: Some(&index_tree),
+                staged: true,
+                worktree: true,
+                ignore_unmatch: true,
+                overlay: false,
+            };
+            match options.pathspecs.is_empty() {
+                true => self.restore_paths(&restore, &[".".to_string()])?,
+                false => self.restore_paths(&restore, options.pathspecs)?,
+            };
+        }
+
+        Ok(Some(message))
+    }
+
+    /// Applies the changes of the stash with the given W commit to the
+    /// working tree, merging them with changes made since. The changes to
+    /// the index are applied to the index too if `restore_index` is set,
+    /// otherwise only new files are added to it. Nothing is changed if
+    /// local changes are in the way.
+    pub fn stash_apply(&self, hash: &str, restore_index: bool) -> Result<ApplyOutcome> {
+        let root = self.get_root();
+        let stash = Stash::read(hash, self)?;
+        let convert = self.convert()?;
+        let mut index = Index::lock(self)?;
+        if index.has_conflicts() {
+            Err(RepoError::UnmergedIndex)?;
+        }
+
+        let base = Tree::read_recursive(&stash.base_tree, self)?;
+        let current = index.files();
+        let stashed = Tree::read_recursive(&stash.worktree_tree, self)?;
+
+        // The index the stash had, applied to the current one
+        let new_index = match restore_index && stash.index_tree != stash.base_tree {
+            true => {
+                let stashed_index = Tree::read_recursive(&stash.index_tree, self)?;
+                let mut files = Files::new();
+                let merged = self.merge_files(&base, &current, &stashed_index, &mut vec![])?;
+                for (path, merged) in merged {
+                    match merged {
+                        MergedPath::Clean(Some(file)) => {
+                            files.insert(path, file);
+                        }
+                        MergedPath::Clean(None) => {}
+                        MergedPath::Conflict { .. } => Err(RepoError::Other(
+                            "Conflicts in index. Try without --index.".to_string(),
+                        ))?,
+                    }
+                }
+                Some(files)
+            }
+            false if restore_index => Some(current.clone()),
+            false => None,
+        };
+
+        // Check that untracked files and local changes aren't in the way
+        let mut outcome = ApplyOutcome::default();
+        let merged: BTreeMap<String, MergedPath> = self
+            .merge_files(&base, &current, &stashed, &mut outcome.messages)?
+            .into_iter()
+            .filter(|(path, merged)| match merged {
+                MergedPath::Clean(file) => file.as_ref() != current.get(path),
+                MergedPath::Conflict { .. } => true,
+            })
+            .collect();
+        let (mut local_changes, mut untracked) = (vec![], vec![]);
+        for path in merged.keys() {
+            match index.get(path) {
+                Some(entry) if index.is_modified(entry, root, &convert)? => {
+                    local_changes.push(path.clone())
+                }
+                Some(_) => {}
+                None if fs::symlink_metadata(root.join(path)).is_ok() => {
+                    untracked.push(path.clone())
+                }
+                None => {}
+            }
+        }
+
+        if !local_changes.is_empty() {
+            Err(RepoError::MergeLocalChanges(local_changes))?;
+        }
+        if !untracked.is_empty() {
+            Err(RepoError::MergeUntrackedOverwritten(untracked))?;
+        }
+        let untracked_files = match &stash.untracked_tree {
+            Some(tree) => Tree::read_recursive(tree, self)?,
+            None => Files::new(),
+        };
+        for path in untracked_files.keys() {
+            if fs::symlink_metadata(root.join(path)).is_ok() {
+                Err(RepoError::Other(format!(
+                    "{path} already exists, no checkout\n\
+                     could not restore untracked files from stash"
+                )))?;
+            }
+        }
+
+        for (path, merged) in merged {
+            let (mode, hash, content) = match merged {
+                MergedPath::Clean(None) => {
+                    remove_path(root, &path)?;
+                    index.remove(&path);
+                    continue;
+                }
+                MergedPath::Clean(Some((mode, hash))) => {
+                    let content = Object::read_blob(&hash, self)?;
+                    (mode, hash, content)
+                }
+                MergedPath::Conflict {
+                    versions,
+                    mode,
+                    content,
+                } => {
+                    outcome.conflicts = true;
+                    // The working tree file isn't in the index, only its versions
+                    write_file(root, &path, mode, "", &content, &convert)?;
+                    index.remove(&path);
+                    for (stage, version) in versions.iter().enumerate() {
+                        if let Some((mode, hash)) = version {
+                            let mut entry = IndexEntry::new(&path, *mode, hash);
+                            entry.stage = stage as u8 + 1;
+                            index.add(entry);
+                        }
+                    }
+                    continue;
+                }
+            };
+            index.add(write_file(root, &path, mode, &hash, &content, &convert)?);
+        }
+
+        if !outcome.conflicts {
+            // Only the files the stash added stay in the index, unless its
+            // index is restored
+            let files = new_index.unwrap_or_else(|| {
+                let mut files = current.clone();
+                for entry in &index.entries {
+                    if !current.contains_key(&entry.path) {
+                        files.insert(entry.path.clone(), (entry.mode, entry.hash.clone()));
+                    }
+                }
+                files
+            });
+            let mut entries = vec![];
+            for (path, (mode, hash)) in files {
+                let entry = match index.get(&path) {
+                    Some(entry) if entry.mode == mode && entry.hash == hash => entry.clone(),
+                    _ => IndexEntry::new(&path, mode, &hash),
+                };
+                entries.push(entry);
+            }
+            index.entries = entries;
+        }
+        index.commit()?;
+
+        for (path, (mode, hash)) in &untracked_files {
+            let content = Object::read_blob(hash, self)?;
+            write_file(root, path, *mode, hash, &content, &convert)?;
+        }
+
+        Ok(outcome)
+    }
+
+    /// Deletes the stash `stash@{n}`.
+    pub fn stash_drop(&self, n: usize) -> Result<()> {
+        self.delete_reflog_entry(STASH_REF, n)
+    }
+
+    /// Deletes all stashes.
+    pub fn stash_clear(&self) -> Result<()> {
+        self.delete_ref(STASH_REF)
+    }
+
+    /// Merges the changes `ours` and `theirs` made to `base`, path by path.
+    /// The contents of files changed on both sides are merged. What happens
+    /// is described in `messages`, like git does.
+    fn merge_files(
+        &self,
+        base: &Files,
+        ours: &Files,
+        theirs: &Files,
+        messages: &mut Vec<String>,
+    ) -> Result<Vec<(String, MergedPath)>> {
+        let mut paths: Vec<&String> = base
+            .keys()
+            .chain(ours.keys())
+            .chain(theirs.keys())
+            .collect();
+        paths.sort();
+        paths.dedup();
+
+        let mut merged = vec![];
+        for path in paths {
+            let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+            let result = if o == t || b == t {
+                MergedPath::Clean(o.cloned())
+            } else if b == o {
+                MergedPath::Clean(t.cloned())
+            } else {
+                let versions = [b.cloned(), o.cloned(), t.cloned()];
+                match (o, t) {
+                    (Some(o), Some(t)) if o.0 != MODE_SYMLINK && t.0 != MODE_SYMLINK => {
+                        messages.push(format!("Auto-merging {path}"));
+                        let read = |file: Option<&(u32, String)>| match file {
+                            Some((_, hash)) => Object::read_blob(hash, self),
+                            None => Ok(vec![]),
+                        };
+                        let result =
+                            merge::merge(&read(b)?, &read(Some(o))?, &read(Some(t))?, &LABELS);
+                        // A mode change on one side wins
+                        let mode = if b.is_some_and(|b| b.0 == o.0) {
+                            t.0
+                        } else {
+                            o.0
+                        };
+                        if result.conflicts {
+                            MergedPath::Conflict {
+                                versions,
+                                mode,
+                                content: result.content,
+                            }
+                        } else {
+                            let hash =
+                                Object::blob_from_bytes(&result.content).write_to_objects(self)?;
+                            MergedPath::Clean(Some((mode, hash)))
+                        }
+                    }
+                    // A deleted or type-changed file conflicts, the
+                    // remaining version is left in the working tree
+                    _ => {
+                        let (mode, hash) = o.or(t).cloned().unwrap_or_default();
+                        MergedPath::Conflict {
+                            versions,
+                            mode,
+                            content: Object::read_blob(&hash, self)?,
+                        }
+                    }
+                }
+            };
+            if let MergedPath::Conflict { versions, .. } = &result {
+                messages.push(conflict_message(path, versions));
+            }
+            merged.push((path.clone(), result));
+        }
+
+        Ok(merged)
+    }
+}
+
+/// Describes a conflict like git does.
+fn conflict_message(path: &str, versions: &[Option<(u32, String)>; 3]) -> String {
+    let (ours, theirs) = (LABELS.ours, LABELS.theirs);
+    match versions {
+        [None, Some(_), Some(_)] => format!("CONFLICT (add/add): Merge conflict in {path}"),
+        [_, None, _] => format!(
+            "CONFLICT (modify/delete): {path} deleted in {ours} and modified in {theirs}.  \
+             Version {theirs} of {path} left in tree."
+        ),
+        [_, _, None] => format!(
+            "CONFLICT (modify/delete): {path} deleted in {theirs} and modified in {ours}.  \
+             Version {ours} of {path} left in tree."
+        ),
+        _ => format!("CONFLICT (content): Merge conflict in {path}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_stash() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+        fs::write(root.join("a"), "1\n2\n3\n4\n5\n").unwrap();
+        let tree = repo.add_all().unwrap();
+        let commit = write_commit(&tree, &[], "one", &repo).unwrap();
+        repo.update_ref("HEAD", &commit, "commit (initial): one")
+            .unwrap();
+        repo.reset_index(&tree).unwrap();
+
+        // Nothing to stash
+        let options = StashOptions {
+            include_untracked: true,
+            ..Default::default()
+        };
+        assert_eq!(repo.stash_push(&options).unwrap(), None);
+
+        fs::write(root.join("a"), "one\n2\n3\n4\n5\n").unwrap();
+        fs::write(root.join("u"), "untracked\n").unwrap();
+        let message = repo.stash_push(&options).unwrap();
+        assert_eq!(
+            message.as_deref(),
+            Some(&*format!("WIP on main: {} one", &commit[..7]))
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("a")).unwrap(),
+            "1\n2\n3\n4\n5\n"
+        );
+        assert!(!root.join("u").exists());
+        let stashes = repo.stash_list().unwrap();
+        assert_eq!(stashes.len(), 1);
+        let stash = Stash::read(&stashes[0].new, &repo).unwrap();
+        assert_eq!(stash.base, commit);
+        assert!(stash.untracked_tree.is_some());
+
+        // Changes committed since are merged with the stashed ones
+        fs::write(root.join("a"), "1\n2\n3\n4\nfive\n").unwrap();
+        let tree = repo.add_all().unwrap();
+        let commit = write_commit(&tree, &[&commit], "two", &repo).unwrap();
+        repo.update_ref("HEAD", &commit, "commit: two").unwrap();
+        repo.reset_index(&tree).unwrap();
+        let outcome = repo.stash_apply(&stashes[0].new, false).unwrap();
+        assert!(!outcome.conflicts);
+        assert_eq!(outcome.messages, ["Auto-merging a"]);
+        assert_eq!(
+            fs::read_to_string(root.join("a")).unwrap(),
+            "one\n2\n3\n4\nfive\n"
+        );
+        assert_eq!(fs::read_to_string(root.join("u")).unwrap(), "untracked\n");
+
+        // Untracked files are in the way of applying it again
+        assert!(repo.stash_apply(&stashes[0].new, false).is_err());
+
+        repo.stash_drop(0).unwrap();
+        assert!(repo.stash_list().unwrap().is_empty());
+        assert_eq!(repo.read_ref(STASH_REF).unwrap(), None);
+    }
+}
@@ -0,0 +1,559 @@
+//! Comparing HEAD, the index and the working tree.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use crate::object::{
+    tree::{file_mode, Tree, MODE_GITLINK, MODE_SYMLINK},
+    Commit,
+};
+
+use super::{
+    error::Result,
+    ignore::{Ignore, TrackedPaths, Tracking},
+    index::{hash_file, is_missing, Index},
+    submodule::SubmoduleChanges,
+    untracked_cache::{
+        self, StatData, UntrackedCache, UntrackedDir, DIR_HIDE_EMPTY_DIRECTORIES,
+        DIR_SHOW_OTHER_DIRECTORIES,
+    },
+    Repository,
+};
+
+/// A file's mode and object hash.
+pub type Version = (u32, String);
+
+/// How a file changed from one side of a comparison to the other, using the
+/// letters of `git status --short`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Unmodified,
+    Added,
+    Modified,
+    Deleted,
+    /// Changed from a file to a symlink or vice versa
+    TypeChanged,
+}
+
+impl Change {
+    pub fn code(self) -> char {
+        match self {
+            Change::Unmodified => '.',
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+            Change::TypeChanged => 'T',
+        }
+    }
+
+    /// Returns the label of the long format, e.g. `new file:`.
+    pub fn label(self) -> &'static str {
+        match self {
+            Change::Unmodified => "unmodified:",
+            Change::Added => "new file:",
+            Change::Modified => "modified:",
+            Change::Deleted => "deleted:",
+            Change::TypeChanged => "typechange:",
+        }
+    }
+}
+
+/// A tracked file that differs between HEAD, the index and the working tree.
+#[derive(Debug, Clone)]
+pub struct FileStatus {
+    pub path: String,
+    /// HEAD compared to the index
+    pub staged: Change,
+    /// The index compared to the working tree
+    pub unstaged: Change,
+    pub head: Option<Version>,
+    pub index: Option<Version>,
+    /// The mode of the file in the working tree
+    pub worktree_mode: Option<u32>,
+    /// How a submodule differs from the commit in the index
+    pub submodule: Option<SubmoduleChanges>,
+}
+
+/// A file with a merge conflict.
+#[derive(Debug, Clone)]
+pub struct UnmergedStatus {
+    pub path: String,
+    /// The base, ours and theirs versions
+    pub stages: [Option<Version>; 3],
+    pub worktree_mode: Option<u32>,
+}
+
+impl UnmergedStatus {
+    /// Returns the two letter code of `git status --short`, e.g. `UU`.
+    pub fn code(&self) -> &'static str {
+        match self.stages.each_ref().map(Option::is_some) {
+            [true, false, false] => "DD",
+            [false, true, false] => "AU",
+            [true, true, false] => "UD",
+            [false, false, true] => "UA",
+            [true, false, true] => "DU",
+            [false, true, true] => "AA",
+            _ => "UU",
+        }
+    }
+
+    /// Returns the label of the long format, e.g. `both modified:`.
+    pub fn label(&self) -> &'static str {
+        match self.code() {
+            "DD" => "both deleted:",
+            "AU" => "added by us:",
+            "UD" => "deleted by them:",
+            "UA" => "added by them:",
+            "DU" => "deleted by us:",
+            "AA" => "both added:",
+            _ => "both modified:",
+        }
+    }
+}
+
+/// Which untracked files to report.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UntrackedFiles {
+    No,
+    /// Untracked directories as a whole, as `dir/`
+    #[default]
+    Normal,
+    All,
+}
+
+/// The differences between HEAD, the index and the working tree.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// Changed tracked files, sorted by path
+    pub changes: Vec<FileStatus>,
+    /// Files with merge conflicts, sorted by path
+    pub unmerged: Vec<UnmergedStatus>,
+    /// Untracked files and directories, sorted
+    pub untracked: Vec<String>,
+    /// The percentage of tracked files in the working tree, if sparse
+    /// checkout is enabled
+    pub sparse_percentage: Option<usize>,
+}
+
+impl Repository {
+    /// Compares HEAD, the index and the working tree. The stat data of the
+    /// index is used to skip hashing unchanged files, and refreshed for
+    /// files that were touched without changing.
+    pub fn status(&self, untracked: UntrackedFiles) -> Result<Status> {
+        let root = self.get_root();
+        let head = match self.read_ref("HEAD")? {
+            Some(hash) => Tree::read_recursive(&Commit::read(&hash, self)?.tree, self)?,
+            None => BTreeMap::new(),
+        };
+        let convert = self.convert()?;
+        let mut index = Index::load(self)?;
+
+        let mut status = Status::default();
+        let mut refreshed = index.refresh_fsmonitor(self)?;
+        let fsmonitor = index.uses_fsmonitor();
+        let mut unmerged: BTreeMap<String, UnmergedStatus> = BTreeMap::new();
+        for i in 0..index.entries.len() {
+            let entry = &index.entries[i];
+            // Files the fsmonitor saw no change to aren't even stat'ed
+            let valid = entry.fsmonitor_valid
+                && entry.stage == 0
+                && !entry.skip_worktree()
+                && entry.mode != MODE_GITLINK;
+            let meta = match fs::symlink_metadata(root.join(&entry.path)) {
+                _ if valid => None,
+                Ok(meta) => Some(meta),
+                Err(e) if is_missing(&e) => None,
+                Err(e) => Err(e)?,
+            };
+            let worktree_mode = match &meta {
+                _ if valid => Some(entry.mode),
+                Some(meta) if entry.mode == MODE_GITLINK && meta.is_dir() => Some(MODE_GITLINK),
+                meta => meta.as_ref().map(file_mode),
+            };
+
+            if entry.stage != 0 {
+                let unmerged =
+                    unmerged
+                        .entry(entry.path.clone())
+                        .or_insert_with(|| UnmergedStatus {
+                            path: entry.path.clone(),
+                            stages: Default::default(),
+                            worktree_mode,
+                        });
+                unmerged.stages[entry.stage as usize - 1] = Some((entry.mode, entry.hash.clone()));
+                continue;
+            }
+
+            let indexed = (entry.mode, entry.hash.clone());
+            let head_version = head.get(&entry.path);
+            let staged = compare(head_version, Some(&indexed));
+            if entry.skip_worktree() && meta.is_some() {
+                // Like git, a file outside the sparse checkout that is in the
+                // working tree after all is no longer left out
+                index.entries[i].set_skip_worktree(false);
+                refreshed = true;
+            }
+            let entry = &index.entries[i];
+            let mut submodule = None;
+            let unstaged = match &meta {
+                _ if valid => Change::Unmodified,
+                None if entry.skip_worktree() => Change::Unmodified,
+                None => Change::Deleted,
+                Some(_) if entry.mode == MODE_GITLINK => {
+                    let changes = self.submodule_changes(&entry.path, &entry.hash)?;
+                    if !changes.any() {
+                        Change::Unmodified
+                    } else {
+                        submodule = Some(changes);
+                        Change::Modified
+                    }
+                }
+                Some(meta) if is_symlink(file_mode(meta)) != is_symlink(entry.mode) => {
+                    Change::TypeChanged
+                }
+                Some(meta) if entry.stat_matches(meta) && !index.is_racy(entry) => {
+                    Change::Unmodified
+                }
+                Some(meta) => {
+                    if file_mode(meta) != entry.mode
+                        || hash_file(&entry.path, meta, &convert)? != entry.hash
+                    {
+                        Change::Modified
+                    } else {
+                        // Unchanged content, remember the new stat data
+                        index.entries[i].update_stat(meta);
+                        refreshed = true;
+                        Change::Unmodified
+                    }
+                }
+            };
+
+            // Files found up to date stay valid until the fsmonitor sees a
+            // change to them
+            let entry = &mut index.entries[i];
+            let up_to_date = unstaged == Change::Unmodified && meta.is_some();
+            if fsmonitor && up_to_date && entry.mode != MODE_GITLINK && !entry.fsmonitor_valid {
+                entry.fsmonitor_valid = true;
+                refreshed = true;
+            }
+
+            if staged != Change::Unmodified || unstaged != Change::Unmodified {
+                status.changes.push(FileStatus {
+                    path: index.entries[i].path.clone(),
+                    staged,
+                    unstaged,
+                    head: head_version.cloned(),
+                    index: Some(indexed),
+                    worktree_mode,
+                    submodule,
+                });
+            }
+        }
+
+        // Files deleted from the index
+        let indexed: BTreeSet<&str> = index.entries.iter().map(|e| e.path.as_str()).collect();
+        for (path, version) in &head {
+            if !indexed.contains(path.as_str()) {
+                status.changes.push(FileStatus {
+                    path: path.clone(),
+                    staged: Change::Deleted,
+                    unstaged: Change::Unmodified,
+                    head: Some(version.clone()),
+                    index: None,
+                    worktree_mode: None,
+                    submodule: None,
+                });
+            }
+        }
+        status.changes.sort_by(|a, b| a.path.cmp(&b.path));
+        let sparse = self.config()?.get_bool("core.sparseCheckout") == Some(true);
+        if sparse && !index.entries.is_empty() {
+            let skipped = index.entries.iter().filter(|e| e.skip_worktree()).count();
+            status.sparse_percentage = Some(100 - 100 * skipped / index.entries.len());
+        }
+        status.unmerged = unmerged.into_values().collect();
+
+        if untracked != UntrackedFiles::No {
+            let config = self.config()?;
+            let flags_of = |mode| match mode {
+                UntrackedFiles::All => 0,
+                _ => DIR_SHOW_OTHER_DIRECTORIES | DIR_HIDE_EMPTY_DIRECTORIES,
+            };
+            let flags = flags_of(untracked);
+            let configured = match config.get("status.showUntrackedFiles") {
+                Some("all") => UntrackedFiles::All,
+                _ => UntrackedFiles::Normal,
+            };
+
+            // core.untrackedCache adds or removes the cache, without it the
+            // index keeps what it has
+            let mut cache = match config.get_bool("core.untrackedCache") {
+                Some(true) => match index.untracked_cache() {
+                    Some(cache) => Some(cache.clone()),
+                    None => Some(UntrackedCache::new(self)?),
+                },
+                Some(false) => None,
+                None => index.untracked_cache().cloned(),
+            };
+            // A walk with other flags than the configured ones bypasses the
+            // cache, to keep it for the usual walks
+            let usable = cache
+                .as_mut()
+                .filter(|cache| cache.dir_flags == flags || flags == flags_of(configured));
+            let mut walk = UntrackedWalk {
+                root,
+                index: &index,
+                tracked: TrackedPaths::new(indexed.iter().copied()),
+                ignore: self.ignore()?,
+                mode: untracked,
+                hash_excludes: usable.is_some(),
+            };
+            let cached = match usable {
+                Some(cache) => {
+                    cache.validate(self, flags)?;
+                    walk.collect("", cache.root.take(), false, false)?
+                }
+                None => walk.collect("", None, false, false)?,
+            };
+            list_untracked(&cached, "", &mut status.untracked);
+
+            if let Some(cache) = cache.as_mut().filter(|cache| cache.dir_flags == flags) {
+                cache.root = Some(cached);
+            }
+            if cache.as_ref() != index.untracked_cache() {
+                index.set_untracked_cache(cache);
+                refreshed = true;
+            }
+        }
+
+        // Saving the refreshed stat data is only an optimization
+        if refreshed {
+            let _ = index.write();
+        }
+
+        Ok(status)
+    }
+}
+
+fn is_symlink(mode: u32) -> bool {
+    mode == MODE_SYMLINK
+}
+
+/// Compares two versions of a file.
+fn compare(old: Option<&Version>, new: Option<&Version>) -> Change {
+    match (old, new) {
+        (None, None) => Change::Unmodified,
+        (None, Some(_)) => Change::Added,
+        (Some(_), None) => Change::Deleted,
+        (Some(old), Some(new)) if is_symlink(old.0) != is_symlink(new.0) => Change::TypeChanged,
+        (Some(old), Some(new)) if old != new => Change::Modified,
+        _ => Change::Unmodified,
+    }
+}
+
+/// The state of collecting untracked files.
+struct UntrackedWalk<'a> {
+    root: &'a Path,
+    index: &'a Index,
+    tracked: TrackedPaths,
+    ignore: Ignore,
+    mode: UntrackedFiles,
+    /// Whether to hash the `.gitignore` files for the untracked cache
+    hash_excludes: bool,
+}
+
+impl UntrackedWalk<'_> {
+    /// Collects the untracked files of the directory `prefix` of the working
+    /// tree and those below it, leaving out ignored ones. In normal mode a
+    /// directory without tracked files is reported as a whole, if it
+    /// contains any untracked file, and only read with `check_only`.
+    ///
+    /// The files of the cached directory are reused as long as neither it
+    /// nor its `.gitignore` changed, in which case only the directories
+    /// below it are looked at. `excludes_changed` says whether the ignore
+    /// files of a directory above changed.
+    fn collect(
+        &mut self,
+        prefix: &str,
+        cached: Option<UntrackedDir>,
+        check_only: bool,
+        excludes_changed: bool,
+    ) -> Result<UntrackedDir> {
+        let mut dir = UntrackedDir {
+            name: prefix
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or("")
+                .to_string(),
+            check_only,
+            ..Default::default()
+        };
+        dir.stat = match fs::symlink_metadata(self.root.join(prefix)) {
+            Ok(meta) => StatData::new(&meta),
+            Err(e) if is_missing(&e) => return Ok(dir),
+            Err(e) => Err(e)?,
+        };
+        if self.hash_excludes {
+            let gitignore = format!("{prefix}.gitignore");
+            let tracked = Some((self.index, gitignore.as_str()));
+            dir.exclude_hash =
+                untracked_cache::exclude_file(&self.root.join(&gitignore), tracked)?.hash;
+        }
+        dir.valid = true;
+
+        let excludes_changed = excludes_changed
+            || cached
+                .as_ref()
+                .is_some_and(|cached| cached.exclude_hash != dir.exclude_hash);
+        let mut cached_dirs = vec![];
+        if let Some(cached) = cached {
+            let unchanged = cached.valid
+                && !excludes_changed
+                && cached.check_only == check_only
+                && cached.stat == dir.stat
+                && !self.index.is_racy_mtime(dir.stat.mtime);
+            if !unchanged {
+                cached_dirs = cached.dirs;
+            } else {
+                dir.untracked = cached.untracked;
+                for sub in cached.dirs {
+                    let path = format!("{prefix}{}/", sub.name);
+                    let sub_check_only = sub.check_only;
+                    let sub = self.collect(&path, Some(sub), sub_check_only, false)?;
+                    // Whether an untracked directory is empty may have
+                    // changed without this one changing
+                    if sub_check_only {
+                        let name = format!("{}/", sub.name);
+                        dir.untracked.retain(|untracked| *untracked != name);
+                        if self.is_shown(&path, &sub) {
+                            dir.untracked.push(name);
+                        }
+                    }
+                    dir.dirs.push(sub);
+                }
+                return Ok(dir);
+            }
+        }
+
+        let mut entries: Vec<_> =
+            fs::read_dir(self.root.join(prefix))?.collect::<std::io::Result<_>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name == ".git" {
+                continue;
+            }
+            let path = format!("{prefix}{name}");
+            let is_dir = entry.file_type()?.is_dir();
+            let (tracking, ignored) =
+                self.tracked
+                    .classify(&path, is_dir, &mut self.ignore, false)?;
+            // Tracked files and submodules are compared with the index
+            if tracking == Tracking::Tracked || ignored {
+                continue;
+            }
+            if !is_dir {
+                dir.untracked.push(name);
+                continue;
+            }
+
+            let path = format!("{path}/");
+            let check_only =
+                self.mode == UntrackedFiles::Normal && tracking != Tracking::HoldsTracked;
+            let cached = cached_dirs
+                .iter()
+                .position(|sub| sub.name == name)
+                .map(|i| cached_dirs.swap_remove(i));
+            let sub = self.collect(&path, cached, check_only, excludes_changed)?;
+            if check_only && self.is_shown(&path, &sub) {
+                dir.untracked.push(format!("{name}/"));
+            }
+            dir.dirs.push(sub);
+        }
+
+        Ok(dir)
+    }
+
+    /// Returns true if an untracked directory is reported as a whole, that
+    /// is if it isn't empty. Nested repositories are reported like
+    /// directories.
+    fn is_shown(&self, path: &str, dir: &UntrackedDir) -> bool {
+        !dir.untracked.is_empty() || self.root.join(path).join(".git").exists()
+    }
+}
+
+/// Lists the untracked files of a directory and the directories below it
+/// that aren't reported as a whole, sorted like a walk of the working tree.
+fn list_untracked(dir: &UntrackedDir, prefix: &str, untracked: &mut Vec<String>) {
+    let mut names: Vec<(&str, Option<&UntrackedDir>)> = dir
+        .untracked
+        .iter()
+        .map(|name| (name.as_str(), None))
+        .chain(
+            dir.dirs
+                .iter()
+                .filter(|dir| !dir.check_only)
+                .map(|dir| (dir.name.as_str(), Some(dir))),
+        )
+        .collect();
+    names.sort_by_key(|(name, _)| name.trim_end_matches('/'));
+
+    for (name, dir) in names {
+        match dir {
+            Some(dir) => list_untracked(dir, &format!("{prefix}{name}/"), untracked),
+            None => untracked.push(format!("{prefix}{name}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_status() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let repo = Repository::init(root).unwrap();
+
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("dir/a"), "a\n").unwrap();
+        fs::write(root.join("b"), "b\n").unwrap();
+        let tree = repo.add_all().unwrap();
+        repo.reset_index(&tree).unwrap();
+
+        fs::write(root.join("dir/a"), "changed\n").unwrap();
+        fs::remove_file(root.join("b")).unwrap();
+        fs::create_dir_all(root.join("new/sub")).unwrap();
+        fs::write(root.join("new/sub/c"), "c\n").unwrap();
+        fs::write(root.join("dir/d"), "d\n").unwrap();
+
+        // Without commits, everything in the index is staged as new
+        let status = repo.status(UntrackedFiles::Normal).unwrap();
+        let changes: Vec<_> = status
+            .changes
+            .iter()
+            .map(|file| (file.path.as_str(), file.staged, file.unstaged))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("b", Change::Added, Change::Deleted),
+                ("dir/a", Change::Added, Change::Modified)
+            ]
+        );
+        assert!(status.unmerged.is_empty());
+        assert_eq!(status.untracked, ["dir/d", "new/"]);
+
+        let status = repo.status(UntrackedFiles::All).unwrap();
+        assert_eq!(status.untracked, ["dir/d", "new/sub/c"]);
+        let status = repo.status(UntrackedFiles::No).unwrap();
+        assert!(status.untracked.is_empty());
+    }
+}
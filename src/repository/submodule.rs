@@ -0,0 +1,538 @@
+//! Submodules: repositories nested in the working tree. Trees record them
+//! as gitlinks, entries with mode 160000 holding the commit checked out in
+//! them, and `.gitmodules` maps their paths to names and URLs. A submodule's
+//! git directory is kept in `.git/modules/<name>`, with a `.git` file in the
+//! submodule pointing to it.
+
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::object::{commit, tree::MODE_GITLINK, Commit, Object};
+
+use super::{
+    config::Config,
+    create_git_dir,
+    error::{RepoError, Result},
+    index::Index,
+    refs::RefFormat,
+    status::UntrackedFiles,
+    transaction::{RefChange, RefTransaction, RefUpdate},
+    worktree::absolute,
+    Repository,
+};
+
+/// A submodule of the index, as configured in `.gitmodules`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Submodule {
+    pub name: String,
+    pub path: String,
+    /// The URL in `.gitmodules`, which may be relative to the superproject's
+    pub url: Option<String>,
+    /// The commit recorded in the index
+    pub hash: String,
+    /// Whether the gitlink has a merge conflict
+    pub unmerged: bool,
+}
+
+/// How the working tree of a submodule differs from the commit recorded in
+/// the superproject.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SubmoduleChanges {
+    /// A different commit is checked out
+    pub new_commits: bool,
+    /// Tracked files are modified
+    pub modified: bool,
+    pub untracked: bool,
+}
+
+impl SubmoduleChanges {
+    pub fn any(&self) -> bool {
+        self.new_commits || self.modified || self.untracked
+    }
+
+    /// Returns the description of the long status format, e.g.
+    /// `new commits, modified content`.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![];
+        if self.new_commits {
+            parts.push("new commits");
+        }
+        if self.modified {
+            parts.push("modified content");
+        }
+        if self.untracked {
+            parts.push("untracked content");
+        }
+        parts.join(", ")
+    }
+}
+
+impl Repository {
+    /// Returns the submodules of the index, sorted by path. Every gitlink
+    /// needs an entry in `.gitmodules`.
+    pub fn submodules(&self) -> Result<Vec<Submodule>> {
+        let gitmodules = self.gitmodules()?;
+        let index = Index::load(self)?;
+
+        let mut submodules: Vec<Submodule> = vec![];
+        for entry in index.entries.iter().filter(|e| e.mode == MODE_GITLINK) {
+            if let Some(last) = submodules.last_mut().filter(|s| s.path == entry.path) {
+                // Conflicts show our version, or the first one there is
+                last.unmerged = true;
+                if entry.stage == 2 {
+                    last.hash = entry.hash.clone();
+                }
+                continue;
+            }
+            let name = self.submodule_name(&entry.path)?.ok_or_else(|| {
+                RepoError::Other(format!(
+                    "no submodule mapping found in .gitmodules for path '{}'",
+                    entry.path
+                ))
+            })?;
+            submodules.push(Submodule {
+                path: entry.path.clone(),
+                url: gitmodules
+                    .get(&format!("submodule.{name}.url"))
+                    .map(str::to_string),
+                name,
+                hash: entry.hash.clone(),
+                unmerged: entry.stage != 0,
+            });
+        }
+
+        Ok(submodules)
+    }
+
+    /// Returns the paths of the gitlinks in the index, which needn't be in
+    /// `.gitmodules`.
+    pub fn gitlinks(&self) -> Result<Vec<String>> {
+        let mut paths: Vec<String> = Index::load(self)?
+            .entries
+            .into_iter()
+            .filter(|e| e.mode == MODE_GITLINK)
+            .map(|e| e.path)
+            .collect();
+        paths.dedup();
+        Ok(paths)
+    }
+
+    /// Returns the name of the submodule at `path` in `.gitmodules`.
+    pub fn submodule_name(&self, path: &str) -> Result<Option<String>> {
+        let gitmodules = self.gitmodules()?;
+        Ok(gitmodules
+            .subsections("submodule")
+            .into_iter()
+            .find(|name| gitmodules.get(&format!("submodule.{name}.path")) == Some(path))
+            .map(|name| name.to_string()))
+    }
+
+    fn gitmodules(&self) -> Result<Config> {
+        Config::load_file(&self.get_root().join(".gitmodules"))
+    }
+
+    /// Opens the repository of a submodule, or returns `None` if it isn't
+    /// checked out.
+    pub fn open_submodule(&self, path: &str) -> Result<Option<Repository>> {
+        let root = self.get_root().join(path);
+        match root.join(".git").exists() {
+            true => Ok(Some(Repository::from_path(root)?)),
+            false => Ok(None),
+        }
+    }
+
+    /// Returns the git directory of a submodule in `.git/modules`.
+    pub fn submodule_git_dir(&self, name: &str) -> PathBuf {
+        self.common_dir.join("modules").join(name)
+    }
+
+    /// Returns the URL a submodule was initialized with, `None` if it
+    /// wasn't.
+    pub fn submodule_url(&self, submodule: &Submodule) -> Result<Option<String>> {
+        let key = format!("submodule.{}.url", submodule.name);
+        Ok(self.config()?.get(&key).map(str::to_string))
+    }
+
+    /// Compares the submodule at `path` with the commit recorded for it.
+    /// A submodule that isn't checked out is unchanged.
+    pub fn submodule_changes(&self, path: &str, recorded: &str) -> Result<SubmoduleChanges> {
+        let Some(submodule) = self.open_submodule(path)? else {
+            return Ok(SubmoduleChanges::default());
+        };
+        let status = submodule.status(UntrackedFiles::Normal)?;
+
+        Ok(SubmoduleChanges {
+            new_commits: submodule.read_ref("HEAD")?.as_deref() != Some(recorded),
+            modified: !status.changes.is_empty() || !status.unmerged.is_empty(),
+            untracked: !status.untracked.is_empty(),
+        })
+    }
+
+    /// Copies the URL of a submodule from `.gitmodules` to the config,
+    /// resolving relative URLs. Returns the URL, or `None` if it was
+    /// initialized already.
+    pub fn init_submodule(&self, submodule: &Submodule) -> Result<Option<String>> {
+        if self.submodule_url(submodule)?.is_some() {
+            return Ok(None);
+        }
+        let url = submodule.url.as_deref().ok_or_else(|| {
+            RepoError::Other(format!(
+                "No url found for submodule path '{}' in .gitmodules",
+                submodule.path
+            ))
+        })?;
+        let url = self.resolve_submodule_url(url)?;
+
+        let mut config = self.config()?;
+        config.set(&format!("submodule.{}.active", submodule.name), "true")?;
+        config.set(&format!("submodule.{}.url", submodule.name), &url)?;
+        config.save()?;
+
+        Ok(Some(url))
+    }
+
+    /// Resolves a URL starting with `./` or `../` against the URL of the
+    /// superproject's origin, or its working tree if it has none.
+    fn resolve_submodule_url(&self, url: &str) -> Result<String> {
+        if !url.starts_with("./") && !url.starts_with("../") {
+            return Ok(url.to_string());
+        }
+        let mut base = match self.config()?.get("remote.origin.url") {
+            Some(base) => base.trim_end_matches('/').to_string(),
+            None => absolute(self.get_root())?.display().to_string(),
+        };
+        let mut rest = url;
+        loop {
+            if let Some(r) = rest.strip_prefix("./") {
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix("../") {
+                base.truncate(base.rfind('/').unwrap_or(0));
+                rest = r;
+            } else {
+                break;
+            }
+        }
+
+        Ok(format!("{base}/{rest}"))
+    }
+
+    /// Clones a submodule from a local repository: its git directory is
+    /// created in `.git/modules`, with the branches of `url` as
+    /// remote-tracking branches of `origin`, and its default branch checked
+    /// out. The working tree is left empty.
+    pub fn clone_submodule(&self, submodule: &Submodule, url: &str) -> Result<()> {
+        let root = self.get_root().join(&submodule.path);
+        let git_dir = self.submodule_git_dir(&submodule.name);
+        if git_dir.exists() {
+            Err(RepoError::Other(format!(
+                "a git directory for '{}' is found locally",
+                submodule.name
+            )))?;
+        }
+        fs::create_dir_all(&root)?;
+        create_git_dir(&git_dir, RefFormat::Files)?;
+        write_gitfile(&root, &git_dir)?;
+        let repo = Repository::open(git_dir.clone(), git_dir, root)?;
+
+        let mut config = repo.config()?;
+        config.set("remote.origin.url", url)?;
+        config.set("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")?;
+        config.save()?;
+        let source = open_url(url)?;
+        fetch(&repo, &source, &format!("clone: from {url}"))?;
+
+        if let Some(branch) = source.read_symbolic_ref("HEAD")? {
+            let name = branch.trim_start_matches("refs/heads/");
+            if let Some(hash) = source.read_ref(&branch)? {
+                repo.update_ref(&branch, &hash, &format!("clone: from {url}"))?;
+                repo.set_symbolic_ref("HEAD", &branch, "")?;
+                let mut config = repo.config()?;
+                config.set(&format!("branch.{name}.remote"), "origin")?;
+                config.set(&format!("branch.{name}.merge"), &branch)?;
+                config.save()?;
+            }
+        }
+        set_core_worktree(&repo)?;
+
+        Ok(())
+    }
+
+    /// Checks out the recorded commit in a submodule, detaching its HEAD.
+    /// The commit is fetched from the submodule's URL if it's missing.
+    /// Returns false if it was checked out already.
+    pub fn checkout_submodule(&self, submodule: &Submodule) -> Result<bool> {
+        let hash = &submodule.hash;
+        let repo = self.open_submodule(&submodule.path)?.ok_or_else(|| {
+            RepoError::Other(format!("submodule '{}' is not checked out", submodule.path))
+        })?;
+        let head = repo.read_ref("HEAD")?;
+        // Nothing is checked out in a fresh clone, whatever its HEAD
+        let fresh = !Index::load(&repo)?.exists();
+        if head.as_deref() == Some(hash) && !fresh {
+            return Ok(false);
+        }
+        if Object::read(hash, &repo).is_err() {
+            if let Some(url) = self.submodule_url(submodule)? {
+                fetch(&repo, &open_url(&url)?, "fetch")?;
+            }
+            if Object::read(hash, &repo).is_err() {
+                Err(RepoError::Other(format!(
+                    "Fetched in submodule path '{}', but it did not contain {hash}. \
+                     Direct fetching of that commit failed.",
+                    submodule.path
+                )))?;
+            }
+        }
+
+        let old_tree = match &head {
+            Some(head) if !fresh => Some(Commit::read(head, &repo)?.tree),
+            _ => None,
+        };
+        let new_tree = Commit::read(hash, &repo)?.tree;
+        repo.checkout_tree(old_tree.as_deref(), &new_tree, &Default::default())?;
+
+        let from = match repo.read_symbolic_ref("HEAD")? {
+            Some(branch) => branch.trim_start_matches("refs/heads/").to_string(),
+            None => head.unwrap_or_default(),
+        };
+        let mut transaction = RefTransaction::new(&repo);
+        transaction.update(RefUpdate {
+            refname: "HEAD".to_string(),
+            change: RefChange::Set(hash.clone()),
+            old: None,
+            no_deref: true,
+            message: format!("checkout: moving from {from} to {hash}"),
+        })?;
+        transaction.commit()?;
+
+        Ok(true)
+    }
+
+    /// Updates the URL of an initialized submodule from `.gitmodules`, in
+    /// the config and in the submodule's origin. Returns false if the
+    /// submodule isn't initialized.
+    pub fn sync_submodule(&self, submodule: &Submodule) -> Result<bool> {
+        if self.submodule_url(submodule)?.is_none() {
+            return Ok(false);
+        }
+        let Some(url) = &submodule.url else {
+            return Ok(true);
+        };
+        let url = self.resolve_submodule_url(url)?;
+
+        let mut config = self.config()?;
+        config.set(&format!("submodule.{}.url", submodule.name), &url)?;
+        config.save()?;
+        if let Some(repo) = self.open_submodule(&submodule.path)? {
+            let mut config = repo.config()?;
+            config.set("remote.origin.url", &url)?;
+            config.save()?;
+        }
+
+        Ok(true)
+    }
+
+    /// Moves the git directory of the submodule `name` at `path` into
+    /// `.git/modules`, leaving a `.git` file in its place. Returns the old
+    /// and new location, or `None` if it's there already or the submodule
+    /// isn't checked out.
+    pub fn absorb_submodule_git_dir(
+        &self,
+        name: &str,
+        path: &str,
+    ) -> Result<Option<(PathBuf, PathBuf)>> {
+        let root = self.get_root().join(path);
+        let old = root.join(".git");
+        if !old.is_dir() {
+            return Ok(None);
+        }
+        let new = self.submodule_git_dir(name);
+        if new.exists() {
+            Err(RepoError::Other(format!(
+                "refusing to move '{}' into an existing git dir",
+                old.display()
+            )))?;
+        }
+        let old = absolute(&old)?;
+        if let Some(parent) = new.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&old, &new)?;
+        write_gitfile(&root, &new)?;
+        set_core_worktree(&Repository::from_path(&root)?)?;
+
+        Ok(Some((old, absolute(&new)?)))
+    }
+}
+
+/// Opens the repository at a local path or `file://` URL, with or without
+/// a working tree.
+fn open_url(url: &str) -> Result<Repository> {
+    let path = Path::new(url.strip_prefix("file://").unwrap_or(url));
+    if path.join(".git").exists() {
+        return Repository::from_path(path);
+    }
+    if path.join("objects").is_dir() && path.join("HEAD").is_file() {
+        let dir = path.to_path_buf();
+        return Repository::open(dir.clone(), dir.clone(), dir);
+    }
+    Err(RepoError::Other(format!(
+        "repository '{url}' does not exist"
+    )))
+}
+
+/// Copies the objects of `source` that `repo` doesn't have, and sets the
+/// remote-tracking branches of `origin` and the tags to those of `source`.
+fn fetch(repo: &Repository, source: &Repository, message: &str) -> Result<()> {
+    copy_objects(
+        &source.common_dir.join("objects"),
+        &repo.common_dir.join("objects"),
+    )?;
+
+    for (refname, hash) in source.list_refs("refs/heads/")? {
+        let branch = refname.trim_start_matches("refs/heads/");
+        let tracking = format!("refs/remotes/origin/{branch}");
+        if repo.read_ref(&tracking)?.as_deref() != Some(&hash) {
+            repo.update_ref(&tracking, &hash, message)?;
+        }
+    }
+    for (refname, hash) in source.list_refs("refs/tags/")? {
+        if repo.read_ref(&refname)?.is_none() {
+            repo.update_ref(&refname, &hash, message)?;
+        }
+    }
+    if let Some(branch) = source.read_symbolic_ref("HEAD")? {
+        let branch = branch.trim_start_matches("refs/heads/");
+        let target = format!("refs/remotes/origin/{branch}");
+        repo.set_symbolic_ref("refs/remotes/origin/HEAD", &target, "")?;
+    }
+
+    Ok(())
+}
+
+/// Copies the files of an objects directory that aren't in `to` yet.
+fn copy_objects(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_objects(&entry.path(), &target)?;
+        } else if !target.exists() {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the `.git` file of a submodule, with the relative path to its git
+/// directory.
+fn write_gitfile(root: &Path, git_dir: &Path) -> Result<()> {
+    let path = relative_path(&absolute(root)?, &absolute(git_dir)?);
+    Ok(fs::write(
+        root.join(".git"),
+        format!("gitdir: {}\n", path.display()),
+    )?)
+}
+
+/// Points `core.worktree` of a submodule's git directory at its working
+/// tree, relative to the git directory.
+fn set_core_worktree(repo: &Repository) -> Result<()> {
+    let path = relative_path(&absolute(&repo.dir)?, &absolute(repo.get_root())?);
+    let mut config = repo.config()?;
+    config.set("core.worktree", &path.display().to_string())?;
+    config.save()
+}
+
+/// Returns the relative path from the directory `from` to `to`, both
+/// absolute.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for component in &to[common..] {
+        path.push(component);
+    }
+    path
+}
+
+/// Describes a commit like `git submodule status` does: by a tag pointing
+/// at it, the nearest tag it's based on (`v1.0-2-g1234567`), a tag it's in
+/// the first-parent history of (`v1.0~2`), a branch pointing at it
+/// (`heads/main`), or else its abbreviated hash.
+pub fn describe(repo: &Repository, hash: &str) -> Result<String> {
+    let mut tags = vec![];
+    for (refname, tag) in repo.list_refs("refs/tags/")? {
+        if let Ok(target) = repo.peel_tags(&tag) {
+            tags.push((refname.trim_start_matches("refs/tags/").to_string(), target));
+        }
+    }
+    if let Some((name, _)) = tags.iter().find(|(_, target)| target == hash) {
+        return Ok(name.clone());
+    }
+
+    let ancestors = commit::ancestors(hash, repo)?;
+    let mut nearest: Option<(usize, &str)> = None;
+    for (name, target) in &tags {
+        if !ancestors.contains(target) {
+            continue;
+        }
+        let depth = ancestors
+            .difference(&commit::ancestors(target, repo)?)
+            .count();
+        if nearest.is_none_or(|(nearest, _)| depth < nearest) {
+            nearest = Some((depth, name));
+        }
+    }
+    if let Some((depth, name)) = nearest {
+        return Ok(format!("{name}-{depth}-g{}", &hash[..7]));
+    }
+
+    for (name, target) in &tags {
+        let mut commit = target.clone();
+        for n in 1.. {
+            let Some(parent) = Commit::read(&commit, repo)?.parents.first().cloned() else {
+                break;
+            };
+            if parent == hash {
+                return Ok(format!("{name}~{n}"));
+            }
+            commit = parent;
+        }
+    }
+
+    for prefix in ["refs/heads/", "refs/remotes/"] {
+        for (refname, target) in repo.list_refs(prefix)? {
+            if target == hash {
+                return Ok(refname.trim_start_matches("refs/").to_string());
+            }
+        }
+    }
+    Ok(hash[..7].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_path() {
+        let path = |from: &str, to: &str| relative_path(Path::new(from), Path::new(to));
+        assert_eq!(
+            path("/a/b", "/a/.git/modules/b"),
+            Path::new("../.git/modules/b")
+        );
+        assert_eq!(
+            path("/a/.git/modules/x/y", "/a/x/y"),
+            Path::new("../../../../x/y")
+        );
+        assert_eq!(path("/a", "/a/b"), Path::new("b"));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::object::Signature;
+
+use super::{
+    error::{RepoError, Result},
+    refs::{RefLock, RefValue, RefWrite, ReflogEntry, NULL_HASH},
+    Repository,
+};
+
+/// What a [`RefUpdate`] does to its ref.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefChange {
+    /// Point the ref at the given hash
+    Set(String),
+    Delete,
+    /// Leave the ref as is, only check its old value
+    Verify,
+}
+
+/// A single ref change within a [`RefTransaction`].
+#[derive(Debug, Clone)]
+pub struct RefUpdate {
+    pub refname: String,
+    pub change: RefChange,
+    /// The value the ref must have before the update: `None` to skip the
+    /// check, or [`NULL_HASH`] if the ref must not exist
+    pub old: Option<String>,
+    /// Update the symbolic ref itself instead of the ref it points to
+    pub no_deref: bool,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Open,
+    Prepared,
+    Closed,
+}
+
+/// A set of ref updates that are applied all together or not at all.
+/// Preparing the transaction locks every ref in the ref store and verifies
+/// the old values, committing hands all writes to the store at once.
+pub struct RefTransaction<'a> {
+    repo: &'a Repository,
+    updates: Vec<RefUpdate>,
+    /// The (dereferenced) refs the updates apply to, once prepared
+    targets: Vec<String>,
+    lock: Option<Box<dyn RefLock + 'a>>,
+    state: State,
+}
+
+impl<'a> RefTransaction<'a> {
+    pub fn new(repo: &'a Repository) -> RefTransaction<'a> {
+        RefTransaction {
+            repo,
+            updates: vec![],
+            targets: vec![],
+            lock: None,
+            state: State::Open,
+        }
+    }
+
+    /// Queues an update. Fails if the transaction has already been prepared.
+    pub fn update(&mut self, update: RefUpdate) -> Result<()> {
+        if self.state != State::Open {
+            Err(RepoError::Transaction(
+                "transaction is no longer open".to_string(),
+            ))?;
+        }
+        if self.updates.iter().any(|u| u.refname == update.refname) {
+            Err(RepoError::Transaction(format!(
+                "multiple updates for ref '{}' not allowed",
+                update.refname
+            )))?;
+        }
+        self.updates.push(update);
+
+        Ok(())
+    }
+
+    /// Locks all refs and verifies their old values.
+    pub fn prepare(&mut self) -> Result<()> {
+        match self.state {
+            State::Open => {}
+            State::Prepared => return Ok(()),
+            State::Closed => Err(RepoError::Transaction(
+                "transaction is already closed".to_string(),
+            ))?,
+        }
+
+        if let Err(e) = self.lock_and_verify() {
+            self.abort()?;
+            return Err(e);
+        }
+        self.state = State::Prepared;
+
+        Ok(())
+    }
+
+    fn lock_and_verify(&mut self) -> Result<()> {
+        let mut targets = vec![];
+        for update in &self.updates {
+            let target = if update.no_deref {
+                update.refname.clone()
+            } else {
+                self.resolve_symbolic(&update.refname)?
+            };
+
+            let creating = self.repo.read_ref(&target)?.is_none();
+            if matches!(update.change, RefChange::Set(_)) && creating {
+                self.repo.check_ref_conflict(&target)?;
+            }
+            targets.push(target);
+        }
+
+        let lock = self
+            .repo
+            .refs
+            .lock(&targets)
+            .map_err(|e| RepoError::Transaction(e.to_string()))?;
+        self.lock = Some(lock);
+
+        for (update, target) in self.updates.iter().zip(&targets) {
+            let cannot_lock = |reason: String| {
+                RepoError::Transaction(format!("cannot lock ref '{}': {reason}", update.refname))
+            };
+            match (&update.old, self.repo.read_ref(target)?) {
+                (None, _) => {}
+                (Some(expected), None) if expected != NULL_HASH => Err(cannot_lock(format!(
+                    "unable to resolve reference '{}'",
+                    update.refname
+                )))?,
+                (Some(expected), Some(_)) if expected == NULL_HASH => {
+                    Err(cannot_lock("reference already exists".to_string()))?
+                }
+                (Some(expected), Some(current)) if current != *expected => Err(cannot_lock(
+                    format!("is at {current} but expected {expected}"),
+                ))?,
+                _ => {}
+            }
+        }
+        self.targets = targets;
+
+        Ok(())
+    }
+
+    /// Follows symbolic refs like HEAD to the ref they point to.
+    fn resolve_symbolic(&self, refname: &str) -> Result<String> {
+        let mut refname = refname.to_string();
+        while let Some(target) = self.repo.read_symbolic_ref(&refname)? {
+            refname = target;
+        }
+        Ok(refname)
+    }
+
+    /// Applies all updates, preparing the transaction first if needed.
+    pub fn commit(&mut self) -> Result<()> {
+        self.prepare()?;
+
+        let head = self.repo.read_symbolic_ref("HEAD")?;
+        let mut writes = vec![];
+        for (update, target) in self.updates.iter().zip(&self.targets) {
+            let old = self
+                .repo
+                .read_ref(target)?
+                .unwrap_or_else(|| NULL_HASH.to_string());
+
+            match &update.change {
+                RefChange::Set(new) => {
+                    writes.push(RefWrite::Ref {
+                        refname: target.clone(),
+                        value: Some(RefValue::Direct(new.clone())),
+                    });
+
+                    let mut logged = vec![target.as_str()];
+                    if target != "HEAD" && head.as_deref() == Some(target) {
+                        logged.push("HEAD");
+                    }
+                    for refname in logged {
+                        if !self.repo.should_log(refname)? {
+                            continue;
+                        }
+                        writes.push(RefWrite::Log {
+                            refname: refname.to_string(),
+                            entry: ReflogEntry {
+                                old: old.clone(),
+                                new: new.clone(),
+                                signature: Signature::now()?,
+                                message: update.message.clone(),
+                            },
+                        });
+                    }
+                }
+                RefChange::Delete => writes.push(RefWrite::Ref {
+                    refname: target.clone(),
+                    value: None,
+                }),
+                RefChange::Verify => {}
+            }
+        }
+
+        if let Some(lock) = self.lock.take() {
+            lock.commit(writes)?;
+        }
+        self.updates.clear();
+        self.targets.clear();
+        self.state = State::Closed;
+
+        Ok(())
+    }
+
+    /// Releases all locks without applying any update.
+    pub fn abort(&mut self) -> Result<()> {
+        // Dropping the lock releases it
+        self.lock = None;
+        self.updates.clear();
+        self.targets.clear();
+        self.state = State::Closed;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const A: &str = "3b18e512dba79e4c8300dd08aeb37f8e728b8dad";
+    const B: &str = "557db03de997c86a4a028e1ebd3a1ceb225be238";
+
+    fn update(refname: &str, new: Option<&str>, old: Option<&str>) -> RefUpdate {
+        RefUpdate {
+            refname: refname.to_string(),
+            change: match new {
+                Some(new) => RefChange::Set(new.to_string()),
+                None => RefChange::Delete,
+            },
+            old: old.map(str::to_string),
+            no_deref: false,
+            message: String::from("test"),
+        }
+    }
+
+    #[test]
+    fn test_transaction() {
+        let temp_dir = tempdir().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+
+        let mut transaction = RefTransaction::new(&repo);
+        transaction
+            .update(update("refs/heads/main", Some(A), Some(NULL_HASH)))
+            .unwrap();
+        transaction
+            .update(update("refs/heads/other", Some(B), None))
+            .unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(repo.get_ref("HEAD").unwrap(), A);
+        assert_eq!(repo.get_ref("refs/heads/other").unwrap(), B);
+        assert_eq!(repo.read_reflog("HEAD").unwrap().len(), 1);
+
+        // A failed verification leaves every ref untouched
+        let mut transaction = RefTransaction::new(&repo);
+        transaction
+            .update(update("refs/heads/other", None, None))
+            .unwrap();
+        transaction
+            .update(update("HEAD", Some(B), Some(B)))
+            .unwrap();
+        assert!(transaction.commit().is_err());
+        assert_eq!(repo.get_ref("refs/heads/main").unwrap(), A);
+        assert_eq!(repo.get_ref("refs/heads/other").unwrap(), B);
+        assert!(!repo.dir.join("refs/heads/other.lock").exists());
+
+        let mut transaction = RefTransaction::new(&repo);
+        transaction
+            .update(update("refs/heads/other", None, Some(B)))
+            .unwrap();
+        transaction.commit().unwrap();
+        assert!(repo.read_ref("refs/heads/other").unwrap().is_none());
+    }
+}
@@ -0,0 +1,552 @@
+//! The untracked cache, the `UNTR` extension of the index. It records the
+//! untracked files of every directory along with the directory's stat data,
+//! so `status` only has to read the directories that changed since. Adding
+//! a file to the index or removing one invalidates its directory, and a
+//! changed ignore file invalidates the directories it applies to.
+
+use std::{
+    fs,
+    os::{
+        raw::{c_char, c_int},
+        unix::fs::MetadataExt,
+    },
+    path::Path,
+};
+
+use crate::object::Object;
+
+use super::{
+    error::{RepoError, Result},
+    ewah,
+    ignore::user_file,
+    index::{is_missing, Index},
+    worktree::absolute,
+    Repository,
+};
+
+/// Flag of git's directory walk to show untracked directories as a whole
+pub const DIR_SHOW_OTHER_DIRECTORIES: u32 = 1 << 1;
+/// Flag of git's directory walk to leave out empty untracked directories
+pub const DIR_HIDE_EMPTY_DIRECTORIES: u32 = 1 << 2;
+const STAT_SIZE: usize = 36;
+const HASH_SIZE: usize = 20;
+
+extern "C" {
+    fn uname(buf: *mut c_char) -> c_int;
+}
+
+fn corrupt() -> RepoError {
+    RepoError::CorruptIndex("bad UNTR extension".to_string())
+}
+
+/// The stat data cached for directories and ignore files.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StatData {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+}
+
+impl StatData {
+    pub fn new(meta: &fs::Metadata) -> StatData {
+        // Like the index, only the lower 32 bits are stored
+        StatData {
+            ctime: (meta.ctime() as u32, meta.ctime_nsec() as u32),
+            mtime: (meta.mtime() as u32, meta.mtime_nsec() as u32),
+            dev: meta.dev() as u32,
+            ino: meta.ino() as u32,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            size: meta.size() as u32,
+        }
+    }
+
+    fn parse(data: &mut &[u8]) -> Option<StatData> {
+        let field = |i: usize| u32::from_be_bytes(data[4 * i..4 * i + 4].try_into().unwrap());
+        if data.len() < STAT_SIZE {
+            return None;
+        }
+        let stat = StatData {
+            ctime: (field(0), field(1)),
+            mtime: (field(2), field(3)),
+            dev: field(4),
+            ino: field(5),
+            uid: field(6),
+            gid: field(7),
+            size: field(8),
+        };
+        *data = &data[STAT_SIZE..];
+        Some(stat)
+    }
+
+    fn serialize(&self, out: &mut Vec<u8>) {
+        for field in [
+            self.ctime.0,
+            self.ctime.1,
+            self.mtime.0,
+            self.mtime.1,
+            self.dev,
+            self.ino,
+            self.uid,
+            self.gid,
+            self.size,
+        ] {
+            out.extend_from_slice(&field.to_be_bytes());
+        }
+    }
+}
+
+/// An ignore file as it was when the cache was made.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExcludeFile {
+    pub stat: StatData,
+    /// The hash of its content, `None` if it doesn't exist
+    pub hash: Option<String>,
+}
+
+/// The cached untracked files of a directory.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UntrackedDir {
+    pub name: String,
+    /// The untracked files in it, with untracked directories shown as a
+    /// whole ending in `/`
+    pub untracked: Vec<String>,
+    /// The cached directories in it, sorted by name
+    pub dirs: Vec<UntrackedDir>,
+    /// Whether the untracked files are still those of the directory with
+    /// the stat data `stat`
+    pub valid: bool,
+    /// Whether the directory is untracked and was only read to find out if
+    /// it's empty
+    pub check_only: bool,
+    pub stat: StatData,
+    /// The hash of its `.gitignore`, as for [`ExcludeFile`]
+    pub exclude_hash: Option<String>,
+}
+
+impl UntrackedDir {
+    fn invalidate(&mut self) {
+        self.valid = false;
+        self.check_only = false;
+        self.stat = StatData::default();
+        self.untracked.clear();
+    }
+
+    /// Returns the directory and those below it, in pre-order.
+    fn preorder(&self) -> Vec<&UntrackedDir> {
+        let mut dirs = vec![self];
+        for dir in &self.dirs {
+            dirs.extend(dir.preorder());
+        }
+        dirs
+    }
+
+    /// Calls `f` on the directory and those below it, in pre-order.
+    fn preorder_mut(&mut self, f: &mut impl FnMut(&mut UntrackedDir)) {
+        f(self);
+        for dir in &mut self.dirs {
+            dir.preorder_mut(f);
+        }
+    }
+}
+
+/// The untracked cache of the index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UntrackedCache {
+    /// The location of the working tree and the system the cache was made
+    /// on, as it's only valid there
+    ident: String,
+    info_exclude: ExcludeFile,
+    excludes_file: ExcludeFile,
+    /// The flags of git's directory walk the cache was made with
+    pub dir_flags: u32,
+    /// The name of the per-directory ignore files
+    exclude_per_dir: String,
+    /// The cached directories, `None` until the first walk
+    pub root: Option<UntrackedDir>,
+}
+
+impl UntrackedCache {
+    /// Returns an empty cache for the working tree, made with the flags of
+    /// a walk showing untracked directories as a whole.
+    pub fn new(repo: &Repository) -> Result<UntrackedCache> {
+        Ok(UntrackedCache {
+            ident: ident(repo)?,
+            info_exclude: ExcludeFile::default(),
+            excludes_file: ExcludeFile::default(),
+            dir_flags: DIR_SHOW_OTHER_DIRECTORIES | DIR_HIDE_EMPTY_DIRECTORIES,
+            exclude_per_dir: ".gitignore".to_string(),
+            root: None,
+        })
+    }
+
+    /// Parses the data of an `UNTR` extension.
+    pub fn parse(mut data: &[u8]) -> Result<UntrackedCache> {
+        let mut cache = parse_cache(&mut data).ok_or_else(corrupt)?;
+        // Data with directories ends in a NUL byte
+        if !matches!(data, b"" | b"\0") {
+            Err(corrupt())?;
+        }
+        if let Some(root) = &mut cache.root {
+            root.preorder_mut(&mut |dir| dir.dirs.sort_by(|a, b| a.name.cmp(&b.name)));
+        }
+        Ok(cache)
+    }
+
+    /// Appends the data of an `UNTR` extension.
+    pub fn serialize(&self, out: &mut Vec<u8>) {
+        let ident = format!("{}\0", self.ident);
+        write_varint(ident.len(), out);
+        out.extend_from_slice(ident.as_bytes());
+        self.info_exclude.stat.serialize(out);
+        self.excludes_file.stat.serialize(out);
+        out.extend_from_slice(&self.dir_flags.to_be_bytes());
+        for file in [&self.info_exclude, &self.excludes_file] {
+            write_hash(file.hash.as_deref(), out);
+        }
+        out.extend_from_slice(self.exclude_per_dir.as_bytes());
+        out.push(0);
+
+        let dirs = self
+            .root
+            .as_ref()
+            .map(|root| root.preorder())
+            .unwrap_or_default();
+        write_varint(dirs.len(), out);
+        if dirs.is_empty() {
+            return;
+        }
+        for dir in &dirs {
+            // Invalid directories are written without their stale files
+            let untracked: &[String] = if dir.valid { &dir.untracked } else { &[] };
+            write_varint(untracked.len(), out);
+            write_varint(dir.dirs.len(), out);
+            out.extend_from_slice(dir.name.as_bytes());
+            out.push(0);
+            for name in untracked {
+                out.extend_from_slice(name.as_bytes());
+                out.push(0);
+            }
+        }
+        let bits =
+            |f: fn(&UntrackedDir) -> bool| dirs.iter().map(|&dir| f(dir)).collect::<Vec<_>>();
+        ewah::write(&bits(|dir| dir.valid), out);
+        ewah::write(&bits(|dir| dir.valid && dir.check_only), out);
+        ewah::write(&bits(|dir| dir.exclude_hash.is_some()), out);
+        for dir in dirs.iter().filter(|dir| dir.valid) {
+            dir.stat.serialize(out);
+        }
+        for dir in &dirs {
+            if dir.exclude_hash.is_some() {
+                write_hash(dir.exclude_hash.as_deref(), out);
+            }
+        }
+        out.push(0);
+    }
+
+    /// Invalidates the directory of a path added to or removed from the
+    /// index. When untracked directories are shown as a whole, the
+    /// directories above it are invalidated too, as the path may have made
+    /// one of them tracked or untracked.
+    pub fn invalidate(&mut self, path: &str) {
+        let Some(mut dir) = self.root.as_mut() else {
+            return;
+        };
+        let whole_dirs = self.dir_flags & DIR_SHOW_OTHER_DIRECTORIES != 0;
+        let mut components: Vec<&str> = path.split('/').collect();
+        components.pop();
+        for name in components {
+            if whole_dirs {
+                dir.invalidate();
+            }
+            match dir.dirs.binary_search_by(|d| d.name.as_str().cmp(name)) {
+                Ok(i) => dir = &mut dir.dirs[i],
+                Err(_) => return,
+            }
+        }
+        dir.invalidate();
+    }
+
+    /// Gets the cache ready for a walk with `dir_flags`, dropping the cached
+    /// directories if it was made elsewhere, with other flags, or with
+    /// other `info/exclude` or `core.excludesFile` files.
+    pub fn validate(&mut self, repo: &Repository, dir_flags: u32) -> Result<()> {
+        let ident = ident(repo)?;
+        let info_exclude = exclude_file(&repo.get_common_path().join("info/exclude"), None)?;
+        let excludes_file = match user_file(&repo.config()?, "core.excludesFile", "ignore") {
+            Some((_, path)) => exclude_file(&path, None)?,
+            None => ExcludeFile::default(),
+        };
+        if ident != self.ident
+            || dir_flags != self.dir_flags
+            || info_exclude.hash != self.info_exclude.hash
+            || excludes_file.hash != self.excludes_file.hash
+        {
+            self.root = None;
+        }
+        self.ident = ident;
+        self.dir_flags = dir_flags;
+        self.info_exclude = info_exclude;
+        self.excludes_file = excludes_file;
+        Ok(())
+    }
+}
+
+/// Returns the stat data and hash of an ignore file the way git records
+/// them: the hash of a blob of its content with a newline added, or the
+/// hash of its index entry if it's tracked at `tracked` and up to date.
+pub fn exclude_file(path: &Path, tracked: Option<(&Index, &str)>) -> Result<ExcludeFile> {
+    let meta = match fs::metadata(path) {
+        Ok(meta) if meta.is_file() => meta,
+        Ok(_) => return Ok(ExcludeFile::default()),
+        Err(e) if is_missing(&e) => return Ok(ExcludeFile::default()),
+        Err(e) => Err(e)?,
+    };
+    let entry = tracked.and_then(|(index, path)| {
+        index
+            .get(path)
+            .filter(|entry| entry.stat_matches(&meta) && !index.is_racy(entry))
+    });
+    let hash = match entry {
+        Some(entry) => entry.hash.clone(),
+        None => {
+            let mut content = fs::read(path)?;
+            if !content.is_empty() {
+                content.push(b'\n');
+            }
+            Object::blob_from_bytes(&content).write(std::io::sink())?
+        }
+    };
+
+    Ok(ExcludeFile {
+        stat: StatData::new(&meta),
+        hash: Some(hash),
+    })
+}
+
+/// Returns the identification of the cache, e.g. `Location /src/repo,
+/// system Linux`.
+fn ident(repo: &Repository) -> Result<String> {
+    let root = absolute(repo.get_root())?;
+    Ok(format!("Location {}, system {}", root.display(), sysname()))
+}
+
+/// Returns the name of the operating system, as `uname -s` prints it.
+fn sysname() -> String {
+    // Larger than `struct utsname` on any system, whose first field is the
+    // NUL terminated system name
+    let mut buf = [0 as c_char; 4096];
+    // SAFETY: uname writes no more than a `struct utsname` to the buffer
+    if unsafe { uname(buf.as_mut_ptr()) } != 0 {
+        return String::new();
+    }
+    let name: Vec<u8> = buf
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
+    String::from_utf8_lossy(&name).to_string()
+}
+
+fn parse_cache(data: &mut &[u8]) -> Option<UntrackedCache> {
+    let ident_len = read_varint(data)?;
+    let ident = data.get(..ident_len)?;
+    let ident = String::from_utf8_lossy(ident.strip_suffix(b"\0")?).to_string();
+    *data = &data[ident_len..];
+    let info_stat = StatData::parse(data)?;
+    let excludes_stat = StatData::parse(data)?;
+    let dir_flags = u32::from_be_bytes(data.get(..4)?.try_into().ok()?);
+    *data = &data[4..];
+    let info_hash = read_hash(data)?;
+    let excludes_hash = read_hash(data)?;
+    let exclude_per_dir = read_string(data)?;
+
+    let mut cache = UntrackedCache {
+        ident,
+        info_exclude: ExcludeFile {
+            stat: info_stat,
+            hash: info_hash,
+        },
+        excludes_file: ExcludeFile {
+            stat: excludes_stat,
+            hash: excludes_hash,
+        },
+        dir_flags,
+        exclude_per_dir,
+        root: None,
+    };
+    let count = read_varint(data)?;
+    if count == 0 {
+        return Some(cache);
+    }
+
+    let mut root = parse_dir(data)?;
+    let mut bitmap = || {
+        let (bits, len) = ewah::read(data)?;
+        *data = &data[len..];
+        Some(bits)
+    };
+    let valid = bitmap()?;
+    let check_only = bitmap()?;
+    let hash_valid = bitmap()?;
+    if root.preorder().len() != count {
+        return None;
+    }
+
+    // The stat data of all valid directories comes before the hashes
+    let (mut i, mut ok) = (0, true);
+    root.preorder_mut(&mut |dir| {
+        dir.valid = valid.get(i).copied().unwrap_or(false);
+        dir.check_only = check_only.get(i).copied().unwrap_or(false);
+        if dir.valid {
+            match StatData::parse(data) {
+                Some(stat) => dir.stat = stat,
+                None => ok = false,
+            }
+        }
+        i += 1;
+    });
+    i = 0;
+    root.preorder_mut(&mut |dir| {
+        if hash_valid.get(i).copied().unwrap_or(false) {
+            match read_hash(data) {
+                Some(hash) => dir.exclude_hash = hash,
+                None => ok = false,
+            }
+        }
+        i += 1;
+    });
+    if !ok {
+        return None;
+    }
+
+    cache.root = Some(root);
+    Some(cache)
+}
+
+/// Parses a directory and those below it, in pre-order.
+fn parse_dir(data: &mut &[u8]) -> Option<UntrackedDir> {
+    let untracked_count = read_varint(data)?;
+    let dir_count = read_varint(data)?;
+    let name = read_string(data)?;
+    let untracked = (0..untracked_count)
+        .map(|_| read_string(data))
+        .collect::<Option<_>>()?;
+    let dirs = (0..dir_count)
+        .map(|_| parse_dir(data))
+        .collect::<Option<_>>()?;
+
+    Some(UntrackedDir {
+        name,
+        untracked,
+        dirs,
+        ..Default::default()
+    })
+}
+
+fn read_string(data: &mut &[u8]) -> Option<String> {
+    let nul = data.iter().position(|&b| b == 0)?;
+    let string = String::from_utf8_lossy(&data[..nul]).to_string();
+    *data = &data[nul + 1..];
+    Some(string)
+}
+
+/// Reads a hash, which is `None` if it's all zeros.
+fn read_hash(data: &mut &[u8]) -> Option<Option<String>> {
+    let hash = data.get(..HASH_SIZE)?;
+    *data = &data[HASH_SIZE..];
+    Some(hash.iter().any(|&b| b != 0).then(|| hex::encode(hash)))
+}
+
+fn write_hash(hash: Option<&str>, out: &mut Vec<u8>) {
+    let hash = hash.and_then(|hash| hex::decode(hash).ok());
+    out.extend(hash.unwrap_or_else(|| vec![0; HASH_SIZE]));
+}
+
+/// Reads a variable-length integer of git's offset encoding: 7 bits per
+/// byte, most significant first, with one added to all but the last group
+/// so that every number has a single encoding.
+fn read_varint(data: &mut &[u8]) -> Option<usize> {
+    let (&first, mut rest) = data.split_first()?;
+    let mut value = usize::from(first & 0x7f);
+    let mut byte = first;
+    while byte & 0x80 != 0 {
+        (byte, rest) = rest.split_first().map(|(&b, rest)| (b, rest))?;
+        value = ((value + 1) << 7) | usize::from(byte & 0x7f);
+    }
+    *data = rest;
+    Some(value)
+}
+
+fn write_varint(mut value: usize, out: &mut Vec<u8>) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_untracked_cache() {
+        let dir = |name: &str, untracked: &[&str], dirs: Vec<UntrackedDir>| UntrackedDir {
+            name: name.to_string(),
+            untracked: untracked.iter().map(|name| name.to_string()).collect(),
+            dirs,
+            valid: true,
+            stat: StatData {
+                mtime: (1, 2),
+                size: 3,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut new = dir("new", &["a"], vec![]);
+        new.check_only = true;
+        let mut root = dir(
+            "",
+            &["new/", "x"],
+            vec![new, dir("src", &[], vec![dir("sub", &["y"], vec![])])],
+        );
+        root.exclude_hash = Some("5761abcfdf0c26a75374c945dfe366eaeee04285".to_string());
+        let mut cache = UntrackedCache {
+            ident: "Location /repo, system Linux".to_string(),
+            info_exclude: ExcludeFile::default(),
+            excludes_file: ExcludeFile::default(),
+            dir_flags: DIR_SHOW_OTHER_DIRECTORIES | DIR_HIDE_EMPTY_DIRECTORIES,
+            exclude_per_dir: ".gitignore".to_string(),
+            root: Some(root),
+        };
+
+        let mut data = vec![];
+        cache.serialize(&mut data);
+        assert_eq!(UntrackedCache::parse(&data).unwrap(), cache);
+
+        // A new file invalidates its directory and, with directories shown
+        // as a whole, those above it
+        cache.invalidate("src/sub/z");
+        let root = cache.root.as_ref().unwrap();
+        assert!(!root.valid && root.untracked.is_empty());
+        assert!(root.dirs[0].valid);
+        assert!(!root.dirs[1].valid && !root.dirs[1].dirs[0].valid);
+        let mut data = vec![];
+        cache.serialize(&mut data);
+        assert_eq!(UntrackedCache::parse(&data).unwrap(), cache);
+
+        for value in [0, 127, 128, 16511, 16512, 1 << 40] {
+            let mut data = vec![];
+            write_varint(value, &mut data);
+            assert_eq!(read_varint(&mut data.as_slice()), Some(value));
+        }
+    }
+}
@@ -0,0 +1,414 @@
+//! Linked worktrees. Every linked worktree has a git directory in
+//! `.git/worktrees/<id>` with its own `HEAD`, index and reflogs, while the
+//! objects, the config and all other refs are shared. The worktree points to
+//! that directory with a `.git` file, and the directory points back with its
+//! `gitdir` file.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::object::Commit;
+
+use super::{
+    checkout::CheckoutOptions,
+    error::{RepoError, Result},
+    refs::{reftable::ReftableStore, RefFormat, RefValue, RefWrite, NULL_HASH},
+    status::UntrackedFiles,
+    Repository,
+};
+
+/// A worktree of a repository.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Worktree {
+    /// The root of the working tree
+    pub path: PathBuf,
+    /// The git directory of the worktree
+    pub git_dir: PathBuf,
+    /// The name of the directory in `.git/worktrees`, `None` for the main
+    /// worktree
+    pub id: Option<String>,
+    /// The commit checked out, `None` on an unborn branch
+    pub head: Option<String>,
+    /// The branch checked out, `None` if HEAD is detached
+    pub branch: Option<String>,
+    /// The reason the worktree is locked, which may be empty
+    pub locked: Option<String>,
+    /// Why the worktree can be pruned, if its directory is gone
+    pub prunable: Option<String>,
+}
+
+impl Worktree {
+    pub fn is_main(&self) -> bool {
+        self.id.is_none()
+    }
+}
+
+/// What to check out in a new worktree.
+#[derive(Debug, Clone)]
+pub enum WorktreeHead {
+    /// A branch, by ref name
+    Branch(String),
+    /// A commit to detach HEAD at
+    Detached(String),
+}
+
+/// Returns the git directory a `.git` file points to.
+pub(super) fn read_gitfile(dot_git: &Path) -> Result<PathBuf> {
+    let content = fs::read_to_string(dot_git)?;
+    let Some(git_dir) = content.trim_end().strip_prefix("gitdir: ") else {
+        Err(RepoError::Other(format!(
+            "invalid gitfile format: {}",
+            dot_git.display()
+        )))?
+    };
+    let base = dot_git.parent().unwrap_or(Path::new("."));
+    let git_dir = base.join(git_dir);
+    if !git_dir.is_dir() {
+        Err(RepoError::Other(format!(
+            "not a git repository: {}",
+            git_dir.display()
+        )))?;
+    }
+
+    Ok(git_dir)
+}
+
+/// Returns the common directory of a git directory, given by its
+/// `commondir` file, or the git directory itself if there is none.
+pub(super) fn read_common_dir(git_dir: &Path) -> Result<PathBuf> {
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common_dir) => Ok(fs::canonicalize(git_dir.join(common_dir.trim_end()))?),
+        Err(_) => Ok(git_dir.to_path_buf()),
+    }
+}
+
+/// Returns the absolute form of a path, resolving symbolic links where it
+/// exists.
+pub fn absolute(path: &Path) -> Result<PathBuf> {
+    match fs::canonicalize(path) {
+        Ok(path) => Ok(path),
+        Err(_) => {
+            let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+            let parent = absolute(parent.unwrap_or(Path::new(".")))?;
+            Ok(match path.file_name() {
+                Some(name) => parent.join(name),
+                None => parent,
+            })
+        }
+    }
+}
+
+impl Repository {
+    /// Returns the main worktree followed by the linked worktrees, sorted by
+    /// id.
+    pub fn worktrees(&self) -> Result<Vec<Worktree>> {
+        let common_dir = absolute(&self.common_dir)?;
+        let root = common_dir.parent().unwrap_or(Path::new("/")).to_path_buf();
+        let mut worktrees = vec![self.read_worktree(None, common_dir.clone(), root)?];
+
+        let Ok(entries) = fs::read_dir(common_dir.join("worktrees")) else {
+            return Ok(worktrees);
+        };
+        let mut ids: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        ids.sort();
+        for id in ids {
+            let git_dir = common_dir.join("worktrees").join(&id);
+            let dot_git = fs::read_to_string(git_dir.join("gitdir")).unwrap_or_default();
+            let root = Path::new(dot_git.trim_end())
+                .parent()
+                .unwrap_or(Path::new(""))
+                .to_path_buf();
+            worktrees.push(self.read_worktree(Some(id), git_dir, root)?);
+        }
+
+        Ok(worktrees)
+    }
+
+    fn read_worktree(
+        &self,
+        id: Option<String>,
+        git_dir: PathBuf,
+        path: PathBuf,
+    ) -> Result<Worktree> {
+        let repo = Repository::open(git_dir.clone(), absolute(&self.common_dir)?, path.clone())?;
+        let locked = id
+            .as_ref()
+            .and_then(|_| fs::read_to_string(git_dir.join("locked")).ok())
+            .map(|reason| reason.trim_end().to_string());
+        let prunable = match (&id, &locked) {
+            (Some(_), None) => prune_reason(&git_dir),
+            _ => None,
+        };
+
+        Ok(Worktree {
+            head: repo.read_ref("HEAD").ok().flatten(),
+            branch: repo.read_symbolic_ref("HEAD").ok().flatten(),
+            path,
+            git_dir,
+            id,
+            locked,
+            prunable,
+        })
+    }
+
+    /// Returns whether a worktree is the one this repository was opened in.
+    pub fn is_current_worktree(&self, worktree: &Worktree) -> Result<bool> {
+        Ok(absolute(&self.dir)? == worktree.git_dir)
+    }
+
+    /// Returns the worktree at the given path, or whose path ends with the
+    /// given components if that's unique.
+    pub fn find_worktree(&self, arg: &str) -> Result<Option<Worktree>> {
+        let worktrees = self.worktrees()?;
+        let path = absolute(Path::new(arg))?;
+        if let Some(worktree) = worktrees.iter().find(|wt| wt.path == path) {
+            return Ok(Some(worktree.clone()));
+        }
+
+        let mut matches = worktrees.into_iter().filter(|wt| wt.path.ends_with(arg));
+        match (matches.next(), matches.next()) {
+            (Some(worktree), None) => Ok(Some(worktree)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the path of another worktree that has the given branch
+    /// checked out.
+    pub fn worktree_using_branch(&self, refname: &str) -> Result<Option<PathBuf>> {
+        for worktree in self.worktrees()? {
+            if worktree.branch.as_deref() == Some(refname)
+                && !self.is_current_worktree(&worktree)?
+            {
+                return Ok(Some(worktree.path));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Creates a linked worktree at `path` and checks out `head` in it. The
+    /// worktree is locked while it's created, and stays locked if a reason
+    /// is given.
+    pub fn add_worktree(&self, path: &Path, head: &WorktreeHead, lock: Option<&str>) -> Result<()> {
+        if path.exists() && fs::read_dir(path).map_or(true, |mut dir| dir.next().is_some()) {
+            Err(RepoError::Other(format!(
+                "'{}' already exists",
+                path.display()
+            )))?;
+        }
+        let hash = match head {
+            WorktreeHead::Branch(refname) => self
+                .read_ref(refname)?
+                .ok_or_else(|| RepoError::RefNotFound(refname.clone()))?,
+            WorktreeHead::Detached(hash) => hash.clone(),
+        };
+        let tree = Commit::read(&hash, self)?.tree;
+
+        // The id is the base name of the path, numbered if it's taken
+        let common_dir = absolute(&self.common_dir)?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "worktree".to_string());
+        let mut id = name.clone();
+        let mut counter = 1;
+        while common_dir.join("worktrees").join(&id).exists() {
+            id = format!("{name}{counter}");
+            counter += 1;
+        }
+        let git_dir = common_dir.join("worktrees").join(&id);
+        fs::create_dir_all(&git_dir)?;
+        fs::write(git_dir.join("locked"), "initializing\n")?;
+
+        fs::create_dir_all(path)?;
+        let path = absolute(path)?;
+        fs::write(
+            git_dir.join("gitdir"),
+            format!("{}\n", path.join(".git").display()),
+        )?;
+        fs::write(git_dir.join("commondir"), "../..\n")?;
+        if RefFormat::from_config(&self.config()?)? == RefFormat::Reftable {
+            ReftableStore::create(&git_dir)?;
+        }
+        fs::write(
+            path.join(".git"),
+            format!("gitdir: {}\n", git_dir.display()),
+        )?;
+
+        let repo = Repository::open(git_dir.clone(), common_dir, path)?;
+        match head {
+            WorktreeHead::Branch(refname) => repo.set_symbolic_ref("HEAD", refname, "")?,
+            WorktreeHead::Detached(hash) => {
+                repo.refs
+                    .lock(&["HEAD".to_string()])?
+                    .commit(vec![RefWrite::Ref {
+                        refname: "HEAD".to_string(),
+                        value: Some(RefValue::Direct(hash.clone())),
+                    }])?
+            }
+        }
+        repo.append_reflog("HEAD", NULL_HASH, &hash, "")?;
+        let options = CheckoutOptions {
+            force: true,
+            ..Default::default()
+        };
+        repo.checkout_tree(None, &tree, &options)?;
+        repo.append_reflog("HEAD", &hash, &hash, "reset: moving to HEAD")?;
+
+        match lock {
+            Some(reason) => write_lock(&git_dir, reason)?,
+            None => fs::remove_file(git_dir.join("locked"))?,
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether a worktree has local changes or untracked files.
+    pub fn worktree_is_dirty(&self, worktree: &Worktree) -> Result<bool> {
+        let repo = Repository::open(
+            worktree.git_dir.clone(),
+            absolute(&self.common_dir)?,
+            worktree.path.clone(),
+        )?;
+        let status = repo.status(UntrackedFiles::Normal)?;
+        Ok(!status.changes.is_empty()
+            || !status.unmerged.is_empty()
+            || !status.untracked.is_empty())
+    }
+
+    /// Deletes a linked worktree along with its git directory.
+    pub fn remove_worktree(&self, worktree: &Worktree) -> Result<()> {
+        if worktree.path.exists() {
+            fs::remove_dir_all(&worktree.path)?;
+        }
+        fs::remove_dir_all(&worktree.git_dir)?;
+        remove_empty_worktrees_dir(&self.common_dir);
+        Ok(())
+    }
+
+    /// Removes the git directories of worktrees that are gone, unless they
+    /// are locked. Returns the ids of the removed directories, with the
+    /// reason they were removed.
+    pub fn prune_worktrees(&self, dry_run: bool) -> Result<Vec<(String, String)>> {
+        let mut pruned = vec![];
+        for worktree in self.worktrees()? {
+            let (Some(id), Some(reason)) = (worktree.id, worktree.prunable) else {
+                continue;
+            };
+            if !dry_run {
+                fs::remove_dir_all(&worktree.git_dir)?;
+            }
+            pruned.push((id, reason));
+        }
+        if !dry_run {
+            remove_empty_worktrees_dir(&self.common_dir);
+        }
+        Ok(pruned)
+    }
+
+    /// Locks a linked worktree against being pruned, moved or removed.
+    pub fn lock_worktree(&self, worktree: &Worktree, reason: &str) -> Result<()> {
+        write_lock(&worktree.git_dir, reason)
+    }
+
+    pub fn unlock_worktree(&self, worktree: &Worktree) -> Result<()> {
+        Ok(fs::remove_file(worktree.git_dir.join("locked"))?)
+    }
+}
+
+/// Returns why the git directory of a linked worktree can be pruned, if
+/// its worktree is gone.
+fn prune_reason(git_dir: &Path) -> Option<String> {
+    if !git_dir.is_dir() {
+        return Some("not a valid directory".to_string());
+    }
+    let Ok(dot_git) = fs::read_to_string(git_dir.join("gitdir")) else {
+        return Some("gitdir file does not exist".to_string());
+    };
+    if !Path::new(dot_git.trim_end()).exists() {
+        return Some("gitdir file points to non-existent location".to_string());
+    }
+    None
+}
+
+/// Writes the `locked` file of a worktree, with the reason on a line of its
+/// own.
+fn write_lock(git_dir: &Path, reason: &str) -> Result<()> {
+    let content = match reason {
+        "" => String::new(),
+        reason => format!("{reason}\n"),
+    };
+    Ok(fs::write(git_dir.join("locked"), content)?)
+}
+
+fn remove_empty_worktrees_dir(common_dir: &Path) {
+    let _ = fs::remove_dir(common_dir.join("worktrees"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::commit::write_commit;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_worktrees() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path().join("main");
+        fs::create_dir(&root).unwrap();
+        let repo = Repository::init(&root).unwrap();
+        fs::write(root.join("a"), "a\n").unwrap();
+        let tree = repo.add_all().unwrap();
+        let commit = write_commit(&tree, &[], "one", &repo).unwrap();
+        repo.update_ref("HEAD", &commit, "commit (initial): one")
+            .unwrap();
+
+        let path = temp_dir.path().join("linked");
+        let head = WorktreeHead::Branch("refs/heads/main".to_string());
+        repo.add_worktree(&path, &head, None).unwrap();
+        assert_eq!(fs::read_to_string(path.join("a")).unwrap(), "a\n");
+
+        // HEAD and its reflog are per-worktree, branches are shared
+        let linked = Repository::from_path(&path).unwrap();
+        assert_eq!(linked.read_ref("HEAD").unwrap(), Some(commit.clone()));
+        assert_eq!(linked.read_reflog("HEAD").unwrap().len(), 2);
+        linked.create_branch("other").unwrap();
+        assert!(repo.branch_exists("other").unwrap());
+        assert_eq!(
+            linked.worktree_using_branch("refs/heads/main").unwrap(),
+            Some(absolute(&root).unwrap())
+        );
+        assert_eq!(
+            repo.worktree_using_branch("refs/heads/main").unwrap(),
+            Some(absolute(&path).unwrap())
+        );
+        assert_eq!(
+            repo.worktree_using_branch("refs/heads/other").unwrap(),
+            None
+        );
+
+        let worktrees = repo.worktrees().unwrap();
+        assert_eq!(worktrees.len(), 2);
+        assert!(worktrees[0].is_main());
+        assert_eq!(worktrees[1].id.as_deref(), Some("linked"));
+        assert_eq!(worktrees[1].prunable, None);
+
+        // Locked worktrees are kept even once they are gone
+        fs::remove_dir_all(&path).unwrap();
+        repo.lock_worktree(&worktrees[1], "").unwrap();
+        assert_eq!(repo.prune_worktrees(false).unwrap(), vec![]);
+        repo.unlock_worktree(&worktrees[1]).unwrap();
+        assert_eq!(
+            repo.prune_worktrees(false).unwrap(),
+            vec![(
+                "linked".to_string(),
+                "gitdir file points to non-existent location".to_string()
+            )]
+        );
+        assert_eq!(repo.worktrees().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,876 @@
+//! Walking the commit history like git's revision machinery: selecting
+//! commits with revision arguments such as `main ^v1.0`, `v1.0..main` or
+//! `main...feature`, ordering them, filtering them by date, parents, author
+//! and message, and limiting them to the commits that change some paths.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque},
+};
+
+use crate::{
+    object::{
+        commit::{self, Commit},
+        tree::{Tree, TreeEntry, EMPTY_TREE, MODE_GITLINK, MODE_TREE},
+        Object, ObjectType, Tag,
+    },
+    pathspec::match_pathspec,
+    regex::Regex,
+    repository::{
+        error::{RepoError, Result},
+        Repository,
+    },
+    wildmatch::{is_glob, wildmatch},
+};
+
+/// A commit found by a [`RevWalk`].
+#[derive(Debug, Clone)]
+pub struct RevCommit {
+    pub hash: String,
+    pub commit: Commit,
+    /// Reached from the left side of a symmetric range `<a>...<b>`
+    pub left: bool,
+}
+
+/// The commits to walk and the options that select and order them.
+#[derive(Debug, Default)]
+pub struct RevWalk {
+    /// The commits to start from
+    include: Vec<String>,
+    /// The commits whose ancestors are left out
+    exclude: Vec<String>,
+    /// The left sides of symmetric ranges
+    left: Vec<String>,
+    /// The objects given before peeling tags, and whether they're excluded,
+    /// whose tags, trees and blobs `objects` lists
+    tips: Vec<(String, bool)>,
+    /// Whether `--not` flipped the meaning of the following revisions
+    not: bool,
+    /// Only follow the first parent of merges
+    pub first_parent: bool,
+    /// Never show a parent before all of its children
+    pub topo_order: bool,
+    /// Like `topo_order`, showing the newest commit first where it can
+    pub date_order: bool,
+    /// Show the commits oldest first
+    pub reverse: bool,
+    /// Only show the commits on a path from an excluded commit to an
+    /// included one
+    pub ancestry_path: bool,
+    /// Only show the commits changing these paths, following a single
+    /// parent of merges that take all those changes from it
+    pub paths: Vec<String>,
+    /// Make the parents of the commits the nearest commits shown, when
+    /// `paths` leaves some out
+    pub rewrite_parents: bool,
+    /// Stop at commits older than this
+    pub since: Option<i64>,
+    /// Skip commits newer than this
+    pub until: Option<i64>,
+    pub min_parents: usize,
+    pub max_parents: Option<usize>,
+    /// Show commits by any of these authors
+    pub authors: Vec<Regex>,
+    /// Show commits with a message matching any of these
+    pub greps: Vec<Regex>,
+    /// Require the message to match all of `greps` instead
+    pub all_match: bool,
+    /// The number of commits to leave out before showing any
+    pub skip: usize,
+    pub max_count: Option<usize>,
+}
+
+impl RevWalk {
+    /// Adds a revision argument: `<rev>` to walk from, `^<rev>` to leave
+    /// out its ancestors, `<a>..<b>` for both at once or `<a>...<b>` for
+    /// the commits reachable from either but not both. The pseudo-options
+    /// `--all`, `--branches[=<pattern>]`, `--tags[=<pattern>]` and
+    /// `--remotes[=<pattern>]` add refs, and `--not` flips the meaning of
+    /// the `^` of the arguments after it.
+    pub fn add_revision(&mut self, repo: &Repository, arg: &str) -> Result<()> {
+        let resolve = |rev: &str| match rev {
+            "" => repo.rev_parse("HEAD"),
+            rev => repo.rev_parse(rev),
+        };
+        let (name, pattern) = match arg.split_once('=') {
+            Some((name, pattern)) => (name, Some(pattern)),
+            None => (arg, None),
+        };
+
+        match (name, pattern) {
+            ("--not", None) => self.not = !self.not,
+            ("--all", None) => {
+                for (_, hash) in repo.list_refs("refs/")? {
+                    self.add_ref(repo, &hash, false)?;
+                }
+                if let Some(head) = repo.read_ref("HEAD")? {
+                    self.add_ref(repo, &head, false)?;
+                }
+            }
+            ("--branches", _) => self.add_refs(repo, "refs/heads/", pattern)?,
+            ("--tags", _) => self.add_refs(repo, "refs/tags/", pattern)?,
+            ("--remotes", _) => self.add_refs(repo, "refs/remotes/", pattern)?,
+            _ => {
+                if let Some((from, to)) = arg.split_once("...") {
+                    let (from, to) = (resolve(from)?, resolve(to)?);
+                    self.tips
+                        .extend([(from.clone(), self.not), (to.clone(), self.not)]);
+                    let from = self.peel(repo, &from)?;
+                    let to = self.peel(repo, &to)?;
+                    if self.not {
+                        self.exclude.extend([from, to]);
+                    } else {
+                        self.exclude.extend(commit::merge_bases(&from, &to, repo)?);
+                        self.left.push(from.clone());
+                        self.include.extend([from, to]);
+                    }
+                } else if let Some((from, to)) = arg.split_once("..") {
+                    let (from, to) = (resolve(from)?, resolve(to)?);
+                    self.peel(repo, &from)?;
+                    self.peel(repo, &to)?;
+                    self.add_ref(repo, &from, true)?;
+                    self.add_ref(repo, &to, false)?;
+                } else if let Some(rev) = arg.strip_suffix("^@") {
+                    // The parents of the commit
+                    let hash = self.peel(repo, &resolve(rev)?)?;
+                    for parent in Commit::read(&hash, repo)?.parents {
+                        self.add_ref(repo, &parent, false)?;
+                    }
+                } else if let Some(rev) = arg.strip_suffix("^!") {
+                    // The commit without its parents
+                    let hash = self.peel(repo, &resolve(rev)?)?;
+                    for parent in Commit::read(&hash, repo)?.parents {
+                        self.add_ref(repo, &parent, true)?;
+                    }
+                    self.add_ref(repo, &hash, false)?;
+                } else if let Some(rev) = arg.strip_prefix('^') {
+                    self.add_ref(repo, &repo.rev_parse(rev)?, true)?;
+                } else {
+                    self.add_ref(repo, &repo.rev_parse(arg)?, false)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the commit a revision names, peeling tags.
+    fn peel(&self, repo: &Repository, hash: &str) -> Result<String> {
+        let hash = repo.peel_tags(hash)?;
+        match Object::read(&hash, repo)?.kind {
+            ObjectType::Commit => Ok(hash),
+            _ => Err(RepoError::Other(format!("object {hash} is not a commit"))),
+        }
+    }
+
+    /// Includes or excludes a commit, as flipped by `--not`.
+    fn add_ref(&mut self, repo: &Repository, hash: &str, negated: bool) -> Result<()> {
+        self.tips.push((hash.to_string(), negated != self.not));
+        // Refs to trees and blobs have no history
+        let hash = repo.peel_tags(hash)?;
+        if Object::read(&hash, repo)?.kind != ObjectType::Commit {
+            return Ok(());
+        }
+        match negated != self.not {
+            true => self.exclude.push(hash),
+            false => self.include.push(hash),
+        }
+        Ok(())
+    }
+
+    /// Adds the refs under `prefix` matching the glob `pattern`, which
+    /// without any glob characters names a directory of refs.
+    fn add_refs(&mut self, repo: &Repository, prefix: &str, pattern: Option<&str>) -> Result<()> {
+        let pattern = pattern.map(|pattern| match is_glob(pattern) {
+            true => format!("{prefix}{pattern}"),
+            false => format!("{prefix}{}/*", pattern.trim_end_matches('/')),
+        });
+        for (refname, hash) in repo.list_refs(prefix)? {
+            if pattern
+                .as_ref()
+                .is_none_or(|pattern| wildmatch(pattern, &refname, 0))
+            {
+                self.add_ref(repo, &hash, false)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks the history and returns the commits to show, newest first,
+    /// before `skip`, `max_count` and `reverse` apply.
+    pub fn walk(&self, repo: &Repository) -> Result<Vec<RevCommit>> {
+        let excluded = self.excluded(repo)?;
+
+        // Walk by commit date, breaking ties in the order the commits were
+        // found
+        let mut found = 0;
+        let mut queue = BinaryHeap::new();
+        let mut commits = HashMap::new();
+        let mut seen = HashSet::new();
+        for hash in &self.include {
+            if excluded.contains(hash) || !seen.insert(hash.clone()) {
+                continue;
+            }
+            let commit = Commit::read(hash, repo)?;
+            queue.push((commit.committer.time, Reverse(found), hash.clone()));
+            commits.insert(hash.clone(), commit);
+            found += 1;
+        }
+
+        let mut left: HashSet<String> = self.left.iter().cloned().collect();
+        let mut treesame = HashSet::new();
+        let mut list = vec![];
+        while let Some((time, _, hash)) = queue.pop() {
+            if self.since.is_some_and(|since| time < since) {
+                continue;
+            }
+            if !self.paths.is_empty() {
+                let (same, parents) = self.simplify(repo, &commits[&hash], &excluded)?;
+                if same {
+                    treesame.insert(hash.clone());
+                }
+                commits.get_mut(&hash).unwrap().parents = parents;
+            }
+
+            let mut parents = commits[&hash].parents.clone();
+            if self.first_parent {
+                parents.truncate(1);
+            }
+            let is_left = left.contains(&hash);
+            for parent in &parents {
+                if is_left {
+                    left.insert(parent.clone());
+                }
+                if excluded.contains(parent) || !seen.insert(parent.clone()) {
+                    continue;
+                }
+                let commit = Commit::read(parent, repo)?;
+                queue.push((commit.committer.time, Reverse(found), parent.clone()));
+                commits.insert(parent.clone(), commit);
+                found += 1;
+            }
+            list.push(hash);
+        }
+
+        if self.ancestry_path {
+            list = self.limit_to_ancestry(list, &commits)?;
+        }
+        if self.topo_order || self.date_order {
+            list = self.sort_topologically(list, &commits);
+        }
+
+        let shown: Vec<String> = list
+            .into_iter()
+            .filter(|hash| !treesame.contains(hash) && self.is_shown(&commits[hash]))
+            .collect();
+        let mut revs = vec![];
+        for hash in shown {
+            let mut commit = commits[&hash].clone();
+            if self.rewrite_parents && !treesame.is_empty() {
+                commit.parents = rewrite_parents(&commit.parents, &commits, &treesame);
+            }
+            revs.push(RevCommit {
+                left: left.contains(&hash),
+                hash,
+                commit,
+            });
+        }
+        Ok(revs)
+    }
+
+    /// Applies `skip`, `max_count` and `reverse` to the commits found by
+    /// [`RevWalk::walk`].
+    pub fn limit(&self, commits: Vec<RevCommit>) -> Vec<RevCommit> {
+        let mut commits: Vec<RevCommit> = commits
+            .into_iter()
+            .skip(self.skip)
+            .take(self.max_count.unwrap_or(usize::MAX))
+            .collect();
+        if self.reverse {
+            commits.reverse();
+        }
+        commits
+    }
+
+    /// Returns the commits reachable from the excluded revisions.
+    fn excluded(&self, repo: &Repository) -> Result<HashSet<String>> {
+        let mut excluded = HashSet::new();
+        let mut queue: VecDeque<String> = self.exclude.iter().cloned().collect();
+        while let Some(hash) = queue.pop_front() {
+            if excluded.insert(hash.clone()) {
+                queue.extend(Commit::read(&hash, repo)?.parents);
+            }
+        }
+        Ok(excluded)
+    }
+
+    /// Returns whether the commit is TREESAME, changing none of `paths`,
+    /// and the parents to follow: the first one the commit took all its
+    /// changes to the paths from, if any, otherwise all of them.
+    ///
+    /// For a merge, only its relevant parents decide whether it is
+    /// TREESAME, unless it has none: those included and the excluded
+    /// revisions themselves.
+    fn simplify(
+        &self,
+        repo: &Repository,
+        commit: &Commit,
+        excluded: &HashSet<String>,
+    ) -> Result<(bool, Vec<String>)> {
+        if commit.parents.is_empty() {
+            let same = !trees_differ(repo, EMPTY_TREE, &commit.tree, "", &self.paths)?;
+            return Ok((same, vec![]));
+        }
+
+        let mut relevant_parents = 0;
+        let mut relevant_change = false;
+        let mut irrelevant_change = false;
+        for (i, parent) in commit.parents.iter().enumerate() {
+            if self.first_parent && i > 0 {
+                break;
+            }
+            let relevant = !excluded.contains(parent) || self.exclude.contains(parent);
+            if relevant {
+                relevant_parents += 1;
+            }
+            let tree = Commit::read(parent, repo)?.tree;
+            let changed = trees_differ(repo, &tree, &commit.tree, "", &self.paths)?;
+            match (changed, relevant) {
+                (false, true) => return Ok((true, vec![parent.clone()])),
+                (false, false) => {}
+                (true, true) => relevant_change = true,
+                (true, false) => irrelevant_change = true,
+            }
+        }
+
+        let same = match relevant_parents {
+            0 => !irrelevant_change,
+            _ => !relevant_change,
+        };
+        Ok((same, commit.parents.clone()))
+    }
+
+    /// Keeps the commits that descend from an excluded revision.
+    fn limit_to_ancestry(
+        &self,
+        mut list: Vec<String>,
+        commits: &HashMap<String, Commit>,
+    ) -> Result<Vec<String>> {
+        if self.exclude.is_empty() {
+            return Err(RepoError::Other(
+                "--ancestry-path given but there are no bottom commits".to_string(),
+            ));
+        }
+
+        let mut descendants: HashSet<&str> = self.exclude.iter().map(String::as_str).collect();
+        loop {
+            let mut progress = false;
+            for hash in list.iter().rev() {
+                if !descendants.contains(hash.as_str())
+                    && commits[hash]
+                        .parents
+                        .iter()
+                        .any(|parent| descendants.contains(parent.as_str()))
+                {
+                    descendants.insert(hash);
+                    progress = true;
+                }
+            }
+            if !progress {
+                break;
+            }
+        }
+
+        let descendants: HashSet<String> = descendants.into_iter().map(String::from).collect();
+        list.retain(|hash| descendants.contains(hash));
+        Ok(list)
+    }
+
+    /// Returns the tags, trees and blobs given and the trees and blobs of
+    /// the commits, as `(hash, path)` in the order git lists them: the
+    /// objects given first, a tag named by its name, and every tree before
+    /// its entries, each object once. Those of the excluded revisions and
+    /// of the excluded commits at the edge of the history are left out, and
+    /// so are those outside of `paths`.
+    pub fn objects(
+        &self,
+        repo: &Repository,
+        commits: &[RevCommit],
+    ) -> Result<Vec<(String, String)>> {
+        let excluded = self.excluded(repo)?;
+        let mut edges: Vec<&String> = self.exclude.iter().collect();
+        for rev in commits {
+            edges.extend(rev.commit.parents.iter().filter(|p| excluded.contains(*p)));
+        }
+
+        let mut seen = HashSet::new();
+        let mut uninteresting = vec![];
+        for hash in edges {
+            let tree = Commit::read(hash, repo)?.tree;
+            walk_tree(repo, &tree, "", &[], &mut seen, &mut uninteresting)?;
+        }
+        for (hash, _) in self.tips.iter().filter(|(_, excluded)| *excluded) {
+            peel_tip(repo, hash, &[], &mut seen, &mut uninteresting)?;
+        }
+
+        let mut objects = vec![];
+        for (hash, _) in self.tips.iter().filter(|(_, excluded)| !excluded) {
+            peel_tip(repo, hash, &self.paths, &mut seen, &mut objects)?;
+        }
+        for rev in commits {
+            walk_tree(
+                repo,
+                &rev.commit.tree,
+                "",
+                &self.paths,
+                &mut seen,
+                &mut objects,
+            )?;
+        }
+        Ok(objects)
+    }
+
+    /// Sorts commits so that children come before their parents, keeping
+    /// the lines of history together like git's graph order: after a
+    /// commit come its unshown parents, the last one first. In date order
+    /// the newest commit whose children were all shown comes next instead.
+    fn sort_topologically(
+        &self,
+        list: Vec<String>,
+        commits: &HashMap<String, Commit>,
+    ) -> Vec<String> {
+        // The number of children in the list plus one, for commits in it
+        let mut indegree: HashMap<&str, usize> =
+            list.iter().map(|hash| (hash.as_str(), 1)).collect();
+        for hash in &list {
+            for parent in &commits[hash].parents {
+                if let Some(degree) = indegree.get_mut(parent.as_str()) {
+                    *degree += 1;
+                }
+            }
+        }
+
+        let mut queue = TopoQueue::new(self.date_order, commits);
+        let tips = list.iter().filter(|hash| indegree[hash.as_str()] == 1);
+        if self.date_order {
+            tips.for_each(|hash| queue.push(hash));
+        } else {
+            tips.rev().for_each(|hash| queue.push(hash));
+        }
+        let mut sorted = vec![];
+        while let Some(hash) = queue.pop() {
+            for parent in &commits[hash].parents {
+                let Some(degree) = indegree.get_mut(parent.as_str()) else {
+                    continue;
+                };
+                if *degree == 0 {
+                    continue;
+                }
+                *degree -= 1;
+                if *degree == 1 {
+                    queue.push(parent);
+                }
+            }
+            indegree.insert(hash, 0);
+            sorted.push(hash.to_string());
+        }
+        sorted
+    }
+
+    /// Returns true if the commit passes the filters on dates, parents,
+    /// authors and messages.
+    pub fn is_shown(&self, commit: &Commit) -> bool {
+        let parents = commit.parents.len();
+        let author = format!("{} <{}>", commit.author.name, commit.author.email);
+
+        self.until
+            .is_none_or(|until| commit.committer.time <= until)
+            && parents >= self.min_parents
+            && self.max_parents.is_none_or(|max| parents <= max)
+            && (self.authors.is_empty() || self.authors.iter().any(|re| re.is_match(&author)))
+            && (self.greps.is_empty()
+                || match self.all_match {
+                    true => self.greps.iter().all(|re| re.is_match(&commit.message)),
+                    false => self.greps.iter().any(|re| re.is_match(&commit.message)),
+                })
+    }
+}
+
+/// The commits ready to be shown by a topological sort: the last one
+/// pushed, or in date order the newest one.
+struct TopoQueue<'a> {
+    commits: &'a HashMap<String, Commit>,
+    date_order: bool,
+    stack: Vec<&'a str>,
+    dates: BinaryHeap<(i64, Reverse<usize>, &'a str)>,
+    pushed: usize,
+}
+
+impl<'a> TopoQueue<'a> {
+    fn new(date_order: bool, commits: &'a HashMap<String, Commit>) -> TopoQueue<'a> {
+        TopoQueue {
+            commits,
+            date_order,
+            stack: vec![],
+            dates: BinaryHeap::new(),
+            pushed: 0,
+        }
+    }
+
+    fn push(&mut self, hash: &'a str) {
+        match self.date_order {
+            true => {
+                let time = self.commits[hash].committer.time;
+                self.dates.push((time, Reverse(self.pushed), hash));
+                self.pushed += 1;
+            }
+            false => self.stack.push(hash),
+        }
+    }
+
+    fn pop(&mut self) -> Option<&'a str> {
+        match self.date_order {
+            true => self.dates.pop().map(|(_, _, hash)| hash),
+            false => self.stack.pop(),
+        }
+    }
+}
+
+/// Replaces each parent that is TREESAME by its nearest ancestor that
+/// isn't, dropping it if there is none.
+fn rewrite_parents(
+    parents: &[String],
+    commits: &HashMap<String, Commit>,
+    treesame: &HashSet<String>,
+) -> Vec<String> {
+    let mut rewritten: Vec<String> = vec![];
+    for parent in parents {
+        let mut parent = Some(parent);
+        while let Some(hash) = parent.filter(|hash| treesame.contains(*hash)) {
+            parent = commits[hash].parents.first();
+        }
+        if let Some(parent) = parent {
+            if !rewritten.contains(parent) {
+                rewritten.push(parent.clone());
+            }
+        }
+    }
+    rewritten
+}
+
+/// Returns true if the trees differ in any of the files matched by
+/// `paths`. Subtrees are only read where the paths could match in them.
+fn trees_differ(
+    repo: &Repository,
+    old: &str,
+    new: &str,
+    prefix: &str,
+    paths: &[String],
+) -> Result<bool> {
+    if old == new {
+        return Ok(false);
+    }
+    let old = Tree::read(old, repo)?.entries;
+    let new = Tree::read(new, repo)?.entries;
+    let find = |entries: &[TreeEntry], name: &str, tree: bool| {
+        entries
+            .iter()
+            .find(|entry| entry.name == name && (entry.mode == MODE_TREE) == tree)
+            .map(|entry| (entry.mode, entry.hash.clone()))
+    };
+
+    let names: BTreeSet<&str> = old.iter().chain(&new).map(|e| e.name.as_str()).collect();
+    for name in names {
+        let path = format!("{prefix}{name}");
+        if find(&old, name, false) != find(&new, name, false)
+            && paths
+                .iter()
+                .any(|spec| match_pathspec(spec, &path).is_some())
+        {
+            return Ok(true);
+        }
+
+        let old_tree = find(&old, name, true).map(|(_, hash)| hash);
+        let new_tree = find(&new, name, true).map(|(_, hash)| hash);
+        if old_tree != new_tree && may_match_in(paths, &path, true) {
+            let old_tree = old_tree.as_deref().unwrap_or(EMPTY_TREE);
+            let new_tree = new_tree.as_deref().unwrap_or(EMPTY_TREE);
+            if trees_differ(repo, old_tree, new_tree, &format!("{path}/"), paths)? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Returns true if any of the paths could match a file in the directory.
+/// Like git, walking trees `recursive`ly looks into every directory up to
+/// the first wildcard of a glob; otherwise the glob has to match the
+/// directory itself.
+fn may_match_in(paths: &[String], dir: &str, recursive: bool) -> bool {
+    paths.iter().any(|spec| {
+        let spec = spec.strip_prefix("./").unwrap_or(spec);
+        let literal = match spec.find(['*', '?', '[', '\\']) {
+            Some(end) => &spec[..end],
+            None => spec,
+        };
+        let dir_slash = format!("{dir}/");
+        match_pathspec(spec, dir).is_some()
+            || literal.starts_with(&dir_slash)
+            || (recursive && literal.len() < spec.len() && dir_slash.starts_with(literal))
+    })
+}
+
+/// Adds the tree and everything in it matching `paths`, if any, not seen
+/// yet to `objects`, as `(hash, path)`. Submodule commits are left out.
+/// Adds the objects of a revision that aren't commits to `objects`: the
+/// tags peeled to reach the object it names, and the tree or blob it is.
+fn peel_tip(
+    repo: &Repository,
+    hash: &str,
+    paths: &[String],
+    seen: &mut HashSet<String>,
+    objects: &mut Vec<(String, String)>,
+) -> Result<()> {
+    let mut hash = hash.to_string();
+    loop {
+        match Object::read(&hash, repo)?.kind {
+            ObjectType::Tag => {
+                let tag = Tag::read(&hash, repo)?;
+                if seen.insert(hash.clone()) {
+                    objects.push((hash, tag.name));
+                }
+                hash = tag.object;
+            }
+            ObjectType::Tree => return walk_tree(repo, &hash, "", paths, seen, objects),
+            ObjectType::Blob => {
+                if seen.insert(hash.clone()) {
+                    objects.push((hash, String::new()));
+                }
+                return Ok(());
+            }
+            ObjectType::Commit => return Ok(()),
+        }
+    }
+}
+
+fn walk_tree(
+    repo: &Repository,
+    hash: &str,
+    path: &str,
+    paths: &[String],
+    seen: &mut HashSet<String>,
+    objects: &mut Vec<(String, String)>,
+) -> Result<()> {
+    if !seen.insert(hash.to_string()) {
+        return Ok(());
+    }
+    objects.push((hash.to_string(), path.to_string()));
+
+    for entry in Tree::read(hash, repo)?.entries {
+        let path = match path {
+            "" => entry.name,
+            dir => format!("{dir}/{}", entry.name),
+        };
+        match entry.mode {
+            MODE_TREE if paths.is_empty() || may_match_in(paths, &path, false) => {
+                walk_tree(repo, &entry.hash, &path, paths, seen, objects)?
+            }
+            MODE_TREE | MODE_GITLINK => {}
+            _ if !paths.is_empty()
+                && !paths
+                    .iter()
+                    .any(|spec| match_pathspec(spec, &path).is_some()) => {}
+            _ => {
+                if seen.insert(entry.hash.clone()) {
+                    objects.push((entry.hash, path));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::commit::Signature;
+
+    fn commit(parents: &[&str], time: i64) -> Commit {
+        let signature = Signature {
+            name: "A U Thor".to_string(),
+            email: "author@example.com".to_string(),
+            time,
+            tz: "+0000".to_string(),
+        };
+        Commit {
+            tree: String::new(),
+            parents: parents.iter().map(|p| p.to_string()).collect(),
+            author: signature.clone(),
+            committer: signature,
+            message: "message\n".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_sort_topologically() {
+        // a - b - m - d
+        //  \     /
+        //   c ---
+        // with c dated last, so that date order shows it first
+        let commits = HashMap::from([
+            ("a".to_string(), commit(&[], 1)),
+            ("b".to_string(), commit(&["a"], 2)),
+            ("c".to_string(), commit(&["a"], 5)),
+            ("m".to_string(), commit(&["b", "c"], 3)),
+            ("d".to_string(), commit(&["m"], 4)),
+        ]);
+        let list = ["d", "c", "m", "b", "a"].map(String::from).to_vec();
+
+        let sorted = RevWalk::default().sort_topologically(list, &commits);
+        assert_eq!(sorted, ["d", "m", "c", "b", "a"]);
+    }
+
+    /// Writes an object with the given content.
+    fn write_object(repo: &Repository, kind: ObjectType, content: &str) -> String {
+        Object {
+            kind,
+            size: content.len() as u64,
+            reader: content.as_bytes(),
+        }
+        .write_to_objects(repo)
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rev_walk() {
+        use crate::object::tree::write_tree_from_files;
+        use tempfile::tempdir;
+
+        let temp_dir = tempdir().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        // a - b - d - m
+        //  \         /
+        //   c ---- e
+        // dated in alphabetical order, each with a file `f` holding its name
+        let mut hashes: HashMap<&str, String> = HashMap::new();
+        let mut trees = HashMap::new();
+        for (time, (name, parents)) in [
+            ("a", vec![]),
+            ("b", vec!["a"]),
+            ("c", vec!["a"]),
+            ("d", vec!["b"]),
+            ("e", vec!["c"]),
+            ("m", vec!["d", "e"]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let blob = write_object(&repo, ObjectType::Blob, name);
+            let files = [("f".to_string(), (0o100644, blob.clone()))].into();
+            let tree = write_tree_from_files(&files, &repo).unwrap();
+            let mut content = format!("tree {tree}\n");
+            for parent in parents {
+                content.push_str(&format!("parent {}\n", hashes[parent]));
+            }
+            content.push_str(&format!(
+                "author A U Thor <author@example.com> {time} +0000\n\
+                 committer A U Thor <author@example.com> {time} +0000\n\n{name}\n"
+            ));
+            hashes.insert(name, write_object(&repo, ObjectType::Commit, &content));
+            trees.insert(name, (tree, blob));
+        }
+        for (name, hash) in &hashes {
+            repo.set_ref(&format!("refs/tags/{name}"), hash).unwrap();
+        }
+        let tag = write_object(
+            &repo,
+            ObjectType::Tag,
+            &format!(
+                "object {}\ntype commit\ntag v1\n\
+                 tagger A U Thor <author@example.com> 9 +0000\n\nv1\n",
+                hashes["m"]
+            ),
+        );
+        repo.set_ref("refs/tags/v1", &tag).unwrap();
+
+        let walk = |args: &[&str], options: fn(&mut RevWalk)| {
+            let mut walk = RevWalk::default();
+            options(&mut walk);
+            for arg in args {
+                walk.add_revision(&repo, arg).unwrap();
+            }
+            let commits = walk.limit(walk.walk(&repo).unwrap());
+            let name = |hash: &str| *hashes.iter().find(|(_, h)| *h == hash).unwrap().0;
+            let names: Vec<&str> = commits.iter().map(|rev| name(&rev.hash)).collect();
+            let left: Vec<&str> = commits
+                .iter()
+                .filter(|rev| rev.left)
+                .map(|rev| name(&rev.hash))
+                .collect();
+            (names, left, walk.objects(&repo, &commits).unwrap())
+        };
+        let names = |args: &[&str], options: fn(&mut RevWalk)| walk(args, options).0;
+        let none = |_: &mut RevWalk| {};
+
+        assert_eq!(names(&["m"], none), ["m", "e", "d", "c", "b", "a"]);
+        assert_eq!(names(&["b..m"], none), ["m", "e", "d", "c"]);
+        assert_eq!(names(&["m", "^b"], none), ["m", "e", "d", "c"]);
+        assert_eq!(names(&["m", "--not", "b"], none), ["m", "e", "d", "c"]);
+        assert_eq!(
+            names(&["--not", "b", "--not", "m"], none),
+            ["m", "e", "d", "c"]
+        );
+        assert_eq!(names(&["b..m", "^e"], none), ["m", "d"]);
+        assert_eq!(names(&["m^@"], none), ["e", "d", "c", "b", "a"]);
+        assert_eq!(names(&["m^!"], none), ["m"]);
+
+        // The sides of a symmetric range, without their merge base
+        let (commits, left, _) = walk(&["d...e"], none);
+        assert_eq!(commits, ["e", "d", "c", "b"]);
+        assert_eq!(left, ["d", "b"]);
+
+        // Orders
+        let topo = |walk: &mut RevWalk| walk.topo_order = true;
+        assert_eq!(names(&["m"], topo), ["m", "e", "c", "d", "b", "a"]);
+        let date = |walk: &mut RevWalk| walk.date_order = true;
+        assert_eq!(names(&["m"], date), ["m", "e", "d", "c", "b", "a"]);
+        let reverse = |walk: &mut RevWalk| {
+            walk.reverse = true;
+            walk.max_count = Some(3);
+        };
+        assert_eq!(names(&["m"], reverse), ["d", "e", "m"]);
+
+        // Only the commits between c and m
+        let ancestry_path = |walk: &mut RevWalk| walk.ancestry_path = true;
+        assert_eq!(names(&["c..m"], none), ["m", "e", "d", "b"]);
+        assert_eq!(names(&["c..m"], ancestry_path), ["m", "e"]);
+
+        // The tag given comes first, and the trees and blobs of the
+        // excluded edge are left out
+        let (commits, _, objects) = walk(&["v1", "^d"], none);
+        assert_eq!(commits, ["m", "e", "c"]);
+        let mut expected = vec![(tag, "v1".to_string())];
+        for name in commits {
+            let (tree, blob) = trees[name].clone();
+            expected.push((tree, String::new()));
+            expected.push((blob, "f".to_string()));
+        }
+        assert_eq!(objects, expected);
+        assert_eq!(walk(&["v1", "^v1"], none).2, []);
+    }
+
+    #[test]
+    fn test_may_match_in() {
+        let paths = |specs: &[&str]| specs.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(may_match_in(&paths(&["src/lib.rs"]), "src", false));
+        assert!(may_match_in(&paths(&["src"]), "src/a", false));
+        assert!(!may_match_in(&paths(&["src/lib.rs"]), "doc", true));
+        assert!(may_match_in(&paths(&["doc/*"]), "doc", false));
+        assert!(!may_match_in(&paths(&["doc/*"]), "src", true));
+        // Only a recursive walk looks for a glob in every directory
+        assert!(may_match_in(&paths(&["*.rs"]), "src", true));
+        assert!(!may_match_in(&paths(&["*.rs"]), "src", false));
+        assert!(may_match_in(&paths(&["d*"]), "doc", false));
+    }
+}
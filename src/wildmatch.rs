@@ -0,0 +1,325 @@
+//! Shell-style pattern matching, following git's `wildmatch()`.
+//!
+//! Supports `*`, `?`, bracket expressions (ranges, negation with `!` or `^`
+//! and `[:class:]` names), backslash escapes and, with [`WM_PATHNAME`], `**`
+//! matching across directory boundaries.
+
+/// `*` and `?` don't match `/`, and `**` matches zero or more directories.
+pub const WM_PATHNAME: u32 = 1;
+/// Match case-insensitively.
+pub const WM_CASEFOLD: u32 = 2;
+
+#[derive(PartialEq)]
+enum Outcome {
+    Matched,
+    NoMatch,
+    AbortAll,
+    AbortToStarStar,
+}
+
+/// Returns true if `text` matches `pattern`.
+pub fn wildmatch(pattern: &str, text: &str, flags: u32) -> bool {
+    dowild(pattern.as_bytes(), 0, text.as_bytes(), 0, flags) == Outcome::Matched
+}
+
+/// Returns true if the pattern contains any glob special characters.
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.bytes().any(is_glob_special)
+}
+
+fn is_glob_special(c: u8) -> bool {
+    matches!(c, b'*' | b'?' | b'[' | b'\\')
+}
+
+/// Returns the byte at `i`, or 0 past the end (like a C string).
+fn at(s: &[u8], i: usize) -> u8 {
+    s.get(i).copied().unwrap_or(0)
+}
+
+fn fold(c: u8, flags: u32) -> u8 {
+    if flags & WM_CASEFOLD != 0 {
+        c.to_ascii_lowercase()
+    } else {
+        c
+    }
+}
+
+fn dowild(pattern: &[u8], mut p: usize, text: &[u8], mut t: usize, flags: u32) -> Outcome {
+    while p < pattern.len() {
+        let mut p_ch = fold(pattern[p], flags);
+        let mut t_ch = fold(at(text, t), flags);
+        if t_ch == 0 && p_ch != b'*' {
+            return Outcome::AbortAll;
+        }
+
+        match p_ch {
+            b'?' => {
+                if flags & WM_PATHNAME != 0 && t_ch == b'/' {
+                    return Outcome::NoMatch;
+                }
+            }
+            b'*' => {
+                let match_slash;
+                p += 1;
+                if at(pattern, p) == b'*' {
+                    let prev_is_boundary = p < 2 || pattern[p - 2] == b'/';
+                    while at(pattern, p) == b'*' {
+                        p += 1;
+                    }
+                    let next = at(pattern, p);
+                    if prev_is_boundary
+                        && (next == 0
+                            || next == b'/'
+                            || (next == b'\\' && at(pattern, p + 1) == b'/'))
+                    {
+                        // `**/` also matches zero directories
+                        if next == b'/'
+                            && dowild(pattern, p + 1, text, t, flags) == Outcome::Matched
+                        {
+                            return Outcome::Matched;
+                        }
+                        match_slash = true;
+                    } else {
+                        match_slash = flags & WM_PATHNAME == 0;
+                    }
+                } else {
+                    // Without WM_PATHNAME, '*' == '**'
+                    match_slash = flags & WM_PATHNAME == 0;
+                }
+
+                if p >= pattern.len() {
+                    // A trailing `**` matches everything, a trailing `*` only
+                    // if there are no more slashes.
+                    if !match_slash && text[t..].contains(&b'/') {
+                        return Outcome::NoMatch;
+                    }
+                    return Outcome::Matched;
+                } else if !match_slash && pattern[p] == b'/' {
+                    // A single asterisk followed by a slash matches up to the next slash
+                    match text[t..].iter().position(|&c| c == b'/') {
+                        Some(slash) => {
+                            t += slash + 1;
+                            p += 1;
+                            continue;
+                        }
+                        None => return Outcome::NoMatch,
+                    }
+                }
+
+                loop {
+                    if t_ch == 0 {
+                        break;
+                    }
+                    // Skip ahead to the next occurrence of a literal following the asterisk
+                    if !is_glob_special(pattern[p]) {
+                        p_ch = fold(pattern[p], flags);
+                        loop {
+                            t_ch = fold(at(text, t), flags);
+                            if t_ch == 0 || (!match_slash && t_ch == b'/') || t_ch == p_ch {
+                                break;
+                            }
+                            t += 1;
+                        }
+                        if t_ch != p_ch {
+                            return Outcome::NoMatch;
+                        }
+                    }
+                    match dowild(pattern, p, text, t, flags) {
+                        Outcome::NoMatch => {
+                            if !match_slash && t_ch == b'/' {
+                                return Outcome::AbortToStarStar;
+                            }
+                        }
+                        Outcome::AbortToStarStar if match_slash => {}
+                        matched => return matched,
+                    }
+                    t += 1;
+                    t_ch = fold(at(text, t), flags);
+                }
+                return Outcome::AbortAll;
+            }
+            b'[' => {
+                p += 1;
+                p_ch = at(pattern, p);
+                let negated = p_ch == b'!' || p_ch == b'^';
+                if negated {
+                    p += 1;
+                    p_ch = at(pattern, p);
+                }
+                let t_orig = at(text, t);
+                let mut prev_ch = 0u8;
+                let mut matched = false;
+                loop {
+                    if p_ch == 0 {
+                        return Outcome::AbortAll;
+                    }
+                    if p_ch == b'\\' {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == 0 {
+                            return Outcome::AbortAll;
+                        }
+                        if t_ch == fold(p_ch, flags) {
+                            matched = true;
+                        }
+                    } else if p_ch == b'-'
+                        && prev_ch != 0
+                        && at(pattern, p + 1) != 0
+                        && at(pattern, p + 1) != b']'
+                    {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == b'\\' {
+                            p += 1;
+                            p_ch = at(pattern, p);
+                            if p_ch == 0 {
+                                return Outcome::AbortAll;
+                            }
+                        }
+                        if (prev_ch..=p_ch).contains(&t_orig)
+                            || (flags & WM_CASEFOLD != 0
+                                && ((prev_ch..=p_ch).contains(&t_orig.to_ascii_lowercase())
+                                    || (prev_ch..=p_ch).contains(&t_orig.to_ascii_uppercase())))
+                        {
+                            matched = true;
+                        }
+                        p_ch = 0;
+                    } else if p_ch == b'[' && at(pattern, p + 1) == b':' {
+                        let start = p + 2;
+                        let mut end = start;
+                        while at(pattern, end) != 0 && at(pattern, end) != b']' {
+                            end += 1;
+                        }
+                        if at(pattern, end) == 0 {
+                            return Outcome::AbortAll;
+                        }
+                        if end == start || pattern[end - 1] != b':' {
+                            // Didn't find ":]", so treat like a normal set
+                            if t_ch == b'[' {
+                                matched = true;
+                            }
+                        } else {
+                            let class = &pattern[start..end - 1];
+                            if class_matches(class, t_orig, flags) {
+                                matched = true;
+                            }
+                            p = end;
+                            p_ch = 0;
+                        }
+                    } else if t_ch == fold(p_ch, flags) {
+                        matched = true;
+                    }
+
+                    prev_ch = p_ch;
+                    p += 1;
+                    p_ch = at(pattern, p);
+                    if p_ch == b']' {
+                        break;
+                    }
+                }
+                if matched == negated || (flags & WM_PATHNAME != 0 && t_ch == b'/') {
+                    return Outcome::NoMatch;
+                }
+            }
+            b'\\' => {
+                p += 1;
+                if t_ch != fold(at(pattern, p), flags) {
+                    return Outcome::NoMatch;
+                }
+            }
+            _ => {
+                if t_ch != p_ch {
+                    return Outcome::NoMatch;
+                }
+            }
+        }
+        p += 1;
+        t += 1;
+    }
+
+    if t < text.len() {
+        Outcome::NoMatch
+    } else {
+        Outcome::Matched
+    }
+}
+
+fn class_matches(class: &[u8], c: u8, flags: u32) -> bool {
+    match class {
+        b"alnum" => c.is_ascii_alphanumeric(),
+        b"alpha" => c.is_ascii_alphabetic(),
+        b"blank" => c == b' ' || c == b'\t',
+        b"cntrl" => c.is_ascii_control(),
+        b"digit" => c.is_ascii_digit(),
+        b"graph" => c.is_ascii_graphic(),
+        b"lower" => c.is_ascii_lowercase() || (flags & WM_CASEFOLD != 0 && c.is_ascii_uppercase()),
+        b"print" => c.is_ascii_graphic() || c == b' ',
+        b"punct" => c.is_ascii_punctuation(),
+        b"space" => c.is_ascii_whitespace() || c == 0x0b,
+        b"upper" => c.is_ascii_uppercase() || (flags & WM_CASEFOLD != 0 && c.is_ascii_lowercase()),
+        b"xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildmatch() {
+        let cases = [
+            ("foo", "foo", true),
+            ("foo", "bar", false),
+            ("???", "foo", true),
+            ("*", "foo/bar", true),
+            ("f*", "foo", true),
+            ("*.rs", "src/main.rs", true),
+            ("[a-c]at", "bat", true),
+            ("[!a-c]at", "bat", false),
+            ("[^a-c]at", "rat", true),
+            ("[[:digit:]]x", "1x", true),
+            ("[[:digit:]]x", "ax", false),
+            ("\\*", "*", true),
+            ("\\*", "a", false),
+            ("feature/*", "feature/login", true),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(wildmatch(pattern, text, 0), expected, "{pattern} {text}");
+        }
+    }
+
+    #[test]
+    fn test_wildmatch_pathname() {
+        let cases = [
+            ("*", "foo/bar", false),
+            ("*.rs", "src/main.rs", false),
+            ("*/*.rs", "src/main.rs", true),
+            ("**/*.rs", "main.rs", true),
+            ("**/*.rs", "src/a/b/main.rs", true),
+            ("src/**", "src/a/b", true),
+            ("src/**", "src", false),
+            ("a/**/b", "a/b", true),
+            ("a/**/b", "a/x/y/b", true),
+            ("a/**b", "a/x/b", false),
+            ("foo?bar", "foo/bar", false),
+            ("foo[/]bar", "foo/bar", false),
+            ("*/bar", "foo/bar", true),
+            ("*/bar", "foo/baz/bar", false),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                wildmatch(pattern, text, WM_PATHNAME),
+                expected,
+                "{pattern} {text}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_wildmatch_casefold() {
+        assert!(wildmatch("FOO*", "foobar", WM_CASEFOLD));
+        assert!(wildmatch("[A-Z]", "q", WM_CASEFOLD));
+        assert!(!wildmatch("FOO*", "foobar", 0));
+    }
+}